@@ -0,0 +1,170 @@
+use std::fmt;
+
+/// Stable codes for the errors of each stage, for tools that filter errors
+/// and tests that shouldn't depend on the exact wording of messages.
+///
+/// The first digit is the stage: 0 scanning, 1 parsing, 2 resolving and
+/// 3 running. Codes are never reused, and errors without a more specific
+/// code get the general code of their stage, ending in 000. Warnings are
+/// numbered the same way but start with a W.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    UnexpectedCharacter,
+    UnterminatedString,
+    MisplacedSeparator,
+
+    SyntaxError,
+    ExpectedExpression,
+    ExpectedToken,
+    TooManyParameters,
+    TooManyArguments,
+    InvalidAssignmentTarget,
+
+    ResolveError,
+    ReturnFromTopLevel,
+    ReturnValueFromInitializer,
+    InheritFromSelf,
+    LocalInOwnInitializer,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    ThisOutsideClass,
+    DuplicateVariable,
+    UndeclaredVariable,
+    DuplicateMethod,
+
+    RuntimeError,
+    UndefinedVariable,
+    UndefinedProperty,
+    ArityMismatch,
+    NotCallable,
+    OperandType,
+    NotAnInstance,
+    InvalidArgument,
+    IndexOutOfBounds,
+    AssertionFailed,
+    IoError,
+    Cancelled,
+    StepLimitExceeded,
+    MemoryLimitExceeded,
+    ExecDisabled,
+    StackOverflow,
+    FileAccessDisabled,
+
+    UnreachableCode,
+    ShadowedVariable,
+    UnusedVariable,
+    ConstantCondition,
+    ArgumentCount,
+}
+
+// Names of the warnings for turning them on and off, as in `loxrun lint`
+const RULE_NAMES: &[(ErrorCode, &str)] = &[
+    (ErrorCode::UnreachableCode, "unreachable-code"),
+    (ErrorCode::ShadowedVariable, "shadowed-variable"),
+    (ErrorCode::UnusedVariable, "unused-variable"),
+    (ErrorCode::ConstantCondition, "constant-condition"),
+    (ErrorCode::ArgumentCount, "argument-count"),
+];
+
+impl ErrorCode {
+    /// All warnings, in the order of their codes.
+    pub fn warnings() -> impl Iterator<Item = ErrorCode> {
+        RULE_NAMES.iter().map(|(code, _)| *code)
+    }
+
+    /// Name of the rule of a warning, such as `unused-variable`. Errors have
+    /// no rule, as they can't be turned off.
+    pub fn rule_name(&self) -> Option<&'static str> {
+        RULE_NAMES
+            .iter()
+            .find(|(code, _)| code == self)
+            .map(|(_, name)| *name)
+    }
+
+    /// The warning with the rule name.
+    pub fn from_rule_name(name: &str) -> Option<Self> {
+        RULE_NAMES
+            .iter()
+            .find(|(_, rule)| *rule == name)
+            .map(|(code, _)| *code)
+    }
+
+    /// The code, such as `E3001`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "E0001",
+            ErrorCode::UnterminatedString => "E0002",
+            ErrorCode::MisplacedSeparator => "E0003",
+            ErrorCode::SyntaxError => "E1000",
+            ErrorCode::ExpectedExpression => "E1001",
+            ErrorCode::ExpectedToken => "E1002",
+            ErrorCode::TooManyParameters => "E1003",
+            ErrorCode::TooManyArguments => "E1004",
+            ErrorCode::InvalidAssignmentTarget => "E1005",
+            ErrorCode::ResolveError => "E2000",
+            ErrorCode::ReturnFromTopLevel => "E2001",
+            ErrorCode::ReturnValueFromInitializer => "E2002",
+            ErrorCode::InheritFromSelf => "E2003",
+            ErrorCode::LocalInOwnInitializer => "E2004",
+            ErrorCode::SuperOutsideClass => "E2005",
+            ErrorCode::SuperWithoutSuperclass => "E2006",
+            ErrorCode::ThisOutsideClass => "E2007",
+            ErrorCode::DuplicateVariable => "E2008",
+            ErrorCode::UndeclaredVariable => "E2009",
+            ErrorCode::DuplicateMethod => "E2010",
+            ErrorCode::RuntimeError => "E3000",
+            ErrorCode::UndefinedVariable => "E3001",
+            ErrorCode::UndefinedProperty => "E3002",
+            ErrorCode::ArityMismatch => "E3003",
+            ErrorCode::NotCallable => "E3004",
+            ErrorCode::OperandType => "E3005",
+            ErrorCode::NotAnInstance => "E3006",
+            ErrorCode::InvalidArgument => "E3007",
+            ErrorCode::IndexOutOfBounds => "E3008",
+            ErrorCode::AssertionFailed => "E3009",
+            ErrorCode::IoError => "E3010",
+            ErrorCode::Cancelled => "E3011",
+            ErrorCode::StepLimitExceeded => "E3012",
+            ErrorCode::MemoryLimitExceeded => "E3013",
+            ErrorCode::ExecDisabled => "E3014",
+            ErrorCode::StackOverflow => "E3015",
+            ErrorCode::FileAccessDisabled => "E3016",
+            ErrorCode::UnreachableCode => "W1001",
+            ErrorCode::ShadowedVariable => "W2001",
+            ErrorCode::UnusedVariable => "W2002",
+            ErrorCode::ConstantCondition => "W2003",
+            ErrorCode::ArgumentCount => "W2004",
+        }
+    }
+}
+
+/// Formats as the code followed by the name, e.g. `E3003 ArityMismatch`.
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}", self.code(), self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            ErrorCode::UndefinedVariable.to_string(),
+            "E3001 UndefinedVariable"
+        );
+        assert_eq!(ErrorCode::ConstantCondition.code(), "W2003");
+    }
+
+    #[test]
+    fn test_rule_names() {
+        for warning in ErrorCode::warnings() {
+            let name = warning.rule_name().unwrap();
+            assert_eq!(ErrorCode::from_rule_name(name), Some(warning));
+        }
+        assert_eq!(ErrorCode::UndefinedVariable.rule_name(), None);
+        assert_eq!(ErrorCode::from_rule_name("no-such-rule"), None);
+    }
+}
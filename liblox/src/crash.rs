@@ -0,0 +1,89 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+
+// Message and location of the most recent panic, captured by the hook.
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+/// Replaces the default panic output (message and backtrace hint) with a
+/// silent hook that records the panic for `catch_internal_error`.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        let report = match info.location() {
+            Some(location) => format!("{} ({}:{})", message, location.file(), location.line()),
+            None => message,
+        };
+        *LAST_PANIC.lock().unwrap_or_else(|err| err.into_inner()) = Some(report);
+    }));
+}
+
+/// A panic inside the interpreter, caught before it could abort the process.
+#[derive(Debug)]
+pub struct InternalError {
+    pub panic: String,
+    pub context: Option<String>,
+}
+
+impl InternalError {
+    /// Adds what the interpreter was doing when the panic happened.
+    pub fn with_context(self, context: String) -> Self {
+        Self {
+            context: Some(context),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Internal interpreter error: {}", self.panic)?;
+        if let Some(context) = &self.context {
+            writeln!(f, "  while {}", context)?;
+        }
+        write!(
+            f,
+            "This is a bug in the interpreter, not in your script. Please report it at \
+             https://github.com/bfierz/lox-rs/issues together with the script that triggered it."
+        )
+    }
+}
+
+/// Runs `f`, turning a panic into an `InternalError` instead of unwinding
+/// further.
+pub fn catch_internal_error<T>(f: impl FnOnce() -> T) -> Result<T, InternalError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|_| InternalError {
+        panic: LAST_PANIC
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take()
+            .unwrap_or_else(|| "unknown panic".to_string()),
+        context: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_internal_error() {
+        assert_eq!(catch_internal_error(|| 7).unwrap(), 7);
+
+        install_panic_hook();
+        let error = catch_internal_error(|| -> i32 { panic!("boom") }).unwrap_err();
+        let _ = panic::take_hook();
+
+        assert!(error.panic.starts_with("boom (src/crash.rs:"));
+        let report = error.with_context("testing".to_string()).to_string();
+        assert!(report.starts_with("Internal interpreter error: boom"));
+        assert!(report.contains("\n  while testing\n"));
+    }
+}
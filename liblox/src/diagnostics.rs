@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::codes::ErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The source can't be run.
+    Error,
+    /// A finding that doesn't stop the source from running.
+    Warning,
+}
+
+/// A message together with where it happened in the source, as far as that
+/// is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    /// The message alone, such as `Expect expression.`.
+    pub message: String,
+    pub line: Option<i32>,
+    /// Column of the start of `span`, counting characters from 1.
+    pub column: Option<usize>,
+    /// Byte range of the offending source text.
+    pub span: Option<Range<usize>>,
+    /// What the error was found at, such as ` at 'x'` or ` at end`, as
+    /// printed after `Error`. `None` for runtime errors, which are printed
+    /// with the line after the message instead.
+    pub location: Option<String>,
+}
+
+impl Diagnostic {
+    /// Creates an error found before running, printed like
+    /// `[line 1] Error at 'x': Expect expression.`.
+    pub fn error(code: ErrorCode, line: i32, location: &str, message: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.to_string(),
+            line: Some(line),
+            column: None,
+            span: None,
+            location: Some(location.to_string()),
+        }
+    }
+
+    /// Creates a runtime error, printed like `Undefined variable 'a'.` and
+    /// its line on the next line.
+    pub fn runtime(code: ErrorCode, line: Option<i32>, message: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.to_string(),
+            line,
+            column: None,
+            span: None,
+            location: None,
+        }
+    }
+
+    /// Creates a warning, printed like the errors found before running.
+    pub fn warning(code: ErrorCode, line: i32, message: &str) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, line, "", message)
+        }
+    }
+
+    /// Turns a warning into an error, for sources where warnings aren't
+    /// allowed.
+    pub fn into_error(self) -> Self {
+        Self {
+            severity: Severity::Error,
+            ..self
+        }
+    }
+
+    /// Sets the span of `source` the diagnostic is about, computing its
+    /// column. Spans that don't lie in `source` are dropped, as they come
+    /// from another source.
+    pub fn with_span(self, source: &str, span: Range<usize>) -> Self {
+        let Some(before) = source
+            .get(..span.start)
+            .filter(|_| span.end <= source.len())
+        else {
+            return Self {
+                column: None,
+                span: None,
+                ..self
+            };
+        };
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Self {
+            column: Some(before[line_start..].chars().count() + 1),
+            span: Some(span),
+            ..self
+        }
+    }
+
+    /// Computes the column of the span the diagnostic has, which is kept
+    /// only if it lies in `source`, see `with_span`.
+    pub fn locate(self, source: &str) -> Self {
+        match self.span.clone() {
+            Some(span) => self.with_span(source, span),
+            None => self,
+        }
+    }
+
+    /// Formats the message followed by the offending line of `source` with
+    /// the error underlined.
+    pub fn render(&self, source: &str) -> String {
+        let excerpt = self
+            .line
+            .and_then(|line| render_excerpt(source, line, self.span.clone()));
+        match excerpt {
+            Some(excerpt) => format!("{}\n{}", self, excerpt),
+            None => self.to_string(),
+        }
+    }
+}
+
+/// Formats like jlox, e.g. `[line 2] Error at ';': Expect expression.`.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        match (&self.location, self.line) {
+            (Some(location), Some(line)) => write!(
+                f,
+                "[line {}] {}{}: {}",
+                line, severity, location, self.message
+            ),
+            (Some(location), None) => write!(f, "{}{}: {}", severity, location, self.message),
+            (None, Some(line)) => write!(f, "{}\n[line {}]", self.message, line),
+            (None, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Receives the warnings found while a source is processed.
+pub trait DiagnosticSink {
+    fn report(&mut self, diagnostic: Diagnostic);
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.push(diagnostic);
+    }
+}
+
+/// A sink shared by the stages that process a source.
+pub type SharedDiagnosticSink = Rc<RefCell<dyn DiagnosticSink>>;
+
+/// Receives the syntax errors as the scanner and parser find them, with
+/// messages like `[line 1] Error: Unexpected character.`.
+pub trait ErrorReporter {
+    fn report(&mut self, message: &str);
+}
+
+/// Prints the errors to stderr, as the binaries do.
+pub struct StderrReporter;
+
+impl ErrorReporter for StderrReporter {
+    fn report(&mut self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+impl ErrorReporter for Vec<String> {
+    fn report(&mut self, message: &str) {
+        self.push(message.to_string());
+    }
+}
+
+/// A reporter shared by the scanner and parser of a source.
+pub type SharedErrorReporter = Rc<RefCell<dyn ErrorReporter>>;
+
+pub fn stderr_reporter() -> SharedErrorReporter {
+    Rc::new(RefCell::new(StderrReporter))
+}
+
+/// Renders the source line of an error with a `^^^` underline, like
+///
+/// ```text
+///   2 | var b = ;
+///     |         ^
+/// ```
+///
+/// `span` is a byte range into `source`. Without one the whole line is
+/// underlined, since only the line is known. Returns `None` if the source has
+/// no such line.
+pub fn render_excerpt(source: &str, line: i32, span: Option<Range<usize>>) -> Option<String> {
+    let index = usize::try_from(line).ok()?.checked_sub(1)?;
+    let text = source.lines().nth(index)?;
+    let line_start: usize = source
+        .split('\n')
+        .take(index)
+        .map(|line| line.len() + 1)
+        .sum();
+    let width = text.chars().count();
+    // The column in characters of a byte offset into the line
+    let column = |offset: usize| text.get(..offset).map(|before| before.chars().count());
+
+    let span_columns = span.and_then(|span| {
+        let start = column(span.start.checked_sub(line_start)?)?;
+        // Spans that continue on the next lines are cut at the line end
+        let end = column((span.end - line_start).min(text.len()))?;
+        Some((start, end.max(start + 1)))
+    });
+    let (start, end) = match span_columns {
+        Some(columns) => columns,
+        None => {
+            let indent = text.chars().take_while(|c| c.is_whitespace()).count();
+            (indent, width.max(indent + 1))
+        }
+    };
+
+    let gutter = line.to_string();
+    let padding = " ".repeat(gutter.len());
+    let marker_indent: String = text
+        .chars()
+        .take(start)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    Some(format!(
+        " {} | {}\n {} | {}{}",
+        gutter,
+        text,
+        padding,
+        marker_indent,
+        "^".repeat(end - start)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_excerpt() {
+        let source = "var a = 1;\nvar b = ;\n  print a + nil;";
+        assert_eq!(
+            render_excerpt(source, 2, Some(19..20)).unwrap(),
+            " 2 | var b = ;\n   |         ^"
+        );
+        assert_eq!(
+            render_excerpt(source, 3, None).unwrap(),
+            " 3 |   print a + nil;\n   |   ^^^^^^^^^^^^^^"
+        );
+        // The end of input is just past the last character
+        assert_eq!(
+            render_excerpt(source, 3, Some(37..37)).unwrap(),
+            " 3 |   print a + nil;\n   |                 ^"
+        );
+        assert!(render_excerpt(source, 4, None).is_none());
+    }
+
+    #[test]
+    fn test_multibyte_and_crlf() {
+        let source = "var é = \"ü\";\r\nprint é + ;";
+        // The `;` is at byte 27, but the eleventh character of its line
+        let diagnostic = Diagnostic::error(
+            ErrorCode::ExpectedExpression,
+            2,
+            " at ';'",
+            "Expect expression.",
+        )
+        .with_span(source, 27..28);
+        assert_eq!(diagnostic.column, Some(11));
+        assert_eq!(
+            render_excerpt(source, 2, diagnostic.span.clone()).unwrap(),
+            " 2 | print é + ;\n   |           ^"
+        );
+        assert_eq!(
+            render_excerpt(source, 1, Some(9..13)).unwrap(),
+            " 1 | var é = \"ü\";\n   |         ^^^"
+        );
+    }
+}
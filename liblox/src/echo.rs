@@ -0,0 +1,62 @@
+use crate::scanner::Scanner;
+use crate::tokens::TokenType;
+
+/// Where a source is run, which decides whether its value is echoed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// A script file or `-e` code, which only prints through `print`.
+    #[default]
+    Script,
+    /// A line entered in a REPL.
+    Repl,
+}
+
+/// The rule for implicit output, shared by both binaries: in the REPL, a
+/// source that consists of a single expression without a terminating `;`
+/// has its value printed. Anything else is run as statements and only
+/// prints through `print`, so in a script `1 + 2` is missing its `;`.
+pub fn should_echo(source: &str, mode: Mode) -> bool {
+    if mode != Mode::Repl {
+        return false;
+    }
+    let mut tokens = Scanner::new(source.to_string()).filter_map(Result::ok);
+    let Some(first) = tokens.next() else {
+        return false;
+    };
+    let starts_statement = matches!(
+        first.token_type,
+        TokenType::Eof
+            | TokenType::Class
+            | TokenType::Fun
+            | TokenType::Var
+            | TokenType::For
+            | TokenType::If
+            | TokenType::While
+            | TokenType::Print
+            | TokenType::Return
+            | TokenType::LeftBrace
+    );
+    // Lox has no expression that contains a `;`, so any semicolon ends a
+    // statement.
+    !starts_statement && !tokens.any(|token| token.token_type == TokenType::Semicolon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_echo() {
+        assert!(should_echo("1 + 2", Mode::Repl));
+        assert!(should_echo("  greet(\"you\")\n", Mode::Repl));
+        assert!(should_echo("a = 3", Mode::Repl));
+        assert!(!should_echo("1 + 2;", Mode::Repl));
+        assert!(!should_echo("print 1", Mode::Repl));
+        assert!(!should_echo("var a = 1", Mode::Repl));
+        assert!(!should_echo("{ 1 }", Mode::Repl));
+        assert!(!should_echo("a; b", Mode::Repl));
+        assert!(!should_echo("// only a comment", Mode::Repl));
+        assert!(!should_echo("", Mode::Repl));
+        assert!(!should_echo("1 + 2", Mode::Script));
+    }
+}
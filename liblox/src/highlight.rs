@@ -0,0 +1,193 @@
+use crate::scanner::Scanner;
+use crate::tokens::TokenType;
+
+const RESET: &str = "\x1b[0m";
+const KEYWORD: &str = "\x1b[1;35m";
+const LITERAL: &str = "\x1b[36m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const COMMENT: &str = "\x1b[2m";
+const ERROR: &str = "\x1b[4;31m";
+
+/// What a highlighted stretch of source is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Keyword,
+    Literal,
+    String,
+    Number,
+    Comment,
+    Error,
+}
+
+impl Kind {
+    fn ansi(self) -> &'static str {
+        match self {
+            Kind::Keyword => KEYWORD,
+            Kind::Literal => LITERAL,
+            Kind::String => STRING,
+            Kind::Number => NUMBER,
+            Kind::Comment => COMMENT,
+            Kind::Error => ERROR,
+        }
+    }
+
+    fn css_class(self) -> &'static str {
+        match self {
+            Kind::Keyword => "keyword",
+            Kind::Literal => "literal",
+            Kind::String => "string",
+            Kind::Number => "number",
+            Kind::Comment => "comment",
+            Kind::Error => "error",
+        }
+    }
+}
+
+/// Colors Lox source with ANSI escape sequences based on the scanner's
+/// token stream. The returned string has the same display width as the
+/// input, so it can be used to redraw a line while it is being edited.
+pub fn highlight_ansi(source: &str) -> String {
+    let mut output = String::with_capacity(source.len() * 2);
+    highlight(source, |text, kind| match kind {
+        Some(kind) => {
+            output.push_str(kind.ansi());
+            output.push_str(text);
+            output.push_str(RESET);
+        }
+        None => output.push_str(text),
+    });
+    output
+}
+
+/// Renders Lox source as an HTML `<pre class="lox">` block. Keywords,
+/// literals, strings, numbers, comments and scan errors are wrapped in
+/// `<span>`s with the class of that name, for a stylesheet to color.
+pub fn highlight_html(source: &str) -> String {
+    let mut output = String::from("<pre class=\"lox\">");
+    highlight(source, |text, kind| {
+        if let Some(kind) = kind {
+            output.push_str(&format!("<span class=\"{}\">", kind.css_class()));
+        }
+        for c in text.chars() {
+            match c {
+                '<' => output.push_str("&lt;"),
+                '>' => output.push_str("&gt;"),
+                '&' => output.push_str("&amp;"),
+                '"' => output.push_str("&quot;"),
+                _ => output.push(c),
+            }
+        }
+        if kind.is_some() {
+            output.push_str("</span>");
+        }
+    });
+    output.push_str("</pre>");
+    output
+}
+
+// Hands the source to `emit` in order, split into the stretches to
+// highlight and the plain text between them
+fn highlight(source: &str, mut emit: impl FnMut(&str, Option<Kind>)) {
+    let mut scanner = Scanner::new(source.to_string());
+
+    let mut regions: Vec<(usize, usize, Kind)> = Vec::new();
+    while let Some(result) = scanner.next() {
+        let kind = match result {
+            Ok(token) => token_kind(&token.token_type),
+            Err(_) => Some(Kind::Error),
+        };
+        if let Some(kind) = kind {
+            let span = scanner.span();
+            regions.push((span.start, span.end, kind));
+        }
+    }
+    regions.extend(
+        scanner
+            .comments()
+            .iter()
+            .map(|comment| (comment.span.start, comment.span.end, Kind::Comment)),
+    );
+    regions.sort_by_key(|(start, _, _)| *start);
+
+    let mut position = 0;
+    for (start, end, kind) in regions {
+        if start < position {
+            continue;
+        }
+        emit(&source[position..start], None);
+        emit(&source[start..end], Some(kind));
+        position = end;
+    }
+    emit(&source[position..], None);
+}
+
+fn token_kind(token_type: &TokenType) -> Option<Kind> {
+    match token_type {
+        TokenType::And
+        | TokenType::Class
+        | TokenType::Else
+        | TokenType::Fun
+        | TokenType::For
+        | TokenType::If
+        | TokenType::Or
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::Super
+        | TokenType::This
+        | TokenType::Var
+        | TokenType::While => Some(Kind::Keyword),
+        TokenType::True | TokenType::False | TokenType::Nil => Some(Kind::Literal),
+        TokenType::String => Some(Kind::String),
+        TokenType::Number => Some(Kind::Number),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_statement() {
+        assert_eq!(
+            highlight_ansi("var a = 1;"),
+            format!("{KEYWORD}var{RESET} a = {NUMBER}1{RESET};")
+        );
+    }
+
+    #[test]
+    fn test_highlight_string_and_literal() {
+        assert_eq!(
+            highlight_ansi("print \"hi\" == nil;"),
+            format!("{KEYWORD}print{RESET} {STRING}\"hi\"{RESET} == {LITERAL}nil{RESET};")
+        );
+    }
+
+    #[test]
+    fn test_highlight_comment() {
+        assert_eq!(
+            highlight_ansi("x; // note\ny"),
+            format!("x; {COMMENT}// note{RESET}\ny")
+        );
+    }
+
+    #[test]
+    fn test_highlight_errors() {
+        assert_eq!(
+            highlight_ansi("a @ \"open"),
+            format!("a {ERROR}@{RESET} {ERROR}\"open{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_highlight_html() {
+        assert_eq!(
+            highlight_html("if (a < 1) print \"<b>\"; // a & b"),
+            "<pre class=\"lox\"><span class=\"keyword\">if</span> (a &lt; \
+             <span class=\"number\">1</span>) <span class=\"keyword\">print</span> \
+             <span class=\"string\">&quot;&lt;b&gt;&quot;</span>; \
+             <span class=\"comment\">// a &amp; b</span></pre>"
+        );
+    }
+}
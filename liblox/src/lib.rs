@@ -1,2 +1,11 @@
+pub mod codes;
+pub mod crash;
+pub mod diagnostics;
+pub mod echo;
+pub mod highlight;
+pub mod log;
+pub mod memory;
+#[cfg(feature = "repl")]
+pub mod repl;
 pub mod scanner;
 pub mod tokens;
@@ -0,0 +1,127 @@
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// How much the binaries report besides the program's own output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only program output and errors.
+    Quiet,
+    /// Banners and traces in addition to program output.
+    Normal,
+    /// Additionally reports phase timings and memory statistics.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static TIMINGS_ENABLED: AtomicBool = AtomicBool::new(false);
+// Accumulated duration per phase, in the order the phases first ran.
+static TIMINGS: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+pub fn enabled(level: Verbosity) -> bool {
+    verbosity() >= level
+}
+
+/// Reports an informational message (banners, hints) unless quiet.
+pub fn info(message: impl Display) {
+    if enabled(Verbosity::Normal) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Reports a message only in verbose mode.
+pub fn verbose(message: impl Display) {
+    if enabled(Verbosity::Verbose) {
+        eprintln!("[verbose] {}", message);
+    }
+}
+
+/// Enables collecting phase durations for `timings_report`.
+pub fn set_timings(enabled: bool) {
+    TIMINGS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Runs `f` as the named pipeline phase and reports its duration in
+/// verbose mode. With timings enabled the duration is also added to the
+/// phase's total. On wasm32, which has no clock in std, `f` just runs.
+pub fn timed<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    if cfg!(target_arch = "wasm32") {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    verbose(format!("{}: {}", phase, format_duration(elapsed)));
+    if TIMINGS_ENABLED.load(Ordering::Relaxed) {
+        record_timing(phase, elapsed);
+    }
+    result
+}
+
+fn record_timing(phase: &'static str, elapsed: Duration) {
+    let mut timings = TIMINGS.lock().unwrap();
+    match timings.iter_mut().find(|(name, _)| *name == phase) {
+        Some((_, total)) => *total += elapsed,
+        None => timings.push((phase, elapsed)),
+    }
+}
+
+/// Table of the time spent in each phase so far, or `None` if timings are
+/// disabled.
+pub fn timings_report() -> Option<String> {
+    if !TIMINGS_ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let timings = TIMINGS.lock().unwrap();
+    let total: Duration = timings.iter().map(|(_, elapsed)| *elapsed).sum();
+    let mut report = "Phase timings:\n".to_string();
+    for (phase, elapsed) in timings.iter().chain([("total", total)].iter()) {
+        let share = if total.is_zero() {
+            0.0
+        } else {
+            elapsed.as_secs_f64() / total.as_secs_f64() * 100.0
+        };
+        report.push_str(&format!(
+            "  {:<10} {:>12} {:>6.1}%\n",
+            phase,
+            format_duration(*elapsed),
+            share
+        ));
+    }
+    Some(report)
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_report() {
+        set_timings(true);
+        let value = timed("test-phase", || 42);
+        assert_eq!(value, 42);
+        timed("test-phase", || ());
+
+        let report = timings_report().unwrap();
+        assert!(report.starts_with("Phase timings:\n"));
+        assert_eq!(report.matches("test-phase").count(), 1);
+        assert!(report.contains("  total "));
+    }
+}
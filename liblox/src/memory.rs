@@ -0,0 +1,98 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Bytes allocated minus bytes freed by the current thread, which is
+    // negative once it frees more than it allocated
+    static THREAD_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Global allocator that forwards to the system allocator while counting
+/// allocations, so the binaries can report memory statistics. Install it
+/// with `#[global_allocator]`.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            record_dealloc(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn record_alloc(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    let current = CURRENT_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+    add_thread_bytes(size as isize);
+}
+
+fn record_dealloc(size: usize) {
+    CURRENT_BYTES.fetch_sub(size, Ordering::Relaxed);
+    add_thread_bytes(-(size as isize));
+}
+
+fn add_thread_bytes(size: isize) {
+    // Fails only while the thread is torn down, when nothing is measured
+    let _ = THREAD_BYTES.try_with(|bytes| bytes.set(bytes.get() + size));
+}
+
+/// Whether `CountingAllocator` is the global allocator, found out by it
+/// having counted anything.
+pub fn is_counting() -> bool {
+    ALLOCATIONS.load(Ordering::Relaxed) > 0
+}
+
+/// Bytes the current thread allocated and didn't free since it started,
+/// which can be negative. Zero unless `CountingAllocator` is installed.
+pub fn thread_bytes() -> isize {
+    THREAD_BYTES.try_with(|bytes| bytes.get()).unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryStats {
+    pub allocations: usize,
+    pub current_bytes: usize,
+    pub peak_bytes: usize,
+}
+
+/// Statistics gathered by `CountingAllocator`. All zero unless it is the
+/// installed global allocator.
+pub fn stats() -> MemoryStats {
+    MemoryStats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        current_bytes: CURRENT_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+impl std::fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} allocations, {} bytes in use, {} bytes peak",
+            self.allocations, self.current_bytes, self.peak_bytes
+        )
+    }
+}
@@ -0,0 +1,351 @@
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+use rustyline::Context;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Config, Editor, Helper};
+
+use crate::highlight::highlight_ansi;
+use crate::log;
+use crate::scanner::Scanner;
+use crate::tokens::TokenType;
+
+/// Supplies the names that are completed with tab.
+pub trait NameProvider {
+    /// The global variables, functions and classes.
+    fn globals(&self) -> Vec<String>;
+
+    /// The methods and fields of the value of the global `receiver`, or
+    /// nothing if its class isn't known.
+    fn members(&self, receiver: &str) -> Vec<String>;
+}
+
+/// Line editor shared by the REPLs of both binaries.
+pub struct LineEditor {
+    editor: Editor<LoxHelper, DefaultHistory>,
+    // Where the history is kept across sessions
+    history_file: Option<PathBuf>,
+}
+
+/// Where the REPLs keep their history unless told otherwise,
+/// `~/.lox_history`. `None` if there is no home directory.
+pub fn default_history_file() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".lox_history"))
+}
+
+impl LineEditor {
+    pub fn new() -> rustyline::Result<Self> {
+        // A bracketed paste arrives as one edit, so a pasted script can be
+        // run as a whole instead of line by line.
+        let config = Config::builder().bracketed_paste(true).build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(LoxHelper {
+            names: None,
+            colors: colors_wanted(),
+        }));
+        Ok(Self {
+            editor,
+            history_file: None,
+        })
+    }
+
+    /// Keeps the history in `path`, starting with what earlier sessions
+    /// saved there. Each line is added to the file as it is entered.
+    pub fn set_history_file(&mut self, path: PathBuf) -> rustyline::Result<()> {
+        match self.editor.load_history(&path) {
+            Err(ReadlineError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+        self.history_file = Some(path);
+        Ok(())
+    }
+
+    /// The lines entered so far, including those of earlier sessions, oldest
+    /// first.
+    pub fn history(&self) -> impl Iterator<Item = &String> {
+        self.editor.history().iter()
+    }
+
+    /// Turns syntax highlighting of the line being edited on or off. It is on
+    /// unless the `NO_COLOR` environment variable is set.
+    pub fn set_highlighting(&mut self, enabled: bool) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.colors = enabled;
+        }
+    }
+
+    /// Completes names from `names` when tab is pressed. Without a provider
+    /// nothing is completed.
+    pub fn set_name_provider(&mut self, names: impl NameProvider + 'static) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.names = Some(Box::new(names));
+        }
+    }
+
+    /// Reads the next line of input. Returns `None` once the input is
+    /// exhausted or the user interrupts the session.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        match self.editor.readline(prompt) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    self.remember(&line);
+                }
+                Some(line)
+            }
+            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => None,
+            Err(err) => {
+                eprintln!("Error reading input: {}", err);
+                None
+            }
+        }
+    }
+
+    fn remember(&mut self, line: &str) {
+        if let Err(err) = self.editor.add_history_entry(line) {
+            log::verbose(format!("Line not added to the history: {}", err));
+            return;
+        }
+        if let Some(path) = &self.history_file
+            && let Err(err) = self.editor.append_history(path)
+        {
+            log::verbose(format!("History not saved to {}: {}", path.display(), err));
+        }
+    }
+}
+
+struct LoxHelper {
+    names: Option<Box<dyn NameProvider>>,
+    colors: bool,
+}
+
+// See https://no-color.org
+fn colors_wanted() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+impl Helper for LoxHelper {}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(match &self.names {
+            Some(names) => complete(names.as_ref(), line, pos),
+            None => (pos, Vec::new()),
+        })
+    }
+}
+
+/// Completes the name that ends at byte `pos` of `line`: a member after
+/// `receiver.`, where the receiver is a global, or else a global. Returns
+/// where the name starts and the candidates in order.
+pub fn complete(names: &dyn NameProvider, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = name_start(before);
+    let prefix = &before[start..];
+    let mut candidates = match before[..start].strip_suffix('.') {
+        Some(object) => {
+            let receiver_start = name_start(object);
+            let receiver = &object[receiver_start..];
+            // Only a global can be looked up, not `a.b.` or `f().`
+            if receiver.is_empty() || object[..receiver_start].ends_with(['.', ')']) {
+                Vec::new()
+            } else {
+                names.members(receiver)
+            }
+        }
+        None => names.globals(),
+    };
+    candidates.retain(|name| name.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+// Where the identifier that `text` ends with starts
+fn name_start(text: &str) -> usize {
+    text.char_indices()
+        .rev()
+        .take_while(|(_, c)| unicode_ident::is_xid_continue(*c))
+        .last()
+        .map_or(text.len(), |(i, _)| i)
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Validator for LoxHelper {
+    // Keeps reading lines while a block, call or string is still open, so a
+    // pasted multi-line snippet is submitted as one unit.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+/// Splits a REPL command such as `:time f(1);` into its name and the rest
+/// of the line. Returns `None` if the line is Lox code.
+pub fn parse_command(line: &str) -> Option<(&str, &str)> {
+    let command = line.trim().strip_prefix(':')?;
+    Some(match command.split_once(char::is_whitespace) {
+        Some((name, rest)) => (name, rest.trim()),
+        None => (command, ""),
+    })
+}
+
+/// Returns true if the source ends inside an unclosed brace, parenthesis
+/// or string literal.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut depth = 0;
+    let mut open_string = false;
+    for result in Scanner::new(source.to_string()) {
+        match result {
+            Ok(token) => match token.token_type {
+                TokenType::LeftBrace | TokenType::LeftParen => depth += 1,
+                TokenType::RightBrace | TokenType::RightParen => depth -= 1,
+                _ => {}
+            },
+            Err(error) => {
+                open_string |= error.span.end == source.len() && error.lexeme.starts_with('"');
+            }
+        }
+    }
+    depth > 0 || open_string
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if self.colors {
+            Cow::Owned(highlight_ansi(line))
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        // Every edit can change how the whole line scans (e.g. opening a
+        // string), so re-highlight unless the cursor merely moved.
+        self.colors && kind != CmdKind::MoveCursor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Names;
+
+    impl NameProvider for Names {
+        fn globals(&self) -> Vec<String> {
+            vec![
+                "point".to_string(),
+                "print_all".to_string(),
+                "Point".to_string(),
+            ]
+        }
+
+        fn members(&self, receiver: &str) -> Vec<String> {
+            match receiver {
+                "point" => vec!["x".to_string(), "y".to_string(), "norm".to_string()],
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(
+            complete(&Names, "print po", 8),
+            (6, vec!["point".to_string()])
+        );
+        assert_eq!(
+            complete(&Names, "pr", 2),
+            (0, vec!["print_all".to_string()])
+        );
+        assert_eq!(
+            complete(&Names, "point.n + 1", 7),
+            (6, vec!["norm".to_string()])
+        );
+        assert_eq!(
+            complete(&Names, "point.", 6),
+            (
+                6,
+                vec!["norm".to_string(), "x".to_string(), "y".to_string()]
+            )
+        );
+        assert_eq!(complete(&Names, "a.point.", 8), (8, Vec::new()));
+        assert_eq!(complete(&Names, "Point.", 6), (6, Vec::new()));
+    }
+
+    #[test]
+    fn test_history_file() {
+        let path = env::temp_dir().join(format!("lox_history_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut editor = LineEditor::new().unwrap();
+        editor.set_history_file(path.clone()).unwrap();
+        assert_eq!(editor.history().count(), 0);
+        editor.remember("var a = 1;");
+        editor.remember("print a;");
+
+        let mut editor = LineEditor::new().unwrap();
+        editor.set_history_file(path.clone()).unwrap();
+        let lines: Vec<&String> = editor.history().collect();
+        assert_eq!(lines, ["var a = 1;", "print a;"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_highlight_line() {
+        let mut helper = LoxHelper {
+            names: None,
+            colors: true,
+        };
+        assert_eq!(helper.highlight("print 1;", 0), highlight_ansi("print 1;"));
+        assert!(helper.highlight_char("print 1;", 8, CmdKind::Other));
+        assert!(!helper.highlight_char("print 1;", 7, CmdKind::MoveCursor));
+
+        helper.colors = false;
+        assert_eq!(helper.highlight("print 1;", 0), "print 1;");
+        assert!(!helper.highlight_char("print 1;", 8, CmdKind::Other));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(" :history "), Some(("history", "")));
+        assert_eq!(
+            parse_command(":time  fib(20);\n"),
+            Some(("time", "fib(20);"))
+        );
+        assert_eq!(parse_command("print \":time\";"), None);
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(!is_incomplete("print 1;"));
+        assert!(is_incomplete("class A {\n  f() {\n    print \"(\";"));
+        assert!(!is_incomplete("class A {\n  f() {}\n}"));
+        assert!(is_incomplete("print foo(1,\n"));
+        assert!(is_incomplete("print \"multi\nline"));
+        assert!(!is_incomplete("} // unbalanced is left to the parser"));
+    }
+}
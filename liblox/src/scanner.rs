@@ -1,14 +1,61 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::codes::ErrorCode;
+use crate::diagnostics::SharedErrorReporter;
 use crate::tokens::{LiteralTypes, Token, TokenType};
 
+/// A part of the input that could not be scanned. The scanner skips it and
+/// goes on, so a source can have several.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub line: i32,
+    /// The offending text, such as an unexpected character or the whole of
+    /// an unterminated string.
+    pub lexeme: String,
+    /// Byte range of `lexeme` in the input.
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// A `//` comment, which the scanner skips but keeps for tools that
+/// re-emit the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The comment including its `//`.
+    pub text: String,
+    pub line: i32,
+    /// Byte range of `text` in the input.
+    pub span: Range<usize>,
+}
+
 pub struct Scanner {
     source: String,
     pub had_error: bool,
 
     tokens: Vec<Token>,
-
-    start: i32,
-    current: i32,
+    // Byte ranges of the scanned tokens, parallel to `tokens`
+    spans: Vec<Range<usize>>,
+    errors: Vec<ScanError>,
+    comments: Vec<Comment>,
+    // Receives the errors as they are found
+    reporter: Option<SharedErrorReporter>,
+
+    // Byte offsets into `source`
+    start: usize,
+    current: usize,
     line: i32,
+    // Set once the `Eof` token was produced, which ends the iteration
+    done: bool,
 }
 
 impl Scanner {
@@ -17,32 +64,67 @@ impl Scanner {
             source,
             had_error: false,
             tokens: Vec::new(),
+            spans: Vec::new(),
+            errors: Vec::new(),
+            comments: Vec::new(),
+            reporter: None,
             start: 0,
             current: 0,
             line: 1,
+            done: false,
         }
     }
 
+    /// Byte ranges covered by each scanned token, parallel to the list
+    /// returned by `scan_tokens`.
+    pub fn token_spans(&self) -> &[Range<usize>] {
+        &self.spans
+    }
+
+    /// The scan errors, in the order they were found. They are only
+    /// printed by a reporter.
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
+    /// Sets where errors are reported as they are found, which is nowhere
+    /// by default.
+    pub fn set_error_reporter(&mut self, reporter: Option<SharedErrorReporter>) {
+        self.reporter = reporter;
+    }
+
+    /// The comments, in the order they appear in the input.
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// Byte range of the token or error the scanner produced last.
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.current
+    }
+
     pub fn scan_tokens(&mut self) -> &Vec<Token> {
-        while !self.is_at_end() {
-            // We are at the beginning of the next lexeme.
-            self.start = self.current;
-            self.scan_token();
+        while let Some(result) = self.next() {
+            if let Ok(token) = result {
+                self.tokens.push(token);
+            }
         }
-
-        // Placeholder: add a single dummy token
-        self.tokens.push(Token::new(
-            TokenType::Eof,
-            "".to_string(),
-            LiteralTypes::Nil,
-            self.line,
-        ));
         &self.tokens
     }
 
-    fn scan_token(&mut self) {
+    /// Scans the tokens like `scan_tokens`, but moves them out of the
+    /// scanner instead of lending them, for callers that would otherwise
+    /// copy them. The spans and errors stay with the scanner.
+    pub fn take_tokens(&mut self) -> Vec<Token> {
+        self.scan_tokens();
+        std::mem::take(&mut self.tokens)
+    }
+
+    // Scans the lexeme at `start`, which is nothing for whitespace and
+    // comments
+    fn scan_token(&mut self) -> Option<Result<Token, ScanError>> {
         let c = self.advance();
-        match c {
+        let token = match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
@@ -87,23 +169,36 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    let text = self.source[self.start..self.current].trim_end().to_string();
+                    self.comments.push(Comment {
+                        span: self.start..self.start + text.len(),
+                        text,
+                        line: self.line,
+                    });
+                    return None;
                 } else {
-                    self.add_token(TokenType::Slash);
+                    self.add_token(TokenType::Slash)
                 }
             }
-            ' ' | '\r' | '\t' => (), // Ignore whitespace.
-            '\n' => self.line += 1,
-            '"' => self.string(),
+            ' ' | '\r' | '\t' => return None, // Ignore whitespace.
+            '\n' => {
+                self.line += 1;
+                return None;
+            }
+            '"' => return Some(self.string()),
             _ => {
                 if c.is_ascii_digit() {
-                    self.number();
-                } else if self.is_alpha(c) {
-                    self.identifier();
+                    return Some(self.number());
+                } else if self.is_identifier_start(c) {
+                    self.identifier()
                 } else {
-                    self.error(self.line, "Unexpected character.")
+                    return Some(Err(
+                        self.error(ErrorCode::UnexpectedCharacter, "Unexpected character.")
+                    ));
                 }
             }
-        }
+        };
+        Some(Ok(token))
     }
 
     fn get_keyword(&self, word: &str) -> Option<TokenType> {
@@ -128,12 +223,12 @@ impl Scanner {
         }
     }
 
-    fn identifier(&mut self) {
-        while self.is_alpha_numeric(self.peek()) {
+    fn identifier(&mut self) -> Token {
+        while self.is_identifier_continue(self.peek()) {
             self.advance();
         }
 
-        let text = self.substr_chars(self.start as usize, self.current as usize);
+        let text = &self.source[self.start..self.current];
         let token_type = self.get_keyword(text);
 
         match token_type {
@@ -149,29 +244,42 @@ impl Scanner {
         }
     }
 
-    fn number(&mut self) {
-        while self.peek().is_ascii_digit() {
+    fn number(&mut self) -> Result<Token, ScanError> {
+        while self.peek().is_ascii_digit() || self.peek() == '_' {
             self.advance();
         }
 
-        // Look for a fractional part.
-        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+        // Look for a fractional part. One starting with `_` is taken as a
+        // misplaced separator rather than a property.
+        if self.peek() == '.' && (self.peek_next().is_ascii_digit() || self.peek_next() == '_') {
             // Consume the "."
             self.advance();
 
-            while self.peek().is_ascii_digit() {
+            while self.peek().is_ascii_digit() || self.peek() == '_' {
                 self.advance();
             }
         }
 
-        let value: f64 = self
-            .substr_chars(self.start as usize, self.current as usize)
-            .parse()
-            .unwrap();
-        self.add_token_with_literal(TokenType::Number, LiteralTypes::Number(value));
+        // Separators go between two digits, as in `1_000`
+        let text = &self.source[self.start..self.current];
+        let bytes = text.as_bytes();
+        let misplaced = bytes.iter().enumerate().any(|(i, byte)| {
+            *byte == b'_'
+                && !(bytes[i - 1].is_ascii_digit()
+                    && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        });
+        if misplaced {
+            return Err(self.error(
+                ErrorCode::MisplacedSeparator,
+                "Separator '_' must be between two digits.",
+            ));
+        }
+
+        let value: f64 = text.replace('_', "").parse().unwrap();
+        Ok(self.add_token_with_literal(TokenType::Number, LiteralTypes::Number(value)))
     }
 
-    fn string(&mut self) {
+    fn string(&mut self) -> Result<Token, ScanError> {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
@@ -180,114 +288,119 @@ impl Scanner {
         }
 
         if self.is_at_end() {
-            self.error(self.line, "Unterminated string.");
-            return;
+            return Err(self.error(ErrorCode::UnterminatedString, "Unterminated string."));
         }
 
         // The closing ".
         self.advance();
 
-        // Trim the surrounding quotes.
-        let value = if self.start + 1 < self.current - 1 {
-            self.substr_chars((self.start + 1) as usize, (self.current - 1) as usize)
-        } else {
-            ""
-        };
-        self.add_token_with_literal(TokenType::String, LiteralTypes::String(value.to_string()));
+        // Trim the surrounding quotes. Line breaks in the value are `\n`,
+        // whichever line endings the source has.
+        let value = self.source[self.start + 1..self.current - 1].replace("\r\n", "\n");
+        Ok(self.add_token_with_literal(TokenType::String, LiteralTypes::String(value)))
     }
 
     fn match_next(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-
-        let c = self.source.chars().nth(self.current as usize).unwrap();
-        if c != expected {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current as usize).unwrap()
-        }
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.chars().count() as i32 {
-            '\0'
-        } else {
-            self.source
-                .chars()
-                .nth((self.current + 1) as usize)
-                .unwrap()
-        }
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
-    fn is_alpha_numeric(&self, c: char) -> bool {
-        self.is_alpha(c) || c.is_ascii_digit()
+    // Identifiers follow Unicode's XID rules, and may also start with `_`
+    fn is_identifier_continue(&self, c: char) -> bool {
+        unicode_ident::is_xid_continue(c)
     }
 
-    fn is_alpha(&self, c: char) -> bool {
-        (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+    fn is_identifier_start(&self, c: char) -> bool {
+        unicode_ident::is_xid_start(c) || c == '_'
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.source.chars().count() as i32
+        self.current >= self.source.len()
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current as usize).unwrap();
-        self.current += 1;
+        let c = self.peek();
+        self.current += c.len_utf8();
         c
     }
 
-    fn add_token(&mut self, token_type: TokenType) {
-        self.add_token_with_literal(token_type, LiteralTypes::Nil);
+    fn add_token(&mut self, token_type: TokenType) -> Token {
+        self.add_token_with_literal(token_type, LiteralTypes::Nil)
     }
 
-    fn add_token_with_literal(&mut self, token_type: TokenType, literal: LiteralTypes) {
-        let text = self.substr_chars(self.start as usize, self.current as usize);
-        self.tokens
-            .push(Token::new(token_type, text.to_string(), literal, self.line));
+    fn add_token_with_literal(&mut self, token_type: TokenType, literal: LiteralTypes) -> Token {
+        let text = self.source[self.start..self.current].to_string();
+        self.spans.push(self.span());
+        Token {
+            span: Some(self.span()),
+            ..Token::new(token_type, text, literal, self.line)
+        }
     }
 
-    fn substr_chars(&self, first: usize, last: usize) -> &str {
-        let len = if first < last { last - first - 1 } else { 0 };
-        let mut char_indices = self.source.char_indices().skip(first);
-        let begin = char_indices
-            .next()
-            .map(|(i, _)| i)
-            .unwrap_or(self.source.len());
-        let end = char_indices
-            .nth(len)
-            .map(|(i, _)| i)
-            .unwrap_or(self.source.len());
-        &self.source[begin..end]
+    fn error(&mut self, code: ErrorCode, message: &str) -> ScanError {
+        let error = ScanError {
+            code,
+            message: message.to_string(),
+            line: self.line,
+            lexeme: self.source[self.start..self.current].to_string(),
+            span: self.span(),
+        };
+        if let Some(reporter) = &self.reporter {
+            reporter.borrow_mut().report(&error.to_string());
+        }
+        self.errors.push(error.clone());
+        self.had_error = true;
+        error
     }
+}
 
-    fn error(&mut self, line: i32, message: &str) {
-        Self::report(self, line, "", message);
-    }
+/// Scans the tokens one at a time, ending with `Eof`. Errors are handed out
+/// in between and the scanner goes on after them; they, the spans and the
+/// comments are also kept as with `scan_tokens`.
+impl Iterator for Scanner {
+    type Item = Result<Token, ScanError>;
 
-    fn report(&mut self, line: i32, location: &str, message: &str) {
-        eprintln!("[line {}] Error: {}", line, message);
-        self.had_error = true;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        while !self.is_at_end() {
+            // We are at the beginning of the next lexeme.
+            self.start = self.current;
+            if let Some(result) = self.scan_token() {
+                return Some(result);
+            }
+        }
+
+        self.done = true;
+        self.start = self.current;
+        Some(Ok(self.add_token(TokenType::Eof)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     fn scan(source: &str) -> Vec<Token> {
         let mut scanner = Scanner::new(source.to_string());
-        scanner.scan_tokens().clone()
+        scanner.take_tokens()
     }
 
     fn assert_tokens(source: &str, expected_tokens: Vec<Token>) {
@@ -354,6 +467,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_unicode_identifiers() {
+        assert_tokens(
+            "prix_café _日本 Ωmega2 \"naïve 🌍\"",
+            vec![
+                Token::new_identifier("prix_café".to_string(), 1),
+                Token::new_identifier("_日本".to_string(), 1),
+                Token::new_identifier("Ωmega2".to_string(), 1),
+                Token::new_string("\"naïve 🌍\"".to_string(), 1),
+                Token::new_keyword(TokenType::Eof, "", 1),
+            ],
+        );
+
+        // Symbols and digits still can't start a name
+        let mut scanner = Scanner::new("🌍 ٣x".to_string());
+        scanner.scan_tokens();
+        let messages: Vec<&str> = scanner
+            .errors()
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Unexpected character.", "Unexpected character."]
+        );
+    }
+
     #[test]
     fn test_numbers() {
         assert_tokens(
@@ -366,6 +506,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_numeric_separators() {
+        let tokens = scan("1_000_000 1.250_5");
+        assert_eq!(tokens[0].literal, LiteralTypes::Number(1_000_000.0));
+        assert_eq!(tokens[0].lexeme, "1_000_000");
+        assert_eq!(tokens[1].literal, LiteralTypes::Number(1.250_5));
+
+        for source in ["1__0", "10_", "1_.5", "1._5", "2.5_"] {
+            let mut scanner = Scanner::new(source.to_string());
+            scanner.scan_tokens();
+            let messages: Vec<&str> = scanner
+                .errors()
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect();
+            assert_eq!(
+                messages,
+                vec!["Separator '_' must be between two digits."],
+                "{}",
+                source
+            );
+        }
+    }
+
     #[test]
     fn test_strings() {
         assert_tokens(
@@ -385,6 +549,137 @@ mod tests {
         assert!(scanner.had_error);
     }
 
+    #[test]
+    fn test_token_spans() {
+        let mut scanner = Scanner::new("var s = \"é!\";".to_string());
+        scanner.scan_tokens();
+        assert_eq!(
+            scanner.token_spans(),
+            &[0..3, 4..5, 6..7, 8..13, 13..14, 14..14]
+        );
+    }
+
+    #[test]
+    fn test_crlf_and_multibyte() {
+        let source = "var é = \"ü\r\nö\";\r\nprint é;";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.take_tokens();
+        assert_eq!(tokens[3].literal, LiteralTypes::String("ü\nö".to_string()));
+        let lines: Vec<i32> = tokens.iter().map(|token| token.line).collect();
+        assert_eq!(lines, vec![1, 1, 1, 2, 2, 3, 3, 3, 3]);
+        // Spans are byte ranges, so they slice the source
+        let lexemes: Vec<&str> = scanner
+            .token_spans()
+            .iter()
+            .map(|span| &source[span.clone()])
+            .collect();
+        assert_eq!(
+            lexemes,
+            vec!["var", "é", "=", "\"ü\r\nö\"", ";", "print", "é", ";", ""]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut scanner = Scanner::new("// é\r\nprint 1; // one\n".to_string());
+        scanner.scan_tokens();
+        assert_eq!(scanner.token_spans().len(), 4);
+        assert_eq!(
+            scanner.comments(),
+            &[
+                Comment {
+                    text: "// é".to_string(),
+                    line: 1,
+                    span: 0..5,
+                },
+                Comment {
+                    text: "// one".to_string(),
+                    line: 2,
+                    span: 16..22,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let mut scanner = Scanner::new("a # \"open".to_string());
+        scanner.scan_tokens();
+        assert!(scanner.had_error);
+        let errors = scanner.errors();
+        assert_eq!(
+            errors[0],
+            ScanError {
+                code: ErrorCode::UnexpectedCharacter,
+                message: "Unexpected character.".to_string(),
+                line: 1,
+                lexeme: "#".to_string(),
+                span: 2..3,
+            }
+        );
+        assert_eq!(
+            (errors[1].lexeme.as_str(), errors[1].span.clone()),
+            ("\"open", 4..9)
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "[line 1] Error: Unterminated string."
+        );
+    }
+
+    #[test]
+    fn test_error_reporter() {
+        let reported = Rc::new(RefCell::new(Vec::<String>::new()));
+        let mut scanner = Scanner::new(
+            "a #
+@"
+            .to_string(),
+        );
+        scanner.set_error_reporter(Some(reported.clone()));
+        scanner.scan_tokens();
+        assert_eq!(
+            *reported.borrow(),
+            vec![
+                "[line 1] Error: Unexpected character.",
+                "[line 2] Error: Unexpected character.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_iterator() {
+        let mut scanner = Scanner::new("a # b".to_string());
+        assert_eq!(
+            scanner.next(),
+            Some(Ok(Token::new_identifier("a".to_string(), 1)))
+        );
+        assert_eq!(scanner.span(), 0..1);
+        let error = scanner.next().unwrap().unwrap_err();
+        assert_eq!((error.lexeme.as_str(), scanner.span()), ("#", 2..3));
+
+        let rest: Vec<Token> = scanner.by_ref().map(Result::unwrap).collect();
+        assert_eq!(
+            rest,
+            vec![
+                Token::new_identifier("b".to_string(), 1),
+                Token::new_keyword(TokenType::Eof, "", 1),
+            ]
+        );
+        assert_eq!(scanner.next(), None);
+        assert_eq!(scanner.errors().len(), 1);
+        assert_eq!(scanner.token_spans(), &[0..1, 4..5, 5..5]);
+    }
+
+    #[test]
+    fn test_large_source() {
+        // Quadratic scanning would take minutes on this
+        let source = "é = \"ü\"; // ö\n".repeat(50_000);
+        let mut scanner = Scanner::new(source);
+        assert_eq!(scanner.scan_tokens().len(), 200_001);
+        assert_eq!(scanner.comments().len(), 50_000);
+        assert_eq!(scanner.token_spans()[199_999], 849_992..849_993);
+    }
+
     #[test]
     fn test_hello_world() {
         assert_tokens(
@@ -1,11 +1,25 @@
 use std::fmt;
+use std::ops::Range;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: LiteralTypes,
     pub line: i32,
+    /// Byte range of the token in its source, unknown for tokens that were
+    /// made up rather than scanned.
+    pub span: Option<Range<usize>>,
+}
+
+/// Tokens are equal regardless of where they were found.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type
+            && self.lexeme == other.lexeme
+            && self.literal == other.literal
+            && self.line == other.line
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -23,6 +37,7 @@ impl Token {
             lexeme,
             literal,
             line,
+            span: None,
         }
     }
 
@@ -32,6 +47,7 @@ impl Token {
             lexeme: lexeme.to_string(),
             literal: LiteralTypes::Nil,
             line,
+            span: None,
         }
     }
 
@@ -41,6 +57,7 @@ impl Token {
             lexeme: lexeme.clone(),
             literal: LiteralTypes::String(lexeme),
             line,
+            span: None,
         }
     }
 
@@ -50,6 +67,7 @@ impl Token {
             lexeme: lexeme.clone(),
             literal: LiteralTypes::String(lexeme[1..lexeme.len() - 1].to_string()),
             line,
+            span: None,
         }
     }
 
@@ -60,6 +78,7 @@ impl Token {
             lexeme,
             literal: LiteralTypes::Number(num),
             line,
+            span: None,
         }
     }
 
@@ -74,6 +93,7 @@ impl Token {
             lexeme,
             literal: LiteralTypes::Bool(boolean),
             line,
+            span: None,
         }
     }
 }
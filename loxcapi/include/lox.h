@@ -0,0 +1,41 @@
+/* C interface to the Lox tree-walking interpreter, implemented by the
+ * loxcapi crate. */
+#ifndef LOX_H
+#define LOX_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Lox Lox;
+
+/* Streams passed to the output callback. */
+#define LOX_STREAM_OUTPUT 0
+#define LOX_STREAM_ERROR 1
+
+/* Receives every line printed by a script, without its line terminator.
+ * The line is only valid for the duration of the call. */
+typedef void (*LoxOutputCallback)(void *user_data, int stream, const char *line);
+
+/* Results of lox_run, matching the exit codes of the loxrun binary. */
+#define LOX_OK 0
+#define LOX_INVALID_ARGUMENT 64
+#define LOX_SYNTAX_ERROR 65
+#define LOX_RUNTIME_ERROR 70
+
+Lox *lox_new(void);
+
+/* Runs a UTF-8 source in the interpreter. Globals persist between runs. */
+int lox_run(Lox *lox, const char *source);
+
+/* Sends output and error messages to the callback instead of stdout and
+ * stderr. Passing NULL restores the standard streams. */
+void lox_set_callback(Lox *lox, LoxOutputCallback callback, void *user_data);
+
+void lox_free(Lox *lox);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
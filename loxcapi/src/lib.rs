@@ -0,0 +1,168 @@
+//! C interface to the tree-walking interpreter, declared in
+//! `include/lox.h`.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io;
+
+use liblox::crash;
+use loxrun::host::OutputSink;
+use loxrun::{Interpreter, Lox, LoxError};
+
+pub const LOX_STREAM_OUTPUT: c_int = 0;
+pub const LOX_STREAM_ERROR: c_int = 1;
+
+pub const LOX_OK: c_int = 0;
+pub const LOX_INVALID_ARGUMENT: c_int = 64;
+pub const LOX_SYNTAX_ERROR: c_int = 65;
+pub const LOX_RUNTIME_ERROR: c_int = 70;
+
+pub type LoxOutputCallback =
+    extern "C" fn(user_data: *mut c_void, stream: c_int, line: *const c_char);
+
+struct CallbackSink {
+    callback: LoxOutputCallback,
+    user_data: *mut c_void,
+    stream: c_int,
+}
+
+impl OutputSink for CallbackSink {
+    fn write_line(&mut self, line: &str) {
+        // C strings can't hold NUL, so cut the line at the first one
+        let line = line.split('\0').next().unwrap_or_default();
+        let line = CString::new(line).unwrap();
+        (self.callback)(self.user_data, self.stream, line.as_ptr());
+    }
+}
+
+/// Creates an interpreter, in which `exec()` is disabled. Free it with
+/// `lox_free`.
+#[no_mangle]
+pub extern "C" fn lox_new() -> *mut Lox {
+    let interpreter = Interpreter::builder().allow_exec(false).build();
+    let mut lox = Lox::with_interpreter(interpreter);
+    // Syntax errors go through the error stream like all other errors
+    lox.set_error_reporting(false);
+    Box::into_raw(Box::new(lox))
+}
+
+/// Runs a NUL-terminated UTF-8 source and returns one of the `LOX_*`
+/// result codes.
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new` and `source` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn lox_run(lox: *mut Lox, source: *const c_char) -> c_int {
+    if lox.is_null() || source.is_null() {
+        return LOX_INVALID_ARGUMENT;
+    }
+    let lox = &mut *lox;
+    let Ok(source) = CStr::from_ptr(source).to_str() else {
+        return LOX_INVALID_ARGUMENT;
+    };
+
+    // Panics must not unwind into C
+    let (code, messages) = match crash::catch_internal_error(|| lox.run(source)) {
+        Ok(Ok(())) => (LOX_OK, Vec::new()),
+        Ok(Err(err @ LoxError::Runtime(_))) => (LOX_RUNTIME_ERROR, vec![err.to_string()]),
+        Ok(Err(err)) => (LOX_SYNTAX_ERROR, vec![err.to_string()]),
+        Err(error) => {
+            let error = error.with_context(lox.activity().to_string());
+            // Unwinding may have left the interpreter inside a nested scope
+            lox.interpreter.environment = lox.interpreter.globals.clone();
+            (LOX_RUNTIME_ERROR, vec![error.to_string()])
+        }
+    };
+    for message in messages {
+        for line in message.lines() {
+            lox.interpreter.error_output.write_line(line);
+        }
+    }
+    code
+}
+
+/// Sends printed lines and error messages to `callback`, or back to stdout
+/// and stderr when `callback` is null.
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new`. `user_data` is passed to the callback
+/// as is and must stay valid for as long as the callback is installed.
+#[no_mangle]
+pub unsafe extern "C" fn lox_set_callback(
+    lox: *mut Lox,
+    callback: Option<LoxOutputCallback>,
+    user_data: *mut c_void,
+) {
+    let Some(lox) = lox.as_mut() else {
+        return;
+    };
+    let interpreter = &mut lox.interpreter;
+    match callback {
+        Some(callback) => {
+            interpreter.output = Box::new(CallbackSink {
+                callback,
+                user_data,
+                stream: LOX_STREAM_OUTPUT,
+            });
+            interpreter.error_output = Box::new(CallbackSink {
+                callback,
+                user_data,
+                stream: LOX_STREAM_ERROR,
+            });
+        }
+        None => {
+            interpreter.output = Box::new(io::stdout());
+            interpreter.error_output = Box::new(io::stderr());
+        }
+    }
+}
+
+/// Frees an interpreter created by `lox_new`. Null is ignored.
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lox_free(lox: *mut Lox) {
+    if !lox.is_null() {
+        drop(Box::from_raw(lox));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn collect(user_data: *mut c_void, stream: c_int, line: *const c_char) {
+        let lines = unsafe { &mut *(user_data as *mut Vec<(c_int, String)>) };
+        let line = unsafe { CStr::from_ptr(line) }.to_str().unwrap();
+        lines.push((stream, line.to_string()));
+    }
+
+    #[test]
+    fn test_run_with_callback() {
+        let mut lines: Vec<(c_int, String)> = Vec::new();
+        unsafe {
+            let lox = lox_new();
+            lox_set_callback(lox, Some(collect), &mut lines as *mut _ as *mut c_void);
+            assert_eq!(lox_run(lox, c"var a = 20; print a + 1;".as_ptr()), LOX_OK);
+            assert_eq!(lox_run(lox, c"print a + b;".as_ptr()), LOX_RUNTIME_ERROR);
+            assert_eq!(lox_run(lox, c"print ;".as_ptr()), LOX_SYNTAX_ERROR);
+            assert_eq!(lox_run(lox, std::ptr::null()), LOX_INVALID_ARGUMENT);
+            lox_free(lox);
+        }
+        assert_eq!(
+            lines,
+            vec![
+                (LOX_STREAM_OUTPUT, "21".to_string()),
+                (LOX_STREAM_ERROR, "Undefined variable 'b'.".to_string()),
+                (LOX_STREAM_ERROR, "[line 1]".to_string()),
+                (
+                    LOX_STREAM_ERROR,
+                    "[line 1] Error at ';': Expect expression.".to_string()
+                ),
+            ]
+        );
+    }
+}
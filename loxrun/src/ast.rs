@@ -0,0 +1,117 @@
+//! The arena the nodes of a syntax tree are allocated in. Nodes refer to
+//! their children by index instead of owning them, so a parsed source lies
+//! in two vectors instead of a heap allocation per node, and passes over the
+//! whole program walk memory in order.
+
+use std::ops::{Index, IndexMut};
+
+use crate::expression::Expression;
+use crate::stmt::Stmt;
+
+/// Index of an expression in its `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+/// Index of a statement in its `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StmtId(u32);
+
+/// The expressions and statements of a source. Ids are only meaningful in
+/// the `Ast` that handed them out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ast {
+    expressions: Vec<Expression>,
+    statements: Vec<Stmt>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_expression(&mut self, expression: Expression) -> ExprId {
+        self.expressions.push(expression);
+        ExprId(self.expressions.len() as u32 - 1)
+    }
+
+    pub fn add_statement(&mut self, statement: Stmt) -> StmtId {
+        self.statements.push(statement);
+        StmtId(self.statements.len() as u32 - 1)
+    }
+
+    /// Number of expressions allocated, including those a pass replaced.
+    pub fn expression_count(&self) -> usize {
+        self.expressions.len()
+    }
+
+    /// Number of statements allocated, including those a pass removed.
+    pub fn statement_count(&self) -> usize {
+        self.statements.len()
+    }
+}
+
+impl Index<ExprId> for Ast {
+    type Output = Expression;
+
+    fn index(&self, id: ExprId) -> &Expression {
+        &self.expressions[id.0 as usize]
+    }
+}
+
+impl IndexMut<ExprId> for Ast {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expression {
+        &mut self.expressions[id.0 as usize]
+    }
+}
+
+impl Index<StmtId> for Ast {
+    type Output = Stmt;
+
+    fn index(&self, id: StmtId) -> &Stmt {
+        &self.statements[id.0 as usize]
+    }
+}
+
+impl IndexMut<StmtId> for Ast {
+    fn index_mut(&mut self, id: StmtId) -> &mut Stmt {
+        &mut self.statements[id.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::{Binary, Literal, NodeId};
+    use crate::stmt::ExpressionStmt;
+    use liblox::tokens::{LiteralTypes, Token, TokenType};
+
+    #[test]
+    fn test_ast() {
+        let mut ast = Ast::new();
+        let left = ast.add_expression(Expression::Literal(Literal {
+            id: NodeId(0),
+            value: LiteralTypes::Number(1.0),
+        }));
+        let right = ast.add_expression(Expression::Literal(Literal {
+            id: NodeId(1),
+            value: LiteralTypes::Number(2.0),
+        }));
+        let sum = ast.add_expression(Expression::Binary(Binary {
+            id: NodeId(2),
+            left,
+            operator: Token::new(TokenType::Plus, "+".to_string(), LiteralTypes::Nil, 1),
+            right,
+        }));
+        let statement = ast.add_statement(Stmt::Expression(ExpressionStmt { expression: sum }));
+
+        assert_eq!((ast.expression_count(), ast.statement_count()), (3, 1));
+        let Stmt::Expression(stmt) = &ast[statement] else {
+            panic!("expected an expression statement");
+        };
+        let Expression::Binary(binary) = &ast[stmt.expression] else {
+            panic!("expected a sum");
+        };
+        assert_eq!(ast[binary.right], ast[right]);
+        assert_eq!(*ast[binary.left], NodeId(0));
+    }
+}
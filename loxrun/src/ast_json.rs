@@ -0,0 +1,637 @@
+//! Converts syntax trees to JSON and back, for tools that consume the parse
+//! tree and for tests that compare it structurally.
+//!
+//! Every node is an object whose `type` names its variant, followed by its
+//! fields in declaration order. Tokens are written as their lexeme and line,
+//! and are scanned again when read, and the resolutions of variables are
+//! left out, as the resolver sets them.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use liblox::scanner::Scanner;
+use liblox::tokens::{LiteralTypes, Token};
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::*;
+use crate::interpreter::Value;
+use crate::json;
+use crate::stmt::*;
+
+#[derive(Debug)]
+pub struct AstJsonError {
+    pub message: String,
+}
+
+impl std::fmt::Display for AstJsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AstJsonError {}
+
+fn error(message: String) -> AstJsonError {
+    AstJsonError { message }
+}
+
+/// Writes the statements as a JSON array. Fails only for numbers JSON can't
+/// hold, which constant folding may produce.
+pub fn to_json(ast: &Ast, statements: &[StmtId]) -> Result<String, AstJsonError> {
+    let mut output = String::new();
+    write_list(&mut output, statements, |output, &statement| {
+        write_statement(output, ast, statement)
+    })?;
+    Ok(output)
+}
+
+/// Writes a single expression as a JSON object, see `to_json`.
+pub fn expression_to_json(ast: &Ast, expression: ExprId) -> Result<String, AstJsonError> {
+    let mut output = String::new();
+    write_expression(&mut output, ast, expression)?;
+    Ok(output)
+}
+
+/// Reads statements written by `to_json` into a new syntax tree.
+pub fn from_json(text: &str) -> Result<(Ast, Vec<StmtId>), AstJsonError> {
+    let mut ast = Ast::new();
+    let statements = array(&json::parse(text).map_err(error)?)?
+        .iter()
+        .map(|statement| read_statement(&mut ast, statement))
+        .collect::<Result<_, _>>()?;
+    Ok((ast, statements))
+}
+
+/// Reads an expression written by `expression_to_json` into a new syntax
+/// tree.
+pub fn expression_from_json(text: &str) -> Result<(Ast, ExprId), AstJsonError> {
+    let mut ast = Ast::new();
+    let expression = read_expression(&mut ast, &json::parse(text).map_err(error)?)?;
+    Ok((ast, expression))
+}
+
+fn begin(output: &mut String, node_type: &str) {
+    output.push_str("{\"type\":");
+    json::write_string(output, node_type);
+}
+
+fn field(output: &mut String, name: &str) {
+    output.push(',');
+    json::write_string(output, name);
+    output.push(':');
+}
+
+fn write_list<T>(
+    output: &mut String,
+    items: &[T],
+    mut write: impl FnMut(&mut String, &T) -> Result<(), AstJsonError>,
+) -> Result<(), AstJsonError> {
+    output.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        write(output, item)?;
+    }
+    output.push(']');
+    Ok(())
+}
+
+fn write_token(output: &mut String, token: &Token) {
+    output.push_str("{\"lexeme\":");
+    json::write_string(output, &token.lexeme);
+    output.push_str(&format!(",\"line\":{}}}", token.line));
+}
+
+fn write_optional<T: Copy>(
+    output: &mut String,
+    value: Option<T>,
+    write: impl FnOnce(&mut String, T) -> Result<(), AstJsonError>,
+) -> Result<(), AstJsonError> {
+    match value {
+        Some(value) => write(output, value),
+        None => {
+            output.push_str("null");
+            Ok(())
+        }
+    }
+}
+
+fn write_expression(
+    output: &mut String,
+    ast: &Ast,
+    expression: ExprId,
+) -> Result<(), AstJsonError> {
+    let node = &ast[expression];
+    let node_type = match node {
+        Expression::Assign(_) => "Assign",
+        Expression::Binary(_) => "Binary",
+        Expression::Call(_) => "Call",
+        Expression::Get(_) => "Get",
+        Expression::Grouping(_) => "Grouping",
+        Expression::Literal(_) => "Literal",
+        Expression::Logical(_) => "Logical",
+        Expression::Set(_) => "Set",
+        Expression::Super(_) => "Super",
+        Expression::This(_) => "This",
+        Expression::Unary(_) => "Unary",
+        Expression::Variable(_) => "Variable",
+    };
+    begin(output, node_type);
+    let NodeId(id) = **node;
+    field(output, "id");
+    output.push_str(&id.to_string());
+    match node {
+        Expression::Assign(assign) => {
+            field(output, "name");
+            write_token(output, &assign.name);
+            field(output, "value");
+            write_expression(output, ast, assign.value)?;
+        }
+        Expression::Binary(Binary {
+            left,
+            operator,
+            right,
+            ..
+        })
+        | Expression::Logical(Logical {
+            left,
+            operator,
+            right,
+            ..
+        }) => {
+            field(output, "left");
+            write_expression(output, ast, *left)?;
+            field(output, "operator");
+            write_token(output, operator);
+            field(output, "right");
+            write_expression(output, ast, *right)?;
+        }
+        Expression::Call(call) => {
+            field(output, "callee");
+            write_expression(output, ast, call.callee)?;
+            field(output, "paren");
+            write_token(output, &call.paren);
+            field(output, "arguments");
+            write_list(output, &call.arguments, |output, &argument| {
+                write_expression(output, ast, argument)
+            })?;
+        }
+        Expression::Get(get) => {
+            field(output, "object");
+            write_expression(output, ast, get.object)?;
+            field(output, "name");
+            write_token(output, &get.name);
+        }
+        Expression::Grouping(grouping) => {
+            field(output, "expression");
+            write_expression(output, ast, grouping.expression)?;
+        }
+        Expression::Literal(literal) => {
+            field(output, "value");
+            let value = match &literal.value {
+                LiteralTypes::String(value) => Value::String(Rc::new(value.clone())),
+                LiteralTypes::Number(value) => Value::Number(*value),
+                LiteralTypes::Bool(value) => Value::Bool(*value),
+                LiteralTypes::Nil => Value::Nil,
+            };
+            output.push_str(&json::stringify(&value).map_err(error)?);
+        }
+        Expression::Set(set) => {
+            field(output, "object");
+            write_expression(output, ast, set.object)?;
+            field(output, "name");
+            write_token(output, &set.name);
+            field(output, "value");
+            write_expression(output, ast, set.value)?;
+        }
+        Expression::Super(super_expr) => {
+            field(output, "keyword");
+            write_token(output, &super_expr.keyword);
+            field(output, "method");
+            write_token(output, &super_expr.method);
+        }
+        Expression::This(this) => {
+            field(output, "keyword");
+            write_token(output, &this.keyword);
+        }
+        Expression::Unary(unary) => {
+            field(output, "operator");
+            write_token(output, &unary.operator);
+            field(output, "right");
+            write_expression(output, ast, unary.right)?;
+        }
+        Expression::Variable(variable) => {
+            field(output, "name");
+            write_token(output, &variable.name);
+        }
+    }
+    output.push('}');
+    Ok(())
+}
+
+fn write_statement(output: &mut String, ast: &Ast, statement: StmtId) -> Result<(), AstJsonError> {
+    match &ast[statement] {
+        Stmt::Expression(stmt) => {
+            begin(output, "Expression");
+            field(output, "expression");
+            write_expression(output, ast, stmt.expression)?;
+        }
+        Stmt::Print(stmt) => {
+            begin(output, "Print");
+            field(output, "keyword");
+            write_token(output, &stmt.keyword);
+            field(output, "expression");
+            write_expression(output, ast, stmt.expression)?;
+        }
+        Stmt::Var(stmt) => {
+            begin(output, "Var");
+            field(output, "name");
+            write_token(output, &stmt.name);
+            field(output, "initializer");
+            write_optional(output, stmt.initializer, |output, initializer| {
+                write_expression(output, ast, initializer)
+            })?;
+        }
+        Stmt::Block(block) => {
+            begin(output, "Block");
+            field(output, "statements");
+            write_list(output, &block.statements, |output, &statement| {
+                write_statement(output, ast, statement)
+            })?;
+        }
+        Stmt::If(stmt) => {
+            begin(output, "If");
+            field(output, "keyword");
+            write_token(output, &stmt.keyword);
+            field(output, "condition");
+            write_expression(output, ast, stmt.condition)?;
+            field(output, "then_branch");
+            write_statement(output, ast, stmt.then_branch)?;
+            field(output, "else_branch");
+            write_optional(output, stmt.else_branch, |output, branch| {
+                write_statement(output, ast, branch)
+            })?;
+        }
+        Stmt::While(stmt) => {
+            begin(output, "While");
+            field(output, "keyword");
+            write_token(output, &stmt.keyword);
+            field(output, "condition");
+            write_expression(output, ast, stmt.condition)?;
+            field(output, "body");
+            write_statement(output, ast, stmt.body)?;
+        }
+        Stmt::ForIn(stmt) => {
+            begin(output, "ForIn");
+            field(output, "name");
+            write_token(output, &stmt.name);
+            field(output, "iterable");
+            write_expression(output, ast, stmt.iterable)?;
+            field(output, "body");
+            write_statement(output, ast, stmt.body)?;
+        }
+        Stmt::Return(stmt) => {
+            begin(output, "Return");
+            field(output, "keyword");
+            write_token(output, &stmt.keyword);
+            field(output, "value");
+            write_optional(output, stmt.value, |output, value| {
+                write_expression(output, ast, value)
+            })?;
+        }
+        Stmt::Function(function) => return write_function(output, ast, function),
+        Stmt::Class(class) => {
+            begin(output, "Class");
+            field(output, "name");
+            write_token(output, &class.name);
+            field(output, "superclass");
+            match &class.superclass {
+                Some(superclass) => {
+                    begin(output, "Variable");
+                    field(output, "id");
+                    output.push_str(&superclass.id.0.to_string());
+                    field(output, "name");
+                    write_token(output, &superclass.name);
+                    output.push('}');
+                }
+                None => output.push_str("null"),
+            }
+            field(output, "methods");
+            write_list(output, &class.methods, |output, method| {
+                write_function(output, ast, method)
+            })?;
+        }
+    }
+    output.push('}');
+    Ok(())
+}
+
+fn write_function(
+    output: &mut String,
+    ast: &Ast,
+    function: &FunctionStmt,
+) -> Result<(), AstJsonError> {
+    begin(output, "Function");
+    field(output, "name");
+    write_token(output, &function.name);
+    field(output, "params");
+    write_list(output, &function.params, |output, param| {
+        write_token(output, param);
+        Ok(())
+    })?;
+    field(output, "body");
+    write_list(output, &function.body, |output, &statement| {
+        write_statement(output, ast, statement)
+    })?;
+    output.push('}');
+    Ok(())
+}
+
+fn get(object: &Value, name: &str) -> Result<Value, AstJsonError> {
+    let Value::Instance(instance) = object else {
+        return Err(error(format!("Expected an object, found {}.", object)));
+    };
+    let value = instance.borrow().fields.get(name).cloned();
+    value.ok_or_else(|| error(format!("Missing field '{}'.", name)))
+}
+
+fn string(value: &Value) -> Result<Rc<String>, AstJsonError> {
+    match value {
+        Value::String(string) => Ok(Rc::clone(string)),
+        _ => Err(error(format!("Expected a string, found {}.", value))),
+    }
+}
+
+fn number(value: &Value) -> Result<f64, AstJsonError> {
+    match value {
+        Value::Number(number) => Ok(*number),
+        _ => Err(error(format!("Expected a number, found {}.", value))),
+    }
+}
+
+fn array(value: &Value) -> Result<Vec<Value>, AstJsonError> {
+    match value {
+        Value::Array(array) => Ok(array.borrow().clone()),
+        _ => Err(error(format!("Expected an array, found {}.", value))),
+    }
+}
+
+// `null` for absent nodes
+fn optional(value: Value) -> Option<Value> {
+    match value {
+        Value::Nil => None,
+        value => Some(value),
+    }
+}
+
+fn node_id(object: &Value) -> Result<NodeId, AstJsonError> {
+    Ok(NodeId(number(&get(object, "id")?)? as usize))
+}
+
+// Scans the lexeme again, which gives the token its type and literal
+fn read_token(object: &Value) -> Result<Token, AstJsonError> {
+    let lexeme = string(&get(object, "lexeme")?)?;
+    let line = number(&get(object, "line")?)? as i32;
+    let mut scanner = Scanner::new(lexeme.to_string());
+    let mut tokens = scanner.take_tokens();
+    if scanner.had_error || tokens.len() != 2 || tokens[0].lexeme != *lexeme {
+        return Err(error(format!("Invalid token '{}'.", lexeme)));
+    }
+    let mut token = tokens.swap_remove(0);
+    token.line = line;
+    Ok(token)
+}
+
+fn read_tokens(value: &Value) -> Result<Vec<Token>, AstJsonError> {
+    array(value)?.iter().map(read_token).collect()
+}
+
+fn read_expression(ast: &mut Ast, object: &Value) -> Result<ExprId, AstJsonError> {
+    let id = node_id(object)?;
+    let expression = match string(&get(object, "type")?)?.as_str() {
+        "Assign" => Expression::Assign(Assign {
+            id,
+            name: read_token(&get(object, "name")?)?,
+            value: read_expression(ast, &get(object, "value")?)?,
+            resolved: Cell::new(None),
+        }),
+        "Binary" => Expression::Binary(Binary {
+            id,
+            left: read_expression(ast, &get(object, "left")?)?,
+            operator: read_token(&get(object, "operator")?)?,
+            right: read_expression(ast, &get(object, "right")?)?,
+        }),
+        "Call" => Expression::Call(Call {
+            id,
+            callee: read_expression(ast, &get(object, "callee")?)?,
+            paren: read_token(&get(object, "paren")?)?,
+            arguments: array(&get(object, "arguments")?)?
+                .iter()
+                .map(|argument| read_expression(ast, argument))
+                .collect::<Result<_, _>>()?,
+        }),
+        "Get" => Expression::Get(Get {
+            id,
+            object: read_expression(ast, &get(object, "object")?)?,
+            name: read_token(&get(object, "name")?)?,
+        }),
+        "Grouping" => Expression::Grouping(Grouping {
+            id,
+            expression: read_expression(ast, &get(object, "expression")?)?,
+        }),
+        "Literal" => Expression::Literal(Literal {
+            id,
+            value: match get(object, "value")? {
+                Value::String(value) => LiteralTypes::String(value.to_string()),
+                Value::Number(value) => LiteralTypes::Number(value),
+                Value::Bool(value) => LiteralTypes::Bool(value),
+                Value::Nil => LiteralTypes::Nil,
+                value => return Err(error(format!("Invalid literal {}.", value))),
+            },
+        }),
+        "Logical" => Expression::Logical(Logical {
+            id,
+            left: read_expression(ast, &get(object, "left")?)?,
+            operator: read_token(&get(object, "operator")?)?,
+            right: read_expression(ast, &get(object, "right")?)?,
+        }),
+        "Set" => Expression::Set(Set {
+            id,
+            object: read_expression(ast, &get(object, "object")?)?,
+            name: read_token(&get(object, "name")?)?,
+            value: read_expression(ast, &get(object, "value")?)?,
+        }),
+        "Super" => Expression::Super(Super {
+            id,
+            keyword: read_token(&get(object, "keyword")?)?,
+            method: read_token(&get(object, "method")?)?,
+            resolved: Cell::new(None),
+        }),
+        "This" => Expression::This(This {
+            id,
+            keyword: read_token(&get(object, "keyword")?)?,
+            resolved: Cell::new(None),
+        }),
+        "Unary" => Expression::Unary(Unary {
+            id,
+            operator: read_token(&get(object, "operator")?)?,
+            right: read_expression(ast, &get(object, "right")?)?,
+        }),
+        "Variable" => Expression::Variable(read_variable(object)?),
+        other => return Err(error(format!("Unknown expression type '{}'.", other))),
+    };
+    Ok(ast.add_expression(expression))
+}
+
+fn read_variable(object: &Value) -> Result<Variable, AstJsonError> {
+    Ok(Variable {
+        id: node_id(object)?,
+        name: read_token(&get(object, "name")?)?,
+        resolved: Cell::new(None),
+    })
+}
+
+fn read_statements(ast: &mut Ast, value: &Value) -> Result<Vec<StmtId>, AstJsonError> {
+    array(value)?
+        .iter()
+        .map(|statement| read_statement(ast, statement))
+        .collect()
+}
+
+fn read_statement(ast: &mut Ast, object: &Value) -> Result<StmtId, AstJsonError> {
+    let statement = match string(&get(object, "type")?)?.as_str() {
+        "Expression" => Stmt::Expression(ExpressionStmt {
+            expression: read_expression(ast, &get(object, "expression")?)?,
+        }),
+        "Print" => Stmt::Print(PrintStmt {
+            keyword: read_token(&get(object, "keyword")?)?,
+            expression: read_expression(ast, &get(object, "expression")?)?,
+        }),
+        "Var" => Stmt::Var(VarStmt {
+            name: read_token(&get(object, "name")?)?,
+            initializer: optional(get(object, "initializer")?)
+                .map(|initializer| read_expression(ast, &initializer))
+                .transpose()?,
+        }),
+        "Block" => Stmt::Block(BlockStmt {
+            statements: read_statements(ast, &get(object, "statements")?)?,
+        }),
+        "If" => Stmt::If(IfStmt {
+            keyword: read_token(&get(object, "keyword")?)?,
+            condition: read_expression(ast, &get(object, "condition")?)?,
+            then_branch: read_statement(ast, &get(object, "then_branch")?)?,
+            else_branch: optional(get(object, "else_branch")?)
+                .map(|branch| read_statement(ast, &branch))
+                .transpose()?,
+        }),
+        "While" => Stmt::While(WhileStmt {
+            keyword: read_token(&get(object, "keyword")?)?,
+            condition: read_expression(ast, &get(object, "condition")?)?,
+            body: read_statement(ast, &get(object, "body")?)?,
+        }),
+        "ForIn" => Stmt::ForIn(ForInStmt {
+            name: read_token(&get(object, "name")?)?,
+            iterable: read_expression(ast, &get(object, "iterable")?)?,
+            body: read_statement(ast, &get(object, "body")?)?,
+        }),
+        "Return" => Stmt::Return(ReturnStmt {
+            keyword: read_token(&get(object, "keyword")?)?,
+            value: optional(get(object, "value")?)
+                .map(|value| read_expression(ast, &value))
+                .transpose()?,
+        }),
+        "Function" => Stmt::Function(Rc::new(read_function(ast, object)?)),
+        "Class" => Stmt::Class(ClassStmt {
+            name: read_token(&get(object, "name")?)?,
+            superclass: optional(get(object, "superclass")?)
+                .map(|superclass| read_variable(&superclass).map(Box::new))
+                .transpose()?,
+            methods: array(&get(object, "methods")?)?
+                .iter()
+                .map(|method| read_function(ast, method).map(Rc::new))
+                .collect::<Result<_, _>>()?,
+        }),
+        other => return Err(error(format!("Unknown statement type '{}'.", other))),
+    };
+    Ok(ast.add_statement(statement))
+}
+
+fn read_function(ast: &mut Ast, object: &Value) -> Result<FunctionStmt, AstJsonError> {
+    Ok(FunctionStmt {
+        name: read_token(&get(object, "name")?)?,
+        params: read_tokens(&get(object, "params")?)?,
+        body: read_statements(ast, &get(object, "body")?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::printer::{pretty_print, pretty_print_stmt};
+
+    fn parse(source: &str) -> (Ast, Vec<StmtId>) {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        (parser.into_ast(), statements)
+    }
+
+    #[test]
+    fn test_expression_json() {
+        let mut scanner = Scanner::new("-a * \"b\"".to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let expression = parser.parse_expression().unwrap();
+        let json = expression_to_json(parser.ast(), expression).unwrap();
+        assert_eq!(
+            json,
+            "{\"type\":\"Binary\",\"id\":3,\
+             \"left\":{\"type\":\"Unary\",\"id\":1,\"operator\":{\"lexeme\":\"-\",\"line\":1},\
+             \"right\":{\"type\":\"Variable\",\"id\":0,\"name\":{\"lexeme\":\"a\",\"line\":1}}},\
+             \"operator\":{\"lexeme\":\"*\",\"line\":1},\
+             \"right\":{\"type\":\"Literal\",\"id\":2,\"value\":\"b\"}}"
+        );
+
+        let (ast, read) = expression_from_json(&json).unwrap();
+        assert_eq!(expression_to_json(&ast, read).unwrap(), json);
+        assert_eq!(pretty_print(&ast, read), "(* (- a) b)");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = "var a = 1; var b;\n\
+                      class A < B { init(x) { this.x = x; } get() { return super.get(); } }\n\
+                      fun f(n) { while (n > 0) n = n - 1; if (!n or nil) print a.b; else return; }\n\
+                      for (var i = 0; i < 3; i = i + 1) { f(i); }\n\
+                      for (var i in items) print \"back\\\\slash\";";
+        let (ast, statements) = parse(source);
+        let json = to_json(&ast, &statements).unwrap();
+        let (read_ast, read_statements) = from_json(&json).unwrap();
+        assert_eq!(to_json(&read_ast, &read_statements).unwrap(), json);
+        for (&statement, &read) in statements.iter().zip(&read_statements) {
+            assert_eq!(
+                pretty_print_stmt(&ast, statement),
+                pretty_print_stmt(&read_ast, read)
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let message = |text: &str| from_json(text).unwrap_err().message;
+        assert_eq!(message("{}"), "Expected an array, found Object instance.");
+        assert_eq!(
+            message("[{\"type\": \"Loop\"}]"),
+            "Unknown statement type 'Loop'."
+        );
+        assert_eq!(
+            message("[{\"type\": \"Expression\"}]"),
+            "Missing field 'expression'."
+        );
+        assert_eq!(
+            message("[{\"type\": \"Expression\", \"expression\": {\"type\": \"Variable\", \"id\": 0, \"name\": {\"lexeme\": \"a b\", \"line\": 1}}}]"),
+            "Invalid token 'a b'."
+        );
+    }
+}
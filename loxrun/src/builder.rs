@@ -0,0 +1,516 @@
+//! Builds syntax trees in code instead of parsing them from source:
+//!
+//! ```
+//! use loxrun::ast::Ast;
+//! use loxrun::builder::{expr, stmt};
+//! use loxrun::expression::NodeIds;
+//!
+//! let program = vec![
+//!     stmt::var("x", expr::num(3.0)),
+//!     stmt::print(expr::num(5.0).plus(expr::var("x"))),
+//! ];
+//! let mut ast = Ast::new();
+//! let statements = stmt::add_all(program, &mut ast, &NodeIds::new());
+//! assert_eq!(statements.len(), 2);
+//! ```
+//!
+//! Built nodes have no source, so their tokens are all on line 0.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use liblox::tokens::{LiteralTypes, Token, TokenType};
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::*;
+use crate::stmt::*;
+
+fn identifier(name: &str) -> Token {
+    Token::new_identifier(name.to_string(), 0)
+}
+
+fn keyword(token_type: TokenType, lexeme: &str) -> Token {
+    Token::new_keyword(token_type, lexeme, 0)
+}
+
+fn operator(token_type: TokenType) -> Token {
+    let lexeme = match token_type {
+        TokenType::Minus => "-",
+        TokenType::Plus => "+",
+        TokenType::Slash => "/",
+        TokenType::Star => "*",
+        TokenType::Bang => "!",
+        TokenType::BangEqual => "!=",
+        TokenType::EqualEqual => "==",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        TokenType::And => "and",
+        TokenType::Or => "or",
+        _ => panic!("{:?} is not an operator", token_type),
+    };
+    Token::new(token_type, lexeme.to_string(), LiteralTypes::Nil, 0)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprNode {
+    Assign(String, Box<ExprBuilder>),
+    Binary(Box<ExprBuilder>, TokenType, Box<ExprBuilder>),
+    Call(Box<ExprBuilder>, Vec<ExprBuilder>),
+    Get(Box<ExprBuilder>, String),
+    Literal(LiteralTypes),
+    Logical(Box<ExprBuilder>, TokenType, Box<ExprBuilder>),
+    Set(Box<ExprBuilder>, String, Box<ExprBuilder>),
+    Super(String),
+    This,
+    Unary(TokenType, Box<ExprBuilder>),
+    Variable(String),
+}
+
+/// An expression to be added to an `Ast`. Made by the functions of `expr`
+/// and combined with the methods here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprBuilder(ExprNode);
+
+impl ExprBuilder {
+    /// `self operator right` for an arithmetic, comparison or equality
+    /// operator, or `and` and `or`.
+    pub fn binary(self, operator: TokenType, right: ExprBuilder) -> ExprBuilder {
+        let (left, right) = (Box::new(self), Box::new(right));
+        match operator {
+            TokenType::And | TokenType::Or => ExprBuilder(ExprNode::Logical(left, operator, right)),
+            _ => ExprBuilder(ExprNode::Binary(left, operator, right)),
+        }
+    }
+
+    pub fn plus(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Plus, right)
+    }
+
+    pub fn minus(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Minus, right)
+    }
+
+    pub fn times(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Star, right)
+    }
+
+    pub fn divided_by(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Slash, right)
+    }
+
+    pub fn equals(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::EqualEqual, right)
+    }
+
+    pub fn less_than(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Less, right)
+    }
+
+    pub fn greater_than(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Greater, right)
+    }
+
+    pub fn and(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::And, right)
+    }
+
+    pub fn or(self, right: ExprBuilder) -> ExprBuilder {
+        self.binary(TokenType::Or, right)
+    }
+
+    pub fn call(self, arguments: Vec<ExprBuilder>) -> ExprBuilder {
+        ExprBuilder(ExprNode::Call(Box::new(self), arguments))
+    }
+
+    /// The property `name` of `self`.
+    pub fn get(self, name: &str) -> ExprBuilder {
+        ExprBuilder(ExprNode::Get(Box::new(self), name.to_string()))
+    }
+
+    /// Assigns `value` to the property `name` of `self`.
+    pub fn set(self, name: &str, value: ExprBuilder) -> ExprBuilder {
+        ExprBuilder(ExprNode::Set(
+            Box::new(self),
+            name.to_string(),
+            Box::new(value),
+        ))
+    }
+
+    /// Allocates the expression and everything in it in `ast`, with ids from
+    /// `ids`.
+    pub fn add_to(self, ast: &mut Ast, ids: &NodeIds) -> ExprId {
+        let id = ids.next_id();
+        let expression = match self.0 {
+            ExprNode::Assign(name, value) => Expression::Assign(Assign {
+                id,
+                name: identifier(&name),
+                value: value.add_to(ast, ids),
+                resolved: Cell::new(None),
+            }),
+            ExprNode::Binary(left, token_type, right) => Expression::Binary(Binary {
+                id,
+                left: left.add_to(ast, ids),
+                operator: operator(token_type),
+                right: right.add_to(ast, ids),
+            }),
+            ExprNode::Call(callee, arguments) => Expression::Call(Call {
+                id,
+                callee: callee.add_to(ast, ids),
+                paren: keyword(TokenType::RightParen, ")"),
+                arguments: arguments
+                    .into_iter()
+                    .map(|argument| argument.add_to(ast, ids))
+                    .collect(),
+            }),
+            ExprNode::Get(object, name) => Expression::Get(Get {
+                id,
+                object: object.add_to(ast, ids),
+                name: identifier(&name),
+            }),
+            ExprNode::Literal(value) => Expression::Literal(Literal { id, value }),
+            ExprNode::Logical(left, token_type, right) => Expression::Logical(Logical {
+                id,
+                left: left.add_to(ast, ids),
+                operator: operator(token_type),
+                right: right.add_to(ast, ids),
+            }),
+            ExprNode::Set(object, name, value) => Expression::Set(Set {
+                id,
+                object: object.add_to(ast, ids),
+                name: identifier(&name),
+                value: value.add_to(ast, ids),
+            }),
+            ExprNode::Super(method) => Expression::Super(Super {
+                id,
+                keyword: keyword(TokenType::Super, "super"),
+                method: identifier(&method),
+                resolved: Cell::new(None),
+            }),
+            ExprNode::This => Expression::This(This {
+                id,
+                keyword: keyword(TokenType::This, "this"),
+                resolved: Cell::new(None),
+            }),
+            ExprNode::Unary(token_type, right) => Expression::Unary(Unary {
+                id,
+                operator: operator(token_type),
+                right: right.add_to(ast, ids),
+            }),
+            ExprNode::Variable(name) => Expression::Variable(Variable {
+                id,
+                name: identifier(&name),
+                resolved: Cell::new(None),
+            }),
+        };
+        ast.add_expression(expression)
+    }
+}
+
+/// Leaves of expressions, and the expressions that aren't built from a
+/// left operand.
+pub mod expr {
+    use super::*;
+
+    pub fn num(value: f64) -> ExprBuilder {
+        ExprBuilder(ExprNode::Literal(LiteralTypes::Number(value)))
+    }
+
+    pub fn string(value: &str) -> ExprBuilder {
+        ExprBuilder(ExprNode::Literal(LiteralTypes::String(value.to_string())))
+    }
+
+    pub fn bool(value: bool) -> ExprBuilder {
+        ExprBuilder(ExprNode::Literal(LiteralTypes::Bool(value)))
+    }
+
+    pub fn nil() -> ExprBuilder {
+        ExprBuilder(ExprNode::Literal(LiteralTypes::Nil))
+    }
+
+    pub fn var(name: &str) -> ExprBuilder {
+        ExprBuilder(ExprNode::Variable(name.to_string()))
+    }
+
+    pub fn assign(name: &str, value: ExprBuilder) -> ExprBuilder {
+        ExprBuilder(ExprNode::Assign(name.to_string(), Box::new(value)))
+    }
+
+    pub fn negate(right: ExprBuilder) -> ExprBuilder {
+        ExprBuilder(ExprNode::Unary(TokenType::Minus, Box::new(right)))
+    }
+
+    pub fn not(right: ExprBuilder) -> ExprBuilder {
+        ExprBuilder(ExprNode::Unary(TokenType::Bang, Box::new(right)))
+    }
+
+    pub fn this() -> ExprBuilder {
+        ExprBuilder(ExprNode::This)
+    }
+
+    /// `super.method`
+    pub fn super_method(method: &str) -> ExprBuilder {
+        ExprBuilder(ExprNode::Super(method.to_string()))
+    }
+}
+
+/// A function or method to be added to an `Ast`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionBuilder {
+    name: String,
+    params: Vec<String>,
+    body: Vec<StmtBuilder>,
+}
+
+impl FunctionBuilder {
+    fn add_to(self, ast: &mut Ast, ids: &NodeIds) -> Rc<FunctionStmt> {
+        Rc::new(FunctionStmt {
+            name: identifier(&self.name),
+            params: self.params.iter().map(|param| identifier(param)).collect(),
+            body: stmt::add_all(self.body, ast, ids),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StmtNode {
+    Block(Vec<StmtBuilder>),
+    Class(String, Option<String>, Vec<FunctionBuilder>),
+    Expression(ExprBuilder),
+    ForIn(String, ExprBuilder, Box<StmtBuilder>),
+    Function(FunctionBuilder),
+    If(ExprBuilder, Box<StmtBuilder>, Option<Box<StmtBuilder>>),
+    Print(ExprBuilder),
+    Return(Option<ExprBuilder>),
+    Var(String, Option<ExprBuilder>),
+    While(ExprBuilder, Box<StmtBuilder>),
+}
+
+/// A statement to be added to an `Ast`, made by the functions of `stmt`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StmtBuilder(StmtNode);
+
+impl StmtBuilder {
+    /// Allocates the statement and everything in it in `ast`, with ids from
+    /// `ids`.
+    pub fn add_to(self, ast: &mut Ast, ids: &NodeIds) -> StmtId {
+        let statement = match self.0 {
+            StmtNode::Block(statements) => Stmt::Block(BlockStmt {
+                statements: stmt::add_all(statements, ast, ids),
+            }),
+            StmtNode::Class(name, superclass, methods) => Stmt::Class(ClassStmt {
+                name: identifier(&name),
+                superclass: superclass.map(|superclass| {
+                    Box::new(Variable {
+                        id: ids.next_id(),
+                        name: identifier(&superclass),
+                        resolved: Cell::new(None),
+                    })
+                }),
+                methods: methods
+                    .into_iter()
+                    .map(|method| method.add_to(ast, ids))
+                    .collect(),
+            }),
+            StmtNode::Expression(expression) => Stmt::Expression(ExpressionStmt {
+                expression: expression.add_to(ast, ids),
+            }),
+            StmtNode::ForIn(name, iterable, body) => Stmt::ForIn(ForInStmt {
+                name: identifier(&name),
+                iterable: iterable.add_to(ast, ids),
+                body: body.add_to(ast, ids),
+            }),
+            StmtNode::Function(function) => Stmt::Function(function.add_to(ast, ids)),
+            StmtNode::If(condition, then_branch, else_branch) => Stmt::If(IfStmt {
+                keyword: keyword(TokenType::If, "if"),
+                condition: condition.add_to(ast, ids),
+                then_branch: then_branch.add_to(ast, ids),
+                else_branch: else_branch.map(|else_branch| else_branch.add_to(ast, ids)),
+            }),
+            StmtNode::Print(expression) => Stmt::Print(PrintStmt {
+                keyword: keyword(TokenType::Print, "print"),
+                expression: expression.add_to(ast, ids),
+            }),
+            StmtNode::Return(value) => Stmt::Return(ReturnStmt {
+                keyword: keyword(TokenType::Return, "return"),
+                value: value.map(|value| value.add_to(ast, ids)),
+            }),
+            StmtNode::Var(name, initializer) => Stmt::Var(VarStmt {
+                name: identifier(&name),
+                initializer: initializer.map(|initializer| initializer.add_to(ast, ids)),
+            }),
+            StmtNode::While(condition, body) => Stmt::While(WhileStmt {
+                keyword: keyword(TokenType::While, "while"),
+                condition: condition.add_to(ast, ids),
+                body: body.add_to(ast, ids),
+            }),
+        };
+        ast.add_statement(statement)
+    }
+}
+
+/// The statements, named after their keyword where it isn't one in Rust.
+pub mod stmt {
+    use super::*;
+
+    /// Adds the statements to `ast` in order.
+    pub fn add_all(statements: Vec<StmtBuilder>, ast: &mut Ast, ids: &NodeIds) -> Vec<StmtId> {
+        statements
+            .into_iter()
+            .map(|statement| statement.add_to(ast, ids))
+            .collect()
+    }
+
+    pub fn expression(expression: ExprBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::Expression(expression))
+    }
+
+    pub fn print(expression: ExprBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::Print(expression))
+    }
+
+    pub fn var(name: &str, initializer: ExprBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::Var(name.to_string(), Some(initializer)))
+    }
+
+    /// `var name;`
+    pub fn declare(name: &str) -> StmtBuilder {
+        StmtBuilder(StmtNode::Var(name.to_string(), None))
+    }
+
+    pub fn block(statements: Vec<StmtBuilder>) -> StmtBuilder {
+        StmtBuilder(StmtNode::Block(statements))
+    }
+
+    pub fn if_then(condition: ExprBuilder, then_branch: StmtBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::If(condition, Box::new(then_branch), None))
+    }
+
+    pub fn if_else(
+        condition: ExprBuilder,
+        then_branch: StmtBuilder,
+        else_branch: StmtBuilder,
+    ) -> StmtBuilder {
+        StmtBuilder(StmtNode::If(
+            condition,
+            Box::new(then_branch),
+            Some(Box::new(else_branch)),
+        ))
+    }
+
+    pub fn while_loop(condition: ExprBuilder, body: StmtBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::While(condition, Box::new(body)))
+    }
+
+    /// `for (var name in iterable) body`
+    pub fn for_in(name: &str, iterable: ExprBuilder, body: StmtBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::ForIn(name.to_string(), iterable, Box::new(body)))
+    }
+
+    pub fn return_value(value: ExprBuilder) -> StmtBuilder {
+        StmtBuilder(StmtNode::Return(Some(value)))
+    }
+
+    /// `return;`
+    pub fn return_nil() -> StmtBuilder {
+        StmtBuilder(StmtNode::Return(None))
+    }
+
+    pub fn function(name: &str, params: &[&str], body: Vec<StmtBuilder>) -> StmtBuilder {
+        StmtBuilder(StmtNode::Function(method(name, params, body)))
+    }
+
+    /// A method for `class`.
+    pub fn method(name: &str, params: &[&str], body: Vec<StmtBuilder>) -> FunctionBuilder {
+        FunctionBuilder {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            body,
+        }
+    }
+
+    pub fn class(
+        name: &str,
+        superclass: Option<&str>,
+        methods: Vec<FunctionBuilder>,
+    ) -> StmtBuilder {
+        StmtBuilder(StmtNode::Class(
+            name.to_string(),
+            superclass.map(str::to_string),
+            methods,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::pretty_print_stmt;
+
+    #[test]
+    fn test_build_matches_parse() {
+        let source = "var x = 5 + 3 * 2;\n\
+                      class A < B { get(n) { return super.get(n) and !this.done; } }\n\
+                      fun f(a, b) { if (a < b) print -a; else { a.b = b = nil; } }\n\
+                      while (x > 0) x = x - 1;\n\
+                      for (var i in items) print \"item\" == i or false;";
+        let mut scanner = liblox::scanner::Scanner::new(source.to_string());
+        let mut parser = crate::parser::Parser::new(scanner.take_tokens());
+        let parsed = parser.parse().unwrap();
+
+        let program = vec![
+            stmt::var(
+                "x",
+                expr::num(5.0).plus(expr::num(3.0).times(expr::num(2.0))),
+            ),
+            stmt::class(
+                "A",
+                Some("B"),
+                vec![stmt::method(
+                    "get",
+                    &["n"],
+                    vec![stmt::return_value(
+                        expr::super_method("get")
+                            .call(vec![expr::var("n")])
+                            .and(expr::not(expr::this().get("done"))),
+                    )],
+                )],
+            ),
+            stmt::function(
+                "f",
+                &["a", "b"],
+                vec![stmt::if_else(
+                    expr::var("a").less_than(expr::var("b")),
+                    stmt::print(expr::negate(expr::var("a"))),
+                    stmt::block(vec![stmt::expression(
+                        expr::var("a").set("b", expr::assign("b", expr::nil())),
+                    )]),
+                )],
+            ),
+            stmt::while_loop(
+                expr::var("x").greater_than(expr::num(0.0)),
+                stmt::expression(expr::assign("x", expr::var("x").minus(expr::num(1.0)))),
+            ),
+            stmt::for_in(
+                "i",
+                expr::var("items"),
+                stmt::print(
+                    expr::string("item")
+                        .equals(expr::var("i"))
+                        .or(expr::bool(false)),
+                ),
+            ),
+        ];
+        let mut ast = Ast::new();
+        let built = stmt::add_all(program, &mut ast, &NodeIds::new());
+
+        let print = |ast: &Ast, statements: &[StmtId]| -> Vec<String> {
+            statements
+                .iter()
+                .map(|&statement| pretty_print_stmt(ast, statement))
+                .collect()
+        };
+        assert_eq!(print(&ast, &built), print(parser.ast(), &parsed));
+    }
+}
@@ -1,9 +1,19 @@
-use crate::class::{Instance, LoxClass};
+use crate::ast::Ast;
+use crate::class::{live_instance_count, Instance, LoxClass};
 use crate::interpreter::{Environment, Interpreter, InterpreterError, InterpreterResult, Value};
+use crate::json;
+use crate::regex::Regex;
 use crate::stmt::FunctionStmt;
+use liblox::codes::ErrorCode;
+use liblox::memory;
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::process::Command;
 use std::rc::Rc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Callable {
@@ -23,6 +33,16 @@ impl std::fmt::Display for Callable {
     }
 }
 
+impl Callable {
+    pub fn arity(&self) -> usize {
+        match self {
+            Callable::DynamicFunction(fun) => fun.callable.borrow().as_ref().arity(),
+            Callable::Function(fun) => fun.arity(),
+            Callable::Class(class) => class.arity(),
+        }
+    }
+}
+
 pub trait LoxCallable {
     fn arity(&self) -> usize;
     fn call(
@@ -58,9 +78,49 @@ impl PartialEq for LoxDynamicFunction {
     }
 }
 
+/// Signature of the Rust closures behind host-defined native functions.
+pub type NativeFn = dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Value, InterpreterError>;
+
+/// A native function defined by the application embedding the interpreter,
+/// see `Interpreter::define_native`.
+pub struct LoxNativeFunction {
+    arity: usize,
+    function: Box<NativeFn>,
+}
+impl LoxNativeFunction {
+    pub fn new(
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value, InterpreterError> + 'static,
+    ) -> Self {
+        Self {
+            arity,
+            function: Box::new(function),
+        }
+    }
+}
+impl LoxCallable for LoxNativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        (self.function)(interpreter, arguments)
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LoxFunction {
-    pub declaration: Box<FunctionStmt>,
+    /// The syntax tree the declaration's body lies in.
+    pub ast: Rc<Ast>,
+    pub declaration: Rc<FunctionStmt>,
 
     /// The closure is an optional environment that captures the variables from the scope where the function was defined.
     pub closure: Rc<RefCell<Environment>>,
@@ -68,13 +128,19 @@ pub struct LoxFunction {
     is_initializer: bool,
 }
 impl LoxFunction {
+    pub fn is_initializer(&self) -> bool {
+        self.is_initializer
+    }
+
     pub fn new(
-        declaration: FunctionStmt,
+        ast: Rc<Ast>,
+        declaration: Rc<FunctionStmt>,
         closure: Rc<RefCell<Environment>>,
         is_initializer: bool,
     ) -> Self {
         Self {
-            declaration: Box::new(declaration),
+            ast,
+            declaration,
             closure,
             is_initializer,
         }
@@ -88,7 +154,8 @@ impl LoxFunction {
             .borrow_mut()
             .define("this".to_string(), Value::Instance(Rc::clone(instance)));
         Self {
-            declaration: self.declaration.clone(),
+            ast: Rc::clone(&self.ast),
+            declaration: Rc::clone(&self.declaration),
             closure: fun_env,
             is_initializer: self.is_initializer,
         }
@@ -97,7 +164,8 @@ impl LoxFunction {
 
 impl PartialEq for LoxFunction {
     fn eq(&self, other: &Self) -> bool {
-        self.declaration == other.declaration && Rc::ptr_eq(&self.closure, &other.closure)
+        Rc::ptr_eq(&self.declaration, &other.declaration)
+            && Rc::ptr_eq(&self.closure, &other.closure)
     }
 }
 
@@ -111,34 +179,67 @@ impl LoxCallable for LoxFunction {
         interpreter: &mut Interpreter,
         arguments: Vec<Value>,
     ) -> Result<Value, InterpreterError> {
-        let fun_env = Rc::new(RefCell::new(Environment::with_enclosing(
-            self.closure.clone(),
-        )));
+        let mut function = self.clone();
+        let mut arguments = arguments;
+        // Functions called from tail position, whose returns are reported
+        // to the hooks once the last of them returned
+        let mut tail_calls = Vec::new();
+        let result = loop {
+            #[cfg(feature = "jit")]
+            if let Some(value) = interpreter.call_native(&function, &arguments) {
+                break Ok(InterpreterResult::Return(value));
+            }
+            let fun_env = interpreter.new_environment(function.closure.clone());
 
-        // Add the function's parameters to the new environment
-        for (i, arg) in arguments.iter().enumerate() {
-            fun_env
-                .borrow_mut()
-                .define(self.declaration.params[i].lexeme.clone(), arg.clone());
+            // Add the function's parameters to the new environment
+            for (param, arg) in function.declaration.params.iter().zip(arguments) {
+                fun_env.borrow_mut().define(param.lexeme.clone(), arg);
+            }
+            match interpreter.execute_body(&function.ast, &function.declaration, fun_env) {
+                // The call replaces this one instead of nesting in it, so
+                // that tail recursion runs in constant stack
+                Ok(InterpreterResult::TailCall(callee, callee_arguments)) => {
+                    let callable = Callable::Function(callee.clone());
+                    if interpreter.on_call(&callable, &callee_arguments) {
+                        tail_calls.push(callable);
+                    }
+                    function = callee;
+                    arguments = callee_arguments;
+                }
+                result => break result,
+            }
+        };
+        if let (Ok(result), false) = (&result, tail_calls.is_empty()) {
+            let value = match result {
+                InterpreterResult::Return(value) => value.clone(),
+                _ => Value::Nil,
+            };
+            for callable in tail_calls.iter().rev() {
+                interpreter.on_return(callable, &value);
+            }
         }
-        let result = interpreter.execute_block(&self.declaration.body, fun_env);
         match result {
             Ok(InterpreterResult::None) | Ok(InterpreterResult::Return(Value::Nil)) => {
                 if self.is_initializer {
                     // If this function is an initializer, return the instance it was called on
-                    let instance = self.closure.borrow().get_at(&"this".to_string(), 0);
+                    // `this` is the only variable of a bound method's closure
+                    let instance = self.closure.borrow().get_at(0, 0);
                     if let Some(Value::Instance(instance)) = instance {
                         return Ok(Value::Instance(Rc::clone(&instance)));
                     } else {
                         return Err(InterpreterError {
+                            code: ErrorCode::RuntimeError,
                             message: "Initializer function called without 'this' instance."
                                 .to_string(),
+                            line: None,
+                            span: None,
                         });
                     }
                 }
                 Ok(Value::Nil)
             }
             Ok(InterpreterResult::Return(value)) => Ok(value),
+            Ok(InterpreterResult::TailCall(..)) => unreachable!("tail calls are made above"),
             Err(err) => Err(err),
         }
     }
@@ -148,7 +249,7 @@ impl LoxCallable for LoxFunction {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct LoxBuiltinFunctionClock {}
 impl LoxBuiltinFunctionClock {
     pub fn new() -> Self {
@@ -160,12 +261,377 @@ impl LoxCallable for LoxBuiltinFunctionClock {
         0
     }
 
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        Ok(Value::Number(interpreter.clock.now()))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionInput {}
+impl LoxBuiltinFunctionInput {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionInput {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Reads the next line from the interpreter's input without its line
+    /// terminator, or returns nil once the input is exhausted.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let mut line = String::new();
+        match interpreter.input.read_line(&mut line) {
+            Ok(0) => Ok(Value::Nil),
+            Ok(_) => {
+                let trimmed = line.strip_suffix('\n').unwrap_or(&line);
+                let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+                Ok(Value::String(Rc::new(trimmed.to_string())))
+            }
+            Err(err) => Err(InterpreterError {
+                code: ErrorCode::IoError,
+                message: format!("Failed to read input: {}", err),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+fn string_argument<'a>(
+    function: &str,
+    name: &str,
+    value: &'a Value,
+) -> Result<&'a str, InterpreterError> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(InterpreterError {
+            code: ErrorCode::InvalidArgument,
+            message: format!("{}() expects {} to be a string.", function, name),
+            line: None,
+            span: None,
+        }),
+    }
+}
+
+// Fails unless the interpreter may access files, see allow_files
+fn check_file_access(interpreter: &Interpreter, function: &str) -> Result<(), InterpreterError> {
+    if interpreter.allow_files {
+        Ok(())
+    } else {
+        Err(InterpreterError {
+            code: ErrorCode::FileAccessDisabled,
+            message: format!("{}() is disabled in this interpreter.", function),
+            line: None,
+            span: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionReadFile {}
+impl LoxBuiltinFunctionReadFile {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionReadFile {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Returns the whole content of the file as a string.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        check_file_access(interpreter, "readFile")?;
+        let path = string_argument("readFile", "the path", &arguments[0])?;
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Value::String(content.into())),
+            Err(err) => Err(InterpreterError {
+                code: ErrorCode::IoError,
+                message: format!("Could not read file '{}': {}", path, err),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionWriteFile {}
+impl LoxBuiltinFunctionWriteFile {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionWriteFile {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Replaces the content of the file, creating it if necessary.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        check_file_access(interpreter, "writeFile")?;
+        let path = string_argument("writeFile", "the path", &arguments[0])?;
+        let text = string_argument("writeFile", "the text", &arguments[1])?;
+        match fs::write(path, text) {
+            Ok(()) => Ok(Value::Bool(true)),
+            Err(err) => Err(InterpreterError {
+                code: ErrorCode::IoError,
+                message: format!("Could not write file '{}': {}", path, err),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionAppendFile {}
+impl LoxBuiltinFunctionAppendFile {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionAppendFile {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Appends to the end of the file, creating it if necessary.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        check_file_access(interpreter, "appendFile")?;
+        let path = string_argument("appendFile", "the path", &arguments[0])?;
+        let text = string_argument("appendFile", "the text", &arguments[1])?;
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()));
+        match result {
+            Ok(()) => Ok(Value::Bool(true)),
+            Err(err) => Err(InterpreterError {
+                code: ErrorCode::IoError,
+                message: format!("Could not append to file '{}': {}", path, err),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionObjectCount {}
+impl LoxBuiltinFunctionObjectCount {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionObjectCount {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Returns the number of class instances that are still alive.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        Ok(Value::Number(live_instance_count() as f64))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionAllocations {}
+impl LoxBuiltinFunctionAllocations {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionAllocations {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Returns the number of heap allocations since the interpreter started.
     fn call(
         &self,
         _interpreter: &mut Interpreter,
         _arguments: Vec<Value>,
     ) -> Result<Value, InterpreterError> {
-        Ok(Value::Number(lox_clock()))
+        Ok(Value::Number(memory::stats().allocations as f64))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionAssert {}
+impl LoxBuiltinFunctionAssert {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionAssert {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Fails with the message if the condition is falsey, otherwise
+    /// returns nil.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        if arguments[0].is_true() {
+            Ok(Value::Nil)
+        } else {
+            Err(InterpreterError {
+                code: ErrorCode::AssertionFailed,
+                message: format!("Assertion failed: {}", arguments[1]),
+                line: None,
+                span: None,
+            })
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionType {}
+impl LoxBuiltinFunctionType {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionType {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Returns the name of the value's type, or its class name for
+    /// instances.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        Ok(Value::String(arguments[0].type_name().into()))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionStr {}
+impl LoxBuiltinFunctionStr {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionStr {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Converts any value to the string `print` would show for it.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        Ok(Value::String(arguments[0].to_string().into()))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionNum {}
+impl LoxBuiltinFunctionNum {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionNum {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Parses a string into a number, returning nil if it isn't one.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        match &arguments[0] {
+            Value::Number(number) => Ok(Value::Number(*number)),
+            Value::String(string) => match string.trim().parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(Value::Number(number)),
+                _ => Ok(Value::Nil),
+            },
+            _ => Err(InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message: "num() expects a string or a number.".to_string(),
+                line: None,
+                span: None,
+            }),
+        }
     }
 
     fn to_string(&self) -> String {
@@ -173,8 +639,937 @@ impl LoxCallable for LoxBuiltinFunctionClock {
     }
 }
 
-fn lox_clock() -> f64 {
-    let now = std::time::SystemTime::now();
-    let duration = now.duration_since(std::time::UNIX_EPOCH).unwrap();
-    duration.as_secs_f64()
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionSleep {}
+impl LoxBuiltinFunctionSleep {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionSleep {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Pauses the script for the given number of milliseconds.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let milliseconds = match arguments[0] {
+            Value::Number(milliseconds) if milliseconds >= 0.0 && milliseconds.is_finite() => {
+                milliseconds
+            }
+            _ => {
+                return Err(InterpreterError {
+                    code: ErrorCode::InvalidArgument,
+                    message: "sleep() expects a non-negative number of milliseconds.".to_string(),
+                    line: None,
+                    span: None,
+                })
+            }
+        };
+        let Ok(duration) = Duration::try_from_secs_f64(milliseconds / 1000.0) else {
+            return Err(InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message: format!("sleep() can't sleep for {} milliseconds.", milliseconds),
+                line: None,
+                span: None,
+            });
+        };
+        if interpreter.clock.sleep(duration, &interpreter.interrupt) {
+            Ok(Value::Nil)
+        } else {
+            Err(InterpreterError {
+                code: ErrorCode::Cancelled,
+                message: "Execution cancelled.".to_string(),
+                line: None,
+                span: None,
+            })
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionJsonParse {}
+impl LoxBuiltinFunctionJsonParse {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionJsonParse {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let text = string_argument("jsonParse", "the text", &arguments[0])?;
+        json::parse(text).map_err(|message| InterpreterError {
+            code: ErrorCode::InvalidArgument,
+            message,
+            line: None,
+            span: None,
+        })
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionJsonStringify {}
+impl LoxBuiltinFunctionJsonStringify {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionJsonStringify {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        json::stringify(&arguments[0])
+            .map(|json| Value::String(json.into()))
+            .map_err(|message| InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message,
+                line: None,
+                span: None,
+            })
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+fn array_argument(
+    function: &str,
+    value: &Value,
+) -> Result<Rc<RefCell<Vec<Value>>>, InterpreterError> {
+    match value {
+        Value::Array(array) => Ok(Rc::clone(array)),
+        _ => Err(InterpreterError {
+            code: ErrorCode::InvalidArgument,
+            message: format!("{}() expects an array.", function),
+            line: None,
+            span: None,
+        }),
+    }
+}
+
+fn index_argument(
+    function: &str,
+    value: &Value,
+    len: usize,
+    container: &str,
+) -> Result<usize, InterpreterError> {
+    match value {
+        Value::Number(index) if index.fract() == 0.0 && *index >= 0.0 && *index < len as f64 => {
+            Ok(*index as usize)
+        }
+        _ => Err(InterpreterError {
+            code: ErrorCode::IndexOutOfBounds,
+            message: format!(
+                "{}() index {} is out of bounds for {} of length {}.",
+                function, value, container, len
+            ),
+            line: None,
+            span: None,
+        }),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionArray {}
+impl LoxBuiltinFunctionArray {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionArray {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Returns a new, empty array.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        Ok(Value::Array(Rc::new(RefCell::new(Vec::new()))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionLen {}
+impl LoxBuiltinFunctionLen {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionLen {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Returns the number of elements of an array or characters of a string.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        match &arguments[0] {
+            Value::Array(array) => Ok(Value::Number(array.borrow().len() as f64)),
+            Value::String(string) => Ok(Value::Number(string.chars().count() as f64)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message: "len() expects an array or a string.".to_string(),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionPush {}
+impl LoxBuiltinFunctionPush {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionPush {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Appends the value to the end of the array.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("push", &arguments[0])?;
+        array.borrow_mut().push(arguments[1].clone());
+        Ok(Value::Nil)
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionGet {}
+impl LoxBuiltinFunctionGet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionGet {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns the element of the array at the given index.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("get", &arguments[0])?;
+        let array = array.borrow();
+        let index = index_argument("get", &arguments[1], array.len(), "an array")?;
+        Ok(array[index].clone())
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionSet {}
+impl LoxBuiltinFunctionSet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionSet {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    /// Replaces the element of the array at the given index.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("set", &arguments[0])?;
+        let mut array = array.borrow_mut();
+        let index = index_argument("set", &arguments[1], array.len(), "an array")?;
+        array[index] = arguments[2].clone();
+        Ok(Value::Nil)
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+fn regex_argument(function: &str, value: &Value) -> Result<Regex, InterpreterError> {
+    let pattern = string_argument(function, "the pattern", value)?;
+    Regex::new(pattern).map_err(|message| InterpreterError {
+        code: ErrorCode::InvalidArgument,
+        message,
+        line: None,
+        span: None,
+    })
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionRegexMatch {}
+impl LoxBuiltinFunctionRegexMatch {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionRegexMatch {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns true if the pattern matches anywhere in the text.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let regex = regex_argument("regexMatch", &arguments[0])?;
+        let text = string_argument("regexMatch", "the text", &arguments[1])?;
+        let found = regex.is_match(text, &interpreter.interrupt);
+        interpreter.check_cancelled()?;
+        Ok(Value::Bool(found))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionRegexFindAll {}
+impl LoxBuiltinFunctionRegexFindAll {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionRegexFindAll {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns an array with every non-overlapping match in the text.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let regex = regex_argument("regexFindAll", &arguments[0])?;
+        let text = string_argument("regexFindAll", "the text", &arguments[1])?;
+        let found = regex.find_all(text, &interpreter.interrupt);
+        interpreter.check_cancelled()?;
+        let matches = found
+            .into_iter()
+            .map(|found| Value::String(found.into()))
+            .collect();
+        Ok(Value::Array(Rc::new(RefCell::new(matches))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionRegexReplace {}
+impl LoxBuiltinFunctionRegexReplace {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionRegexReplace {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    /// Replaces every match in the text with the replacement string.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let regex = regex_argument("regexReplace", &arguments[0])?;
+        let text = string_argument("regexReplace", "the text", &arguments[1])?;
+        let replacement = string_argument("regexReplace", "the replacement", &arguments[2])?;
+        let replaced = regex.replace_all(text, replacement, &interpreter.interrupt);
+        interpreter.check_cancelled()?;
+        Ok(Value::String(replaced.into()))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionArgs {}
+impl LoxBuiltinFunctionArgs {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionArgs {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Returns the command line arguments passed after the script name.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let args = interpreter
+            .script_args
+            .iter()
+            .map(|arg| Value::String(Rc::new(arg.clone())))
+            .collect();
+        Ok(Value::Array(Rc::new(RefCell::new(args))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionExec {}
+impl LoxBuiltinFunctionExec {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionExec {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Runs the command through the system shell and returns what it wrote
+    /// to stdout. Its exit code is available from exitCode() afterwards.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        if !interpreter.allow_exec {
+            return Err(InterpreterError {
+                code: ErrorCode::ExecDisabled,
+                message: "exec() is disabled in this interpreter.".to_string(),
+                line: None,
+                span: None,
+            });
+        }
+        let command = string_argument("exec", "the command", &arguments[0])?;
+        let output = if cfg!(windows) {
+            Command::new("cmd").args(["/C", command]).output()
+        } else {
+            Command::new("sh").args(["-c", command]).output()
+        };
+        match output {
+            Ok(output) => {
+                interpreter.last_exit_code = output.status.code();
+                Ok(Value::String(Rc::new(
+                    String::from_utf8_lossy(&output.stdout).into_owned(),
+                )))
+            }
+            Err(err) => Err(InterpreterError {
+                code: ErrorCode::IoError,
+                message: format!("Could not run '{}': {}", command, err),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionExitCode {}
+impl LoxBuiltinFunctionExitCode {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionExitCode {
+    fn arity(&self) -> usize {
+        0
+    }
+
+    /// Returns the exit code of the last exec() call, or nil if there was
+    /// none or the command was killed by a signal.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        Ok(interpreter
+            .last_exit_code
+            .map_or(Value::Nil, |code| Value::Number(code as f64)))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionPrintErr {}
+impl LoxBuiltinFunctionPrintErr {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionPrintErr {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Prints the value like `print` does, but to the error stream.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        interpreter
+            .error_output
+            .write_line(&arguments[0].to_string());
+        Ok(Value::Nil)
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionSplit {}
+impl LoxBuiltinFunctionSplit {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionSplit {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Splits the string at every separator. An empty separator splits the
+    /// string into its characters.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let string = string_argument("split", "the string", &arguments[0])?;
+        let separator = string_argument("split", "the separator", &arguments[1])?;
+        let parts = if separator.is_empty() {
+            string
+                .chars()
+                .map(|c| Value::String(c.to_string().into()))
+                .collect()
+        } else {
+            string
+                .split(separator)
+                .map(|part| Value::String(Rc::new(part.to_string())))
+                .collect()
+        };
+        Ok(Value::Array(Rc::new(RefCell::new(parts))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionJoin {}
+impl LoxBuiltinFunctionJoin {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionJoin {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Joins the elements of an array, converted like `str()`, with the
+    /// separator between them.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("join", &arguments[0])?;
+        let separator = string_argument("join", "the separator", &arguments[1])?;
+        let parts: Vec<String> = array.borrow().iter().map(Value::to_string).collect();
+        Ok(Value::String(parts.join(separator).into()))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionCharAt {}
+impl LoxBuiltinFunctionCharAt {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionCharAt {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns the character at the index. Like `len()`, indices count
+    /// Unicode characters rather than UTF-8 bytes.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let string = string_argument("charAt", "the string", &arguments[0])?;
+        let len = string.chars().count();
+        let index = index_argument("charAt", &arguments[1], len, "a string")?;
+        let c = string.chars().nth(index).unwrap();
+        Ok(Value::String(c.to_string().into()))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionOrd {}
+impl LoxBuiltinFunctionOrd {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionOrd {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Returns the Unicode code point of a single-character string.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let string = string_argument("ord", "the character", &arguments[0])?;
+        let mut chars = string.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Value::Number(c as u32 as f64)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message: "ord() expects a string of exactly one character.".to_string(),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionChr {}
+impl LoxBuiltinFunctionChr {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionChr {
+    fn arity(&self) -> usize {
+        1
+    }
+
+    /// Returns the one-character string for a Unicode code point.
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let c = match &arguments[0] {
+            Value::Number(code)
+                if code.fract() == 0.0 && *code >= 0.0 && *code <= u32::MAX as f64 =>
+            {
+                char::from_u32(*code as u32)
+            }
+            _ => None,
+        };
+        match c {
+            Some(c) => Ok(Value::String(c.to_string().into())),
+            None => Err(InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message: format!("chr() expects a valid code point, got {}.", arguments[0]),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+fn function_argument(
+    function: &str,
+    value: &Value,
+    arity: usize,
+) -> Result<Callable, InterpreterError> {
+    match value {
+        Value::Callable(callable) if callable.arity() == arity => Ok(callable.clone()),
+        _ => Err(InterpreterError {
+            code: ErrorCode::InvalidArgument,
+            message: format!(
+                "{}() expects a function that takes {} argument{}.",
+                function,
+                arity,
+                if arity == 1 { "" } else { "s" }
+            ),
+            line: None,
+            span: None,
+        }),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionMap {}
+impl LoxBuiltinFunctionMap {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionMap {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns a new array with the function applied to every element.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("map", &arguments[0])?;
+        let function = function_argument("map", &arguments[1], 1)?;
+        // Iterate over a copy, the function may modify the array
+        let elements = array.borrow().clone();
+        let mut mapped = Vec::with_capacity(elements.len());
+        for element in elements {
+            mapped.push(interpreter.call_callable(&function, vec![element])?);
+        }
+        Ok(Value::Array(Rc::new(RefCell::new(mapped))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionFilter {}
+impl LoxBuiltinFunctionFilter {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionFilter {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns a new array with the elements for which the function returns
+    /// a truthy value.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("filter", &arguments[0])?;
+        let function = function_argument("filter", &arguments[1], 1)?;
+        let elements = array.borrow().clone();
+        let mut filtered = Vec::new();
+        for element in elements {
+            if interpreter
+                .call_callable(&function, vec![element.clone()])?
+                .is_true()
+            {
+                filtered.push(element);
+            }
+        }
+        Ok(Value::Array(Rc::new(RefCell::new(filtered))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionReduce {}
+impl LoxBuiltinFunctionReduce {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionReduce {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    /// Folds the elements from left to right, calling the function with the
+    /// accumulated value and each element.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        mut arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("reduce", &arguments[0])?;
+        let function = function_argument("reduce", &arguments[1], 2)?;
+        let elements = array.borrow().clone();
+        let mut accumulator = arguments.pop().unwrap();
+        for element in elements {
+            accumulator = interpreter.call_callable(&function, vec![accumulator, element])?;
+        }
+        Ok(accumulator)
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+// A stable bottom-up merge sort that stops at the first error of `compare`.
+// Unlike slice::sort_by it doesn't panic when a comparator written in Lox
+// isn't a total order, the order of the result is just unspecified then.
+fn merge_sort(
+    mut values: Vec<Value>,
+    mut compare: impl FnMut(&Value, &Value) -> Result<Ordering, InterpreterError>,
+) -> Result<Vec<Value>, InterpreterError> {
+    let len = values.len();
+    let mut width = 1;
+    while width < len {
+        let mut merged = Vec::with_capacity(len);
+        for start in (0..len).step_by(2 * width) {
+            let middle = (start + width).min(len);
+            let end = (start + 2 * width).min(len);
+            let (mut left, mut right) = (start, middle);
+            while left < middle && right < end {
+                // Ties take the left value, which keeps the sort stable
+                if compare(&values[left], &values[right])? == Ordering::Greater {
+                    merged.push(values[right].clone());
+                    right += 1;
+                } else {
+                    merged.push(values[left].clone());
+                    left += 1;
+                }
+            }
+            merged.extend_from_slice(&values[left..middle]);
+            merged.extend_from_slice(&values[right..end]);
+        }
+        values = merged;
+        width *= 2;
+    }
+    Ok(values)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoxBuiltinFunctionSort {}
+impl LoxBuiltinFunctionSort {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl LoxCallable for LoxBuiltinFunctionSort {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Returns a new array sorted with the comparator, which returns a
+    /// negative number, zero or a positive number when its first argument
+    /// is less than, equal to or greater than the second. The sort is stable.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let array = array_argument("sort", &arguments[0])?;
+        let comparator = function_argument("sort", &arguments[1], 2)?;
+        let values = array.borrow().clone();
+        let sorted = merge_sort(values, |a, b| {
+            match interpreter.call_callable(&comparator, vec![a.clone(), b.clone()])? {
+                Value::Number(order) => Ok(order.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                _ => Err(InterpreterError {
+                    code: ErrorCode::InvalidArgument,
+                    message: "sort() expects the comparator to return a number.".to_string(),
+                    line: None,
+                    span: None,
+                }),
+            }
+        })?;
+        Ok(Value::Array(Rc::new(RefCell::new(sorted))))
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
 }
@@ -1,10 +1,13 @@
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
+use liblox::codes::ErrorCode;
 use liblox::tokens::Token;
 
-use crate::callable::{Callable, LoxCallable, LoxFunction};
+use crate::callable::{Callable, LoxCallable, LoxDynamicFunction, LoxFunction};
 use crate::interpreter::{Interpreter, InterpreterError, Value};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +15,9 @@ pub struct LoxClass {
     pub name: String,
     pub superclass: Option<Rc<RefCell<LoxClass>>>,
     pub methods: HashMap<String, Box<LoxFunction>>,
+    // Methods implemented in Rust, for classes defined by embedders
+    pub native_methods: HashMap<String, NativeMethod>,
+    method_cache: MethodCache,
 }
 impl LoxClass {
     pub fn new(
@@ -23,26 +29,119 @@ impl LoxClass {
             name,
             superclass,
             methods,
+            native_methods: HashMap::new(),
+            method_cache: MethodCache::default(),
         }
     }
 
-    pub fn find_method(&self, name: &String) -> Option<Box<LoxFunction>> {
-        self.methods.get(name).cloned().or_else(|| {
+    pub fn find_method(&self, name: &String) -> Option<LoxFunction> {
+        self.methods
+            .get(name)
+            .map(|method| method.as_ref().clone())
+            .or_else(|| {
+                self.superclass
+                    .as_ref()
+                    .and_then(|superclass| superclass.borrow().find_method(name))
+            })
+    }
+
+    /// Names of the methods of the class, including inherited ones.
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .methods
+            .keys()
+            .chain(self.native_methods.keys())
+            .cloned()
+            .collect();
+        if let Some(superclass) = &self.superclass {
+            names.extend(superclass.borrow().method_names());
+        }
+        names
+    }
+
+    pub fn find_native_method(&self, name: &String) -> Option<NativeMethod> {
+        self.native_methods.get(name).cloned().or_else(|| {
             self.superclass
                 .as_ref()
-                .and_then(|superclass| superclass.borrow().find_method(name))
+                .and_then(|superclass| superclass.borrow().find_native_method(name))
         })
     }
 
+    /// Looks up a method and binds it to the instance. Methods written in
+    /// Lox come first: only the root of a class hierarchy can be native, so
+    /// they always belong to a subclass of the native one.
+    pub fn bind_method(&self, name: &String, instance: &Rc<RefCell<Instance>>) -> Option<Value> {
+        match self.lookup(name)? {
+            Method::Lox(method) => Some(Value::Callable(Callable::Function(method.bind(instance)))),
+            Method::Native(method) => {
+                let bound: Box<dyn LoxCallable> = Box::new(BoundNativeMethod {
+                    method,
+                    instance: Rc::clone(instance),
+                });
+                Some(Value::Callable(Callable::DynamicFunction(
+                    LoxDynamicFunction {
+                        callable: Rc::new(RefCell::new(bound)),
+                    },
+                )))
+            }
+        }
+    }
+
+    // Finds a method through the cache, which remembers missing ones too
+    fn lookup(&self, name: &String) -> Option<Method> {
+        if let Some(method) = self.method_cache.0.borrow().get(name) {
+            return method.clone();
+        }
+        let method = match self.find_method(name) {
+            Some(method) => Some(Method::Lox(method)),
+            None => self.find_native_method(name).map(Method::Native),
+        };
+        self.method_cache
+            .0
+            .borrow_mut()
+            .insert(name.clone(), method.clone());
+        method
+    }
+
     pub fn to_string(&self) -> String {
         format!("{}", self.name)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+enum Method {
+    Lox(LoxFunction),
+    Native(NativeMethod),
+}
+
+/// The methods a class looked up by name, including inherited and missing
+/// ones, so that calling a method again skips the walk up the superclasses.
+/// Classes don't change once defined, so the entries stay valid.
+#[derive(Debug, Clone, Default)]
+struct MethodCache(RefCell<HashMap<String, Option<Method>>>);
+
+// What was looked up doesn't make classes different
+impl PartialEq for MethodCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+thread_local! {
+    // Number of instances currently alive, reported by objectCount()
+    static LIVE_INSTANCES: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn live_instance_count() -> usize {
+    LIVE_INSTANCES.with(|count| count.get())
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Instance {
     pub class: Rc<RefCell<LoxClass>>,
     pub fields: HashMap<String, Value>,
+    // Rust state of instances of native classes, usually set by `init`
+    pub state: Option<NativeState>,
 }
 
 pub fn get_instance_field(
@@ -52,23 +151,30 @@ pub fn get_instance_field(
     if instance.borrow().fields.contains_key(&name.lexeme) {
         return Ok(instance.borrow().fields[&name.lexeme].clone());
     }
-    if let Some(method) = instance.borrow().class.borrow().find_method(&name.lexeme) {
-        return Ok(Value::Callable(Callable::Function(method.bind(&instance))));
+    let method = instance
+        .borrow()
+        .class
+        .borrow()
+        .bind_method(&name.lexeme, instance);
+    if let Some(method) = method {
+        return Ok(method);
     }
 
     Err(InterpreterError {
-        message: format!(
-            "Undefined property '{}'.\n[line {}]",
-            name.lexeme, name.line
-        ),
+        code: ErrorCode::UndefinedProperty,
+        message: format!("Undefined property '{}'.", name.lexeme),
+        line: Some(name.line),
+        span: name.span.clone(),
     })
 }
 
 impl Instance {
     pub fn new(class: Rc<RefCell<LoxClass>>) -> Self {
+        LIVE_INSTANCES.with(|count| count.set(count.get() + 1));
         Self {
             class,
             fields: HashMap::new(),
+            state: None,
         }
     }
 
@@ -76,16 +182,114 @@ impl Instance {
         self.fields.insert(name, value);
     }
 
+    /// Names of the fields and methods, which can repeat when a field has
+    /// the name of a method.
+    pub fn member_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.fields.keys().cloned().collect();
+        names.extend(self.class.borrow().method_names());
+        names
+    }
+
+    /// Returns the Rust state of a native instance if it has type `T`.
+    pub fn state<T: Any>(&self) -> Option<&T> {
+        self.state.as_ref()?.0.downcast_ref()
+    }
+
+    pub fn state_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.state.as_mut()?.0.downcast_mut()
+    }
+
+    pub fn set_state<T: Any>(&mut self, state: T) {
+        self.state = Some(NativeState(Box::new(state)));
+    }
+
     pub fn to_string(&self) -> String {
         format!("{} instance", self.class.borrow().to_string())
     }
 }
 
+/// Displays an instance with its fields, like `Foo { flavor: "Sesame" }`.
+/// Instances in the fields are shown the same way, except for an instance
+/// that is shown already, which is cut short as `Foo { .. }`.
+pub struct InstanceFields<'a>(pub &'a Rc<RefCell<Instance>>);
+
+impl fmt::Display for InstanceFields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instance(f, self.0, &mut Vec::new())
+    }
+}
+
+// `shown` holds the instances and arrays that are being written, to stop at
+// cycles
+fn write_instance(
+    f: &mut fmt::Formatter<'_>,
+    instance: &Rc<RefCell<Instance>>,
+    shown: &mut Vec<usize>,
+) -> fmt::Result {
+    let instance_ref = instance.borrow();
+    let name = &instance_ref.class.borrow().name;
+    if shown.contains(&(Rc::as_ptr(instance) as usize)) {
+        return write!(f, "{} {{ .. }}", name);
+    }
+    let mut fields: Vec<(&String, &Value)> = instance_ref.fields.iter().collect();
+    if fields.is_empty() {
+        return write!(f, "{} {{}}", name);
+    }
+    fields.sort_by_key(|(field, _)| *field);
+
+    shown.push(Rc::as_ptr(instance) as usize);
+    write!(f, "{} {{ ", name)?;
+    for (i, (field, value)) in fields.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}: ", field)?;
+        write_field(f, value, shown)?;
+    }
+    shown.pop();
+    write!(f, " }}")
+}
+
+fn write_field(f: &mut fmt::Formatter<'_>, value: &Value, shown: &mut Vec<usize>) -> fmt::Result {
+    match value {
+        Value::Instance(instance) => write_instance(f, instance, shown),
+        Value::String(string) => write!(f, "{:?}", string),
+        Value::Array(array) => {
+            let pointer = Rc::as_ptr(array) as usize;
+            if shown.contains(&pointer) {
+                return write!(f, "[...]");
+            }
+            shown.push(pointer);
+            write!(f, "[")?;
+            for (i, element) in array.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_field(f, element, shown)?;
+            }
+            shown.pop();
+            write!(f, "]")
+        }
+        _ => write!(f, "{}", value),
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        LIVE_INSTANCES.with(|count| count.set(count.get() - 1));
+    }
+}
+
 impl LoxCallable for Rc<RefCell<LoxClass>> {
     fn arity(&self) -> usize {
-        self.borrow()
-            .find_method(&"init".to_string())
-            .map_or(0, |method| method.arity())
+        let class = self.borrow();
+        let init = "init".to_string();
+        match class.find_method(&init) {
+            Some(method) => method.arity(),
+            None => class
+                .find_native_method(&init)
+                .map_or(0, |method| method.arity),
+        }
     }
 
     fn call(
@@ -94,10 +298,9 @@ impl LoxCallable for Rc<RefCell<LoxClass>> {
         arguments: Vec<Value>,
     ) -> Result<Value, InterpreterError> {
         let instance = Rc::new(RefCell::new(Instance::new(self.clone())));
-        let method = self.borrow().find_method(&"init".to_string());
-        if let Some(method) = method {
-            let method = method.bind(&instance);
-            method.call(interpreter, arguments)?;
+        let method = self.borrow().bind_method(&"init".to_string(), &instance);
+        if let Some(Value::Callable(method)) = method {
+            interpreter.call_callable(&method, arguments)?;
         }
         Ok(Value::Instance(instance))
     }
@@ -106,3 +309,182 @@ impl LoxCallable for Rc<RefCell<LoxClass>> {
         self.borrow().to_string()
     }
 }
+
+/// Signature of the Rust closures implementing methods of native classes.
+/// They receive the instance the method was called on.
+pub type NativeMethodFn =
+    dyn Fn(&mut Interpreter, &Rc<RefCell<Instance>>, Vec<Value>) -> Result<Value, InterpreterError>;
+
+#[derive(Clone)]
+pub struct NativeMethod {
+    pub arity: usize,
+    pub function: Rc<NativeMethodFn>,
+}
+impl fmt::Debug for NativeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeMethod {{ arity: {} }}", self.arity)
+    }
+}
+impl PartialEq for NativeMethod {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+/// Rust value held by an instance of a native class.
+pub struct NativeState(pub Box<dyn Any>);
+impl fmt::Debug for NativeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeState")
+    }
+}
+impl PartialEq for NativeState {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            self.0.as_ref() as *const dyn Any as *const u8,
+            other.0.as_ref() as *const dyn Any as *const u8,
+        )
+    }
+}
+
+struct BoundNativeMethod {
+    method: NativeMethod,
+    instance: Rc<RefCell<Instance>>,
+}
+impl LoxCallable for BoundNativeMethod {
+    fn arity(&self) -> usize {
+        self.method.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        (self.method.function)(interpreter, &self.instance, arguments)
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+}
+
+/// Describes a class whose methods are implemented in Rust, to be defined
+/// with `Interpreter::define_native_class`. Lox code constructs instances by
+/// calling the class, which runs the native `init` method if there is one;
+/// that is the place to attach Rust state with `Instance::set_state`.
+/// Lox classes may inherit from native classes.
+pub struct NativeClass {
+    name: String,
+    methods: HashMap<String, NativeMethod>,
+}
+impl NativeClass {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            methods: HashMap::new(),
+        }
+    }
+
+    pub fn method(
+        mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(
+                &mut Interpreter,
+                &Rc<RefCell<Instance>>,
+                Vec<Value>,
+            ) -> Result<Value, InterpreterError>
+            + 'static,
+    ) -> Self {
+        self.methods.insert(
+            name.to_string(),
+            NativeMethod {
+                arity,
+                function: Rc::new(function),
+            },
+        );
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn build(self) -> Rc<RefCell<LoxClass>> {
+        let mut class = LoxClass::new(self.name, None, HashMap::new());
+        class.native_methods = self.methods;
+        Rc::new(RefCell::new(class))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_cache() {
+        let base = NativeClass::new("Base")
+            .method("get", 0, |_, _, _| Ok(Value::Number(1.0)))
+            .build();
+        let class = LoxClass::new("Derived".to_string(), Some(base), HashMap::new());
+        let instance = Rc::new(RefCell::new(Instance::new(Rc::new(RefCell::new(
+            class.clone(),
+        )))));
+        let mut interpreter = Interpreter::without_prelude();
+        for _ in 0..2 {
+            let Some(Value::Callable(method)) = class.bind_method(&"get".to_string(), &instance)
+            else {
+                panic!("expected the inherited method");
+            };
+            let value = interpreter.call_callable(&method, Vec::new()).unwrap();
+            assert_eq!(value, Value::Number(1.0));
+            assert!(class
+                .bind_method(&"missing".to_string(), &instance)
+                .is_none());
+        }
+        // Found and missing methods are both remembered
+        assert_eq!(class.method_cache.0.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_instance_fields() {
+        let mut lox = crate::Lox::new();
+        lox.run(
+            "class Bagel { init(flavor) { this.flavor = flavor; } }
+            class Node {}
+            var empty = Node();
+            var a = Node();
+            a.bagels = array();
+            push(a.bagels, Bagel(\"Sesame\"));
+            push(a.bagels, 2);
+            a.next = Node();
+            a.next.next = a;",
+        )
+        .unwrap();
+        let shown = |name: &str| match lox.interpreter.globals.borrow().get(&name.to_string()) {
+            Some(Value::Instance(instance)) => InstanceFields(&instance).to_string(),
+            _ => panic!("expected an instance"),
+        };
+        assert_eq!(shown("empty"), "Node {}");
+        assert_eq!(
+            shown("a"),
+            "Node { bagels: [Bagel { flavor: \"Sesame\" }, 2], \
+             next: Node { next: Node { .. } } }"
+        );
+    }
+
+    #[test]
+    fn test_member_names() {
+        let mut lox = crate::Lox::new();
+        lox.run("class A { f() {} }\nclass B < A { init() { this.x = 1; } g() {} }\nvar b = B();")
+            .unwrap();
+        let Some(Value::Instance(b)) = lox.interpreter.globals.borrow().get(&"b".to_string())
+        else {
+            panic!("expected an instance");
+        };
+        let mut names = b.borrow().member_names();
+        names.sort();
+        assert_eq!(names, vec!["f", "g", "init", "x"]);
+    }
+}
@@ -0,0 +1,463 @@
+//! Runs statements by first compiling them into Rust closures, which then
+//! run without matching on the kind of each node again, see
+//! `Engine::Closures`.
+//!
+//! The closures count steps and check for cancellation where the tree walker
+//! does, and share its operators, calls and classes, so that both behave the
+//! same.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use liblox::tokens::{LiteralTypes, TokenType};
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::callable::{Callable, LoxFunction};
+use crate::expression::Expression;
+use crate::interpreter::{
+    binary_operation, get_property, unary_operation, Environment, Interpreter, InterpreterError,
+    InterpreterResult, Value,
+};
+use crate::stmt::Stmt;
+
+pub(crate) type CompiledExpr = Box<dyn Fn(&mut Interpreter) -> Result<Value, InterpreterError>>;
+pub(crate) type CompiledStmt =
+    Box<dyn Fn(&mut Interpreter) -> Result<InterpreterResult, InterpreterError>>;
+
+/// Compiles resolved statements. Function bodies are compiled when they are
+/// first called instead.
+pub(crate) fn compile_statements(ast: &Rc<Ast>, statements: &[StmtId]) -> Vec<CompiledStmt> {
+    statements
+        .iter()
+        .map(|&statement| compile_statement(ast, statement))
+        .collect()
+}
+
+/// Runs compiled statements in `environment`, as `execute_block` does.
+pub(crate) fn run_block(
+    interpreter: &mut Interpreter,
+    statements: &[CompiledStmt],
+    environment: Rc<RefCell<Environment>>,
+) -> Result<InterpreterResult, InterpreterError> {
+    let previous = std::mem::replace(&mut interpreter.environment, environment);
+    let mut result = Ok(InterpreterResult::None);
+    for statement in statements {
+        match statement(interpreter) {
+            Ok(InterpreterResult::None) => {}
+            returned => {
+                result = returned;
+                break;
+            }
+        }
+    }
+    let environment = std::mem::replace(&mut interpreter.environment, previous);
+    interpreter.recycle_environment(environment);
+    result
+}
+
+fn compile_statement(ast: &Rc<Ast>, statement: StmtId) -> CompiledStmt {
+    match &ast[statement] {
+        Stmt::Expression(stmt) => {
+            let expression = compile_expression(ast, stmt.expression);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                expression(interpreter)?;
+                Ok(InterpreterResult::None)
+            })
+        }
+        Stmt::Print(stmt) => {
+            let expression = compile_expression(ast, stmt.expression);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let value = expression(interpreter)?;
+                interpreter.output.write_line(&value.to_string());
+                Ok(InterpreterResult::None)
+            })
+        }
+        Stmt::Var(stmt) => {
+            let name = stmt.name.lexeme.clone();
+            let initializer = stmt
+                .initializer
+                .map(|initializer| compile_expression(ast, initializer));
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let value = match &initializer {
+                    Some(initializer) => initializer(interpreter)?,
+                    None => Value::Nil,
+                };
+                interpreter
+                    .environment
+                    .borrow_mut()
+                    .define(name.clone(), value);
+                Ok(InterpreterResult::None)
+            })
+        }
+        Stmt::Block(block) => {
+            let statements = compile_statements(ast, &block.statements);
+            let scoped = block.declares_variables(ast);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let environment = if scoped {
+                    interpreter.new_environment(interpreter.environment.clone())
+                } else {
+                    interpreter.environment.clone()
+                };
+                run_block(interpreter, &statements, environment)
+            })
+        }
+        Stmt::If(stmt) => {
+            let condition = compile_expression(ast, stmt.condition);
+            let then_branch = compile_statement(ast, stmt.then_branch);
+            let else_branch = stmt
+                .else_branch
+                .map(|branch| compile_statement(ast, branch));
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                if condition(interpreter)?.is_true() {
+                    then_branch(interpreter)
+                } else if let Some(else_branch) = &else_branch {
+                    else_branch(interpreter)
+                } else {
+                    Ok(InterpreterResult::None)
+                }
+            })
+        }
+        Stmt::While(stmt) => {
+            let condition = compile_expression(ast, stmt.condition);
+            let body = compile_statement(ast, stmt.body);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                while condition(interpreter)?.is_true() {
+                    interpreter.check_cancelled()?;
+                    match body(interpreter)? {
+                        InterpreterResult::None => {}
+                        result => return Ok(result),
+                    }
+                }
+                Ok(InterpreterResult::None)
+            })
+        }
+        Stmt::ForIn(stmt) => {
+            let iterable = compile_expression(ast, stmt.iterable);
+            let body = [compile_statement(ast, stmt.body)];
+            let name = stmt.name.lexeme.clone();
+            let at = stmt.name.clone();
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let iterable = iterable(interpreter)?;
+                let mut iteration = interpreter.start_iteration(iterable, &at)?;
+                while let Some(value) = interpreter.next_value(&mut iteration, &at)? {
+                    interpreter.check_cancelled()?;
+                    let environment = interpreter.new_environment(interpreter.environment.clone());
+                    environment.borrow_mut().define(name.clone(), value);
+                    let result = run_block(interpreter, &body, environment)?;
+                    if !matches!(result, InterpreterResult::None) {
+                        return Ok(result);
+                    }
+                }
+                Ok(InterpreterResult::None)
+            })
+        }
+        Stmt::Return(stmt) => match stmt.value.map(|value| (value, &ast[value])) {
+            // Calls of Lox functions are left to the calling function, as
+            // in Interpreter::tail_call
+            Some((_, Expression::Call(call))) => {
+                let callee = compile_expression(ast, call.callee);
+                let arguments = compile_arguments(ast, &call.arguments);
+                let paren = call.paren.clone();
+                Box::new(move |interpreter| {
+                    // One step for the statement and one for the call
+                    interpreter.count_step()?;
+                    interpreter.count_step()?;
+                    let callee = callee(interpreter)?;
+                    let arguments = evaluate_arguments(interpreter, &callee, &arguments)?;
+                    interpreter.tail_call_value(callee, arguments, &paren)
+                })
+            }
+            Some((value, _)) => {
+                let value = compile_expression(ast, value);
+                Box::new(move |interpreter| {
+                    interpreter.count_step()?;
+                    Ok(InterpreterResult::Return(value(interpreter)?))
+                })
+            }
+            None => Box::new(|interpreter| {
+                interpreter.count_step()?;
+                Ok(InterpreterResult::Return(Value::Nil))
+            }),
+        },
+        Stmt::Function(function) => {
+            let ast = Rc::clone(ast);
+            let function = Rc::clone(function);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let closure = interpreter.environment.clone();
+                interpreter.environment.borrow_mut().define(
+                    function.name.lexeme.clone(),
+                    Value::Callable(Callable::Function(LoxFunction::new(
+                        Rc::clone(&ast),
+                        Rc::clone(&function),
+                        closure,
+                        false,
+                    ))),
+                );
+                Ok(InterpreterResult::None)
+            })
+        }
+        // Runs once per declaration, and its methods are compiled when
+        // called, so it's left to the tree walker
+        Stmt::Class(_) => {
+            let ast = Rc::clone(ast);
+            Box::new(move |interpreter| interpreter.execute_statement(&ast, statement))
+        }
+    }
+}
+
+fn compile_expression(ast: &Ast, expression: ExprId) -> CompiledExpr {
+    match &ast[expression] {
+        Expression::Literal(literal) => match &literal.value {
+            LiteralTypes::String(value) => {
+                let value = value.clone();
+                Box::new(move |interpreter| {
+                    interpreter.count_step()?;
+                    Ok(Value::String(interpreter.intern(&value)))
+                })
+            }
+            value => {
+                let value = match value {
+                    LiteralTypes::Number(value) => Value::Number(*value),
+                    LiteralTypes::Bool(value) => Value::Bool(*value),
+                    _ => Value::Nil,
+                };
+                Box::new(move |interpreter| {
+                    interpreter.count_step()?;
+                    Ok(value.clone())
+                })
+            }
+        },
+        Expression::Variable(variable) => {
+            let name = variable.name.clone();
+            let resolved = variable.resolved.get();
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                interpreter.lookup_variable(&name, resolved)
+            })
+        }
+        Expression::This(this) => {
+            let keyword = this.keyword.clone();
+            let resolved = this.resolved.get();
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                interpreter.lookup_variable(&keyword, resolved)
+            })
+        }
+        Expression::Assign(assign) => {
+            if let Some(sum) = assign.sum_with_itself(ast) {
+                let operator = sum.operator.clone();
+                let left = compile_expression(ast, sum.left);
+                let right = compile_expression(ast, sum.right);
+                let assign = assign.clone();
+                return Box::new(move |interpreter| {
+                    // Steps of the assignment and the sum
+                    interpreter.count_step()?;
+                    interpreter.count_step()?;
+                    let left = left(interpreter)?;
+                    let right = right(interpreter)?;
+                    interpreter.assign_sum(&assign, &operator, left, right)
+                });
+            }
+            let name = assign.name.clone();
+            let resolved = assign.resolved.get();
+            let value = compile_expression(ast, assign.value);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let value = value(interpreter)?;
+                interpreter.assign_variable(&name, resolved, value.clone())?;
+                Ok(value)
+            })
+        }
+        Expression::Grouping(grouping) => {
+            let expression = compile_expression(ast, grouping.expression);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                expression(interpreter)
+            })
+        }
+        Expression::Unary(unary) => {
+            let operator = unary.operator.clone();
+            let right = compile_expression(ast, unary.right);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let right = right(interpreter)?;
+                unary_operation(&operator, right)
+            })
+        }
+        Expression::Binary(binary) => {
+            let operator = binary.operator.clone();
+            let left = compile_expression(ast, binary.left);
+            let right = compile_expression(ast, binary.right);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let left = left(interpreter)?;
+                let right = right(interpreter)?;
+                binary_operation(&operator, left, right)
+            })
+        }
+        Expression::Logical(logical) => {
+            let or = logical.operator.token_type == TokenType::Or;
+            let left = compile_expression(ast, logical.left);
+            let right = compile_expression(ast, logical.right);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let left = left(interpreter)?;
+                if left.is_true() == or {
+                    return Ok(left);
+                }
+                right(interpreter)
+            })
+        }
+        Expression::Call(call) => {
+            let callee = compile_expression(ast, call.callee);
+            let arguments = compile_arguments(ast, &call.arguments);
+            let paren = call.paren.clone();
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let callee = callee(interpreter)?;
+                let arguments = evaluate_arguments(interpreter, &callee, &arguments)?;
+                interpreter.call_value(&callee, arguments, &paren)
+            })
+        }
+        Expression::Get(get) => {
+            let object = compile_expression(ast, get.object);
+            let name = get.name.clone();
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let object = object(interpreter)?;
+                get_property(object, &name)
+            })
+        }
+        Expression::Set(set) => {
+            let object = compile_expression(ast, set.object);
+            let name = set.name.clone();
+            let value = compile_expression(ast, set.value);
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                let object = object(interpreter)?;
+                interpreter.set_property(&object, &name, |interpreter| value(interpreter))
+            })
+        }
+        Expression::Super(super_expr) => {
+            let super_expr = super_expr.clone();
+            Box::new(move |interpreter| {
+                interpreter.count_step()?;
+                interpreter.super_method(&super_expr)
+            })
+        }
+    }
+}
+
+fn compile_arguments(ast: &Ast, arguments: &[ExprId]) -> Vec<CompiledExpr> {
+    arguments
+        .iter()
+        .map(|&argument| compile_expression(ast, argument))
+        .collect()
+}
+
+// Arguments are only evaluated for values that can be called
+fn evaluate_arguments(
+    interpreter: &mut Interpreter,
+    callee: &Value,
+    arguments: &[CompiledExpr],
+) -> Result<Vec<Value>, InterpreterError> {
+    let mut values = Vec::new();
+    if let Value::Callable(_) = callee {
+        for argument in arguments {
+            values.push(argument(interpreter)?);
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Interpreter, Lox};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // What the source prints with the engine, and the error it fails with
+    fn run(source: &str, engine: Engine, step_limit: Option<u64>) -> (String, Option<String>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::builder()
+            .output(SharedBuffer(Rc::clone(&output)))
+            .prelude(false)
+            .build();
+        interpreter.set_engine(engine);
+        interpreter.set_step_limit(step_limit);
+        let mut lox = Lox::with_interpreter(interpreter);
+        lox.set_error_reporting(false);
+        let error = lox.run(source).err().map(|err| err.to_string());
+        let printed = String::from_utf8_lossy(&output.borrow()).to_string();
+        (printed, error)
+    }
+
+    #[test]
+    fn test_same_as_tree_walker() {
+        let sources = [
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+             print fib(15);",
+            "var total = 0;\nfor (var i = 0; i < 10; i = i + 1) { if (i == 3 or i > 7) total = total + i; }\n\
+             print total; print !total; print -total; print \"a\" + \"b\";",
+            "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }\n\
+             var next = counter(); next(); print next();",
+            "class A { init(x) { this.x = x; } get() { return this.x; } }\n\
+             class B < A { get() { return super.get() * 2; } }\n\
+             var b = B(21); print b.get(); b.x = 1; print b.get(); print B;",
+            "class Range { init(n) { this.i = 0; this.n = n; } iter() { return this; }\n\
+             done() { return this.i >= this.n; } next() { this.i = this.i + 1; return this.i; } }\n\
+             for (var i in Range(3)) { print i; }",
+            "fun loop(n) { if (n == 0) return \"done\"; return loop(n - 1); }\nprint loop(100000);",
+            "var s = \"\"; for (var i = 0; i < 3; i = i + 1) s = s + \"x\";\n\
+             var t = s; s = s + \"y\"; print s; print t; s = s + 1;",
+            "var a = 1; print a + nil;",
+            "print 1; undefined = 2;",
+            "var s = \"x\"; s.field;",
+        ];
+        for source in sources {
+            assert_eq!(
+                run(source, Engine::Closures, None),
+                run(source, Engine::TreeWalker, None),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_steps() {
+        let source = "fun f(n) { var x = n * 2; return x; }\n\
+                      var i = 0; while (i < 50) { i = i + f(i) + 1; }";
+        let (_, error) = run(source, Engine::TreeWalker, Some(300));
+        assert!(error.is_none());
+        // The most steps that fail are the same for both
+        let mut limit = 300;
+        while run(source, Engine::TreeWalker, Some(limit)).1.is_none() {
+            limit -= 1;
+        }
+        assert!(run(source, Engine::Closures, Some(limit)).1.is_some());
+        assert!(run(source, Engine::Closures, Some(limit + 1)).1.is_none());
+    }
+}
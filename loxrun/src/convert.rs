@@ -0,0 +1,171 @@
+//! Conversions between Rust values and Lox values, for host code that
+//! exchanges data with scripts.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use liblox::codes::ErrorCode;
+
+use crate::host::NativeHandle;
+use crate::interpreter::{InterpreterError, Value};
+
+pub trait ToLox {
+    fn to_lox(self) -> Value;
+}
+
+pub trait FromLox: Sized {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError>;
+}
+
+fn type_error(expected: &str, value: &Value) -> InterpreterError {
+    InterpreterError {
+        code: ErrorCode::InvalidArgument,
+        message: format!("Expected {} but got {}.", expected, value.type_name()),
+        line: None,
+        span: None,
+    }
+}
+
+impl ToLox for Value {
+    fn to_lox(self) -> Value {
+        self
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        Ok(value.clone())
+    }
+}
+
+impl ToLox for f64 {
+    fn to_lox(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Number(n) => Ok(*n),
+            _ => Err(type_error("a number", value)),
+        }
+    }
+}
+
+impl ToLox for bool {
+    fn to_lox(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(type_error("a bool", value)),
+        }
+    }
+}
+
+impl ToLox for String {
+    fn to_lox(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl ToLox for &str {
+    fn to_lox(self) -> Value {
+        Value::String(Rc::new(self.to_string()))
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err(type_error("a string", value)),
+        }
+    }
+}
+
+/// `None` is converted to nil and back.
+impl ToLox for NativeHandle {
+    fn to_lox(self) -> Value {
+        Value::Native(self)
+    }
+}
+
+impl FromLox for NativeHandle {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Native(handle) => Ok(handle.clone()),
+            _ => Err(type_error("a handle", value)),
+        }
+    }
+}
+
+impl<T: ToLox> ToLox for Option<T> {
+    fn to_lox(self) -> Value {
+        match self {
+            Some(value) => value.to_lox(),
+            None => Value::Nil,
+        }
+    }
+}
+
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_lox(value).map(Some),
+        }
+    }
+}
+
+/// Vectors are converted to new arrays and back, element by element.
+impl<T: ToLox> ToLox for Vec<T> {
+    fn to_lox(self) -> Value {
+        let elements = self.into_iter().map(ToLox::to_lox).collect();
+        Value::Array(Rc::new(RefCell::new(elements)))
+    }
+}
+
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: &Value) -> Result<Self, InterpreterError> {
+        match value {
+            Value::Array(array) => array.borrow().iter().map(T::from_lox).collect(),
+            _ => Err(type_error("an array", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = vec![Some(1.5), None].to_lox();
+        assert_eq!(value.to_string(), "[1.5, nil]");
+        assert_eq!(
+            Vec::<Option<f64>>::from_lox(&value).unwrap(),
+            vec![Some(1.5), None]
+        );
+        assert_eq!(String::from_lox(&"lox".to_lox()).unwrap(), "lox");
+        assert!(bool::from_lox(&true.to_lox()).unwrap());
+    }
+
+    #[test]
+    fn test_conversion_errors() {
+        let value = vec!["a", "b"].to_lox();
+        assert_eq!(
+            Vec::<f64>::from_lox(&value).unwrap_err().message,
+            "Expected a number but got string."
+        );
+        assert_eq!(
+            String::from_lox(&Value::Nil).unwrap_err().message,
+            "Expected a string but got nil."
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt;
+use std::ops::Range;
+
+use liblox::codes::ErrorCode;
+use liblox::diagnostics::{Diagnostic, Severity};
+use liblox::scanner::Scanner;
+
+use crate::interpreter::InterpreterError;
+use crate::json;
+use crate::parser::Parser;
+use crate::resolver::ResolverError;
+
+/// The step of running a source that an error comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Syntax,
+    Resolve,
+    Runtime,
+}
+
+/// Everything that can go wrong when running Lox source.
+#[derive(Debug)]
+pub enum LoxError {
+    /// The source has syntax errors, with one diagnostic per error.
+    Syntax(Vec<Diagnostic>),
+    /// The source has resolve errors, with one diagnostic per error.
+    Resolve(Vec<Diagnostic>),
+    Runtime(Diagnostic),
+    /// The source has warnings, which are errors because of
+    /// `Lox::set_warnings_as_errors`.
+    Warnings(Vec<Diagnostic>),
+}
+
+impl LoxError {
+    pub fn stage(&self) -> Stage {
+        match self {
+            LoxError::Syntax(_) => Stage::Syntax,
+            LoxError::Resolve(_) | LoxError::Warnings(_) => Stage::Resolve,
+            LoxError::Runtime(_) => Stage::Runtime,
+        }
+    }
+
+    /// The diagnostics of the error, of which only runtime errors have
+    /// exactly one.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            LoxError::Syntax(diagnostics)
+            | LoxError::Resolve(diagnostics)
+            | LoxError::Warnings(diagnostics) => diagnostics,
+            LoxError::Runtime(diagnostic) => std::slice::from_ref(diagnostic),
+        }
+    }
+
+    /// Code of the first error.
+    pub fn code(&self) -> Option<ErrorCode> {
+        Some(self.diagnostics().first()?.code)
+    }
+
+    /// Line of the first error.
+    pub fn line(&self) -> Option<i32> {
+        self.diagnostics().first()?.line
+    }
+
+    /// Column of the first error.
+    pub fn column(&self) -> Option<usize> {
+        self.diagnostics().first()?.column
+    }
+
+    /// Source span of the first error.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.diagnostics().first()?.span.clone()
+    }
+
+    /// Renders every diagnostic with its source excerpt, see
+    /// `Diagnostic::render`.
+    pub fn render(&self, source: &str) -> String {
+        let rendered: Vec<String> = self
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.render(source))
+            .collect();
+        rendered.join("\n")
+    }
+
+    /// Formats the diagnostics as JSON, see `diagnostics_to_json`.
+    pub fn to_json(&self, file: &str) -> String {
+        diagnostics_to_json(self.diagnostics(), file)
+    }
+
+    /// Computes the columns of the diagnostics from the source their spans
+    /// lie in, which resolve and runtime errors don't know.
+    pub(crate) fn locate(self, source: &str) -> Self {
+        let locate = |diagnostic: Diagnostic| diagnostic.locate(source);
+        let locate_all =
+            |diagnostics: Vec<Diagnostic>| diagnostics.into_iter().map(locate).collect();
+        match self {
+            LoxError::Syntax(diagnostics) => LoxError::Syntax(locate_all(diagnostics)),
+            LoxError::Resolve(diagnostics) => LoxError::Resolve(locate_all(diagnostics)),
+            LoxError::Warnings(diagnostics) => LoxError::Warnings(locate_all(diagnostics)),
+            LoxError::Runtime(diagnostic) => LoxError::Runtime(locate(diagnostic)),
+        }
+    }
+
+    /// Collects the scan and parse errors of a source, with the spans of
+    /// the offending text.
+    pub(crate) fn syntax(source: &str, scanner: &Scanner, parser: &Parser) -> Self {
+        let scan_errors = scanner.errors().iter().map(|error| {
+            Diagnostic::error(error.code, error.line, "", &error.message)
+                .with_span(source, error.span.clone())
+        });
+        let parse_errors =
+            parser
+                .errors()
+                .iter()
+                .zip(parser.error_tokens())
+                .map(|(error, token)| {
+                    let span = scanner.token_spans()[*token].clone();
+                    let diagnostic = match error.line {
+                        Some(line) => {
+                            Diagnostic::error(error.code, line, &error.location, &error.message)
+                        }
+                        None => Diagnostic::runtime(error.code, None, &error.message),
+                    };
+                    diagnostic.with_span(source, span)
+                });
+        LoxError::Syntax(scan_errors.chain(parse_errors).collect())
+    }
+}
+
+/// Formats diagnostics as a JSON array of objects with the fields `file`,
+/// `severity`, `line`, `column`, `code` and `message`, for editors and CI.
+/// Unknown lines and columns are `null`.
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic], file: &str) -> String {
+    let mut output = String::from("[");
+    for (i, diagnostic) in diagnostics.iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        output.push_str("{\"file\":");
+        json::write_string(&mut output, file);
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let line = diagnostic
+            .line
+            .map_or("null".to_string(), |line| line.to_string());
+        let column = diagnostic
+            .column
+            .map_or("null".to_string(), |column| column.to_string());
+        output.push_str(&format!(
+            ",\"severity\":\"{}\",\"line\":{},\"column\":{},\"code\":\"{}\",\"message\":",
+            severity,
+            line,
+            column,
+            diagnostic.code.code()
+        ));
+        json::write_string(&mut output, &diagnostic.message);
+        output.push('}');
+    }
+    output.push(']');
+    output
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.diagnostics().iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+impl From<ResolverError> for LoxError {
+    fn from(err: ResolverError) -> Self {
+        LoxError::from(vec![err])
+    }
+}
+
+impl From<Vec<ResolverError>> for LoxError {
+    fn from(errors: Vec<ResolverError>) -> Self {
+        let diagnostics = errors
+            .into_iter()
+            .map(|err| {
+                let location = format!(" at '{}'", err.lexeme);
+                Diagnostic {
+                    span: err.span,
+                    ..Diagnostic::error(err.code, err.line, &location, &err.message)
+                }
+            })
+            .collect();
+        LoxError::Resolve(diagnostics)
+    }
+}
+
+impl From<InterpreterError> for LoxError {
+    fn from(err: InterpreterError) -> Self {
+        LoxError::Runtime(Diagnostic {
+            span: err.span,
+            ..Diagnostic::runtime(err.code, err.line, &err.message)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syntax_diagnostics() {
+        let source = "var a = 1;\nvar b = @;\nprint ;";
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        assert!(parser.parse().is_err());
+
+        let err = LoxError::syntax(source, &scanner, &parser);
+        assert_eq!(err.stage(), Stage::Syntax);
+        let diagnostics = err.diagnostics();
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].message, "Unexpected character.");
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(2), Some(9))
+        );
+        assert_eq!(diagnostics[0].span, Some(19..20));
+        assert_eq!(diagnostics[0].code, ErrorCode::UnexpectedCharacter);
+        assert_eq!(diagnostics[1].code, ErrorCode::ExpectedExpression);
+        assert_eq!(
+            (diagnostics[2].line, diagnostics[2].column),
+            (Some(3), Some(7))
+        );
+        assert_eq!(
+            err.to_string(),
+            "[line 2] Error: Unexpected character.\n\
+             [line 2] Error at ';': Expect expression.\n\
+             [line 3] Error at ';': Expect expression."
+        );
+    }
+
+    #[test]
+    fn test_runtime_diagnostic() {
+        let err = LoxError::from(InterpreterError {
+            code: ErrorCode::UndefinedVariable,
+            message: "Undefined variable 'x'.".to_string(),
+            line: Some(12),
+            span: None,
+        });
+        assert_eq!(err.stage(), Stage::Runtime);
+        assert_eq!(err.line(), Some(12));
+        assert_eq!(err.code(), Some(ErrorCode::UndefinedVariable));
+        assert_eq!(err.column(), None);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let source = "print 1\nprint \"a";
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        assert!(parser.parse().is_err());
+        assert_eq!(
+            LoxError::syntax(source, &scanner, &parser).to_json("a.lox"),
+            "[{\"file\":\"a.lox\",\"severity\":\"error\",\"line\":2,\"column\":7,\"code\":\"E0002\",\
+             \"message\":\"Unterminated string.\"},\
+             {\"file\":\"a.lox\",\"severity\":\"error\",\"line\":2,\"column\":1,\"code\":\"E1002\",\
+             \"message\":\"Expect ';' after value.\"}]"
+        );
+
+        let err = LoxError::from(InterpreterError {
+            code: ErrorCode::Cancelled,
+            message: "Execution cancelled.".to_string(),
+            line: None,
+            span: None,
+        });
+        assert_eq!(
+            err.to_json("a.lox"),
+            "[{\"file\":\"a.lox\",\"severity\":\"error\",\"line\":null,\"column\":null,\"code\":\"E3011\",\
+             \"message\":\"Execution cancelled.\"}]"
+        );
+
+        let err = LoxError::from(InterpreterError {
+            code: ErrorCode::UndefinedVariable,
+            message: "Undefined variable 'x'.".to_string(),
+            line: Some(3),
+            span: None,
+        });
+        assert_eq!(
+            err.to_json("a.lox"),
+            "[{\"file\":\"a.lox\",\"severity\":\"error\",\"line\":3,\"column\":null,\"code\":\"E3001\",\
+             \"message\":\"Undefined variable 'x'.\"}]"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "var a = 1;\nvar b = ;";
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        assert!(parser.parse().is_err());
+        assert_eq!(
+            LoxError::syntax(source, &scanner, &parser).render(source),
+            "[line 2] Error at ';': Expect expression.\n 2 | var b = ;\n   |         ^"
+        );
+
+        let err = LoxError::from(InterpreterError {
+            code: ErrorCode::UndefinedVariable,
+            message: "Undefined variable 'c'.".to_string(),
+            line: Some(1),
+            span: None,
+        });
+        assert_eq!(
+            err.render("print c;"),
+            "Undefined variable 'c'.\n[line 1]\n 1 | print c;\n   | ^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_crlf_and_multibyte() {
+        let source = "var é = \"ü\";\r\nvar ö = é ä;";
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        assert!(parser.parse().is_err());
+        let err = LoxError::syntax(source, &scanner, &parser);
+        assert_eq!(err.column(), Some(11));
+        assert_eq!(
+            err.render(source),
+            "[line 2] Error at 'ä': Expect ';' after variable declaration.\n \
+             2 | var ö = é ä;\n   |           ^"
+        );
+    }
+}
@@ -1,124 +1,204 @@
-use std::ops::Deref;
-
-use liblox::tokens::{LiteralTypes, Token};
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Expression {
-    Assign(Assign),
-    Binary(Binary),
-    Call(Call),
-    Get(Get),
-    Grouping(Grouping),
-    Literal(Literal),
-    Logical(Logical),
-    Set(Set),
-    Super(Super),
-    This(This),
-    Unary(Unary),
-    Variable(Variable),
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Assign {
-    pub id: usize,
-    pub name: Token,
-    pub value: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Binary {
-    pub id: usize,
-    pub left: Box<Expression>,
-    pub operator: Token,
-    pub right: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Call {
-    pub id: usize,
-    pub callee: Box<Expression>,
-    pub paren: Token,
-    pub arguments: Vec<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Get {
-    pub id: usize,
-    pub object: Box<Expression>,
-    pub name: Token,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Grouping {
-    pub id: usize,
-    pub expression: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Literal {
-    pub id: usize,
-    pub value: LiteralTypes,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Logical {
-    pub id: usize,
-    pub left: Box<Expression>,
-    pub operator: Token,
-    pub right: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Set {
-    pub id: usize,
-    pub object: Box<Expression>,
-    pub name: Token,
-    pub value: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Super {
-    pub id: usize,
-    pub keyword: Token,
-    pub method: Token,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct This {
-    pub id: usize,
-    pub keyword: Token,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Unary {
-    pub id: usize,
-    pub operator: Token,
-    pub right: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Variable {
-    pub id: usize,
-    pub name: Token,
-}
-
-impl Deref for Expression {
-    type Target = usize;
-
-    fn deref(&self) -> &Self::Target {
-        match self {
-            Expression::Assign(assign) => &assign.id,
-            Expression::Binary(binary) => &binary.id,
-            Expression::Call(call) => &call.id,
-            Expression::Get(get) => &get.id,
-            Expression::Grouping(grouping) => &grouping.id,
-            Expression::Literal(literal) => &literal.id,
-            Expression::Logical(logical) => &logical.id,
-            Expression::Set(set) => &set.id,
-            Expression::Super(super_expr) => &super_expr.id,
-            Expression::This(this_expr) => &this_expr.id,
-            Expression::Unary(unary) => &unary.id,
-            Expression::Variable(variable) => &variable.id,
-        }
-    }
-}
+use std::cell::Cell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use liblox::tokens::{LiteralTypes, Token, TokenType};
+
+use crate::ast::{Ast, ExprId};
+
+/// Identifies an expression, such as for the resolution of the variable it
+/// refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+/// Hands out the ids of expressions. Clones share the count, so expressions
+/// parsed with clones of the same `NodeIds`, like the sources run by one
+/// interpreter, never get the same id.
+#[derive(Debug, Clone, Default)]
+pub struct NodeIds {
+    next: Rc<Cell<usize>>,
+}
+
+impl NodeIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next_id(&self) -> NodeId {
+        let id = self.next.get();
+        self.next.set(id + 1);
+        NodeId(id)
+    }
+}
+
+/// Where the resolver found a local variable: how many environments up from
+/// the current one it is, and its position among that environment's
+/// variables in the order they are defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalSlot {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Assign(Assign),
+    Binary(Binary),
+    Call(Call),
+    Get(Get),
+    Grouping(Grouping),
+    Literal(Literal),
+    Logical(Logical),
+    Set(Set),
+    Super(Super),
+    This(This),
+    Unary(Unary),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assign {
+    pub id: NodeId,
+    pub name: Token,
+    pub value: ExprId,
+    // Set by the resolver for local variables
+    pub resolved: Cell<Option<LocalSlot>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binary {
+    pub id: NodeId,
+    pub left: ExprId,
+    pub operator: Token,
+    pub right: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub id: NodeId,
+    pub callee: ExprId,
+    pub paren: Token,
+    pub arguments: Vec<ExprId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Get {
+    pub id: NodeId,
+    pub object: ExprId,
+    pub name: Token,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grouping {
+    pub id: NodeId,
+    pub expression: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Literal {
+    pub id: NodeId,
+    pub value: LiteralTypes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logical {
+    pub id: NodeId,
+    pub left: ExprId,
+    pub operator: Token,
+    pub right: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub id: NodeId,
+    pub object: ExprId,
+    pub name: Token,
+    pub value: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Super {
+    pub id: NodeId,
+    pub keyword: Token,
+    pub method: Token,
+    // Set by the resolver for local variables
+    pub resolved: Cell<Option<LocalSlot>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct This {
+    pub id: NodeId,
+    pub keyword: Token,
+    // Set by the resolver for local variables
+    pub resolved: Cell<Option<LocalSlot>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unary {
+    pub id: NodeId,
+    pub operator: Token,
+    pub right: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub id: NodeId,
+    pub name: Token,
+    // Set by the resolver for local variables
+    pub resolved: Cell<Option<LocalSlot>>,
+}
+
+impl Assign {
+    /// The sum the variable is assigned if it is added to, as in `a = a + b`.
+    pub fn sum_with_itself<'a>(&self, ast: &'a Ast) -> Option<&'a Binary> {
+        let Expression::Binary(binary) = &ast[self.value] else {
+            return None;
+        };
+        match &ast[binary.left] {
+            Expression::Variable(variable)
+                if binary.operator.token_type == TokenType::Plus
+                    && variable.name.lexeme == self.name.lexeme
+                    && variable.resolved.get() == self.resolved.get() =>
+            {
+                Some(binary)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Expression {
+    /// Where the resolver found the variable the expression refers to, for
+    /// the expressions that refer to one. Expressions left unresolved refer
+    /// to globals.
+    pub fn resolution(&self) -> Option<&Cell<Option<LocalSlot>>> {
+        match self {
+            Expression::Assign(assign) => Some(&assign.resolved),
+            Expression::Super(super_expr) => Some(&super_expr.resolved),
+            Expression::This(this) => Some(&this.resolved),
+            Expression::Variable(variable) => Some(&variable.resolved),
+            _ => None,
+        }
+    }
+}
+
+impl Deref for Expression {
+    type Target = NodeId;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Expression::Assign(assign) => &assign.id,
+            Expression::Binary(binary) => &binary.id,
+            Expression::Call(call) => &call.id,
+            Expression::Get(get) => &get.id,
+            Expression::Grouping(grouping) => &grouping.id,
+            Expression::Literal(literal) => &literal.id,
+            Expression::Logical(logical) => &logical.id,
+            Expression::Set(set) => &set.id,
+            Expression::Super(super_expr) => &super_expr.id,
+            Expression::This(this_expr) => &this_expr.id,
+            Expression::Unary(unary) => &unary.id,
+            Expression::Variable(variable) => &variable.id,
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::any::Any;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::callable::Callable;
+use crate::interpreter::{InterpreterError, Value};
+use crate::stmt::Stmt;
+
+/// Destination for the lines produced by `print` and `printErr()`.
+pub trait OutputSink {
+    fn write_line(&mut self, line: &str);
+}
+
+/// Any `std::io::Write` can serve as output; write errors are ignored just
+/// like a closed stdout would be.
+impl<W: Write + ?Sized> OutputSink for W {
+    fn write_line(&mut self, line: &str) {
+        let _ = writeln!(self, "{}", line);
+    }
+}
+
+/// Time source for the `clock()` and `sleep()` builtins.
+pub trait Clock {
+    /// Seconds elapsed since an arbitrary but fixed epoch.
+    fn now(&self) -> f64;
+
+    /// Blocks for `duration`, waking up early once `interrupt` is set.
+    /// Returns false if the sleep was interrupted.
+    ///
+    /// Threads can't block on wasm32 and std has no clock there, so the
+    /// default returns at once; clocks for the browser implement it
+    /// themselves, as `loxwasm` does.
+    fn sleep(&self, duration: Duration, interrupt: &AtomicBool) -> bool {
+        const SLICE: Duration = Duration::from_millis(10);
+        if cfg!(target_arch = "wasm32") {
+            return !interrupt.load(Ordering::Relaxed);
+        }
+        // A deadline too far out to represent is never reached
+        let deadline = Instant::now().checked_add(duration);
+        loop {
+            if interrupt.load(Ordering::Relaxed) {
+                return false;
+            }
+            let remaining = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => SLICE,
+            };
+            if remaining.is_zero() {
+                return true;
+            }
+            std::thread::sleep(remaining.min(SLICE));
+        }
+    }
+}
+
+/// Clock backed by the system time, measuring seconds since the Unix epoch.
+/// On wasm32, where std has no clock, it always reads 0 and embedders inject
+/// a clock of their own.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        if cfg!(target_arch = "wasm32") {
+            return 0.0;
+        }
+        let now = std::time::SystemTime::now();
+        let duration = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+        duration.as_secs_f64()
+    }
+}
+
+/// Callbacks for observing a running script, for profilers, tracers and
+/// debuggers. Install them with `Interpreter::set_hooks`; every method does
+/// nothing by default.
+pub trait InterpreterHooks {
+    /// Called before each statement is executed.
+    fn on_statement(&mut self, _statement: &Stmt) {}
+
+    /// Called before a function, method or class is called.
+    fn on_call(&mut self, _callable: &Callable, _arguments: &[Value]) {}
+
+    /// Called when a call returns normally.
+    fn on_return(&mut self, _callable: &Callable, _value: &Value) {}
+
+    /// Called once when a runtime error ends the execution.
+    fn on_error(&mut self, _error: &InterpreterError) {}
+}
+
+/// Opaque reference to a Rust resource, such as a file or a database
+/// connection, that natives hand out to Lox code and later take back.
+/// Scripts can only pass handles around and compare them: two handles are
+/// equal when they refer to the same resource.
+#[derive(Clone)]
+pub struct NativeHandle {
+    name: Rc<str>,
+    resource: Rc<dyn Any>,
+}
+
+impl NativeHandle {
+    /// Wraps `resource`; `name` is what `type()` returns for the handle.
+    pub fn new<T: Any>(name: &str, resource: T) -> Self {
+        Self {
+            name: Rc::from(name),
+            resource: Rc::new(resource),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the resource if it has type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.resource.downcast_ref()
+    }
+}
+
+impl fmt::Debug for NativeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeHandle({})", self.name)
+    }
+}
+
+impl fmt::Display for NativeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} handle>", self.name)
+    }
+}
+
+impl PartialEq for NativeHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.resource, &other.resource)
+    }
+}
+
+/// Handle for cancelling a running script from another thread, obtained from
+/// `Interpreter::cancel_token`. The interpreter checks it on every loop
+/// iteration and call and fails with "Execution cancelled.".
+#[derive(Debug, Clone)]
+pub struct CancelToken(pub(crate) Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears a cancellation so the interpreter can run again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
@@ -1,1598 +1,3642 @@
-use crate::callable::{
-    Callable, LoxBuiltinFunctionClock, LoxCallable, LoxDynamicFunction, LoxFunction,
-};
-use crate::class::{get_instance_field, Instance, LoxClass};
-use crate::expression::{
-    Binary, Call, Expression, Get, Grouping, Literal, Logical, Set, Unary, Variable,
-};
-use crate::stmt::Stmt;
-use liblox::tokens::{LiteralTypes, Token, TokenType};
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io::Write;
-use std::rc::Rc;
-
-#[derive(Debug)]
-pub struct InterpreterError {
-    pub message: String,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    Callable(Callable),
-    Instance(Rc<RefCell<Instance>>),
-    Number(f64),
-    String(String),
-    Bool(bool),
-    Nil,
-}
-impl Value {
-    pub fn is_nil(&self) -> bool {
-        matches!(self, Value::Nil)
-    }
-
-    pub fn is_true(&self) -> bool {
-        match self {
-            Value::Bool(value) => *value,
-            Value::Nil => false,
-            _ => true,
-        }
-    }
-}
-
-impl std::fmt::Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Callable(c) => write!(f, "{}", c),
-            Value::Instance(i) => write!(f, "{}", i.borrow().to_string()),
-            Value::Number(n) => write!(f, "{}", n),
-            Value::String(s) => write!(f, "{}", s),
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Nil => write!(f, "nil"),
-        }
-    }
-}
-
-pub enum InterpreterResult {
-    None,
-    Return(Value),
-}
-
-#[derive(Debug, PartialEq)]
-pub struct Environment {
-    // Parent environment for nested scopes
-    enclosing: Option<Rc<RefCell<Environment>>>,
-
-    // HashMap to store variable names and their values
-    values: HashMap<String, Value>,
-}
-impl Environment {
-    pub fn new() -> Self {
-        Environment {
-            enclosing: None,
-            values: HashMap::new(),
-        }
-    }
-
-    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
-        Environment {
-            enclosing: Some(enclosing),
-            values: HashMap::new(),
-        }
-    }
-
-    pub fn deep_clone(&self) -> Self {
-        Self {
-            values: self.values.clone(),
-            enclosing: self
-                .enclosing
-                .as_ref()
-                .map(|env| std::rc::Rc::new(std::cell::RefCell::new(env.borrow().deep_clone()))),
-        }
-    }
-
-    pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
-    }
-
-    pub fn assign(
-        &mut self,
-        name: &Token,
-        value: Value,
-    ) -> Result<InterpreterResult, InterpreterError> {
-        if self.values.contains_key(name.lexeme.as_str()) {
-            self.values.insert(name.lexeme.clone(), value);
-            return Ok(InterpreterResult::None);
-        }
-        match &self.enclosing {
-            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
-            None => Err(InterpreterError {
-                message: format!(
-                    "Undefined variable '{}'.\n[line {}]",
-                    name.lexeme, name.line
-                ),
-            }),
-        }
-    }
-
-    pub fn assign_at(
-        &mut self,
-        name: &Token,
-        value: Value,
-        depth: usize,
-    ) -> Result<InterpreterResult, InterpreterError> {
-        if depth == 0 {
-            return self.assign(name, value);
-        }
-        let mut environment = self.enclosing.clone();
-        for _ in 1..depth {
-            if let Some(env) = environment {
-                environment = env.borrow().enclosing.clone();
-            } else {
-                return Err(InterpreterError {
-                    message: format!(
-                        "Undefined variable '{}'.\n[line {}]",
-                        name.lexeme, name.line
-                    ),
-                });
-            }
-        }
-        if let Some(env) = environment {
-            env.borrow_mut().assign(name, value)
-        } else {
-            Err(InterpreterError {
-                message: format!(
-                    "Undefined variable '{}'.\n[line {}]",
-                    name.lexeme, name.line
-                ),
-            })
-        }
-    }
-
-    pub fn get(&self, name: &String) -> Option<Value> {
-        let result = self.values.get(name.as_str());
-
-        if result.is_some() {
-            return Some(result.unwrap().clone());
-        }
-        match &self.enclosing {
-            Some(enclosing) => enclosing.as_ref().borrow().get(name),
-            None => None,
-        }
-    }
-
-    pub fn get_at(&self, name: &String, depth: usize) -> Option<Value> {
-        if depth == 0 {
-            return self.get(name);
-        }
-        let mut environment = self.enclosing.clone();
-        for _ in 1..depth {
-            if let Some(env) = environment {
-                environment = env.borrow().enclosing.clone();
-            } else {
-                return None;
-            }
-        }
-        environment.unwrap().borrow().get(name)
-    }
-}
-
-pub struct Interpreter {
-    // Global environment for variable storage
-    pub globals: Rc<RefCell<Environment>>,
-    // Local variable lookup
-    pub locals: HashMap<usize, usize>,
-    // Environment for variable storage
-    pub environment: Rc<RefCell<Environment>>,
-    // Dedicated output stream for the interpreter
-    pub output: Box<dyn Write>,
-}
-
-impl Interpreter {
-    pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        globals.borrow_mut().define(
-            "clock".to_string(),
-            Value::Callable(Callable::DynamicFunction(LoxDynamicFunction {
-                callable: Rc::new(RefCell::new(Box::new(LoxBuiltinFunctionClock::new()))),
-            })),
-        );
-        Interpreter {
-            globals: Rc::clone(&globals),
-            locals: HashMap::new(),
-            environment: globals,
-            output: Box::new(std::io::stdout()),
-        }
-    }
-
-    pub fn resolve(&mut self, expr: &Expression, depth: usize) {
-        match expr {
-            Expression::Literal(_) => {}
-            _ => {
-                self.locals.insert(**expr, depth);
-            }
-        }
-    }
-
-    pub fn execute(
-        &mut self,
-        statements: &Vec<Stmt>,
-    ) -> Result<InterpreterResult, InterpreterError> {
-        for statement in statements {
-            self.execute_statement(statement)?;
-        }
-        Ok(InterpreterResult::None)
-    }
-
-    fn execute_statement(
-        &mut self,
-        statement: &Stmt,
-    ) -> Result<InterpreterResult, InterpreterError> {
-        match statement {
-            Stmt::Expression(expr_stmt) => {
-                self.expression(&*expr_stmt.expression)?;
-            }
-            Stmt::Function(fun_stmt) => {
-                self.environment.borrow_mut().define(
-                    fun_stmt.name.lexeme.clone(),
-                    Value::Callable(Callable::Function(LoxFunction::new(
-                        fun_stmt.clone(),
-                        self.environment.clone(),
-                        false,
-                    ))),
-                );
-            }
-            Stmt::Return(return_stmt) => {
-                if let Some(value) = &return_stmt.value {
-                    let return_value = self.expression(&*value)?;
-                    return Ok(InterpreterResult::Return(return_value));
-                } else {
-                    return Ok(InterpreterResult::Return(Value::Nil));
-                }
-            }
-            Stmt::If(if_stmt) => {
-                let condition = self.expression(&*if_stmt.condition)?;
-                if condition.is_true() {
-                    return self.execute_statement(&*if_stmt.then_branch);
-                } else if let Some(else_branch) = &if_stmt.else_branch {
-                    return self.execute_statement(else_branch);
-                }
-            }
-            Stmt::Print(print_stmt) => {
-                let value = self.expression(&*print_stmt.expression)?;
-                writeln!(self.output, "{}", value);
-            }
-            Stmt::Block(block_stmt) => {
-                return self.execute_block(&block_stmt.statements, self.environment.clone());
-            }
-            Stmt::Var(var_stmt) => {
-                if let Some(initializer) = &var_stmt.initializer {
-                    let value = self.expression(&*initializer)?;
-                    self.environment
-                        .borrow_mut()
-                        .define(var_stmt.name.lexeme.clone(), value.clone());
-                } else {
-                    self.environment
-                        .borrow_mut()
-                        .define(var_stmt.name.lexeme.clone(), Value::Nil);
-                }
-            }
-            Stmt::While(while_stmt) => {
-                while self.expression(&*while_stmt.condition)?.is_true() {
-                    match self.execute_statement(&*while_stmt.body) {
-                        Err(e) => {
-                            return Err(e);
-                        }
-                        Ok(InterpreterResult::Return(value)) => {
-                            return Ok(InterpreterResult::Return(value));
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            Stmt::Class(class_stmt) => {
-                let mut superclass: Option<Rc<RefCell<LoxClass>>> = None;
-                if let Some(super_class) = &class_stmt.superclass {
-                    let superclass_value =
-                        self.lookup_variable(&super_class.name, super_class.as_ref())?;
-                    if let Value::Callable(Callable::Class(class)) = superclass_value {
-                        superclass = Some(class.clone());
-                    } else {
-                        return Err(InterpreterError {
-                            message: format!(
-                                "Superclass must be a class.\n[line {}]",
-                                super_class.name.line
-                            ),
-                        });
-                    }
-                }
-
-                self.environment
-                    .borrow_mut()
-                    .define(class_stmt.name.lexeme.clone(), Value::Nil);
-
-                if superclass.is_some() {
-                    let new_environment = Environment::with_enclosing(self.environment.clone());
-                    self.environment = Rc::new(RefCell::new(new_environment));
-
-                    self.environment.borrow_mut().define(
-                        "super".to_string(),
-                        Value::Callable(Callable::Class(superclass.clone().unwrap())),
-                    );
-                }
-
-                let mut methods = HashMap::new();
-                for method in &class_stmt.methods {
-                    let is_initializer = method.name.lexeme == "init";
-                    methods.insert(
-                        method.name.lexeme.clone(),
-                        Box::new(LoxFunction::new(
-                            method.clone(),
-                            self.environment.clone(),
-                            is_initializer,
-                        )),
-                    );
-                }
-
-                let class = Rc::new(RefCell::new(LoxClass::new(
-                    class_stmt.name.lexeme.clone(),
-                    superclass.clone(),
-                    methods,
-                )));
-
-                if superclass.is_some() {
-                    let enclosing = self.environment.as_ref().borrow().enclosing.clone();
-                    self.environment = enclosing.unwrap();
-                }
-
-                self.environment
-                    .borrow_mut()
-                    .assign(&class_stmt.name, Value::Callable(Callable::Class(class)))?;
-            }
-        }
-        Ok(InterpreterResult::None)
-    }
-
-    pub fn execute_block(
-        &mut self,
-        statements: &Vec<Stmt>,
-        environment: Rc<RefCell<Environment>>,
-    ) -> Result<InterpreterResult, InterpreterError> {
-        let previous = Rc::clone(&self.environment);
-        let new_environment = Environment::with_enclosing(environment);
-        self.environment = Rc::new(RefCell::new(new_environment));
-
-        let mut result = InterpreterResult::None;
-        for statement in statements {
-            match self.execute_statement(statement) {
-                Err(e) => {
-                    self.environment = previous;
-                    return Err(e);
-                }
-                Ok(InterpreterResult::Return(value)) => {
-                    result = InterpreterResult::Return(value);
-                    break;
-                }
-                _ => {}
-            }
-        }
-        self.environment = previous;
-        Ok(result)
-    }
-
-    fn expression(&mut self, expression: &Expression) -> Result<Value, InterpreterError> {
-        match expression {
-            Expression::Binary(binary) => self.binary(binary),
-            Expression::Call(call) => self.call(call),
-            Expression::Get(get) => self.get(get),
-            Expression::Grouping(grouping) => self.grouping(grouping),
-            Expression::Literal(literal) => self.literal(literal),
-            Expression::Logical(logical) => self.logical(logical),
-            Expression::Set(set) => self.set(set),
-            Expression::Super(super_expr) => {
-                let depth = self.locals.get(&super_expr.id);
-                if depth.is_none() {
-                    return Err(InterpreterError {
-                        message: format!(
-                            "Cannot use 'super' outside of a class.\n[line {}]",
-                            super_expr.keyword.line
-                        ),
-                    });
-                }
-                let super_value = self
-                    .environment
-                    .borrow()
-                    .get_at(&"super".to_string(), *depth.unwrap());
-                if super_value.is_none() {
-                    return Err(InterpreterError {
-                        message: format!(
-                            "Undefined variable '{}'.\n[line {}]",
-                            super_expr.keyword.lexeme, super_expr.keyword.line
-                        ),
-                    });
-                }
-                let this_value = self
-                    .environment
-                    .borrow()
-                    .get_at(&"this".to_string(), *depth.unwrap() - 1);
-                if let Some(Value::Instance(instance)) = this_value {
-                    if let Value::Callable(Callable::Class(super_class)) = super_value.unwrap() {
-                        let method = super_class.borrow().find_method(&super_expr.method.lexeme);
-                        if method.is_none() {
-                            return Err(InterpreterError {
-                                message: format!(
-                                    "Undefined property '{}'.\n[line {}]",
-                                    super_expr.method.lexeme, super_expr.method.line
-                                ),
-                            });
-                        }
-
-                        return Ok(Value::Callable(Callable::Function(
-                            method.unwrap().bind(&instance),
-                        )));
-                    }
-                }
-                return Err(InterpreterError {
-                    message: format!(
-                        "Superclass must be a class.\n[line {}]",
-                        super_expr.keyword.line
-                    ),
-                });
-            }
-            Expression::This(this) => self.lookup_variable(
-                &this.keyword,
-                &Variable {
-                    id: this.id,
-                    name: this.keyword.clone(),
-                },
-            ),
-            Expression::Unary(unary) => self.unary(unary),
-            Expression::Variable(variable) => self.lookup_variable(&variable.name, variable),
-            Expression::Assign(assign) => {
-                let value = self.expression(&*assign.value)?;
-
-                self.locals
-                    .get(&assign.id)
-                    .map(|depth| {
-                        self.environment
-                            .borrow_mut()
-                            .assign_at(&assign.name, value.clone(), *depth)
-                    })
-                    .unwrap_or_else(|| {
-                        self.globals
-                            .borrow_mut()
-                            .assign(&assign.name, value.clone())
-                    })?;
-                Ok(value)
-            }
-        }
-    }
-
-    fn lookup_variable(
-        &mut self,
-        name: &Token,
-        variable: &Variable,
-    ) -> Result<Value, InterpreterError> {
-        if let Some(depth) = self.locals.get(&variable.id) {
-            return self
-                .environment
-                .borrow()
-                .get_at(&name.lexeme, *depth)
-                .ok_or(InterpreterError {
-                    message: format!(
-                        "Undefined variable '{}'.\n[line {}]",
-                        name.lexeme, name.line
-                    ),
-                });
-        }
-        self.globals
-            .borrow()
-            .get(&name.lexeme)
-            .ok_or(InterpreterError {
-                message: format!(
-                    "Undefined variable '{}'.\n[line {}]",
-                    name.lexeme, name.line
-                ),
-            })
-    }
-
-    fn call(&mut self, call: &Call) -> Result<Value, InterpreterError> {
-        let callee = self.expression(&*call.callee)?;
-        if let Value::Callable(callable) = &callee {
-            match callable {
-                Callable::DynamicFunction(func) => {
-                    let mut arguments = Vec::new();
-                    for arg in &call.arguments {
-                        arguments.push(self.expression(arg)?);
-                    }
-                    let arity = func.callable.borrow().as_ref().arity();
-                    if arguments.len() != arity {
-                        return Err(InterpreterError {
-                            message: format!(
-                                "Expected {} arguments but got {}.\n[line {}]",
-                                arity,
-                                arguments.len(),
-                                call.paren.line
-                            ),
-                        });
-                    }
-                    func.callable.borrow().as_ref().call(self, arguments)
-                }
-                Callable::Function(func) => {
-                    let mut arguments = Vec::new();
-                    for arg in &call.arguments {
-                        arguments.push(self.expression(arg)?);
-                    }
-                    if arguments.len() != func.arity() {
-                        return Err(InterpreterError {
-                            message: format!(
-                                "Expected {} arguments but got {}.\n[line {}]",
-                                func.arity(),
-                                arguments.len(),
-                                call.paren.line
-                            ),
-                        });
-                    }
-                    func.call(self, arguments)
-                }
-                Callable::Class(class) => {
-                    let mut arguments = Vec::new();
-                    for arg in &call.arguments {
-                        arguments.push(self.expression(arg)?);
-                    }
-                    if arguments.len() != class.arity() {
-                        return Err(InterpreterError {
-                            message: format!(
-                                "Expected {} arguments but got {}.\n[line {}]",
-                                class.arity(),
-                                arguments.len(),
-                                call.paren.line
-                            ),
-                        });
-                    }
-                    class.call(self, arguments)
-                }
-            }
-        } else {
-            return Err(InterpreterError {
-                message: format!(
-                    "Can only call functions and classes.\n[line {}]",
-                    call.paren.line
-                ),
-            });
-        }
-    }
-
-    fn get(&mut self, get: &Get) -> Result<Value, InterpreterError> {
-        let object = self.expression(&*get.object)?;
-        match object {
-            Value::Instance(instance) => get_instance_field(&instance, &get.name),
-            _ => Err(InterpreterError {
-                message: format!("Only instances have properties.\n[line {}]", get.name.line),
-            }),
-        }
-    }
-
-    fn set(&mut self, set: &Set) -> Result<Value, InterpreterError> {
-        let object = &self.expression(&*set.object)?;
-
-        match object {
-            Value::Instance(instance) => {
-                let value = self.expression(&*set.value)?;
-                instance
-                    .borrow_mut()
-                    .set(set.name.lexeme.clone(), value.clone());
-                Ok(value)
-            }
-            _ => Err(InterpreterError {
-                message: format!("Only instances have fields.\n[line {}]", set.name.line),
-            }),
-        }
-    }
-
-    fn grouping(&mut self, grouping: &Grouping) -> Result<Value, InterpreterError> {
-        self.expression(&*grouping.expression)
-    }
-
-    fn logical(&mut self, logical: &Logical) -> Result<Value, InterpreterError> {
-        let left = self.expression(&*logical.left)?;
-        if logical.operator.token_type == TokenType::Or {
-            if left.is_true() {
-                return Ok(left);
-            }
-        } else {
-            if !left.is_true() {
-                return Ok(left);
-            }
-        }
-        self.expression(&*logical.right)
-    }
-
-    pub fn literal(&self, literal: &Literal) -> Result<Value, InterpreterError> {
-        match &literal.value {
-            LiteralTypes::String(value) => Ok(Value::String(value.clone())),
-            LiteralTypes::Number(value) => Ok(Value::Number(*value)),
-            LiteralTypes::Bool(value) => Ok(Value::Bool(*value)),
-            LiteralTypes::Nil => Ok(Value::Nil),
-        }
-    }
-
-    fn unary(&mut self, unary: &Unary) -> Result<Value, InterpreterError> {
-        let right = self.expression(&*unary.right)?;
-
-        match unary.operator.token_type {
-            TokenType::Bang => match right {
-                Value::Bool(value) => Ok(Value::Bool(!value)),
-                Value::Nil => Ok(Value::Bool(true)),
-                _ => Ok(Value::Bool(false)),
-            },
-            TokenType::Minus => match right {
-                Value::Number(value) => Ok(Value::Number(-value)),
-                _ => Err(InterpreterError {
-                    message: format!("Operand must be a number.\n[line {}]", unary.operator.line),
-                }),
-            },
-            _ => Err(InterpreterError {
-                message: format!(
-                    "Invalid operator '{}'.\n[line {}]",
-                    unary.operator.lexeme, unary.operator.line
-                ),
-            }),
-        }
-    }
-
-    fn binary(&mut self, binary: &Binary) -> Result<Value, InterpreterError> {
-        let left = self.expression(&*binary.left)?;
-        let right = self.expression(&*binary.right)?;
-
-        match binary.operator.token_type {
-            TokenType::Minus => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left - right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::Slash => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left / right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::Star => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left * right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::Plus => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
-                (Value::String(left), Value::String(right)) => {
-                    Ok(Value::String(format!("{}{}", left, right)))
-                }
-                _ => Err(InterpreterError {
-                    message: format!(
-                        "Operands must be two numbers or two strings.\n[line {}]",
-                        binary.operator.line
-                    ),
-                }),
-            },
-            TokenType::Greater => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left > right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::GreaterEqual => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left >= right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::Less => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left < right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::LessEqual => match (left, right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left <= right)),
-                _ => Err(InterpreterError {
-                    message: format!("Operands must be numbers.\n[line {}]", binary.operator.line),
-                }),
-            },
-            TokenType::BangEqual => match (left, right) {
-                (Value::Nil, Value::Nil) => Ok(Value::Bool(false)),
-                (Value::Bool(left), Value::Bool(right)) => Ok(Value::Bool(left != right)),
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left != right)),
-                (Value::String(left), Value::String(right)) => Ok(Value::Bool(left != right)),
-                (Value::Callable(left), Value::Callable(right)) => Ok(Value::Bool(left != right)),
-                _ => Ok(Value::Bool(true)),
-            },
-            TokenType::EqualEqual => match (left, right) {
-                (Value::Nil, Value::Nil) => Ok(Value::Bool(true)),
-                (Value::Bool(left), Value::Bool(right)) => Ok(Value::Bool(left == right)),
-                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left == right)),
-                (Value::String(left), Value::String(right)) => Ok(Value::Bool(left == right)),
-                (Value::Callable(left), Value::Callable(right)) => Ok(Value::Bool(left == right)),
-                _ => Ok(Value::Bool(false)),
-            },
-            _ => Err(InterpreterError {
-                message: "Invalid operator.".to_string(),
-            }),
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::parser::Parser;
-    use crate::resolver::Resolver;
-    use crate::stmt::PrintStmt;
-    use liblox::scanner::Scanner;
-    use liblox::tokens::Token;
-    use std::io;
-    use std::io::Write;
-
-    // Mocking the output stream for testing
-    struct VecWriter(Rc<RefCell<Vec<u8>>>);
-
-    impl Write for VecWriter {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            self.0.borrow_mut().extend_from_slice(buf);
-            Ok(buf.len())
-        }
-
-        fn flush(&mut self) -> io::Result<()> {
-            Ok(())
-        }
-    }
-
-    fn run(source: String) -> Result<String, InterpreterError> {
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens().clone();
-        assert!(!scanner.had_error);
-
-        let mut parser = Parser::new(tokens);
-        let parse_result = parser.parse();
-        assert!(parse_result.is_ok());
-
-        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        let mut interpreter = Interpreter {
-            globals: Rc::clone(&globals),
-            locals: HashMap::new(),
-            environment: globals,
-            output: Box::new(VecWriter(Rc::clone(&output))),
-        };
-
-        let mut resolver = Resolver::new(&mut interpreter);
-        let resolver_result = resolver.resolve_stmts(parse_result.as_ref().unwrap());
-        assert!(resolver_result.is_ok());
-
-        let result = interpreter.execute(parse_result.as_ref().unwrap());
-
-        match result {
-            Ok(_) => Ok(String::from_utf8_lossy(&output.borrow()).to_string()),
-            Err(err) => Err(err),
-        }
-    }
-
-    #[test]
-    fn test_interpret_sum() {
-        let expression = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Literal(Literal {
-                id: 1,
-                value: LiteralTypes::Number(5.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Plus,
-                lexeme: "+".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 2,
-                value: LiteralTypes::Number(3.0),
-            })),
-        });
-
-        let mut interpreter = Interpreter::new();
-        let result = interpreter.expression(&expression).unwrap();
-        assert_eq!(result, Value::Number(8.0));
-    }
-
-    #[test]
-    fn test_interpret_subtraction() {
-        let expression = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Literal(Literal {
-                id: 1,
-                value: LiteralTypes::Number(5.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Minus,
-                lexeme: "-".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 2,
-                value: LiteralTypes::Number(3.0),
-            })),
-        });
-
-        let mut interpreter = Interpreter::new();
-        let result = interpreter.expression(&expression).unwrap();
-        assert_eq!(result, Value::Number(2.0));
-    }
-
-    #[test]
-    fn test_interpret_multiplication() {
-        let expression = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Literal(Literal {
-                id: 1,
-                value: LiteralTypes::Number(5.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Star,
-                lexeme: "*".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 2,
-                value: LiteralTypes::Number(3.0),
-            })),
-        });
-
-        let mut interpreter = Interpreter::new();
-        let result = interpreter.expression(&expression).unwrap();
-        assert_eq!(result, Value::Number(15.0));
-    }
-    #[test]
-    fn test_interpret_division() {
-        let expression = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Literal(Literal {
-                id: 1,
-                value: LiteralTypes::Number(6.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Slash,
-                lexeme: "/".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 2,
-                value: LiteralTypes::Number(3.0),
-            })),
-        });
-
-        let mut interpreter = Interpreter::new();
-        let result = interpreter.expression(&expression).unwrap();
-        assert_eq!(result, Value::Number(2.0));
-    }
-    #[test]
-    fn test_star_before_plus() {
-        let expression = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Binary(Binary {
-                id: 1,
-                left: Box::new(Expression::Literal(Literal {
-                    id: 2,
-                    value: LiteralTypes::Number(5.0),
-                })),
-                operator: Token {
-                    token_type: TokenType::Star,
-                    lexeme: "*".to_string(),
-                    literal: LiteralTypes::Nil,
-                    line: 1,
-                },
-                right: Box::new(Expression::Literal(Literal {
-                    id: 3,
-                    value: LiteralTypes::Number(3.0),
-                })),
-            })),
-            operator: Token {
-                token_type: TokenType::Plus,
-                lexeme: "+".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 4,
-                value: LiteralTypes::Number(2.0),
-            })),
-        });
-
-        let mut interpreter = Interpreter::new();
-        let result = interpreter.expression(&expression).unwrap();
-        assert_eq!(result, Value::Number(17.0));
-    }
-
-    #[test]
-    fn test_print_expression() {
-        let expression = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Literal(Literal {
-                id: 1,
-                value: LiteralTypes::Number(5.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Plus,
-                lexeme: "+".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 2,
-                value: LiteralTypes::Number(3.0),
-            })),
-        });
-
-        let print_stmt = Stmt::Print(PrintStmt {
-            expression: Box::new(expression),
-        });
-        let statements = vec![print_stmt];
-        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        let mut interpreter = Interpreter {
-            globals: Rc::clone(&globals),
-            locals: HashMap::new(),
-            environment: globals,
-            output: Box::new(VecWriter(Rc::clone(&output))),
-        };
-        interpreter.execute(&statements).unwrap();
-        assert_eq!(String::from_utf8_lossy(&output.borrow()), "8\n");
-    }
-
-    #[test]
-    fn test_print_multiple_expressions() {
-        let source = "
-        print \"one\";
-        print true;
-        print 2 + 1;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "one\ntrue\n3\n");
-    }
-
-    #[test]
-    fn test_uninitialized_variable() {
-        let source = "
-        var a;
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "nil\n");
-    }
-
-    #[test]
-    fn test_print_variable() {
-        let source = "
-        var a = 5;
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "5\n");
-    }
-
-    #[test]
-    fn print_redefined_variable() {
-        let source = "
-        var a = 5;
-        print a;
-        var a = 10;
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "5\n10\n");
-    }
-
-    #[test]
-    fn test_error_undefined_variable() {
-        let source = "
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err().message,
-            "Undefined variable 'a'.\n[line 2]"
-        );
-    }
-
-    #[test]
-    fn test_expression_from_variables() {
-        let source = "
-        var a = 5;
-        var b = 3;
-        print a + b;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "8\n");
-    }
-
-    #[test]
-    fn test_assignment() {
-        let source = "
-        var a = 5;
-        print a;
-        a = 10;
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "5\n10\n");
-    }
-
-    #[test]
-    fn test_variable_used_outside_scope() {
-        let source = "
-        {
-            var a = 5;
-            print a;
-        }
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err().message,
-            "Undefined variable 'a'.\n[line 6]"
-        );
-    }
-
-    #[test]
-    fn test_variable_shadowing() {
-        let source = "
-        var a = 5;
-        {
-            var a = 10;
-            print a;
-        }
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "10\n5\n");
-    }
-
-    #[test]
-    fn test_variables_from_inner_scope() {
-        let source = "
-        var a = 5;
-        {
-            var b = 10;
-            print a + b;
-        }
-        print a;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "15\n5\n");
-    }
-
-    #[test]
-    fn test_variables_from_three_scopes() {
-        let source = "
-        var a = \"global a\";
-        var b = \"global b\";
-        var c = \"global c\";
-        {
-            var a = \"outer a\";
-            var b = \"outer b\";
-            {
-                var a = \"inner a\";
-                print a;
-                print b;
-                print c;
-            }
-            print a;
-            print b;
-            print c;
-        }
-        print a;
-        print b;
-        print c;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "inner a\nouter b\nglobal c\nouter a\nouter b\nglobal c\nglobal a\nglobal b\nglobal c\n");
-    }
-
-    #[test]
-    fn test_if_statement_true() {
-        let source = "
-        if (true) {
-            print \"True\";
-        } else {
-            print \"False\";
-        }
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "True\n");
-    }
-
-    #[test]
-    fn test_if_statement_false() {
-        let source = "
-        if (false) {
-            print \"True\";
-        } else {
-            print \"False\";
-        }
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "False\n");
-    }
-
-    #[test]
-    fn test_if_statement_expression() {
-        let source = "
-        if (3 < 2) {
-            print \"True\";
-        } else {
-            print \"False\";
-        }
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "False\n");
-    }
-
-    #[test]
-    fn test_if_statement_zero_is_true() {
-        let source = "
-        if (0) {
-            print \"True\";
-        } else {
-            print \"False\";
-        }
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "True\n");
-    }
-
-    #[test]
-    fn test_logical_or() {
-        let source = "
-        print true or false;
-        print false or true;
-        print false or false;
-        print true or true;
-        print 0 or 1;
-        print 0 or false;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "true\ntrue\nfalse\ntrue\n0\n0\n");
-    }
-
-    #[test]
-    fn test_while_statement() {
-        let source = "
-        var i = 0;
-        while (i < 5) {
-            print i;
-            i = i + 1;
-        }
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "0\n1\n2\n3\n4\n");
-    }
-
-    #[test]
-    fn test_for_statement() {
-        let source = "
-        for (var i = 0; i < 5; i = i + 1) {
-            print i;
-        }
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "0\n1\n2\n3\n4\n");
-    }
-
-    #[test]
-    fn test_function_definition_and_call() {
-        let source = "
-        fun greet() {
-            print \"Hello, World!\";
-        }
-        greet();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Hello, World!\n");
-    }
-
-    #[test]
-    fn test_function_definition_and_call_with_param() {
-        let source = "
-        fun greet(name) {
-            print \"Hello, \" + name + \"!\";
-        }
-        greet(\"World\");
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Hello, World!\n");
-    }
-
-    #[test]
-    fn test_function_definition_and_call_with_return() {
-        let source = "
-        fun greet() {
-            return \"Hello, World!\";
-        }
-        print greet();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Hello, World!\n");
-    }
-
-    #[test]
-    fn test_function_definition_and_call_with_return_from_loop() {
-        let source = "
-        fun bar() {
-            for (var i = 0;; i = i + 1) {
-                print i;
-                if (i >= 2) return;
-            }
-        }
-        bar();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "0\n1\n2\n");
-    }
-
-    #[test]
-    fn test_function_definition_and_call_with_multiple_params() {
-        let source = "
-        fun add(a, b) {
-            return a + b;
-        }
-        print add(5, 3);
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "8\n");
-    }
-
-    #[test]
-    fn test_recursion() {
-        let source = "
-        fun factorial(n) {
-            if (n == 0) {
-                return 1;
-            }
-            return n * factorial(n - 1);
-        }
-        print factorial(5);
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "120\n");
-    }
-
-    #[test]
-    fn test_fibonacci() {
-        let source = "
-        fun fib(n) {
-            if (n <= 1) return n;
-            return fib(n - 2) + fib(n - 1);
-        }
-        print fib(8);
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "21\n");
-    }
-
-    #[test]
-    fn test_function_object_with_closure() {
-        let source = "
-        fun makeCounter() {
-            var i = 0;
-            fun count() {
-                i = i + 1;
-                return i;
-            }
-            return count;
-        }
-        var counter = makeCounter();
-        print counter();
-        print counter();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "1\n2\n");
-    }
-
-    #[test]
-    fn test_function_object_with_closure_and_outer_variable() {
-        let source = "
-        var outerVar = 10;
-        fun makeCounter() {
-            var i = 0;
-            fun count() {
-                i = i + 1;
-                return i + outerVar;
-            }
-            return count;
-        }
-        var counter = makeCounter();
-        print counter();
-        print counter();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "11\n12\n");
-    }
-
-    #[test]
-    fn test_class_declaration() {
-        let source = "
-        class DevonshireCream {
-            serveOn() {
-                return \"Scones\";
-            }
-        }
-        print DevonshireCream;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "DevonshireCream\n");
-    }
-
-    #[test]
-    fn test_class_instance() {
-        let source = "
-        class Bagel {}
-        var bagel = Bagel();
-        print bagel;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Bagel instance\n");
-    }
-
-    #[test]
-    fn test_class_instance_fields() {
-        let source = "
-        class Bagel {}
-        var bagel = Bagel();
-        bagel.flavor = \"Sesame\";
-        print bagel.flavor;
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Sesame\n");
-    }
-
-    #[test]
-    fn test_class_method() {
-        let source = "
-        class Bacon {
-            eat() {
-                print \"Crunch crunch crunch!\";
-            }
-        }
-        Bacon().eat();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Crunch crunch crunch!\n");
-    }
-
-    #[test]
-    fn test_class_instance_print_this() {
-        let source = "
-        class Egotist {
-          speak() {
-            print this;
-          }
-        }
-
-        var method = Egotist().speak;
-        method();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Egotist instance\n");
-    }
-
-    #[test]
-    fn test_class_instance_field() {
-        let source = "
-        class Cake {
-          taste() {
-            var adjective = \"delicious\";
-            print \"The \" + this.flavor + \" cake is \" + adjective + \"!\";
-          }
-        }
-
-        var cake = Cake();
-        cake.flavor = \"German chocolate\";
-        cake.taste();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "The German chocolate cake is delicious!\n");
-    }
-
-    #[test]
-    fn test_class_instance_method_closure() {
-        let source = "
-        class Thing {
-          getCallback() {
-            fun localFunction() {
-              print this;
-            }
-
-            return localFunction;
-          }
-        }
-
-        var callback = Thing().getCallback();
-        callback();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Thing instance\n");
-    }
-
-    #[test]
-    fn test_class_instance_init() {
-        let source = "
-        class Foo {
-          init() {
-            print this;
-          }
-        }
-
-        var foo = Foo();
-        print foo.init();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(
-            result.unwrap(),
-            "Foo instance\nFoo instance\nFoo instance\n"
-        );
-    }
-
-    #[test]
-    fn test_class_inheritance_method_call() {
-        let source = "
-        class Doughnut {
-          cook() {
-            print \"Fry until golden brown.\";
-          }
-        }
-
-        class BostonCream < Doughnut {}
-
-        BostonCream().cook();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Fry until golden brown.\n");
-    }
-
-    #[test]
-    fn test_class_inheritance_superclass_method_call() {
-        let source = "
-        class Doughnut {
-          cook() {
-            print \"Fry until golden brown.\";
-          }
-        }
-
-        class BostonCream < Doughnut {
-          cook() {
-            super.cook();
-            print \"Pipe full of custard and coat with chocolate.\";
-          }
-        }
-
-        BostonCream().cook();
-        "
-        .to_string();
-
-        let result = run(source);
-        assert!(result.is_ok());
-        assert_eq!(
-            result.unwrap(),
-            "Fry until golden brown.\nPipe full of custard and coat with chocolate.\n"
-        );
-    }
-}
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::callable::{
+    Callable, LoxBuiltinFunctionAllocations, LoxBuiltinFunctionAppendFile, LoxBuiltinFunctionArgs,
+    LoxBuiltinFunctionArray, LoxBuiltinFunctionAssert, LoxBuiltinFunctionCharAt,
+    LoxBuiltinFunctionChr, LoxBuiltinFunctionClock, LoxBuiltinFunctionExec,
+    LoxBuiltinFunctionExitCode, LoxBuiltinFunctionFilter, LoxBuiltinFunctionGet,
+    LoxBuiltinFunctionInput, LoxBuiltinFunctionJoin, LoxBuiltinFunctionJsonParse,
+    LoxBuiltinFunctionJsonStringify, LoxBuiltinFunctionLen, LoxBuiltinFunctionMap,
+    LoxBuiltinFunctionNum, LoxBuiltinFunctionObjectCount, LoxBuiltinFunctionOrd,
+    LoxBuiltinFunctionPrintErr, LoxBuiltinFunctionPush, LoxBuiltinFunctionReadFile,
+    LoxBuiltinFunctionReduce, LoxBuiltinFunctionRegexFindAll, LoxBuiltinFunctionRegexMatch,
+    LoxBuiltinFunctionRegexReplace, LoxBuiltinFunctionSet, LoxBuiltinFunctionSleep,
+    LoxBuiltinFunctionSort, LoxBuiltinFunctionSplit, LoxBuiltinFunctionStr, LoxBuiltinFunctionType,
+    LoxBuiltinFunctionWriteFile, LoxCallable, LoxDynamicFunction, LoxFunction, LoxNativeFunction,
+};
+use crate::class::{get_instance_field, Instance, InstanceFields, LoxClass, NativeClass};
+use crate::closures::{self, CompiledStmt};
+use crate::convert::{FromLox, ToLox};
+use crate::expression::{
+    Assign, Binary, Call, Expression, Get, Grouping, Literal, LocalSlot, Logical, NodeIds, Set,
+    Super, Unary,
+};
+use crate::host::{CancelToken, Clock, InterpreterHooks, NativeHandle, OutputSink, SystemClock};
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::stmt::{FunctionStmt, Stmt};
+use crate::LoxError;
+use liblox::codes::ErrorCode;
+use liblox::memory;
+use liblox::scanner::Scanner;
+use liblox::tokens::{LiteralTypes, Token, TokenType};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const PRELUDE: &str = include_str!("prelude.lox");
+
+const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+// Environments kept for reuse at most, which is enough for the calls and
+// blocks nested at any one time in most scripts
+const MAX_POOLED_ENVIRONMENTS: usize = 256;
+
+#[derive(Debug)]
+pub struct InterpreterError {
+    pub code: ErrorCode,
+    pub message: String,
+    // Line of the code that failed, unknown for errors outside of scripts
+    pub line: Option<i32>,
+    // Byte range of the token that failed in its source
+    pub span: Option<Range<usize>>,
+}
+
+/// Formats like jlox, with the line after the message.
+impl std::fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}\n[line {}]", self.message, line),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for InterpreterError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Callable(Callable),
+    Instance(Rc<RefCell<Instance>>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Native(NativeHandle),
+    Number(f64),
+    // Shared, so copies are cheap and equal strings that are the same
+    // allocation compare without looking at their contents. Concatenation
+    // appends to strings nothing else refers to, see Interpreter::assign_sum
+    String(Rc<String>),
+    Bool(bool),
+    Nil,
+}
+impl Value {
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn is_true(&self) -> bool {
+        match self {
+            Value::Bool(value) => *value,
+            Value::Nil => false,
+            _ => true,
+        }
+    }
+
+    /// Returns the name of the value's type, or its class name for
+    /// instances, as returned by the type() builtin.
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::Number(_) => "number".to_string(),
+            Value::String(_) => "string".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::Nil => "nil".to_string(),
+            Value::Callable(Callable::Class(_)) => "class".to_string(),
+            Value::Callable(_) => "function".to_string(),
+            Value::Instance(instance) => instance.borrow().class.borrow().name.clone(),
+            Value::Array(_) => "array".to_string(),
+            Value::Native(handle) => handle.name().to_string(),
+        }
+    }
+
+    /// Describes the value's type in more detail than `type_name`, with the
+    /// parameters of functions and the superclass of classes.
+    pub fn describe_type(&self) -> String {
+        match self {
+            Value::Callable(Callable::Function(function)) => {
+                let declaration = &function.declaration;
+                let params: Vec<&str> = declaration
+                    .params
+                    .iter()
+                    .map(|param| param.lexeme.as_str())
+                    .collect();
+                format!("fun {}({})", declaration.name.lexeme, params.join(", "))
+            }
+            Value::Callable(callable @ Callable::DynamicFunction(_)) => {
+                format!("native fun({})", vec!["_"; callable.arity()].join(", "))
+            }
+            Value::Callable(Callable::Class(class)) => {
+                let class = class.borrow();
+                match &class.superclass {
+                    Some(superclass) => {
+                        format!("class {} < {}", class.name, superclass.borrow().name)
+                    }
+                    None => format!("class {}", class.name),
+                }
+            }
+            Value::Instance(instance) => {
+                format!("instance of {}", instance.borrow().class.borrow().name)
+            }
+            _ => self.type_name(),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Callable(c) => write!(f, "{}", c),
+            Value::Instance(i) => write!(f, "{}", i.borrow().to_string()),
+            Value::Array(a) => write_array(f, a, &mut Vec::new()),
+            Value::Native(handle) => write!(f, "{}", handle),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+// `shown` holds the arrays being written, so that an array that contains
+// itself is cut short as `[...]`
+fn write_array(
+    f: &mut std::fmt::Formatter<'_>,
+    array: &Rc<RefCell<Vec<Value>>>,
+    shown: &mut Vec<usize>,
+) -> std::fmt::Result {
+    let pointer = Rc::as_ptr(array) as usize;
+    if shown.contains(&pointer) {
+        return write!(f, "[...]");
+    }
+    shown.push(pointer);
+    write!(f, "[")?;
+    for (i, element) in array.borrow().iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        match element {
+            Value::Array(inner) => write_array(f, inner, shown)?,
+            element => write!(f, "{}", element)?,
+        }
+    }
+    shown.pop();
+    write!(f, "]")
+}
+
+/// How the interpreter runs statements, see `Interpreter::set_engine`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Walks the syntax tree.
+    #[default]
+    TreeWalker,
+    /// Compiles the syntax tree into Rust closures before running them.
+    Closures,
+    /// Runs like `Closures`, and compiles functions that are called often
+    /// and only compute with numbers to native code.
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+pub enum InterpreterResult {
+    None,
+    Return(Value),
+    // A function call in tail position, which the calling function makes
+    // once its own frame is gone
+    TailCall(LoxFunction, Vec<Value>),
+}
+
+// A running for-in loop. Arrays are iterated directly, other values with
+// their iterator protocol of iter(), done() and next().
+pub(crate) enum Iteration {
+    // The array and the index of the next element
+    Array(Rc<RefCell<Vec<Value>>>, usize),
+    Iterator(Value),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Environment {
+    // Parent environment for nested scopes
+    enclosing: Option<Rc<RefCell<Environment>>>,
+
+    // Values of the variables of a local scope, in the slots the resolver
+    // gave them, which follow the order the variables are defined in
+    slots: Vec<Value>,
+
+    // Values of the global variables by name, as only globals aren't resolved
+    values: HashMap<String, Value>,
+}
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            enclosing: None,
+            slots: Vec::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            enclosing: Some(enclosing),
+            slots: Vec::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Defines a variable, which in a local scope takes the next slot.
+    pub fn define(&mut self, name: String, value: Value) {
+        if self.enclosing.is_some() {
+            self.slots.push(value);
+        } else {
+            self.values.insert(name, value);
+        }
+    }
+
+    /// Assigns a global variable.
+    pub fn assign(
+        &mut self,
+        name: &Token,
+        value: Value,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        if self.values.contains_key(name.lexeme.as_str()) {
+            self.values.insert(name.lexeme.clone(), value);
+            return Ok(InterpreterResult::None);
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(InterpreterError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Undefined variable '{}'.", name.lexeme),
+                line: Some(name.line),
+                span: name.span.clone(),
+            }),
+        }
+    }
+
+    /// Assigns the local variable in `slot` of the environment `depth`
+    /// scopes up.
+    pub fn assign_at(
+        &mut self,
+        name: &Token,
+        value: Value,
+        depth: usize,
+        slot: usize,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        let undefined = || InterpreterError {
+            code: ErrorCode::UndefinedVariable,
+            message: format!("Undefined variable '{}'.", name.lexeme),
+            line: Some(name.line),
+            span: name.span.clone(),
+        };
+        if depth == 0 {
+            *self.slots.get_mut(slot).ok_or_else(undefined)? = value;
+            return Ok(InterpreterResult::None);
+        }
+        let environment = self.ancestor(depth).ok_or_else(undefined)?;
+        let mut environment = environment.borrow_mut();
+        *environment.slots.get_mut(slot).ok_or_else(undefined)? = value;
+        Ok(InterpreterResult::None)
+    }
+
+    /// Reads a global variable.
+    pub fn get(&self, name: &String) -> Option<Value> {
+        let result = self.values.get(name.as_str());
+
+        if result.is_some() {
+            return Some(result.unwrap().clone());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.as_ref().borrow().get(name),
+            None => None,
+        }
+    }
+
+    /// Names of the global variables, in no particular order.
+    pub fn names(&self) -> Vec<String> {
+        self.values.keys().cloned().collect()
+    }
+
+    /// Reads the local variable in `slot` of the environment `depth` scopes
+    /// up.
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<Value> {
+        if depth == 0 {
+            return self.slots.get(slot).cloned();
+        }
+        let environment = self.ancestor(depth)?;
+        let value = environment.borrow().slots.get(slot).cloned();
+        value
+    }
+
+    // The environment `depth` > 0 scopes up
+    fn ancestor(&self, depth: usize) -> Option<Rc<RefCell<Environment>>> {
+        let mut environment = self.enclosing.clone()?;
+        for _ in 1..depth {
+            let enclosing = environment.borrow().enclosing.clone()?;
+            environment = enclosing;
+        }
+        Some(environment)
+    }
+}
+
+// Nested calls recurse on the Rust stack, which is extended on the heap as
+// needed, so that their depth is only limited by set_max_call_depth()
+#[cfg(not(target_arch = "wasm32"))]
+fn with_stack<T>(f: impl FnOnce() -> T) -> T {
+    // A call gets a new segment when less than the red zone is left
+    const RED_ZONE: usize = 64 * 1024;
+    const SEGMENT_SIZE: usize = 1024 * 1024;
+    stacker::maybe_grow(RED_ZONE, SEGMENT_SIZE, f)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn with_stack<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
+
+fn define_builtin(globals: &Rc<RefCell<Environment>>, name: &str, callable: Box<dyn LoxCallable>) {
+    globals.borrow_mut().define(
+        name.to_string(),
+        Value::Callable(Callable::DynamicFunction(LoxDynamicFunction {
+            callable: Rc::new(RefCell::new(callable)),
+        })),
+    );
+}
+
+pub struct Interpreter {
+    // Global environment for variable storage
+    pub globals: Rc<RefCell<Environment>>,
+    // Ids of the expressions of all sources run, so that no two of them
+    // share an id
+    pub node_ids: NodeIds,
+    // Environment for variable storage
+    pub environment: Rc<RefCell<Environment>>,
+    // Dedicated output stream for the interpreter
+    pub output: Box<dyn OutputSink>,
+    // Error stream written by the printErr() builtin
+    pub error_output: Box<dyn OutputSink>,
+    // Input stream read by the input() builtin
+    pub input: Box<dyn BufRead>,
+    // Time source for the clock() and sleep() builtins
+    pub clock: Box<dyn Clock>,
+    // Set from another thread to cancel the running script, see cancel_token()
+    pub interrupt: Arc<AtomicBool>,
+    // Command line arguments following the script, returned by args()
+    pub script_args: Vec<String>,
+    // Capability flag for the exec() builtin, off unless embedders opt in
+    pub allow_exec: bool,
+    // Capability flag for readFile(), writeFile() and appendFile()
+    pub allow_files: bool,
+    // Exit code of the last command run by exec()
+    pub last_exit_code: Option<i32>,
+    // Statements and expressions evaluated so far, and the optional budget
+    steps: u64,
+    step_limit: Option<u64>,
+    // Calls of Lox functions and classes in progress, and the limit on them
+    call_depth: usize,
+    max_call_depth: Option<usize>,
+    // Bytes the heap may grow by beyond the baseline, see set_memory_limit()
+    memory_limit: Option<usize>,
+    memory_baseline: isize,
+    // Instrumentation installed by the embedder, see set_hooks()
+    hooks: Option<Box<dyn InterpreterHooks>>,
+    // Functions kept by the embedder to be called later, see store_callback()
+    callbacks: HashMap<String, Callable>,
+    // String literals, so each is allocated once however often it's evaluated
+    strings: HashMap<String, Rc<String>>,
+    // How statements are run, and for Engine::Closures the compiled bodies
+    // of the functions called so far, which are kept alive with them
+    engine: Engine,
+    compiled_bodies: HashMap<*const FunctionStmt, (Rc<FunctionStmt>, Rc<[CompiledStmt]>)>,
+    // Environments of finished calls and blocks that nothing refers to
+    // anymore, reused instead of allocating new ones
+    environment_pool: Vec<Rc<RefCell<Environment>>>,
+    // For Engine::Jit, how often functions were called and their native code
+    #[cfg(feature = "jit")]
+    pub(crate) jit: Jit,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Configures an interpreter before creating it, see `Interpreter::builder`.
+pub struct InterpreterBuilder {
+    output: Box<dyn OutputSink>,
+    error_output: Box<dyn OutputSink>,
+    input: Box<dyn BufRead>,
+    clock: Box<dyn Clock>,
+    script_args: Vec<String>,
+    allow_exec: bool,
+    allow_files: bool,
+    prelude: bool,
+}
+
+impl InterpreterBuilder {
+    pub fn output(mut self, output: impl OutputSink + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn error_output(mut self, error_output: impl OutputSink + 'static) -> Self {
+        self.error_output = Box::new(error_output);
+        self
+    }
+
+    /// Sets the stream read by the input() builtin, stdin by default.
+    pub fn input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn script_args(mut self, script_args: Vec<String>) -> Self {
+        self.script_args = script_args;
+        self
+    }
+
+    pub fn allow_exec(mut self, allow_exec: bool) -> Self {
+        self.allow_exec = allow_exec;
+        self
+    }
+
+    /// Whether scripts may read and write files, which they may by default.
+    pub fn allow_files(mut self, allow_files: bool) -> Self {
+        self.allow_files = allow_files;
+        self
+    }
+
+    pub fn prelude(mut self, prelude: bool) -> Self {
+        self.prelude = prelude;
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut interpreter = Interpreter::without_prelude();
+        interpreter.output = self.output;
+        interpreter.error_output = self.error_output;
+        interpreter.input = self.input;
+        interpreter.clock = self.clock;
+        interpreter.script_args = self.script_args;
+        interpreter.allow_exec = self.allow_exec;
+        interpreter.allow_files = self.allow_files;
+        if self.prelude {
+            interpreter.load_prelude();
+        }
+        interpreter
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut interpreter = Self::without_prelude();
+        interpreter.load_prelude();
+        interpreter
+    }
+
+    /// Creates an interpreter that prints to `output` instead of stdout.
+    pub fn with_output(output: impl OutputSink + 'static) -> Self {
+        Self::builder().output(output).build()
+    }
+
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder {
+            output: Box::new(std::io::stdout()),
+            error_output: Box::new(std::io::stderr()),
+            input: Box::new(std::io::BufReader::new(std::io::stdin())),
+            clock: Box::new(SystemClock),
+            script_args: Vec::new(),
+            allow_exec: false,
+            allow_files: true,
+            prelude: true,
+        }
+    }
+
+    /// Creates an interpreter with only the native functions defined.
+    pub fn without_prelude() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        define_builtin(&globals, "clock", Box::new(LoxBuiltinFunctionClock::new()));
+        define_builtin(&globals, "input", Box::new(LoxBuiltinFunctionInput::new()));
+        define_builtin(
+            &globals,
+            "readFile",
+            Box::new(LoxBuiltinFunctionReadFile::new()),
+        );
+        define_builtin(
+            &globals,
+            "writeFile",
+            Box::new(LoxBuiltinFunctionWriteFile::new()),
+        );
+        define_builtin(
+            &globals,
+            "appendFile",
+            Box::new(LoxBuiltinFunctionAppendFile::new()),
+        );
+        define_builtin(
+            &globals,
+            "objectCount",
+            Box::new(LoxBuiltinFunctionObjectCount::new()),
+        );
+        define_builtin(
+            &globals,
+            "allocations",
+            Box::new(LoxBuiltinFunctionAllocations::new()),
+        );
+        define_builtin(
+            &globals,
+            "assert",
+            Box::new(LoxBuiltinFunctionAssert::new()),
+        );
+        define_builtin(&globals, "type", Box::new(LoxBuiltinFunctionType::new()));
+        define_builtin(&globals, "str", Box::new(LoxBuiltinFunctionStr::new()));
+        define_builtin(&globals, "num", Box::new(LoxBuiltinFunctionNum::new()));
+        define_builtin(&globals, "sleep", Box::new(LoxBuiltinFunctionSleep::new()));
+        define_builtin(
+            &globals,
+            "jsonParse",
+            Box::new(LoxBuiltinFunctionJsonParse::new()),
+        );
+        define_builtin(
+            &globals,
+            "jsonStringify",
+            Box::new(LoxBuiltinFunctionJsonStringify::new()),
+        );
+        define_builtin(&globals, "array", Box::new(LoxBuiltinFunctionArray::new()));
+        define_builtin(&globals, "len", Box::new(LoxBuiltinFunctionLen::new()));
+        define_builtin(&globals, "push", Box::new(LoxBuiltinFunctionPush::new()));
+        define_builtin(&globals, "get", Box::new(LoxBuiltinFunctionGet::new()));
+        define_builtin(&globals, "set", Box::new(LoxBuiltinFunctionSet::new()));
+        define_builtin(
+            &globals,
+            "regexMatch",
+            Box::new(LoxBuiltinFunctionRegexMatch::new()),
+        );
+        define_builtin(
+            &globals,
+            "regexFindAll",
+            Box::new(LoxBuiltinFunctionRegexFindAll::new()),
+        );
+        define_builtin(
+            &globals,
+            "regexReplace",
+            Box::new(LoxBuiltinFunctionRegexReplace::new()),
+        );
+        define_builtin(&globals, "args", Box::new(LoxBuiltinFunctionArgs::new()));
+        define_builtin(&globals, "exec", Box::new(LoxBuiltinFunctionExec::new()));
+        define_builtin(
+            &globals,
+            "exitCode",
+            Box::new(LoxBuiltinFunctionExitCode::new()),
+        );
+        define_builtin(
+            &globals,
+            "printErr",
+            Box::new(LoxBuiltinFunctionPrintErr::new()),
+        );
+        define_builtin(&globals, "split", Box::new(LoxBuiltinFunctionSplit::new()));
+        define_builtin(&globals, "join", Box::new(LoxBuiltinFunctionJoin::new()));
+        define_builtin(
+            &globals,
+            "charAt",
+            Box::new(LoxBuiltinFunctionCharAt::new()),
+        );
+        define_builtin(&globals, "ord", Box::new(LoxBuiltinFunctionOrd::new()));
+        define_builtin(&globals, "chr", Box::new(LoxBuiltinFunctionChr::new()));
+        define_builtin(&globals, "map", Box::new(LoxBuiltinFunctionMap::new()));
+        define_builtin(
+            &globals,
+            "filter",
+            Box::new(LoxBuiltinFunctionFilter::new()),
+        );
+        define_builtin(
+            &globals,
+            "reduce",
+            Box::new(LoxBuiltinFunctionReduce::new()),
+        );
+        define_builtin(&globals, "sort", Box::new(LoxBuiltinFunctionSort::new()));
+        Interpreter {
+            globals: Rc::clone(&globals),
+            node_ids: NodeIds::new(),
+            environment: globals,
+            output: Box::new(std::io::stdout()),
+            error_output: Box::new(std::io::stderr()),
+            input: Box::new(std::io::BufReader::new(std::io::stdin())),
+            clock: Box::new(SystemClock),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            script_args: Vec::new(),
+            allow_exec: false,
+            allow_files: true,
+            last_exit_code: None,
+            steps: 0,
+            step_limit: None,
+            call_depth: 0,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            memory_limit: None,
+            memory_baseline: 0,
+            hooks: None,
+            callbacks: HashMap::new(),
+            strings: HashMap::new(),
+            engine: Engine::TreeWalker,
+            compiled_bodies: HashMap::new(),
+            environment_pool: Vec::new(),
+            #[cfg(feature = "jit")]
+            jit: Jit::default(),
+        }
+    }
+
+    /// Defines a global native function implemented by a Rust closure. The
+    /// arity is checked before the closure is called, and errors it returns
+    /// get the line of the call appended.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value, InterpreterError> + 'static,
+    ) {
+        define_builtin(
+            &self.globals,
+            name,
+            Box::new(LoxNativeFunction::new(arity, function)),
+        );
+    }
+
+    /// Returns a handle that cancels the running script from another thread.
+    /// The cancellation stays in effect until the token is reset.
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken(Arc::clone(&self.interrupt))
+    }
+
+    /// Limits the number of statements and expressions the interpreter may
+    /// evaluate from now on, failing with "Step limit exceeded." beyond it.
+    /// `None` removes the limit.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+        self.steps = 0;
+    }
+
+    /// Limits how deeply calls may nest, failing with "Stack overflow."
+    /// beyond it. The default is 10000, and `None` removes the limit, leaving
+    /// only the memory for the stack as one.
+    pub fn set_max_call_depth(&mut self, limit: Option<usize>) {
+        self.max_call_depth = limit;
+    }
+
+    /// The number of statements and expressions evaluated since the step
+    /// limit was last set.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Limits how many bytes the heap may grow by from now on, failing with
+    /// "Memory limit exceeded." beyond it. `None` removes the limit.
+    ///
+    /// The interpreter measures what its thread allocates, its strings,
+    /// arrays, instances and environments as well as the embedder's own
+    /// allocations on that thread, but not those of other threads. That
+    /// needs `liblox::memory::CountingAllocator` as the global allocator,
+    /// without which setting a limit fails.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) -> Result<(), InterpreterError> {
+        if limit.is_some() && !memory::is_counting() {
+            return Err(InterpreterError {
+                code: ErrorCode::RuntimeError,
+                message: "Memory limits need liblox::memory::CountingAllocator as the \
+                          global allocator."
+                    .to_string(),
+                line: None,
+                span: None,
+            });
+        }
+        self.memory_limit = limit;
+        self.memory_baseline = memory::thread_bytes();
+        Ok(())
+    }
+
+    /// Installs hooks that observe the execution, replacing any previous
+    /// ones. `None` removes them.
+    pub fn set_hooks(&mut self, hooks: Option<Box<dyn InterpreterHooks>>) {
+        self.hooks = hooks;
+    }
+
+    pub(crate) fn count_step(&mut self) -> Result<(), InterpreterError> {
+        self.steps += 1;
+        if let Some(limit) = self.step_limit {
+            if self.steps > limit {
+                return Err(InterpreterError {
+                    code: ErrorCode::StepLimitExceeded,
+                    message: format!("Step limit of {} exceeded.", limit),
+                    line: None,
+                    span: None,
+                });
+            }
+        }
+        if let Some(limit) = self.memory_limit {
+            let used = memory::thread_bytes() - self.memory_baseline;
+            if used > limit as isize {
+                return Err(InterpreterError {
+                    code: ErrorCode::MemoryLimitExceeded,
+                    message: format!("Memory limit of {} bytes exceeded.", limit),
+                    line: None,
+                    span: None,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(), InterpreterError> {
+        if self.interrupt.load(Ordering::Relaxed) {
+            return Err(InterpreterError {
+                code: ErrorCode::Cancelled,
+                message: "Execution cancelled.".to_string(),
+                line: None,
+                span: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Chooses how statements are run. The engines behave the same, but as
+    /// hooks are given the statements, they are run by the tree walker while
+    /// hooks are set.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    fn compiles(&self) -> bool {
+        self.engine != Engine::TreeWalker && self.hooks.is_none()
+    }
+
+    // Calls the function natively for Engine::Jit, if it has been compiled.
+    // Native code neither counts steps nor measures memory, so it is left
+    // to the interpreter while those are limited.
+    #[cfg(feature = "jit")]
+    pub(crate) fn call_native(
+        &mut self,
+        function: &LoxFunction,
+        arguments: &[Value],
+    ) -> Option<Value> {
+        if self.engine != Engine::Jit
+            || !self.compiles()
+            || self.step_limit.is_some()
+            || self.memory_limit.is_some()
+        {
+            return None;
+        }
+        let calls_left = self
+            .max_call_depth
+            .map(|max| max.saturating_sub(self.call_depth));
+        self.jit.call(
+            function,
+            arguments,
+            calls_left,
+            &self.globals.borrow(),
+            &self.interrupt,
+        )
+    }
+
+    /// Defines a global class whose methods are implemented in Rust.
+    pub fn define_native_class(&mut self, class: NativeClass) {
+        let name = class.name().to_string();
+        self.globals
+            .borrow_mut()
+            .define(name, Value::Callable(Callable::Class(class.build())));
+    }
+
+    /// Defines or redefines a global variable from a Rust value.
+    pub fn set_global(&mut self, name: &str, value: impl ToLox) {
+        self.globals
+            .borrow_mut()
+            .define(name.to_string(), value.to_lox());
+    }
+
+    /// Keeps a function, method or class under `name` so the host can call
+    /// it later with `invoke_callback`, for example from its event loop.
+    /// Storing another value under the same name replaces it.
+    pub fn store_callback(&mut self, name: &str, value: Value) -> Result<(), InterpreterError> {
+        let Value::Callable(callable) = value else {
+            return Err(InterpreterError {
+                code: ErrorCode::RuntimeError,
+                message: format!("Callback '{}' must be a function.", name),
+                line: None,
+                span: None,
+            });
+        };
+        self.callbacks.insert(name.to_string(), callable);
+        Ok(())
+    }
+
+    /// Removes a stored callback, returning whether there was one.
+    pub fn remove_callback(&mut self, name: &str) -> bool {
+        self.callbacks.remove(name).is_some()
+    }
+
+    /// Calls a stored callback. This may happen between runs or from a
+    /// native while a script is running, since every call sets up and
+    /// restores its own environment.
+    pub fn invoke_callback(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        let Some(callable) = self.callbacks.get(name).cloned() else {
+            return Err(InterpreterError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Undefined callback '{}'.", name),
+                line: None,
+                span: None,
+            });
+        };
+        self.check_cancelled()?;
+        if arguments.len() != callable.arity() {
+            return Err(InterpreterError {
+                code: ErrorCode::ArityMismatch,
+                message: format!(
+                    "Expected {} arguments but got {}.",
+                    callable.arity(),
+                    arguments.len()
+                ),
+                line: None,
+                span: None,
+            });
+        }
+        self.call_callable(&callable, arguments)
+    }
+
+    /// Reads a global variable as a Rust value.
+    pub fn get_global<T: FromLox>(&self, name: &str) -> Result<T, InterpreterError> {
+        match self.globals.borrow().get(&name.to_string()) {
+            Some(value) => T::from_lox(&value),
+            None => Err(InterpreterError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Undefined variable '{}'.", name),
+                line: None,
+                span: None,
+            }),
+        }
+    }
+
+    fn load_prelude(&mut self) {
+        let tokens = Scanner::new(PRELUDE.to_string())
+            .map(|result| result.expect("the prelude should scan"));
+        let mut parser = Parser::with_ids(tokens, self.node_ids.clone());
+        let mut statements = parser.parse().expect("the prelude should parse");
+        let mut ast = parser.into_ast();
+        optimizer::fold_constants(&mut ast, &statements);
+        let mut resolver = Resolver::new();
+        resolver
+            .resolve_stmts(&ast, &statements)
+            .expect("the prelude should resolve");
+        optimizer::eliminate_dead_code(&mut ast, &mut statements);
+        if self.execute(&Rc::new(ast), &statements).is_err() {
+            panic!("the prelude should run without errors");
+        }
+    }
+
+    /// Scans, parses, resolves and evaluates a source consisting of a single
+    /// expression, and returns its value. Syntax errors are not printed,
+    /// only returned.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.take_tokens();
+        let mut parser = Parser::with_ids(tokens, self.node_ids.clone());
+        parser.set_error_reporting(false);
+        let expression = parser.parse_expression();
+        if scanner.had_error || expression.is_err() {
+            return Err(LoxError::syntax(source, &scanner, &parser));
+        }
+        let expression = expression.unwrap();
+        let mut ast = parser.into_ast();
+        optimizer::fold_expression(&mut ast, expression);
+        let mut resolver = Resolver::new();
+        resolver
+            .resolve_expr(&ast, expression)
+            .map_err(|err| LoxError::from(err).locate(source))?;
+
+        self.expression(&Rc::new(ast), expression).map_err(|err| {
+            if let Some(hooks) = self.hooks.as_mut() {
+                hooks.on_error(&err);
+            }
+            LoxError::from(err).locate(source)
+        })
+    }
+
+    /// Executes the statements, which lie in `ast`.
+    pub fn execute(
+        &mut self,
+        ast: &Rc<Ast>,
+        statements: &[StmtId],
+    ) -> Result<InterpreterResult, InterpreterError> {
+        if self.compiles() {
+            for statement in closures::compile_statements(ast, statements) {
+                statement(self)?;
+            }
+            return Ok(InterpreterResult::None);
+        }
+        for &statement in statements {
+            if let Err(err) = self.execute_statement(ast, statement) {
+                if let Some(hooks) = self.hooks.as_mut() {
+                    hooks.on_error(&err);
+                }
+                return Err(err);
+            }
+        }
+        Ok(InterpreterResult::None)
+    }
+
+    /// Prints the value of the expression like a print statement, except
+    /// that instances show their fields.
+    pub(crate) fn print_with_fields(
+        &mut self,
+        ast: &Rc<Ast>,
+        expression: ExprId,
+    ) -> Result<(), InterpreterError> {
+        self.count_step()?;
+        let value = self.expression(ast, expression).inspect_err(|err| {
+            if let Some(hooks) = self.hooks.as_mut() {
+                hooks.on_error(err);
+            }
+        })?;
+        match &value {
+            Value::Instance(instance) => self
+                .output
+                .write_line(&InstanceFields(instance).to_string()),
+            _ => self.output.write_line(&value.to_string()),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn execute_statement(
+        &mut self,
+        ast: &Rc<Ast>,
+        statement: StmtId,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        self.count_step()?;
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_statement(&ast[statement]);
+        }
+        match &ast[statement] {
+            Stmt::Expression(expr_stmt) => {
+                self.expression(ast, expr_stmt.expression)?;
+            }
+            Stmt::Function(fun_stmt) => {
+                self.environment.borrow_mut().define(
+                    fun_stmt.name.lexeme.clone(),
+                    Value::Callable(Callable::Function(LoxFunction::new(
+                        Rc::clone(ast),
+                        Rc::clone(fun_stmt),
+                        self.environment.clone(),
+                        false,
+                    ))),
+                );
+            }
+            Stmt::Return(return_stmt) => {
+                if let Some(value) = return_stmt.value {
+                    if let Expression::Call(call) = &ast[value] {
+                        return self.tail_call(ast, call);
+                    }
+                    let return_value = self.expression(ast, value)?;
+                    return Ok(InterpreterResult::Return(return_value));
+                } else {
+                    return Ok(InterpreterResult::Return(Value::Nil));
+                }
+            }
+            Stmt::If(if_stmt) => {
+                let condition = self.expression(ast, if_stmt.condition)?;
+                if condition.is_true() {
+                    return self.execute_statement(ast, if_stmt.then_branch);
+                } else if let Some(else_branch) = if_stmt.else_branch {
+                    return self.execute_statement(ast, else_branch);
+                }
+            }
+            Stmt::Print(print_stmt) => {
+                let value = self.expression(ast, print_stmt.expression)?;
+                self.output.write_line(&value.to_string());
+            }
+            Stmt::Block(block_stmt) => {
+                // The resolver gives blocks without declarations no scope
+                let environment = if block_stmt.declares_variables(ast) {
+                    self.new_environment(self.environment.clone())
+                } else {
+                    self.environment.clone()
+                };
+                return self.execute_block(ast, &block_stmt.statements, environment);
+            }
+            Stmt::Var(var_stmt) => {
+                if let Some(initializer) = var_stmt.initializer {
+                    let value = self.expression(ast, initializer)?;
+                    self.environment
+                        .borrow_mut()
+                        .define(var_stmt.name.lexeme.clone(), value.clone());
+                } else {
+                    self.environment
+                        .borrow_mut()
+                        .define(var_stmt.name.lexeme.clone(), Value::Nil);
+                }
+            }
+            Stmt::While(while_stmt) => {
+                while self.expression(ast, while_stmt.condition)?.is_true() {
+                    self.check_cancelled()?;
+                    match self.execute_statement(ast, while_stmt.body)? {
+                        InterpreterResult::None => {}
+                        result => return Ok(result),
+                    }
+                }
+            }
+            Stmt::ForIn(for_in) => {
+                let iterable = self.expression(ast, for_in.iterable)?;
+                let name = &for_in.name;
+                let mut iteration = self.start_iteration(iterable, name)?;
+                while let Some(value) = self.next_value(&mut iteration, name)? {
+                    self.check_cancelled()?;
+                    let environment = self.new_environment(self.environment.clone());
+                    environment
+                        .borrow_mut()
+                        .define(for_in.name.lexeme.clone(), value);
+                    let result =
+                        self.execute_block(ast, std::slice::from_ref(&for_in.body), environment)?;
+                    if !matches!(result, InterpreterResult::None) {
+                        return Ok(result);
+                    }
+                }
+            }
+            Stmt::Class(class_stmt) => {
+                let mut superclass: Option<Rc<RefCell<LoxClass>>> = None;
+                if let Some(super_class) = &class_stmt.superclass {
+                    let superclass_value =
+                        self.lookup_variable(&super_class.name, super_class.resolved.get())?;
+                    if let Value::Callable(Callable::Class(class)) = superclass_value {
+                        superclass = Some(class.clone());
+                    } else {
+                        return Err(InterpreterError {
+                            code: ErrorCode::RuntimeError,
+                            message: "Superclass must be a class.".to_string(),
+                            line: Some(super_class.name.line),
+                            span: super_class.name.span.clone(),
+                        });
+                    }
+                }
+
+                if superclass.is_some() {
+                    let new_environment = Environment::with_enclosing(self.environment.clone());
+                    self.environment = Rc::new(RefCell::new(new_environment));
+
+                    self.environment.borrow_mut().define(
+                        "super".to_string(),
+                        Value::Callable(Callable::Class(superclass.clone().unwrap())),
+                    );
+                }
+
+                let mut methods = HashMap::new();
+                for method in &class_stmt.methods {
+                    let is_initializer = method.name.lexeme == "init";
+                    methods.insert(
+                        method.name.lexeme.clone(),
+                        Box::new(LoxFunction::new(
+                            Rc::clone(ast),
+                            Rc::clone(method),
+                            self.environment.clone(),
+                            is_initializer,
+                        )),
+                    );
+                }
+
+                let class = Rc::new(RefCell::new(LoxClass::new(
+                    class_stmt.name.lexeme.clone(),
+                    superclass.clone(),
+                    methods,
+                )));
+
+                if superclass.is_some() {
+                    let enclosing = self.environment.as_ref().borrow().enclosing.clone();
+                    self.environment = enclosing.unwrap();
+                }
+
+                // Defined once complete, as the methods only refer to it when called
+                self.environment.borrow_mut().define(
+                    class_stmt.name.lexeme.clone(),
+                    Value::Callable(Callable::Class(class)),
+                );
+            }
+        }
+        Ok(InterpreterResult::None)
+    }
+
+    // Runs the body of a function in the environment of its call
+    pub(crate) fn execute_body(
+        &mut self,
+        ast: &Rc<Ast>,
+        declaration: &Rc<FunctionStmt>,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        if !self.compiles() {
+            return self.execute_block(ast, &declaration.body, environment);
+        }
+        let key = Rc::as_ptr(declaration);
+        let body = match self.compiled_bodies.get(&key) {
+            Some((_, body)) => Rc::clone(body),
+            None => {
+                let body: Rc<[CompiledStmt]> =
+                    closures::compile_statements(ast, &declaration.body).into();
+                self.compiled_bodies
+                    .insert(key, (Rc::clone(declaration), Rc::clone(&body)));
+                body
+            }
+        };
+        closures::run_block(self, &body, environment)
+    }
+
+    /// Executes the statements in `environment`, which holds the variables
+    /// of their scope.
+    pub fn execute_block(
+        &mut self,
+        ast: &Rc<Ast>,
+        statements: &[StmtId],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let mut result = Ok(InterpreterResult::None);
+        for &statement in statements {
+            match self.execute_statement(ast, statement) {
+                Ok(InterpreterResult::None) => {}
+                returned => {
+                    result = returned;
+                    break;
+                }
+            }
+        }
+        let environment = std::mem::replace(&mut self.environment, previous);
+        self.recycle_environment(environment);
+        result
+    }
+
+    /// Returns an empty local environment in `enclosing`, reusing one from
+    /// the pool if there is any.
+    pub(crate) fn new_environment(
+        &mut self,
+        enclosing: Rc<RefCell<Environment>>,
+    ) -> Rc<RefCell<Environment>> {
+        match self.environment_pool.pop() {
+            Some(environment) => {
+                environment.borrow_mut().enclosing = Some(enclosing);
+                environment
+            }
+            None => Rc::new(RefCell::new(Environment::with_enclosing(enclosing))),
+        }
+    }
+
+    // Puts a local environment that was left into the pool, unless something
+    // such as a closure still refers to it
+    pub(crate) fn recycle_environment(&mut self, mut environment: Rc<RefCell<Environment>>) {
+        if self.environment_pool.len() >= MAX_POOLED_ENVIRONMENTS {
+            return;
+        }
+        let Some(unshared) = Rc::get_mut(&mut environment) else {
+            return;
+        };
+        let unshared = unshared.get_mut();
+        if unshared.enclosing.take().is_none() {
+            // Globals are never left, but don't become a local scope
+            return;
+        }
+        unshared.slots.clear();
+        self.environment_pool.push(environment);
+    }
+
+    fn expression(&mut self, ast: &Rc<Ast>, expression: ExprId) -> Result<Value, InterpreterError> {
+        self.count_step()?;
+        match &ast[expression] {
+            Expression::Binary(binary) => self.binary(ast, binary),
+            Expression::Call(call) => self.call(ast, call),
+            Expression::Get(get) => self.get(ast, get),
+            Expression::Grouping(grouping) => self.grouping(ast, grouping),
+            Expression::Literal(literal) => self.literal(literal),
+            Expression::Logical(logical) => self.logical(ast, logical),
+            Expression::Set(set) => self.set(ast, set),
+            Expression::Super(super_expr) => self.super_method(super_expr),
+            Expression::This(this) => self.lookup_variable(&this.keyword, this.resolved.get()),
+            Expression::Unary(unary) => self.unary(ast, unary),
+            Expression::Variable(variable) => {
+                self.lookup_variable(&variable.name, variable.resolved.get())
+            }
+            Expression::Assign(assign) => {
+                if let Some(sum) = assign.sum_with_itself(ast) {
+                    self.count_step()?;
+                    let left = self.expression(ast, sum.left)?;
+                    let right = self.expression(ast, sum.right)?;
+                    return self.assign_sum(assign, &sum.operator, left, right);
+                }
+                let value = self.expression(ast, assign.value)?;
+                self.assign_variable(&assign.name, assign.resolved.get(), value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+
+    pub(crate) fn super_method(&mut self, super_expr: &Super) -> Result<Value, InterpreterError> {
+        let local = super_expr.resolved.get();
+        if local.is_none() {
+            return Err(InterpreterError {
+                code: ErrorCode::RuntimeError,
+                message: "Cannot use 'super' outside of a class.".to_string(),
+                line: Some(super_expr.keyword.line),
+                span: super_expr.keyword.span.clone(),
+            });
+        }
+        let local = local.unwrap();
+        let super_value = self.environment.borrow().get_at(local.depth, local.slot);
+        if super_value.is_none() {
+            return Err(InterpreterError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Undefined variable '{}'.", super_expr.keyword.lexeme),
+                line: Some(super_expr.keyword.line),
+                span: super_expr.keyword.span.clone(),
+            });
+        }
+        // `this` is alone in the scope just inside that of `super`
+        let this_value = self.environment.borrow().get_at(local.depth - 1, 0);
+        if let Some(Value::Instance(instance)) = this_value {
+            if let Value::Callable(Callable::Class(super_class)) = super_value.unwrap() {
+                let method = super_class
+                    .borrow()
+                    .bind_method(&super_expr.method.lexeme, &instance);
+                return method.ok_or_else(|| InterpreterError {
+                    code: ErrorCode::UndefinedProperty,
+                    message: format!("Undefined property '{}'.", super_expr.method.lexeme),
+                    line: Some(super_expr.method.line),
+                    span: super_expr.method.span.clone(),
+                });
+            }
+        }
+        Err(InterpreterError {
+            code: ErrorCode::RuntimeError,
+            message: "Superclass must be a class.".to_string(),
+            line: Some(super_expr.keyword.line),
+            span: super_expr.keyword.span.clone(),
+        })
+    }
+
+    pub(crate) fn assign_variable(
+        &mut self,
+        name: &Token,
+        resolved: Option<LocalSlot>,
+        value: Value,
+    ) -> Result<(), InterpreterError> {
+        match resolved {
+            Some(local) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(name, value, local.depth, local.slot)?;
+            }
+            None => {
+                self.globals.borrow_mut().assign(name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Assigns `name = name + right` once both operands are evaluated. The
+    // variable lets go of a string first, so that it is appended to in place
+    // when nothing else refers to it, which makes building a string in a
+    // loop take linear instead of quadratic time.
+    pub(crate) fn assign_sum(
+        &mut self,
+        assign: &Assign,
+        operator: &Token,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, InterpreterError> {
+        let resolved = assign.resolved.get();
+        if let (Value::String(_), Value::String(_)) = (&left, &right) {
+            self.assign_variable(&assign.name, resolved, Value::Nil)?;
+        }
+        let value = binary_operation(operator, left, right)?;
+        self.assign_variable(&assign.name, resolved, value.clone())?;
+        Ok(value)
+    }
+
+    pub(crate) fn lookup_variable(
+        &mut self,
+        name: &Token,
+        resolved: Option<LocalSlot>,
+    ) -> Result<Value, InterpreterError> {
+        if let Some(local) = resolved {
+            return self
+                .environment
+                .borrow()
+                .get_at(local.depth, local.slot)
+                .ok_or(InterpreterError {
+                    code: ErrorCode::UndefinedVariable,
+                    message: format!("Undefined variable '{}'.", name.lexeme),
+                    line: Some(name.line),
+                    span: name.span.clone(),
+                });
+        }
+        self.globals
+            .borrow()
+            .get(&name.lexeme)
+            .ok_or(InterpreterError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Undefined variable '{}'.", name.lexeme),
+                line: Some(name.line),
+                span: name.span.clone(),
+            })
+    }
+
+    fn call(&mut self, ast: &Rc<Ast>, call: &Call) -> Result<Value, InterpreterError> {
+        let callee = self.expression(ast, call.callee)?;
+        let mut arguments = Vec::new();
+        if let Value::Callable(_) = &callee {
+            for &arg in &call.arguments {
+                arguments.push(self.expression(ast, arg)?);
+            }
+        }
+        self.call_value(&callee, arguments, &call.paren)
+    }
+
+    // Evaluates a call that is returned by a function. Calls of Lox functions
+    // are left to the calling function, see LoxFunction::call.
+    fn tail_call(
+        &mut self,
+        ast: &Rc<Ast>,
+        call: &Call,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        self.count_step()?;
+        let callee = self.expression(ast, call.callee)?;
+        let mut arguments = Vec::new();
+        if let Value::Callable(_) = &callee {
+            for &arg in &call.arguments {
+                arguments.push(self.expression(ast, arg)?);
+            }
+        }
+        self.tail_call_value(callee, arguments, &call.paren)
+    }
+
+    pub(crate) fn tail_call_value(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<InterpreterResult, InterpreterError> {
+        match &callee {
+            Value::Callable(Callable::Function(function))
+                if !function.is_initializer() && function.arity() == arguments.len() =>
+            {
+                self.check_cancelled()?;
+                Ok(InterpreterResult::TailCall(function.clone(), arguments))
+            }
+            _ => {
+                let value = self.call_value(&callee, arguments, paren)?;
+                Ok(InterpreterResult::Return(value))
+            }
+        }
+    }
+
+    /// Calls a function or class value with already evaluated arguments.
+    /// Errors are reported at `paren`, the closing parenthesis of the call.
+    pub fn call_value(
+        &mut self,
+        callee: &Value,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, InterpreterError> {
+        let Value::Callable(callable) = callee else {
+            return Err(InterpreterError {
+                code: ErrorCode::NotCallable,
+                message: "Can only call functions and classes.".to_string(),
+                line: Some(paren.line),
+                span: paren.span.clone(),
+            });
+        };
+        self.check_cancelled()?;
+        let arity = callable.arity();
+        if arguments.len() != arity {
+            return Err(InterpreterError {
+                code: ErrorCode::ArityMismatch,
+                message: format!("Expected {} arguments but got {}.", arity, arguments.len()),
+                line: Some(paren.line),
+                span: paren.span.clone(),
+            });
+        }
+        self.on_call(callable, &arguments);
+        let result = self.call_callable(callable, arguments);
+        if let Ok(value) = &result {
+            self.on_return(callable, value);
+        }
+        result.map_err(|mut err| {
+            // Natives don't know where they were called from
+            if err.line.is_none() {
+                err.line = Some(paren.line);
+                err.span = paren.span.clone();
+            }
+            err
+        })
+    }
+
+    // Reports a call to the hooks, returning whether there are any
+    pub(crate) fn on_call(&mut self, callable: &Callable, arguments: &[Value]) -> bool {
+        match self.hooks.as_mut() {
+            Some(hooks) => {
+                hooks.on_call(callable, arguments);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn on_return(&mut self, callable: &Callable, value: &Value) {
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_return(callable, value);
+        }
+    }
+
+    /// Calls a callable whose arity has already been checked. Used by
+    /// natives that call back into Lox functions.
+    pub fn call_callable(
+        &mut self,
+        callable: &Callable,
+        arguments: Vec<Value>,
+    ) -> Result<Value, InterpreterError> {
+        if self
+            .max_call_depth
+            .is_some_and(|max| self.call_depth >= max)
+        {
+            return Err(InterpreterError {
+                code: ErrorCode::StackOverflow,
+                message: "Stack overflow.".to_string(),
+                line: None,
+                span: None,
+            });
+        }
+        self.call_depth += 1;
+        let result = with_stack(|| match callable {
+            Callable::DynamicFunction(func) => {
+                func.callable.borrow().as_ref().call(self, arguments)
+            }
+            Callable::Function(func) => func.call(self, arguments),
+            Callable::Class(class) => class.call(self, arguments),
+        });
+        self.call_depth -= 1;
+        result
+    }
+
+    // Looks up and calls a method without arguments, as used by protocols
+    // such as iteration.
+    pub(crate) fn call_method(
+        &mut self,
+        object: &Value,
+        name: &str,
+        at: &Token,
+    ) -> Result<Value, InterpreterError> {
+        let Value::Instance(instance) = object else {
+            return Err(InterpreterError {
+                code: ErrorCode::NotAnInstance,
+                message: format!("Only instances have methods, can't call '{}'.", name),
+                line: Some(at.line),
+                span: at.span.clone(),
+            });
+        };
+        let name = Token {
+            span: at.span.clone(),
+            ..Token::new_identifier(name.to_string(), at.line)
+        };
+        let method = get_instance_field(instance, &name)?;
+        self.call_value(&method, Vec::new(), at)
+    }
+
+    pub(crate) fn start_iteration(
+        &mut self,
+        iterable: Value,
+        at: &Token,
+    ) -> Result<Iteration, InterpreterError> {
+        match iterable {
+            Value::Array(array) => Ok(Iteration::Array(array, 0)),
+            iterable => Ok(Iteration::Iterator(
+                self.call_method(&iterable, "iter", at)?,
+            )),
+        }
+    }
+
+    // Returns the next value of a for-in loop, or None once it is done. An
+    // array can change while it is iterated, so its length is checked anew
+    // for each element.
+    pub(crate) fn next_value(
+        &mut self,
+        iteration: &mut Iteration,
+        at: &Token,
+    ) -> Result<Option<Value>, InterpreterError> {
+        match iteration {
+            Iteration::Array(array, index) => {
+                let value = array.borrow().get(*index).cloned();
+                *index += 1;
+                Ok(value)
+            }
+            Iteration::Iterator(iterator) => {
+                if self.call_method(iterator, "done", at)?.is_true() {
+                    Ok(None)
+                } else {
+                    self.call_method(iterator, "next", at).map(Some)
+                }
+            }
+        }
+    }
+
+    fn get(&mut self, ast: &Rc<Ast>, get: &Get) -> Result<Value, InterpreterError> {
+        let object = self.expression(ast, get.object)?;
+        get_property(object, &get.name)
+    }
+
+    fn set(&mut self, ast: &Rc<Ast>, set: &Set) -> Result<Value, InterpreterError> {
+        let object = self.expression(ast, set.object)?;
+        self.set_property(&object, &set.name, |interpreter| {
+            interpreter.expression(ast, set.value)
+        })
+    }
+
+    // Sets a field of the object, evaluating the value only if the object
+    // is an instance
+    pub(crate) fn set_property(
+        &mut self,
+        object: &Value,
+        name: &Token,
+        value: impl FnOnce(&mut Self) -> Result<Value, InterpreterError>,
+    ) -> Result<Value, InterpreterError> {
+        match object {
+            Value::Instance(instance) => {
+                let value = value(self)?;
+                instance
+                    .borrow_mut()
+                    .set(name.lexeme.clone(), value.clone());
+                Ok(value)
+            }
+            _ => Err(InterpreterError {
+                code: ErrorCode::NotAnInstance,
+                message: "Only instances have fields.".to_string(),
+                line: Some(name.line),
+                span: name.span.clone(),
+            }),
+        }
+    }
+
+    fn grouping(&mut self, ast: &Rc<Ast>, grouping: &Grouping) -> Result<Value, InterpreterError> {
+        self.expression(ast, grouping.expression)
+    }
+
+    fn logical(&mut self, ast: &Rc<Ast>, logical: &Logical) -> Result<Value, InterpreterError> {
+        let left = self.expression(ast, logical.left)?;
+        if logical.operator.token_type == TokenType::Or {
+            if left.is_true() {
+                return Ok(left);
+            }
+        } else {
+            if !left.is_true() {
+                return Ok(left);
+            }
+        }
+        self.expression(ast, logical.right)
+    }
+
+    pub fn literal(&mut self, literal: &Literal) -> Result<Value, InterpreterError> {
+        match &literal.value {
+            LiteralTypes::String(value) => Ok(Value::String(self.intern(value))),
+            LiteralTypes::Number(value) => Ok(Value::Number(*value)),
+            LiteralTypes::Bool(value) => Ok(Value::Bool(*value)),
+            LiteralTypes::Nil => Ok(Value::Nil),
+        }
+    }
+
+    /// Returns the shared copy of the string, which is made on first use.
+    pub fn intern(&mut self, value: &str) -> Rc<String> {
+        if let Some(interned) = self.strings.get(value) {
+            return Rc::clone(interned);
+        }
+        let interned = Rc::new(value.to_string());
+        self.strings.insert(value.to_string(), Rc::clone(&interned));
+        interned
+    }
+
+    fn unary(&mut self, ast: &Rc<Ast>, unary: &Unary) -> Result<Value, InterpreterError> {
+        let right = self.expression(ast, unary.right)?;
+        unary_operation(&unary.operator, right)
+    }
+
+    fn binary(&mut self, ast: &Rc<Ast>, binary: &Binary) -> Result<Value, InterpreterError> {
+        let left = self.expression(ast, binary.left)?;
+        let right = self.expression(ast, binary.right)?;
+        binary_operation(&binary.operator, left, right)
+    }
+}
+
+/// Reads a property of the evaluated object.
+pub(crate) fn get_property(object: Value, name: &Token) -> Result<Value, InterpreterError> {
+    match object {
+        Value::Instance(instance) => get_instance_field(&instance, name),
+        _ => Err(InterpreterError {
+            code: ErrorCode::NotAnInstance,
+            message: "Only instances have properties.".to_string(),
+            line: Some(name.line),
+            span: name.span.clone(),
+        }),
+    }
+}
+
+/// Applies a unary operator to its evaluated operand.
+pub(crate) fn unary_operation(operator: &Token, right: Value) -> Result<Value, InterpreterError> {
+    match operator.token_type {
+        TokenType::Bang => match right {
+            Value::Bool(value) => Ok(Value::Bool(!value)),
+            Value::Nil => Ok(Value::Bool(true)),
+            _ => Ok(Value::Bool(false)),
+        },
+        TokenType::Minus => match right {
+            Value::Number(value) => Ok(Value::Number(-value)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operand must be a number.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        _ => Err(InterpreterError {
+            code: ErrorCode::RuntimeError,
+            message: format!("Invalid operator '{}'.", operator.lexeme),
+            line: Some(operator.line),
+            span: operator.span.clone(),
+        }),
+    }
+}
+
+/// Applies a binary operator to its evaluated operands.
+pub(crate) fn binary_operation(
+    operator: &Token,
+    left: Value,
+    right: Value,
+) -> Result<Value, InterpreterError> {
+    match operator.token_type {
+        TokenType::Minus => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left - right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::Slash => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left / right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::Star => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left * right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::Plus => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+            (Value::String(mut left), Value::String(right)) => {
+                match Rc::get_mut(&mut left) {
+                    Some(string) => string.push_str(&right),
+                    None => {
+                        let mut string = String::with_capacity(left.len() + right.len());
+                        string.push_str(&left);
+                        string.push_str(&right);
+                        left = Rc::new(string);
+                    }
+                }
+                Ok(Value::String(left))
+            }
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be two numbers or two strings.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::Greater => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left > right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::GreaterEqual => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left >= right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::Less => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left < right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::LessEqual => match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left <= right)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::OperandType,
+                message: "Operands must be numbers.".to_string(),
+                line: Some(operator.line),
+                span: operator.span.clone(),
+            }),
+        },
+        TokenType::BangEqual => match (left, right) {
+            (Value::Nil, Value::Nil) => Ok(Value::Bool(false)),
+            (Value::Bool(left), Value::Bool(right)) => Ok(Value::Bool(left != right)),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left != right)),
+            (Value::String(left), Value::String(right)) => Ok(Value::Bool(left != right)),
+            (Value::Callable(left), Value::Callable(right)) => Ok(Value::Bool(left != right)),
+            (Value::Array(left), Value::Array(right)) => {
+                Ok(Value::Bool(!Rc::ptr_eq(&left, &right)))
+            }
+            (Value::Native(left), Value::Native(right)) => Ok(Value::Bool(left != right)),
+            _ => Ok(Value::Bool(true)),
+        },
+        TokenType::EqualEqual => match (left, right) {
+            (Value::Nil, Value::Nil) => Ok(Value::Bool(true)),
+            (Value::Bool(left), Value::Bool(right)) => Ok(Value::Bool(left == right)),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left == right)),
+            (Value::String(left), Value::String(right)) => Ok(Value::Bool(left == right)),
+            (Value::Callable(left), Value::Callable(right)) => Ok(Value::Bool(left == right)),
+            (Value::Array(left), Value::Array(right)) => Ok(Value::Bool(Rc::ptr_eq(&left, &right))),
+            (Value::Native(left), Value::Native(right)) => Ok(Value::Bool(left == right)),
+            _ => Ok(Value::Bool(false)),
+        },
+        _ => Err(InterpreterError {
+            code: ErrorCode::RuntimeError,
+            message: "Invalid operator.".to_string(),
+            line: None,
+            span: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{expr, stmt, ExprBuilder};
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use liblox::scanner::Scanner;
+    use std::io;
+    use std::io::Write;
+    use std::time::Duration;
+
+    // Mocking the output stream for testing
+    struct VecWriter(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for VecWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(source: String) -> Result<String, InterpreterError> {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::builder()
+            .output(VecWriter(Rc::clone(&output)))
+            .error_output(io::sink())
+            .input(io::empty())
+            .prelude(false)
+            .build();
+
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.take_tokens();
+        assert!(!scanner.had_error);
+
+        let mut parser = Parser::with_ids(tokens, interpreter.node_ids.clone());
+        let parse_result = parser.parse();
+        assert!(parse_result.is_ok());
+        let statements = parse_result.unwrap();
+        let ast = Rc::new(parser.into_ast());
+
+        let mut resolver = Resolver::new();
+        let resolver_result = resolver.resolve_stmts(&ast, &statements);
+        assert!(resolver_result.is_ok());
+
+        let result = interpreter.execute(&ast, &statements);
+
+        match result {
+            Ok(_) => Ok(String::from_utf8_lossy(&output.borrow()).to_string()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Runs the source with the builtins registered and the given input text
+    fn run_with_input(source: &str, input: &str) -> Result<String, InterpreterError> {
+        let builder = Interpreter::builder().input(io::Cursor::new(input.to_string()));
+        run_with_builder(source, builder)
+    }
+
+    // Runs the source in an interpreter from the builder, returning its output
+    fn run_with_builder(
+        source: &str,
+        builder: InterpreterBuilder,
+    ) -> Result<String, InterpreterError> {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = builder.output(VecWriter(Rc::clone(&output))).build();
+
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.take_tokens();
+        assert!(!scanner.had_error);
+
+        let mut parser = Parser::with_ids(tokens, interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+
+        let mut resolver = Resolver::new();
+        assert!(resolver.resolve_stmts(&ast, &statements).is_ok());
+
+        interpreter.execute(&ast, &statements)?;
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        Ok(result)
+    }
+
+    fn evaluate(expression: ExprBuilder) -> Value {
+        let mut interpreter = Interpreter::new();
+        let mut ast = Ast::new();
+        let expression = expression.add_to(&mut ast, &interpreter.node_ids);
+        interpreter.expression(&Rc::new(ast), expression).unwrap()
+    }
+
+    #[test]
+    fn test_interpret_sum() {
+        assert_eq!(
+            evaluate(expr::num(5.0).plus(expr::num(3.0))),
+            Value::Number(8.0)
+        );
+    }
+
+    #[test]
+    fn test_interpret_subtraction() {
+        assert_eq!(
+            evaluate(expr::num(5.0).minus(expr::num(3.0))),
+            Value::Number(2.0)
+        );
+    }
+
+    #[test]
+    fn test_interpret_multiplication() {
+        assert_eq!(
+            evaluate(expr::num(5.0).times(expr::num(3.0))),
+            Value::Number(15.0)
+        );
+    }
+    #[test]
+    fn test_interpret_division() {
+        assert_eq!(
+            evaluate(expr::num(6.0).divided_by(expr::num(3.0))),
+            Value::Number(2.0)
+        );
+    }
+    #[test]
+    fn test_star_before_plus() {
+        let product = expr::num(5.0).times(expr::num(3.0));
+        assert_eq!(evaluate(product.plus(expr::num(2.0))), Value::Number(17.0));
+    }
+
+    #[test]
+    fn test_print_expression() {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::builder()
+            .output(VecWriter(Rc::clone(&output)))
+            .error_output(io::sink())
+            .input(io::empty())
+            .prelude(false)
+            .build();
+        let mut ast = Ast::new();
+        let print_stmt = stmt::print(expr::num(5.0).plus(expr::num(3.0)));
+        let statements = vec![print_stmt.add_to(&mut ast, &interpreter.node_ids)];
+        interpreter.execute(&Rc::new(ast), &statements).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.borrow()), "8\n");
+    }
+
+    #[test]
+    fn test_print_multiple_expressions() {
+        let source = "
+        print \"one\";
+        print true;
+        print 2 + 1;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "one\ntrue\n3\n");
+    }
+
+    #[test]
+    fn test_uninitialized_variable() {
+        let source = "
+        var a;
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "nil\n");
+    }
+
+    #[test]
+    fn test_unicode_names() {
+        let source = "
+        var prix_café = 3;
+        fun doppelt(zahl_ü) { return zahl_ü * 2; }
+        print \"prix: \" + str(doppelt(prix_café)) + \" €\";
+        "
+        .to_string();
+
+        assert_eq!(run(source).unwrap(), "prix: 6 €\n");
+    }
+
+    #[test]
+    fn test_print_variable() {
+        let source = "
+        var a = 5;
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "5\n");
+    }
+
+    #[test]
+    fn print_redefined_variable() {
+        let source = "
+        var a = 5;
+        print a;
+        var a = 10;
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "5\n10\n");
+    }
+
+    #[test]
+    fn test_error_undefined_variable() {
+        let source = "
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Undefined variable 'a'.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_expression_from_variables() {
+        let source = "
+        var a = 5;
+        var b = 3;
+        print a + b;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "8\n");
+    }
+
+    #[test]
+    fn test_assignment() {
+        let source = "
+        var a = 5;
+        print a;
+        a = 10;
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "5\n10\n");
+    }
+
+    #[test]
+    fn test_variable_used_outside_scope() {
+        let source = "
+        {
+            var a = 5;
+            print a;
+        }
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Undefined variable 'a'.\n[line 6]"
+        );
+    }
+
+    #[test]
+    fn test_variable_shadowing() {
+        let source = "
+        var a = 5;
+        {
+            var a = 10;
+            print a;
+        }
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "10\n5\n");
+    }
+
+    #[test]
+    fn test_variables_from_inner_scope() {
+        let source = "
+        var a = 5;
+        {
+            var b = 10;
+            print a + b;
+        }
+        print a;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "15\n5\n");
+    }
+
+    #[test]
+    fn test_variables_from_three_scopes() {
+        let source = "
+        var a = \"global a\";
+        var b = \"global b\";
+        var c = \"global c\";
+        {
+            var a = \"outer a\";
+            var b = \"outer b\";
+            {
+                var a = \"inner a\";
+                print a;
+                print b;
+                print c;
+            }
+            print a;
+            print b;
+            print c;
+        }
+        print a;
+        print b;
+        print c;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "inner a\nouter b\nglobal c\nouter a\nouter b\nglobal c\nglobal a\nglobal b\nglobal c\n");
+    }
+
+    #[test]
+    fn test_if_statement_true() {
+        let source = "
+        if (true) {
+            print \"True\";
+        } else {
+            print \"False\";
+        }
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "True\n");
+    }
+
+    #[test]
+    fn test_if_statement_false() {
+        let source = "
+        if (false) {
+            print \"True\";
+        } else {
+            print \"False\";
+        }
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "False\n");
+    }
+
+    #[test]
+    fn test_if_statement_expression() {
+        let source = "
+        if (3 < 2) {
+            print \"True\";
+        } else {
+            print \"False\";
+        }
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "False\n");
+    }
+
+    #[test]
+    fn test_if_statement_zero_is_true() {
+        let source = "
+        if (0) {
+            print \"True\";
+        } else {
+            print \"False\";
+        }
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "True\n");
+    }
+
+    #[test]
+    fn test_logical_or() {
+        let source = "
+        print true or false;
+        print false or true;
+        print false or false;
+        print true or true;
+        print 0 or 1;
+        print 0 or false;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "true\ntrue\nfalse\ntrue\n0\n0\n");
+    }
+
+    #[test]
+    fn test_while_statement() {
+        let source = "
+        var i = 0;
+        while (i < 5) {
+            print i;
+            i = i + 1;
+        }
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "0\n1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn test_for_statement() {
+        let source = "
+        for (var i = 0; i < 5; i = i + 1) {
+            print i;
+        }
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "0\n1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn test_function_definition_and_call() {
+        let source = "
+        fun greet() {
+            print \"Hello, World!\";
+        }
+        greet();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Hello, World!\n");
+    }
+
+    #[test]
+    fn test_function_definition_and_call_with_param() {
+        let source = "
+        fun greet(name) {
+            print \"Hello, \" + name + \"!\";
+        }
+        greet(\"World\");
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Hello, World!\n");
+    }
+
+    #[test]
+    fn test_function_definition_and_call_with_return() {
+        let source = "
+        fun greet() {
+            return \"Hello, World!\";
+        }
+        print greet();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Hello, World!\n");
+    }
+
+    #[test]
+    fn test_function_definition_and_call_with_return_from_loop() {
+        let source = "
+        fun bar() {
+            for (var i = 0;; i = i + 1) {
+                print i;
+                if (i >= 2) return;
+            }
+        }
+        bar();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "0\n1\n2\n");
+    }
+
+    #[test]
+    fn test_function_definition_and_call_with_multiple_params() {
+        let source = "
+        fun add(a, b) {
+            return a + b;
+        }
+        print add(5, 3);
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "8\n");
+    }
+
+    #[test]
+    fn test_recursion() {
+        let source = "
+        fun factorial(n) {
+            if (n == 0) {
+                return 1;
+            }
+            return n * factorial(n - 1);
+        }
+        print factorial(5);
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "120\n");
+    }
+
+    #[test]
+    fn test_fibonacci() {
+        let source = "
+        fun fib(n) {
+            if (n <= 1) return n;
+            return fib(n - 2) + fib(n - 1);
+        }
+        print fib(8);
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "21\n");
+    }
+
+    #[test]
+    fn test_function_object_with_closure() {
+        let source = "
+        fun makeCounter() {
+            var i = 0;
+            fun count() {
+                i = i + 1;
+                return i;
+            }
+            return count;
+        }
+        var counter = makeCounter();
+        print counter();
+        print counter();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "1\n2\n");
+    }
+
+    #[test]
+    fn test_function_object_with_closure_and_outer_variable() {
+        let source = "
+        var outerVar = 10;
+        fun makeCounter() {
+            var i = 0;
+            fun count() {
+                i = i + 1;
+                return i + outerVar;
+            }
+            return count;
+        }
+        var counter = makeCounter();
+        print counter();
+        print counter();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "11\n12\n");
+    }
+
+    #[test]
+    fn test_class_declaration() {
+        let source = "
+        class DevonshireCream {
+            serveOn() {
+                return \"Scones\";
+            }
+        }
+        print DevonshireCream;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "DevonshireCream\n");
+    }
+
+    #[test]
+    fn test_class_instance() {
+        let source = "
+        class Bagel {}
+        var bagel = Bagel();
+        print bagel;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Bagel instance\n");
+    }
+
+    #[test]
+    fn test_class_instance_fields() {
+        let source = "
+        class Bagel {}
+        var bagel = Bagel();
+        bagel.flavor = \"Sesame\";
+        print bagel.flavor;
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Sesame\n");
+    }
+
+    #[test]
+    fn test_class_method() {
+        let source = "
+        class Bacon {
+            eat() {
+                print \"Crunch crunch crunch!\";
+            }
+        }
+        Bacon().eat();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Crunch crunch crunch!\n");
+    }
+
+    #[test]
+    fn test_class_instance_print_this() {
+        let source = "
+        class Egotist {
+          speak() {
+            print this;
+          }
+        }
+
+        var method = Egotist().speak;
+        method();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Egotist instance\n");
+    }
+
+    #[test]
+    fn test_class_instance_field() {
+        let source = "
+        class Cake {
+          taste() {
+            var adjective = \"delicious\";
+            print \"The \" + this.flavor + \" cake is \" + adjective + \"!\";
+          }
+        }
+
+        var cake = Cake();
+        cake.flavor = \"German chocolate\";
+        cake.taste();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "The German chocolate cake is delicious!\n");
+    }
+
+    #[test]
+    fn test_class_instance_method_closure() {
+        let source = "
+        class Thing {
+          getCallback() {
+            fun localFunction() {
+              print this;
+            }
+
+            return localFunction;
+          }
+        }
+
+        var callback = Thing().getCallback();
+        callback();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Thing instance\n");
+    }
+
+    #[test]
+    fn test_class_instance_init() {
+        let source = "
+        class Foo {
+          init() {
+            print this;
+          }
+        }
+
+        var foo = Foo();
+        print foo.init();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap(),
+            "Foo instance\nFoo instance\nFoo instance\n"
+        );
+    }
+
+    #[test]
+    fn test_class_inheritance_method_call() {
+        let source = "
+        class Doughnut {
+          cook() {
+            print \"Fry until golden brown.\";
+          }
+        }
+
+        class BostonCream < Doughnut {}
+
+        BostonCream().cook();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), "Fry until golden brown.\n");
+    }
+
+    #[test]
+    fn test_class_inheritance_superclass_method_call() {
+        let source = "
+        class Doughnut {
+          cook() {
+            print \"Fry until golden brown.\";
+          }
+        }
+
+        class BostonCream < Doughnut {
+          cook() {
+            super.cook();
+            print \"Pipe full of custard and coat with chocolate.\";
+          }
+        }
+
+        BostonCream().cook();
+        "
+        .to_string();
+
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap(),
+            "Fry until golden brown.\nPipe full of custard and coat with chocolate.\n"
+        );
+    }
+
+    #[test]
+    fn test_builtin_input() {
+        let source = "
+        var name = input();
+        print \"Hello, \" + name + \"!\";
+        print input();
+        print input();
+        ";
+
+        let result = run_with_input(source, "Lox\r\nsecond line\n");
+        assert_eq!(result.unwrap(), "Hello, Lox!\nsecond line\nnil\n");
+    }
+
+    #[test]
+    fn test_injected_output_sink_and_clock() {
+        struct LineSink(Rc<RefCell<Vec<String>>>);
+        impl OutputSink for LineSink {
+            fn write_line(&mut self, line: &str) {
+                self.0.borrow_mut().push(line.to_string());
+            }
+        }
+        struct FixedClock;
+        impl Clock for FixedClock {
+            fn now(&self) -> f64 {
+                42.0
+            }
+        }
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("print clock(); print \"done\";".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+
+        interpreter.output = Box::new(LineSink(Rc::clone(&lines)));
+        interpreter.clock = Box::new(FixedClock);
+        interpreter.execute(&ast, &statements).unwrap();
+
+        assert_eq!(*lines.borrow(), vec!["42".to_string(), "done".to_string()]);
+    }
+
+    #[test]
+    fn test_builtin_file_io() {
+        let path = std::env::temp_dir().join(format!("lox_file_io_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let source = format!(
+            "
+        print writeFile(\"{path}\", \"one\");
+        print appendFile(\"{path}\", \" two\");
+        print readFile(\"{path}\");
+        "
+        );
+
+        let result = run_with_input(&source, "");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), "true\ntrue\none two\n");
+
+        let result = run_with_input(&format!("\n\nreadFile(\"{path}\");"), "");
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with(&format!("Could not read file '{path}': ")));
+        assert!(message.ends_with("\n[line 3]"));
+
+        let builder = Interpreter::builder().allow_files(false);
+        let result = run_with_builder(&format!("writeFile(\"{path}\", \"one\");"), builder);
+        let err = result.unwrap_err();
+        assert_eq!(err.code, ErrorCode::FileAccessDisabled);
+        assert_eq!(
+            err.to_string(),
+            "writeFile() is disabled in this interpreter.\n[line 1]"
+        );
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_builtin_object_count() {
+        let source = "
+        class Node {}
+        print objectCount();
+        var a = Node();
+        {
+            var b = Node();
+            print objectCount();
+        }
+        print objectCount();
+        a = nil;
+        print objectCount();
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "0\n2\n1\n0\n");
+    }
+
+    #[test]
+    fn test_for_in_iterator_protocol() {
+        let source = "
+        class RangeIterator {
+            init(from, to) {
+                this.current = from;
+                this.to = to;
+            }
+            done() { return this.current >= this.to; }
+            next() {
+                this.current = this.current + 1;
+                return this.current - 1;
+            }
+        }
+        class Range {
+            init(from, to) {
+                this.from = from;
+                this.to = to;
+            }
+            iter() { return RangeIterator(this.from, this.to); }
+        }
+        var in = 0;
+        for (var i in Range(1, 4)) {
+            in = in + i;
+            print i;
+        }
+        print in;
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "1\n2\n3\n6\n");
+
+        let source = "
+        var letters = array();
+        push(letters, \"a\");
+        push(letters, \"b\");
+        for (var letter in letters) {
+            if (letter == \"a\") push(letters, \"c\");
+            print letter;
+        }
+        ";
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "a\nb\nc\n");
+
+        let result = run_with_input("for (var x in 3) print x;", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Only instances have methods, can't call 'iter'.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_assert() {
+        let result = run_with_input("assert(1 < 2, \"math works\"); print \"ok\";", "");
+        assert_eq!(result.unwrap(), "ok\n");
+
+        let result = run_with_input("\nassert(nil, \"value is set\");", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Assertion failed: value is set\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_type() {
+        let source = "
+        class Point {}
+        fun f() {}
+        print type(1);
+        print type(\"s\");
+        print type(true);
+        print type(nil);
+        print type(f);
+        print type(clock);
+        print type(Point);
+        print type(Point());
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(
+            result.unwrap(),
+            "number\nstring\nbool\nnil\nfunction\nfunction\nclass\nPoint\n"
+        );
+    }
+
+    #[test]
+    fn test_describe_type() {
+        let mut lox = crate::Lox::new();
+        lox.run("class Shape {} class Point < Shape {} fun add(a, b) { return a + b; }")
+            .unwrap();
+        let described = [
+            ("1 + 2", "number"),
+            ("\"s\"", "string"),
+            ("nil", "nil"),
+            ("add", "fun add(a, b)"),
+            ("clock", "native fun()"),
+            ("Shape", "class Shape"),
+            ("Point", "class Point < Shape"),
+            ("Point()", "instance of Point"),
+        ];
+        for (source, description) in described {
+            let value = lox.interpreter.eval(source).unwrap();
+            assert_eq!(value.describe_type(), description, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_echo_fields() {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let interpreter = Interpreter::with_output(VecWriter(Rc::clone(&output)));
+        let mut lox = crate::Lox::with_interpreter(interpreter);
+        lox.set_mode(liblox::echo::Mode::Repl);
+        lox.run("class Bagel { init(flavor) { this.flavor = flavor; } }")
+            .unwrap();
+        lox.run("Bagel(\"Sesame\")").unwrap();
+        lox.set_echo_fields(true);
+        lox.run("Bagel(\"Sesame\")").unwrap();
+        lox.run("print Bagel(\"Plain\");").unwrap();
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "Bagel instance\nBagel { flavor: \"Sesame\" }\nBagel instance\n"
+        );
+    }
+
+    #[test]
+    fn test_builtin_str_and_num() {
+        let source = "
+        print str(1.5) + \"!\";
+        print str(nil) + str(true);
+        print num(\" 42 \") + 1;
+        print num(\"4e2\");
+        print num(\"abc\");
+        print num(\"\");
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "1.5!\nniltrue\n43\n400\nnil\nnil\n");
+
+        let result = run_with_input("num(nil);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "num() expects a string or a number.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_sleep() {
+        let result = run_with_input("print sleep(0);", "");
+        assert_eq!(result.unwrap(), "nil\n");
+
+        let result = run_with_input("sleep(-5);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "sleep() expects a non-negative number of milliseconds.\n[line 1]"
+        );
+
+        let result = run_with_input("sleep(1000000000000000000000000000000);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "sleep() can't sleep for 1000000000000000000000000000000 milliseconds.\n[line 1]"
+        );
+
+        // Longer than an Instant can be, which sleeps until cancelled
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("sleep(10000000000 * 1000000000000);".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        let token = interpreter.cancel_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        let result = interpreter.execute(&ast, &statements);
+        canceller.join().unwrap();
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Execution cancelled.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_cancel_token() {
+        let source = "while (true) {}";
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        let token = interpreter.cancel_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        let result = interpreter.execute(&ast, &statements);
+        canceller.join().unwrap();
+        assert_eq!(result.err().unwrap().to_string(), "Execution cancelled.");
+
+        // The cancellation sticks until the token is reset
+        let mut scanner = Scanner::new("fun f() {} f();".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(result.err().unwrap().to_string(), "Execution cancelled.");
+        interpreter.cancel_token().reset();
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("var i = 0; while (true) { i = i + 1; }".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.set_step_limit(Some(1000));
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Step limit of 1000 exceeded."
+        );
+
+        // Each statement and expression is one step
+        let mut scanner = Scanner::new("print 1 + 2;".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.set_step_limit(Some(4));
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+        assert_eq!(interpreter.steps(), 4);
+        interpreter.set_step_limit(Some(3));
+        assert!(interpreter.execute(&ast, &statements).is_err());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let source = "var s = \"x\"; while (true) { s = s + s; }";
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.set_memory_limit(Some(1 << 20)).unwrap();
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Memory limit of 1048576 bytes exceeded."
+        );
+
+        // What other threads allocate doesn't count
+        let mut scanner = Scanner::new("var s = \"x\";".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.set_memory_limit(Some(1 << 20)).unwrap();
+        let held = std::thread::spawn(|| vec![0u8; 4 << 20]).join().unwrap();
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+        drop(held);
+    }
+
+    #[test]
+    fn test_native_class() {
+        let source = "
+        var counter = Counter(5);
+        counter.increment();
+        print counter.value();
+        counter.label = \"clicks\";
+        print counter.label;
+        class Double < Counter {
+            increment() {
+                super.increment();
+                super.increment();
+            }
+        }
+        var double = Double(0);
+        double.increment();
+        print double.value();
+        print Counter.value;
+        ";
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::with_output(VecWriter(Rc::clone(&output)));
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.define_native_class(
+            NativeClass::new("Counter")
+                .method("init", 1, |_, this, arguments| {
+                    let Value::Number(start) = arguments[0] else {
+                        return Err(InterpreterError {
+                            code: ErrorCode::InvalidArgument,
+                            message: "Counter() expects a number.".to_string(),
+                            line: None,
+                            span: None,
+                        });
+                    };
+                    this.borrow_mut().set_state(start);
+                    Ok(Value::Nil)
+                })
+                .method("increment", 0, |_, this, _| {
+                    *this.borrow_mut().state_mut::<f64>().unwrap() += 1.0;
+                    Ok(Value::Nil)
+                })
+                .method("value", 0, |_, this, _| {
+                    Ok(Value::Number(*this.borrow().state::<f64>().unwrap()))
+                }),
+        );
+        let mut resolver = Resolver::new();
+        assert!(resolver.resolve_stmts(&ast, &statements).is_ok());
+
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Only instances have properties.\n[line 16]"
+        );
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        assert_eq!(result, "6\nclicks\n2\n");
+    }
+
+    #[test]
+    fn test_builtin_json_and_arrays() {
+        let source = "
+        var config = jsonParse(input());
+        print config.name;
+        print type(config.sizes);
+        push(config.sizes, 3);
+        set(config.sizes, 0, nil);
+        print len(config.sizes) + get(config.sizes, 2);
+        print config.sizes;
+        print jsonStringify(config);
+        var list = array();
+        print list == list;
+        print list == array();
+        push(list, list);
+        print list;
+        ";
+
+        let result = run_with_input(source, "{\"name\": \"lox\", \"sizes\": [1, 2]}\n");
+        assert_eq!(
+            result.unwrap(),
+            "lox\narray\n6\n[nil, 2, 3]\n{\"name\":\"lox\",\"sizes\":[null,2,3]}\ntrue\nfalse\n[[...]]\n"
+        );
+
+        let result = run_with_input("jsonStringify(clock);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Can't convert <native fn> to JSON.\n[line 1]"
+        );
+
+        let result = run_with_input("get(array(), 0);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "get() index 0 is out of bounds for an array of length 0.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_regex() {
+        let source = "
+        var line = \"width=80 height=24\";
+        print regexMatch(\"^width=[0-9]+\", line);
+        print regexFindAll(\"[0-9]+\", line);
+        print regexReplace(\"[a-z]+=\", line, \"\");
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "true\n[80, 24]\n80 24\n");
+
+        let result = run_with_input("regexMatch(\"(\", \"\");", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid regex at position 1: missing ')'.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_split_join() {
+        let source = "
+        var parts = split(\"a,b,,c\", \",\");
+        print parts;
+        print len(split(\"héllo\", \"\"));
+        print join(parts, \"-\");
+        var mixed = array();
+        push(mixed, 1);
+        push(mixed, true);
+        push(mixed, nil);
+        print join(mixed, \" \");
+        print join(array(), \",\");
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "[a, b, , c]\n5\na-b--c\n1 true nil\n\n");
+
+        let result = run_with_input("join(\"abc\", \",\");", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "join() expects an array.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_characters() {
+        let source = "
+        var word = \"añb😀\";
+        print charAt(word, 1);
+        print charAt(word, 3);
+        print ord(charAt(word, 3));
+        print chr(ord(\"A\") + 1);
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "ñ\n😀\n128512\nB\n");
+
+        let result = run_with_input("charAt(\"ab\", 2);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "charAt() index 2 is out of bounds for a string of length 2.\n[line 1]"
+        );
+        let result = run_with_input("ord(\"ab\");", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "ord() expects a string of exactly one character.\n[line 1]"
+        );
+        let result = run_with_input("chr(55296);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "chr() expects a valid code point, got 55296.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_higher_order() {
+        let source = "
+        var numbers = split(\"3,1,2\", \",\");
+        fun toNumber(s) { return num(s); }
+        numbers = map(numbers, toNumber);
+        fun isBig(n) { return n > 1; }
+        fun add(a, b) { return a + b; }
+        fun descending(a, b) { return b - a; }
+        fun byLength(a, b) { return len(a) - len(b); }
+        fun inconsistent(a, b) { return 1; }
+        print numbers;
+        print filter(numbers, isBig);
+        print reduce(numbers, add, 0);
+        print sort(numbers, descending);
+        print numbers;
+        print sort(split(\"bb,a,cc,b\", \",\"), byLength);
+        print len(sort(split(\"a,b,c,d,e\", \",\"), inconsistent));
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(
+            result.unwrap(),
+            "[3, 1, 2]\n[3, 2]\n6\n[3, 2, 1]\n[3, 1, 2]\n[a, b, bb, cc]\n5\n"
+        );
+
+        let result = run_with_input("map(array(), clock);", "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "map() expects a function that takes 1 argument.\n[line 1]"
+        );
+        let source = "
+        fun fails(a, b) { return a.field; }
+        var values = split(\"a,b\", \",\");
+        sort(values, fails);
+        ";
+        let result = run_with_input(source, "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Only instances have properties.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_prelude() {
+        let source = "
+        var numbers = range(1, 5);
+        print numbers;
+        print max(3, 7) + min(3, 7);
+        print clampTo(12, 0, 10) + abs(-2);
+        print indexOf(numbers, 3);
+        print contains(numbers, 9);
+        print sum(numbers);
+        ";
+
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "[1, 2, 3, 4]\n10\n12\n2\nfalse\n10\n");
+
+        // Unaffected by scripts that define functions of the same names
+        let source = "
+        fun len(list) { return 0; }
+        fun get(list, index) { return nil; }
+        fun push(list, value) {}
+        fun max(a, b) { return nil; }
+        fun min(a, b) { return nil; }
+        fun indexOf(list, value) { return -1; }
+        var numbers = range(1, 5);
+        print clampTo(-3, 0, 10);
+        print contains(numbers, 4);
+        print sum(numbers);
+        ";
+        let result = run_with_input(source, "");
+        assert_eq!(result.unwrap(), "0\ntrue\n10\n");
+
+        let globals = Interpreter::without_prelude().globals;
+        assert!(globals.borrow().get(&"max".to_string()).is_none());
+    }
+
+    #[test]
+    fn test_builtin_args() {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("print args(); print len(args());".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.output = Box::new(VecWriter(Rc::clone(&output)));
+        interpreter.script_args = vec!["one".to_string(), "--two".to_string()];
+
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        assert_eq!(result, "[one, --two]\n2\n");
+    }
+
+    #[test]
+    fn test_define_native() {
+        let source = "print double(21); print double; double(\"x\");";
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::with_output(VecWriter(Rc::clone(&output)));
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.define_native("double", 1, |_, arguments| match &arguments[0] {
+            Value::Number(n) => Ok(Value::Number(n * 2.0)),
+            _ => Err(InterpreterError {
+                code: ErrorCode::InvalidArgument,
+                message: "double() expects a number.".to_string(),
+                line: None,
+                span: None,
+            }),
+        });
+
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "double() expects a number.\n[line 1]"
+        );
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        assert_eq!(result, "42\n<native fn>\n");
+    }
+
+    #[test]
+    fn test_native_handles() {
+        let source = "
+        var a = open(\"a\");
+        var b = open(\"b\");
+        print a;
+        print type(a);
+        print name(b);
+        print a == a;
+        print a == b;
+        name(\"a\");
+        ";
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::with_output(VecWriter(Rc::clone(&output)));
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.define_native("open", 1, |_, arguments| {
+            let path = String::from_lox(&arguments[0])?;
+            Ok(NativeHandle::new("File", path).to_lox())
+        });
+        interpreter.define_native("name", 1, |_, arguments| {
+            let handle = NativeHandle::from_lox(&arguments[0])?;
+            Ok(handle.downcast_ref::<String>().unwrap().clone().to_lox())
+        });
+
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Expected a handle but got string.\n[line 9]"
+        );
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        assert_eq!(result, "<File handle>\nFile\nb\ntrue\nfalse\n");
+    }
+
+    struct Tracer(Rc<RefCell<Vec<String>>>);
+
+    impl InterpreterHooks for Tracer {
+        fn on_statement(&mut self, _statement: &Stmt) {
+            self.0.borrow_mut().push("statement".to_string());
+        }
+
+        fn on_call(&mut self, callable: &Callable, arguments: &[Value]) {
+            self.0
+                .borrow_mut()
+                .push(format!("call {} with {}", callable, arguments.len()));
+        }
+
+        fn on_return(&mut self, callable: &Callable, value: &Value) {
+            self.0
+                .borrow_mut()
+                .push(format!("return {} from {}", value, callable));
+        }
+
+        fn on_error(&mut self, error: &InterpreterError) {
+            self.0.borrow_mut().push(format!("error {}", error));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let source = "fun double(n) { return n * 2; } var a = double(2); double(nil);";
+        let mut interpreter = Interpreter::without_prelude();
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        interpreter.set_hooks(Some(Box::new(Tracer(Rc::clone(&trace)))));
+        let mut resolver = Resolver::new();
+        assert!(resolver.resolve_stmts(&ast, &statements).is_ok());
+
+        assert!(interpreter.execute(&ast, &statements).is_err());
+        assert_eq!(
+            *trace.borrow(),
+            vec![
+                "statement",
+                "statement",
+                "call <fn double> with 1",
+                "statement",
+                "return 4 from <fn double>",
+                "statement",
+                "call <fn double> with 1",
+                "statement",
+                "error Operands must be numbers.\n[line 1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // Deeper than the stack would allow without tail calls
+        let source =
+            "fun count(n, total) { if (n == 0) return total; return count(n - 1, total + 1); }\n\
+                      print count(200000, 0);\n\
+                      fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }\n\
+                      fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }\n\
+                      print isEven(100001);\n\
+                      class A { init(n) { this.n = n; } get() { return this.n; } }\n\
+                      fun make(n) { return A(n); }\n\
+                      fun read(a) { return a.get(); }\n\
+                      print read(make(3));";
+        assert_eq!(run(source.to_string()).unwrap(), "200000\nfalse\n3\n");
+
+        let source = "fun f(n) { if (n == 0) return 0; return f(n - 1); } f(1);";
+        let mut interpreter = Interpreter::without_prelude();
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        interpreter.set_hooks(Some(Box::new(Tracer(Rc::clone(&trace)))));
+        let mut resolver = Resolver::new();
+        assert!(resolver.resolve_stmts(&ast, &statements).is_ok());
+
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+        assert_eq!(
+            *trace.borrow(),
+            vec![
+                "statement",
+                "statement",
+                "call <fn f> with 1",
+                "statement",
+                "statement",
+                "call <fn f> with 1",
+                "statement",
+                "statement",
+                "return 0 from <fn f>",
+                "return 0 from <fn f>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environment_pool() {
+        let mut interpreter = Interpreter::without_prelude();
+        let execute = |interpreter: &mut Interpreter, source: &str| {
+            let mut scanner = Scanner::new(source.to_string());
+            let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+            let statements = parser.parse().unwrap();
+            let ast = Rc::new(parser.into_ast());
+            assert!(Resolver::new().resolve_stmts(&ast, &statements).is_ok());
+            interpreter.execute(&ast, &statements).unwrap();
+        };
+        execute(
+            &mut interpreter,
+            "fun add(a, b) { var sum = a + b; { var twice = sum * 2; } return sum; }\n\
+             fun make(n) { fun get() { return n; } return get; }\n\
+             var total = add(1, 2);",
+        );
+        // The environments of the call and the block are reused
+        let pooled = interpreter.environment_pool.len();
+        assert!(pooled > 0);
+        execute(&mut interpreter, "total = total + add(3, 4);");
+        assert_eq!(interpreter.environment_pool.len(), pooled);
+        assert_eq!(interpreter.get_global::<f64>("total").unwrap(), 10.0);
+        // But not those closures refer to
+        execute(
+            &mut interpreter,
+            "var first = make(1); var second = make(2);",
+        );
+        assert_eq!(interpreter.eval("first()").unwrap(), Value::Number(1.0));
+        assert_eq!(interpreter.eval("second()").unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_interned_strings() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("s", "text");
+        let first = interpreter.eval("\"lox\"").unwrap();
+        let second = interpreter.eval("\"lox\"").unwrap();
+        match (&first, &second) {
+            (Value::String(first), Value::String(second)) => assert!(Rc::ptr_eq(first, second)),
+            _ => panic!("expected strings"),
+        }
+        // Copies share the string too
+        let copy = interpreter.eval("s").unwrap();
+        let global = interpreter.globals.borrow().get(&"s".to_string()).unwrap();
+        match (&copy, &global) {
+            (Value::String(copy), Value::String(global)) => assert!(Rc::ptr_eq(copy, global)),
+            _ => panic!("expected strings"),
+        }
+        assert_eq!(
+            interpreter.eval("\"lo\" + \"x\" == \"lox\"").unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_string_accumulation() {
+        let source = "var s = \"\"; for (var i = 0; i < 1000; i = i + 1) { s = s + \"ab\"; }\n\
+                      print len(s); var t = s; s = s + \"!\"; print len(t); print len(s);";
+        assert_eq!(run_with_input(source, "").unwrap(), "2000\n2000\n2001\n");
+
+        // A string only the variable refers to is appended to in place
+        let mut interpreter = Interpreter::without_prelude();
+        let mut execute = |source: &str| {
+            let mut scanner = Scanner::new(source.to_string());
+            let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+            let statements = parser.parse().unwrap();
+            let ast = Rc::new(parser.into_ast());
+            assert!(Resolver::new().resolve_stmts(&ast, &statements).is_ok());
+            interpreter.execute(&ast, &statements).unwrap();
+            match interpreter.globals.borrow().get(&"s".to_string()) {
+                Some(Value::String(string)) => Rc::as_ptr(&string),
+                _ => panic!("expected a string"),
+            }
+        };
+        let first = execute("var s = \"a\"; s = s + \"b\";");
+        assert_eq!(execute("s = s + \"c\";"), first);
+    }
+
+    #[test]
+    fn test_shared_declarations() {
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("fun f() {}\nclass A { m() {} }\nvar a = A();".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.execute(&ast, &statements).unwrap();
+        // Reading a function or binding a method doesn't copy its body
+        let declaration = |value: Value| match value {
+            Value::Callable(Callable::Function(function)) => function.declaration,
+            _ => panic!("expected a function"),
+        };
+        let first = declaration(interpreter.eval("f").unwrap());
+        let second = declaration(interpreter.eval("f").unwrap());
+        assert!(Rc::ptr_eq(&first, &second));
+        let first = declaration(interpreter.eval("a.m").unwrap());
+        let second = declaration(interpreter.eval("a.m").unwrap());
+        assert!(Rc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("price", 4.0);
+        let value = interpreter.eval("max(price * 2, 5) + 1").unwrap();
+        assert_eq!(value, Value::Number(9.0));
+        let value = interpreter.eval("\"a\" + \"b\"").unwrap();
+        assert_eq!(value, Value::String(Rc::new("ab".to_string())));
+
+        match interpreter.eval("1 +") {
+            Err(LoxError::Syntax(errors)) => {
+                assert_eq!(
+                    errors[0].to_string(),
+                    "[line 1] Error at end: Expect expression."
+                )
+            }
+            _ => panic!("expected a syntax error"),
+        }
+        assert!(matches!(
+            interpreter.eval("print 1;"),
+            Err(LoxError::Syntax(_))
+        ));
+        match interpreter.eval("-\"a\"") {
+            Err(LoxError::Runtime(err)) => {
+                assert_eq!(err.to_string(), "Operand must be a number.\n[line 1]")
+            }
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let source = "
+        var ticks = 0;
+        fun tick(n) {
+            ticks = ticks + n;
+            return ticks;
+        }
+        onTick(tick);
+        print fire(2);
+        ";
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::with_output(VecWriter(Rc::clone(&output)));
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.define_native("onTick", 1, |interpreter, arguments| {
+            interpreter.store_callback("tick", arguments[0].clone())?;
+            Ok(Value::Nil)
+        });
+        interpreter.define_native("fire", 1, |interpreter, arguments| {
+            interpreter.invoke_callback("tick", arguments)
+        });
+        let mut resolver = Resolver::new();
+        assert!(resolver.resolve_stmts(&ast, &statements).is_ok());
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+
+        let value = interpreter.invoke_callback("tick", vec![Value::Number(3.0)]);
+        assert_eq!(value.unwrap(), Value::Number(5.0));
+        assert_eq!(interpreter.get_global::<f64>("ticks").unwrap(), 5.0);
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        assert_eq!(result, "2\n");
+
+        assert_eq!(
+            interpreter
+                .invoke_callback("tick", vec![])
+                .unwrap_err()
+                .to_string(),
+            "Expected 1 arguments but got 0."
+        );
+        assert!(interpreter.remove_callback("tick"));
+        assert_eq!(
+            interpreter
+                .invoke_callback("tick", vec![])
+                .unwrap_err()
+                .to_string(),
+            "Undefined callback 'tick'."
+        );
+        assert_eq!(
+            interpreter
+                .store_callback("x", Value::Nil)
+                .unwrap_err()
+                .to_string(),
+            "Callback 'x' must be a function."
+        );
+    }
+
+    #[test]
+    fn test_globals_from_rust() {
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("var total = sum(numbers); var name = nil;".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+        interpreter.set_global("numbers", vec![1.0, 2.0, 3.5]);
+
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+        assert_eq!(interpreter.get_global::<f64>("total").unwrap(), 6.5);
+        assert_eq!(
+            interpreter.get_global::<Option<String>>("name").unwrap(),
+            None
+        );
+        assert_eq!(
+            interpreter
+                .get_global::<f64>("missing")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Undefined variable 'missing'."
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_builtin_exec() {
+        let source = "
+        print exitCode();
+        print exec(\"echo hello\");
+        print exitCode();
+        exec(\"exit 3\");
+        print exitCode();
+        ";
+
+        let result = run_with_builder(source, Interpreter::builder().allow_exec(true));
+        assert_eq!(result.unwrap(), "nil\nhello\n\n0\n3\n");
+    }
+
+    #[test]
+    fn test_builtin_exec_disabled() {
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new("exec(\"echo hello\");".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+
+        // Embedders have to opt in to running commands
+        let result = interpreter.execute(&ast, &statements);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "exec() is disabled in this interpreter.\n[line 1]"
+        );
+    }
+
+    #[test]
+    fn test_builtin_print_err() {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let error_output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut interpreter = Interpreter::builder()
+            .output(VecWriter(Rc::clone(&output)))
+            .error_output(VecWriter(Rc::clone(&error_output)))
+            .build();
+        let mut scanner = Scanner::new("print 1; printErr(\"oops\"); print 2;".to_string());
+        let mut parser = Parser::with_ids(scanner.take_tokens(), interpreter.node_ids.clone());
+        let statements = parser.parse().unwrap();
+        let ast = Rc::new(parser.into_ast());
+
+        assert!(interpreter.execute(&ast, &statements).is_ok());
+        assert_eq!(String::from_utf8_lossy(&output.borrow()), "1\n2\n");
+        assert_eq!(String::from_utf8_lossy(&error_output.borrow()), "oops\n");
+    }
+}
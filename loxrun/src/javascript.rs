@@ -0,0 +1,731 @@
+//! Lowers a resolved program to JavaScript that runs without the
+//! interpreter. The output starts with a small runtime for what JavaScript
+//! does differently: Lox's truthiness, `and` and `or` returning an operand,
+//! printing values the way Lox does, and classes that are called instead of
+//! constructed with `new`. Its names start with `$`, which Lox identifiers
+//! can't contain, and Lox names that JavaScript reserves get a `$` appended.
+//! Locals that shadow another variable get a `$` and a number appended, as a
+//! `let` is visible in the whole of its block and not only after it.
+//!
+//! Runtime errors aren't reproduced: adding a number to a string or calling
+//! a function with the wrong number of arguments does what it does in
+//! JavaScript. Of the native functions only `clock`, `str` and the array
+//! functions are provided.
+
+use std::collections::HashSet;
+
+use liblox::tokens::{LiteralTypes, Token, TokenType};
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::*;
+use crate::json;
+use crate::stmt::{FunctionStmt, Stmt};
+
+const RUNTIME: &str = r#"// Lox runtime
+const $CLASS = Symbol("class");
+function $truthy(value) {
+  return value !== null && value !== undefined && value !== false;
+}
+function $and(left, right) {
+  return $truthy(left) ? right() : left;
+}
+function $or(left, right) {
+  return $truthy(left) ? left : right();
+}
+function $number(value) {
+  if (Object.is(value, -0)) return "-0";
+  if (value === Infinity) return "inf";
+  if (value === -Infinity) return "-inf";
+  const text = String(value);
+  if (!text.includes("e")) return text;
+  // Written out in full, as Lox never prints an exponent
+  const sign = value < 0 ? "-" : "";
+  const [mantissa, exponent] = text.slice(sign.length).split("e");
+  const digits = mantissa.replace(".", "");
+  const point = (mantissa.includes(".") ? mantissa.indexOf(".") : mantissa.length) + Number(exponent);
+  if (point <= 0) return `${sign}0.${"0".repeat(-point)}${digits}`;
+  if (point >= digits.length) return sign + digits + "0".repeat(point - digits.length);
+  return `${sign}${digits.slice(0, point)}.${digits.slice(point)}`;
+}
+function $string(value) {
+  if (value === null || value === undefined) return "nil";
+  if (typeof value === "number") return $number(value);
+  if (Array.isArray(value)) return "[" + value.map($string).join(", ") + "]";
+  if (typeof value === "function") return value[$CLASS] ? value.name : `<fn ${value.name}>`;
+  if (typeof value === "object") return `${value.constructor.name} instance`;
+  return String(value);
+}
+function $print(value) {
+  console.log($string(value));
+}
+function $iter(value) {
+  if (!Array.isArray(value)) return value.iter();
+  // Like Lox, sees elements pushed while the loop runs
+  let index = 0;
+  return { done: () => index >= value.length, next: () => value[index++] };
+}
+function $class(name, cls) {
+  Object.defineProperty(cls, "name", { value: name });
+  cls[$CLASS] = true;
+  return new Proxy(cls, {
+    apply(target, thisArg, args) {
+      const instance = new target();
+      for (let proto = target.prototype; proto !== Object.prototype; proto = Object.getPrototypeOf(proto)) {
+        for (const key of Object.getOwnPropertyNames(proto)) {
+          if (key !== "constructor" && !Object.hasOwn(instance, key)) {
+            instance[key] = proto[key].bind(instance);
+          }
+        }
+      }
+      if (instance.init) instance.init(...args);
+      return instance;
+    },
+  });
+}
+function clock() { return Date.now() / 1000; }
+function str(value) { return $string(value); }
+function array() { return []; }
+function len(value) { return Array.isArray(value) ? value.length : [...value].length; }
+function push(array, value) { array.push(value); return null; }
+function get(array, index) { return array[index]; }
+function set(array, index, value) { array[index] = value; return null; }
+
+"#;
+
+// Words JavaScript reserves or the runtime relies on
+const RESERVED: &[&str] = &[
+    "arguments",
+    "Array",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "console",
+    "const",
+    "continue",
+    "Date",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "finally",
+    "function",
+    "implements",
+    "import",
+    "in",
+    "Infinity",
+    "instanceof",
+    "interface",
+    "let",
+    "NaN",
+    "new",
+    "null",
+    "Object",
+    "package",
+    "private",
+    "protected",
+    "Proxy",
+    "public",
+    "static",
+    "String",
+    "switch",
+    "Symbol",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "void",
+    "with",
+    "yield",
+];
+
+// Functions of the runtime that Lox code calls as globals
+const NATIVES: &[&str] = &["clock", "str", "array", "len", "push", "get", "set"];
+
+/// Translates the statements, which the resolver accepted, to a JavaScript
+/// script.
+pub fn to_javascript(ast: &Ast, statements: &[StmtId]) -> String {
+    let mut globals: HashSet<String> = NATIVES.iter().map(|native| native.to_string()).collect();
+    for &statement in statements {
+        match &ast[statement] {
+            Stmt::Var(stmt) => globals.insert(stmt.name.lexeme.clone()),
+            Stmt::Function(function) => globals.insert(function.name.lexeme.clone()),
+            Stmt::Class(class) => globals.insert(class.name.lexeme.clone()),
+            _ => false,
+        };
+    }
+    let mut emitter = Emitter {
+        ast,
+        output: String::from(RUNTIME),
+        indent: 0,
+        depth: 0,
+        in_initializer: false,
+        scopes: Vec::new(),
+        globals,
+        renamed: 0,
+    };
+    for &statement in statements {
+        emitter.begin_line();
+        emitter.statement(statement);
+        emitter.output.push('\n');
+    }
+    emitter.output
+}
+
+fn name(token: &Token) -> String {
+    if RESERVED.contains(&token.lexeme.as_str()) {
+        format!("{}$", token.lexeme)
+    } else {
+        token.lexeme.clone()
+    }
+}
+
+// Property names may be reserved words, except for the one that would make
+// a method the class's constructor
+fn property(token: &Token) -> String {
+    if token.lexeme == "constructor" {
+        "constructor$".to_string()
+    } else {
+        token.lexeme.clone()
+    }
+}
+
+// Binding strength of JavaScript's operators
+const ASSIGNMENT: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const TERM: u8 = 6;
+const FACTOR: u8 = 7;
+const UNARY: u8 = 8;
+const CALL: u8 = 9;
+const PRIMARY: u8 = 10;
+
+fn operator_precedence(operator: &TokenType) -> u8 {
+    match operator {
+        TokenType::Or => OR,
+        TokenType::And => AND,
+        TokenType::BangEqual | TokenType::EqualEqual => EQUALITY,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            COMPARISON
+        }
+        TokenType::Minus | TokenType::Plus => TERM,
+        _ => FACTOR,
+    }
+}
+
+fn is_nil(ast: &Ast, expression: ExprId) -> bool {
+    match &ast[expression] {
+        Expression::Literal(literal) => literal.value == LiteralTypes::Nil,
+        Expression::Grouping(grouping) => is_nil(ast, grouping.expression),
+        _ => false,
+    }
+}
+
+// Whether the expression always evaluates to `true` or `false`, for which
+// JavaScript's truthiness is Lox's
+fn is_boolean(ast: &Ast, expression: ExprId) -> bool {
+    match &ast[expression] {
+        Expression::Binary(binary) => {
+            operator_precedence(&binary.operator.token_type) <= COMPARISON
+        }
+        Expression::Logical(logical) => {
+            is_boolean(ast, logical.left) && is_boolean(ast, logical.right)
+        }
+        Expression::Unary(unary) => unary.operator.token_type == TokenType::Bang,
+        Expression::Literal(literal) => matches!(literal.value, LiteralTypes::Bool(_)),
+        Expression::Grouping(grouping) => is_boolean(ast, grouping.expression),
+        _ => false,
+    }
+}
+
+struct Emitter<'a> {
+    ast: &'a Ast,
+    output: String,
+    indent: usize,
+    // Scopes the current statement is nested in; globals are at depth 0
+    depth: usize,
+    // In the body of an `init` method, where `return` returns the instance
+    in_initializer: bool,
+    // The Lox and JavaScript names of the locals of the scopes the resolver
+    // opened, in the order of their slots
+    scopes: Vec<Vec<(String, String)>>,
+    // Names of the global variables
+    globals: HashSet<String>,
+    // Number of locals renamed so far, which numbers the next
+    renamed: usize,
+}
+
+impl Emitter<'_> {
+    fn begin_line(&mut self) {
+        for _ in 0..self.indent {
+            self.output.push_str("  ");
+        }
+    }
+
+    fn declaration_keyword(&self) -> &'static str {
+        // `let` can't declare a global again, which Lox allows
+        if self.depth == 0 {
+            "var"
+        } else {
+            "let"
+        }
+    }
+
+    // Adds a local to the innermost scope, under the JavaScript name
+    fn bind(&mut self, token: &Token, js_name: String) -> String {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((token.lexeme.clone(), js_name.clone()));
+        }
+        js_name
+    }
+
+    // Declares a variable, renaming a local that shadows another variable.
+    // Code in its block that runs before the declaration means the other
+    // one, which a `let` of the same name would hide.
+    fn declare(&mut self, token: &Token) -> String {
+        let shadows = !self.scopes.is_empty()
+            && (self.globals.contains(&token.lexeme)
+                || self
+                    .scopes
+                    .iter()
+                    .flatten()
+                    .any(|(lox_name, _)| *lox_name == token.lexeme));
+        let js_name = if shadows {
+            self.renamed += 1;
+            format!("{}${}", token.lexeme, self.renamed)
+        } else {
+            name(token)
+        };
+        self.bind(token, js_name)
+    }
+
+    // The JavaScript name of the variable the resolver bound the name to
+    fn variable(&self, token: &Token, resolved: Option<LocalSlot>) -> String {
+        match resolved {
+            Some(LocalSlot { depth, slot }) => {
+                self.scopes[self.scopes.len() - 1 - depth][slot].1.clone()
+            }
+            None => name(token),
+        }
+    }
+
+    // Writes the statements on lines of their own, between braces
+    fn block(&mut self, statements: &[StmtId], epilogue: Option<&str>) {
+        if statements.is_empty() && epilogue.is_none() {
+            self.output.push_str("{}");
+            return;
+        }
+        self.output.push_str("{\n");
+        self.indent += 1;
+        self.depth += 1;
+        for &statement in statements {
+            self.begin_line();
+            self.statement(statement);
+            self.output.push('\n');
+        }
+        if let Some(epilogue) = epilogue {
+            self.begin_line();
+            self.output.push_str(epilogue);
+            self.output.push('\n');
+        }
+        self.depth -= 1;
+        self.indent -= 1;
+        self.begin_line();
+        self.output.push('}');
+    }
+
+    // Opens the scope of the function, returning its parameter list.
+    // Nothing can refer to a variable the parameters shadow before them, so
+    // they keep their names.
+    fn params(&mut self, function: &FunctionStmt) -> String {
+        self.scopes.push(Vec::new());
+        let params: Vec<String> = function
+            .params
+            .iter()
+            .map(|param| self.bind(param, name(param)))
+            .collect();
+        params.join(", ")
+    }
+
+    // Writes the body of the function whose parameters were written last
+    fn function_body(&mut self, function: &FunctionStmt, in_initializer: bool) {
+        let outer = std::mem::replace(&mut self.in_initializer, in_initializer);
+        let epilogue = in_initializer.then_some("return this;");
+        self.block(&function.body, epilogue);
+        self.in_initializer = outer;
+        self.scopes.pop();
+    }
+
+    // Writes a condition, leaving out the truthiness check if it can't
+    // make a difference
+    fn condition(&mut self, condition: ExprId) {
+        if is_boolean(self.ast, condition) {
+            self.expression(condition, ASSIGNMENT);
+        } else {
+            self.output.push_str("$truthy(");
+            self.expression(condition, ASSIGNMENT);
+            self.output.push(')');
+        }
+    }
+
+    fn statement(&mut self, statement: StmtId) {
+        let ast = self.ast;
+        match &ast[statement] {
+            Stmt::Expression(stmt) => {
+                self.expression(stmt.expression, ASSIGNMENT);
+                self.output.push(';');
+            }
+            Stmt::Print(stmt) => {
+                self.output.push_str("$print(");
+                self.expression(stmt.expression, ASSIGNMENT);
+                self.output.push_str(");");
+            }
+            Stmt::Var(stmt) => {
+                let keyword = self.declaration_keyword();
+                let name = self.declare(&stmt.name);
+                self.output.push_str(&format!("{} {} = ", keyword, name));
+                match stmt.initializer {
+                    Some(initializer) => self.expression(initializer, ASSIGNMENT),
+                    None => self.output.push_str("null"),
+                }
+                self.output.push(';');
+            }
+            Stmt::Block(block) => {
+                // The resolver opens a scope only for blocks that need one
+                let scoped = block.declares_variables(ast);
+                if scoped {
+                    self.scopes.push(Vec::new());
+                }
+                self.block(&block.statements, None);
+                if scoped {
+                    self.scopes.pop();
+                }
+            }
+            Stmt::If(stmt) => {
+                self.output.push_str("if (");
+                self.condition(stmt.condition);
+                self.output.push_str(") ");
+                self.statement(stmt.then_branch);
+                if let Some(else_branch) = stmt.else_branch {
+                    if self.output.ends_with('}') {
+                        self.output.push(' ');
+                    } else {
+                        self.output.push('\n');
+                        self.begin_line();
+                    }
+                    self.output.push_str("else ");
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While(stmt) => {
+                self.output.push_str("while (");
+                self.condition(stmt.condition);
+                self.output.push_str(") ");
+                self.statement(stmt.body);
+            }
+            Stmt::ForIn(stmt) => {
+                self.output.push_str("for (const $iterator = $iter(");
+                self.expression(stmt.iterable, ASSIGNMENT);
+                self.output.push_str("); !$truthy($iterator.done()); ) {\n");
+                self.indent += 1;
+                self.depth += 1;
+                self.scopes.push(Vec::new());
+                // Declared before the body, so it needn't be renamed
+                let name = self.bind(&stmt.name, name(&stmt.name));
+                self.begin_line();
+                self.output
+                    .push_str(&format!("let {} = $iterator.next();\n", name));
+                self.begin_line();
+                self.statement(stmt.body);
+                self.output.push('\n');
+                self.scopes.pop();
+                self.depth -= 1;
+                self.indent -= 1;
+                self.begin_line();
+                self.output.push('}');
+            }
+            Stmt::Return(stmt) => match stmt.value {
+                _ if self.in_initializer => self.output.push_str("return this;"),
+                Some(value) => {
+                    self.output.push_str("return ");
+                    self.expression(value, ASSIGNMENT);
+                    self.output.push(';');
+                }
+                None => self.output.push_str("return null;"),
+            },
+            Stmt::Function(function) => {
+                let name = self.declare(&function.name);
+                let params = self.params(function);
+                if self.depth == 0 {
+                    self.output
+                        .push_str(&format!("function {}({}) ", name, params));
+                    self.function_body(function, false);
+                } else {
+                    // Arrow functions see the `this` of the method they are in
+                    self.output
+                        .push_str(&format!("const {} = ({}) => ", name, params));
+                    self.function_body(function, false);
+                    self.output.push(';');
+                }
+            }
+            Stmt::Class(class) => {
+                let keyword = self.declaration_keyword();
+                let name = self.declare(&class.name);
+                self.output.push_str(&format!(
+                    "{} {} = $class(\"{}\", class",
+                    keyword, name, class.name.lexeme
+                ));
+                if let Some(superclass) = &class.superclass {
+                    self.output.push_str(" extends ");
+                    let superclass = self.variable(&superclass.name, superclass.resolved.get());
+                    self.output.push_str(&superclass);
+                    self.scopes
+                        .push(vec![("super".to_string(), "super".to_string())]);
+                }
+                self.scopes
+                    .push(vec![("this".to_string(), "this".to_string())]);
+                self.output.push_str(" {\n");
+                self.indent += 1;
+                for method in &class.methods {
+                    self.begin_line();
+                    let params = self.params(method);
+                    self.output
+                        .push_str(&format!("{}({}) ", property(&method.name), params));
+                    self.depth += 1;
+                    self.function_body(method, method.name.lexeme == "init");
+                    self.depth -= 1;
+                    self.output.push('\n');
+                }
+                self.scopes.pop();
+                if class.superclass.is_some() {
+                    self.scopes.pop();
+                }
+                self.indent -= 1;
+                self.begin_line();
+                self.output.push_str("});");
+            }
+        }
+    }
+
+    // Writes an expression, parenthesized if it binds less tightly than
+    // `min_precedence`
+    fn expression(&mut self, mut expression: ExprId, min_precedence: u8) {
+        let ast = self.ast;
+        while let Expression::Grouping(grouping) = &ast[expression] {
+            expression = grouping.expression;
+        }
+        let precedence = self.precedence(expression);
+        if precedence < min_precedence {
+            self.output.push('(');
+        }
+        match &ast[expression] {
+            Expression::Assign(assign) => {
+                let name = self.variable(&assign.name, assign.resolved.get());
+                self.output.push_str(&name);
+                self.output.push_str(" = ");
+                self.expression(assign.value, ASSIGNMENT);
+            }
+            Expression::Binary(binary) => {
+                let nil_check = is_nil(ast, binary.left) || is_nil(ast, binary.right);
+                let operator = match binary.operator.token_type {
+                    // Loosely, as `undefined` stands for `nil` as well
+                    TokenType::EqualEqual if nil_check => "==",
+                    TokenType::BangEqual if nil_check => "!=",
+                    TokenType::EqualEqual => "===",
+                    TokenType::BangEqual => "!==",
+                    _ => binary.operator.lexeme.as_str(),
+                };
+                self.expression(binary.left, precedence);
+                self.output.push_str(&format!(" {} ", operator));
+                self.expression(binary.right, precedence + 1);
+            }
+            Expression::Logical(logical) if is_boolean(ast, logical.left) => {
+                let operator = match logical.operator.token_type {
+                    TokenType::And => "&&",
+                    _ => "||",
+                };
+                self.expression(logical.left, precedence);
+                self.output.push_str(&format!(" {} ", operator));
+                self.expression(logical.right, precedence + 1);
+            }
+            Expression::Logical(logical) => {
+                let helper = match logical.operator.token_type {
+                    TokenType::And => "$and",
+                    _ => "$or",
+                };
+                self.output.push_str(helper);
+                self.output.push('(');
+                self.expression(logical.left, ASSIGNMENT);
+                self.output.push_str(", () => ");
+                self.expression(logical.right, ASSIGNMENT);
+                self.output.push(')');
+            }
+            Expression::Call(call) => {
+                match &ast[call.callee] {
+                    // Called right away, so it needs no binding
+                    Expression::Super(super_expr) => self
+                        .output
+                        .push_str(&format!("super.{}", property(&super_expr.method))),
+                    _ => self.expression(call.callee, CALL),
+                }
+                self.output.push('(');
+                for (i, &argument) in call.arguments.iter().enumerate() {
+                    if i > 0 {
+                        self.output.push_str(", ");
+                    }
+                    self.expression(argument, ASSIGNMENT);
+                }
+                self.output.push(')');
+            }
+            Expression::Get(get) => {
+                self.expression(get.object, CALL);
+                self.output.push('.');
+                self.output.push_str(&property(&get.name));
+            }
+            Expression::Grouping(_) => unreachable!("groupings are unwrapped"),
+            Expression::Literal(literal) => match &literal.value {
+                LiteralTypes::String(s) => json::write_string(&mut self.output, s),
+                LiteralTypes::Number(n) if n.is_nan() => self.output.push_str("NaN"),
+                LiteralTypes::Number(n) if n.is_infinite() => {
+                    self.output
+                        .push_str(if *n > 0.0 { "Infinity" } else { "-Infinity" })
+                }
+                LiteralTypes::Number(n) => self.output.push_str(&n.to_string()),
+                LiteralTypes::Bool(b) => self.output.push_str(&b.to_string()),
+                LiteralTypes::Nil => self.output.push_str("null"),
+            },
+            Expression::Set(set) => {
+                self.expression(set.object, CALL);
+                self.output.push('.');
+                self.output.push_str(&property(&set.name));
+                self.output.push_str(" = ");
+                self.expression(set.value, ASSIGNMENT);
+            }
+            Expression::Super(super_expr) => {
+                // A method taken off `super` stays bound to the instance
+                self.output.push_str(&format!(
+                    "super.{}.bind(this)",
+                    property(&super_expr.method)
+                ));
+            }
+            Expression::This(_) => self.output.push_str("this"),
+            Expression::Unary(unary) => {
+                self.output.push_str(&unary.operator.lexeme);
+                if unary.operator.token_type == TokenType::Bang && !is_boolean(ast, unary.right) {
+                    self.output.push_str("$truthy(");
+                    self.expression(unary.right, ASSIGNMENT);
+                    self.output.push(')');
+                } else {
+                    // `- -a` rather than `--a`
+                    if self.precedence(unary.right) == UNARY {
+                        self.output.push(' ');
+                    }
+                    self.expression(unary.right, UNARY);
+                }
+            }
+            Expression::Variable(variable) => {
+                let name = self.variable(&variable.name, variable.resolved.get());
+                self.output.push_str(&name);
+            }
+        }
+        if precedence < min_precedence {
+            self.output.push(')');
+        }
+    }
+
+    fn precedence(&self, expression: ExprId) -> u8 {
+        let ast = self.ast;
+        match &ast[expression] {
+            Expression::Assign(_) | Expression::Set(_) => ASSIGNMENT,
+            Expression::Binary(binary) => operator_precedence(&binary.operator.token_type),
+            Expression::Logical(logical) if is_boolean(ast, logical.left) => {
+                operator_precedence(&logical.operator.token_type)
+            }
+            Expression::Unary(_) => UNARY,
+            Expression::Literal(Literal {
+                value: LiteralTypes::Number(n),
+                ..
+            }) if n.is_sign_negative() && !n.is_nan() => UNARY,
+            Expression::Call(_) | Expression::Get(_) | Expression::Super(_) => CALL,
+            Expression::Grouping(grouping) => self.precedence(grouping.expression),
+            _ => PRIMARY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use liblox::scanner::Scanner;
+
+    // The translation of the source, without the runtime
+    fn translate(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        Resolver::new()
+            .resolve_stmts(parser.ast(), &statements)
+            .unwrap();
+        let javascript = to_javascript(parser.ast(), &statements);
+        javascript.strip_prefix(RUNTIME).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(
+            translate(
+                "print (1 + 2) * -3 == nil; print a or b; print a < b and !c;\n\
+                 var delete = !x; print a != b and f(1)(2).c;"
+            ),
+            "$print((1 + 2) * -3 == null);\n\
+             $print($or(a, () => b));\n\
+             $print(a < b && !$truthy(c));\n\
+             var delete$ = !$truthy(x);\n\
+             $print(a !== b && f(1)(2).c);\n"
+        );
+    }
+
+    #[test]
+    fn test_functions_and_classes() {
+        assert_eq!(
+            translate(
+                "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }\n\
+                 class B < A { init(x) { super.init(); this.x = x; if (x) return; } get() { return super.get; } }\n\
+                 for (var i = 0; i < 3; i = i + 1) print i;\n\
+                 for (var item in items) print item;"
+            ),
+            "function counter() {\n  let n = 0;\n  const next = () => {\n    n = n + 1;\n    return n;\n  };\n  return next;\n}\n\
+             var B = $class(\"B\", class extends A {\n  init(x) {\n    super.init();\n    this.x = x;\n    \
+             if ($truthy(x)) return this;\n    return this;\n  }\n  get() {\n    return super.get.bind(this);\n  }\n});\n\
+             {\n  let i = 0;\n  while (i < 3) {\n    $print(i);\n    i = i + 1;\n  }\n}\n\
+             for (const $iterator = $iter(items); !$truthy($iterator.done()); ) {\n  let item = $iterator.next();\n  \
+             $print(item);\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_shadowing() {
+        assert_eq!(
+            translate(
+                "var a = \"global\";\n\
+                 { fun showA() { print a; } showA(); var a = \"block\"; showA(); print a; }\n\
+                 fun f(a) { { print a; var a = 1; a = 2; } return a; }\n\
+                 for (var x in 5) { print len(x); var len = x; print len; }"
+            ),
+            "var a = \"global\";\n\
+             {\n  const showA = () => {\n    $print(a);\n  };\n  showA();\n  let a$1 = \"block\";\n  \
+             showA();\n  $print(a$1);\n}\n\
+             function f(a) {\n  {\n    $print(a);\n    let a$2 = 1;\n    a$2 = 2;\n  }\n  return a;\n}\n\
+             for (const $iterator = $iter(5); !$truthy($iterator.done()); ) {\n  let x = $iterator.next();\n  \
+             {\n    $print(len(x));\n    let len$3 = x;\n    $print(len$3);\n  }\n}\n"
+        );
+    }
+}
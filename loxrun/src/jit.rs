@@ -0,0 +1,731 @@
+//! Compiles hot Lox functions to native code with Cranelift, see
+//! `Engine::Jit`.
+//!
+//! A function is compiled once it has been called `Jit::threshold` times,
+//! and only if its body is numeric: it may use numbers and booleans in local
+//! variables, arithmetic, comparisons, `if`, `while`, `return` and calls of
+//! itself through its global name, but nothing that could have effects
+//! outside of the call, such as printing or reading globals. Everything else
+//! keeps running in the interpreter.
+//!
+//! As such a body has no effects, a native call that can't finish, for
+//! example because a call of itself returned `nil` to an addition, gives up
+//! and the call is run again by the interpreter, which then reports the same
+//! error it always would have. The function isn't compiled again.
+
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+
+use cranelift::codegen::ir::{self, FuncRef};
+use cranelift::jit::{JITBuilder, JITModule};
+use cranelift::module::{default_libcall_names, FuncId, Module};
+use cranelift::prelude::*;
+use liblox::tokens::{LiteralTypes, TokenType};
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::callable::{Callable, LoxFunction};
+use crate::expression::{Call, Expression};
+use crate::interpreter::{Environment, Value};
+use crate::stmt::{FunctionStmt, Stmt};
+
+// What native code returns, and its result is in `out` for RETURNED_NUMBER
+const RETURNED_NUMBER: i64 = 0;
+const RETURNED_NIL: i64 = 1;
+const CANCELLED: i64 = 2;
+const GAVE_UP: i64 = 3;
+
+// Nesting of native calls when the interpreter has no limit on it, beyond
+// which they give up to the interpreter
+const UNLIMITED_CALLS: usize = 10_000;
+
+// Stack needed by a native call beyond its frame, and in addition to all of
+// them before entering native code
+const CALL_OVERHEAD: usize = 64;
+const RED_ZONE: usize = 64 * 1024;
+
+// Arguments, calls that may still nest, the interrupt flag, and the result
+type NativeFn = unsafe extern "C" fn(*const f64, i64, *const AtomicBool, *mut f64) -> i32;
+
+struct NativeFunction {
+    code: NativeFn,
+    frame_size: usize,
+    // Whether the body calls the function through its global name, which
+    // must still be bound to it
+    recursive: bool,
+}
+
+enum Tier {
+    // Called as often so far
+    Interpreted(u32),
+    Native(NativeFunction),
+    Unsupported,
+}
+
+/// The functions called while running with `Engine::Jit`, and the native
+/// code of those that were compiled.
+pub(crate) struct Jit {
+    /// Calls of a function before it is compiled.
+    pub(crate) threshold: u32,
+    // Created with the first function compiled
+    module: Option<JITModule>,
+    // Keyed like the compiled bodies of the interpreter, and keeping the
+    // declarations alive with them
+    functions: HashMap<*const FunctionStmt, (Rc<FunctionStmt>, Tier)>,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self {
+            threshold: 100,
+            module: None,
+            functions: HashMap::new(),
+        }
+    }
+}
+
+impl Jit {
+    /// Calls the function natively if it is hot and can be compiled, or
+    /// returns `None` for the interpreter to call it. `calls_left` is how
+    /// deeply calls may still nest.
+    pub(crate) fn call(
+        &mut self,
+        function: &LoxFunction,
+        arguments: &[Value],
+        calls_left: Option<usize>,
+        globals: &Environment,
+        interrupt: &AtomicBool,
+    ) -> Option<Value> {
+        if function.is_initializer() {
+            return None;
+        }
+        let key = Rc::as_ptr(&function.declaration);
+        let (_, tier) = self
+            .functions
+            .entry(key)
+            .or_insert_with(|| (Rc::clone(&function.declaration), Tier::Interpreted(0)));
+        if let Tier::Interpreted(calls) = tier {
+            *calls += 1;
+            if *calls < self.threshold {
+                return None;
+            }
+            let module = self.module.get_or_insert_with(new_module);
+            *tier = match compile(module, &function.ast, &function.declaration) {
+                Some(native) => Tier::Native(native),
+                None => Tier::Unsupported,
+            };
+        }
+        let Tier::Native(native) = tier else {
+            return None;
+        };
+        if native.recursive {
+            let name = &function.declaration.name.lexeme;
+            match globals.get(name) {
+                Some(Value::Callable(Callable::Function(global))) if global == *function => {}
+                _ => return None,
+            }
+        }
+        let mut numbers = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            let Value::Number(number) = argument else {
+                return None;
+            };
+            numbers.push(*number);
+        }
+        let calls_left = calls_left.unwrap_or(UNLIMITED_CALLS);
+        let stack = calls_left
+            .saturating_mul(native.frame_size + CALL_OVERHEAD)
+            .saturating_add(RED_ZONE);
+        let code = native.code;
+        let mut out = 0.0;
+        let status = stacker::maybe_grow(stack, stack, || unsafe {
+            code(numbers.as_ptr(), calls_left as i64, interrupt, &mut out)
+        });
+        match i64::from(status) {
+            RETURNED_NUMBER => Some(Value::Number(out)),
+            RETURNED_NIL => Some(Value::Nil),
+            // The interpreter notices on its own
+            CANCELLED => None,
+            _ => {
+                *tier = Tier::Unsupported;
+                None
+            }
+        }
+    }
+}
+
+fn new_module() -> JITModule {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").unwrap();
+    let isa = cranelift::native::builder()
+        .expect("the host is supported by Cranelift")
+        .finish(settings::Flags::new(flags))
+        .unwrap();
+    JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()))
+}
+
+// Compiles the function, or returns `None` if its body isn't supported
+fn compile(
+    module: &mut JITModule,
+    ast: &Ast,
+    declaration: &FunctionStmt,
+) -> Option<NativeFunction> {
+    let pointer = module.target_config().pointer_type();
+    let mut context = module.make_context();
+    let signature = &mut context.func.signature;
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(types::I64));
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I32));
+    let id = module
+        .declare_anonymous_function(&context.func.signature)
+        .ok()?;
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let recursive = FunctionCompiler::compile(builder, module, id, ast, declaration)?;
+
+    module.define_function(id, &mut context).ok()?;
+    let frame_size = context.compiled_code()?.frame_size as usize;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(id);
+    Some(NativeFunction {
+        code: unsafe { mem::transmute::<*const u8, NativeFn>(code) },
+        frame_size,
+        recursive,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    Bool,
+}
+
+struct FunctionCompiler<'a> {
+    builder: FunctionBuilder<'a>,
+    ast: &'a Ast,
+    declaration: &'a FunctionStmt,
+    itself: FuncRef,
+    pointer: Type,
+    depth: ir::Value,
+    interrupt: ir::Value,
+    out: ir::Value,
+    // Start of the body, which calls of itself in tail position jump to
+    start: Block,
+    // The local variables of the scopes the resolver sees, innermost last
+    scopes: Vec<Vec<(Variable, Kind)>>,
+    variables: usize,
+    recursive: bool,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    // Returns whether the function calls itself
+    fn compile(
+        mut builder: FunctionBuilder<'a>,
+        module: &mut JITModule,
+        id: FuncId,
+        ast: &'a Ast,
+        declaration: &'a FunctionStmt,
+    ) -> Option<bool> {
+        let itself = module.declare_func_in_func(id, builder.func);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let [arguments, depth, interrupt, out] = builder.block_params(entry) else {
+            unreachable!("the signature has four parameters");
+        };
+        let (arguments, depth, interrupt, out) = (*arguments, *depth, *interrupt, *out);
+        let start = builder.create_block();
+        let mut compiler = Self {
+            builder,
+            ast,
+            declaration,
+            itself,
+            pointer: module.target_config().pointer_type(),
+            depth,
+            interrupt,
+            out,
+            start,
+            scopes: vec![Vec::new()],
+            variables: 0,
+            recursive: false,
+        };
+        for i in 0..declaration.params.len() {
+            let variable = compiler.declare(Kind::Number);
+            let offset = (i * mem::size_of::<f64>()) as i32;
+            let argument =
+                compiler
+                    .builder
+                    .ins()
+                    .load(types::F64, MemFlags::trusted(), arguments, offset);
+            compiler.builder.def_var(variable, argument);
+        }
+        compiler.builder.ins().jump(start, &[]);
+
+        compiler.builder.switch_to_block(start);
+        compiler.check_interrupt();
+        for &statement in &declaration.body {
+            compiler.statement(statement)?;
+        }
+        compiler.finish(RETURNED_NIL);
+        compiler.builder.seal_all_blocks();
+        compiler.builder.finalize();
+        Some(compiler.recursive)
+    }
+
+    fn declare(&mut self, kind: Kind) -> Variable {
+        let variable = Variable::new(self.variables);
+        self.variables += 1;
+        let ty = match kind {
+            Kind::Number => types::F64,
+            Kind::Bool => types::I8,
+        };
+        self.builder.declare_var(variable, ty);
+        self.scopes.last_mut().unwrap().push((variable, kind));
+        variable
+    }
+
+    fn local(&self, expression: ExprId) -> Option<(Variable, Kind)> {
+        let local = self.ast[expression].resolution()?.get()?;
+        let scope = self.scopes.len().checked_sub(local.depth + 1)?;
+        self.scopes[scope].get(local.slot).copied()
+    }
+
+    // Returns from the function, continuing in an unreachable block
+    fn finish(&mut self, status: i64) {
+        let status = self.builder.ins().iconst(types::I32, status);
+        self.builder.ins().return_(&[status]);
+        let unreachable = self.builder.create_block();
+        self.builder.switch_to_block(unreachable);
+    }
+
+    fn check_interrupt(&mut self) {
+        let interrupted =
+            self.builder
+                .ins()
+                .atomic_load(types::I8, MemFlags::trusted(), self.interrupt);
+        let cancelled = self.builder.create_block();
+        let running = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(interrupted, cancelled, &[], running, &[]);
+        self.builder.switch_to_block(cancelled);
+        self.finish(CANCELLED);
+        self.builder.ins().jump(running, &[]);
+        self.builder.switch_to_block(running);
+    }
+
+    fn statement(&mut self, statement: StmtId) -> Option<()> {
+        let ast = self.ast;
+        match &ast[statement] {
+            Stmt::Expression(stmt) => {
+                self.expression(stmt.expression)?;
+            }
+            Stmt::Var(stmt) => {
+                let (value, kind) = self.expression(stmt.initializer?)?;
+                let variable = self.declare(kind);
+                self.builder.def_var(variable, value);
+            }
+            Stmt::Block(block) => {
+                let scoped = block.declares_variables(ast);
+                if scoped {
+                    self.scopes.push(Vec::new());
+                }
+                for &statement in &block.statements {
+                    self.statement(statement)?;
+                }
+                if scoped {
+                    self.scopes.pop();
+                }
+            }
+            Stmt::If(stmt) => {
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let after = self.builder.create_block();
+                self.condition(stmt.condition, then_block, else_block)?;
+                self.builder.switch_to_block(then_block);
+                self.statement(stmt.then_branch)?;
+                self.builder.ins().jump(after, &[]);
+                self.builder.switch_to_block(else_block);
+                if let Some(else_branch) = stmt.else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.builder.ins().jump(after, &[]);
+                self.builder.switch_to_block(after);
+            }
+            Stmt::While(stmt) => {
+                let header = self.builder.create_block();
+                let body = self.builder.create_block();
+                let after = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                self.check_interrupt();
+                self.condition(stmt.condition, body, after)?;
+                self.builder.switch_to_block(body);
+                self.statement(stmt.body)?;
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(after);
+            }
+            Stmt::Return(stmt) => match stmt.value.map(|value| (value, &ast[value])) {
+                Some((_, Expression::Call(call))) if self.calls_itself(call) => {
+                    let arguments = self.arguments(call)?;
+                    for (i, argument) in arguments.into_iter().enumerate() {
+                        let (variable, _) = self.scopes[0][i];
+                        self.builder.def_var(variable, argument);
+                    }
+                    self.builder.ins().jump(self.start, &[]);
+                    let unreachable = self.builder.create_block();
+                    self.builder.switch_to_block(unreachable);
+                }
+                Some((value, _)) => {
+                    let value = self.number(value)?;
+                    self.builder
+                        .ins()
+                        .store(MemFlags::trusted(), value, self.out, 0);
+                    self.finish(RETURNED_NUMBER);
+                }
+                None => self.finish(RETURNED_NIL),
+            },
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // Branches on the truthiness of the condition
+    fn condition(&mut self, condition: ExprId, then_block: Block, else_block: Block) -> Option<()> {
+        match self.expression(condition)? {
+            (value, Kind::Bool) => {
+                self.builder
+                    .ins()
+                    .brif(value, then_block, &[], else_block, &[]);
+            }
+            // Numbers are always true
+            (_, Kind::Number) => {
+                self.builder.ins().jump(then_block, &[]);
+            }
+        }
+        Some(())
+    }
+
+    fn number(&mut self, expression: ExprId) -> Option<ir::Value> {
+        match self.expression(expression)? {
+            (value, Kind::Number) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self, expression: ExprId) -> Option<(ir::Value, Kind)> {
+        let ast = self.ast;
+        Some(match &ast[expression] {
+            Expression::Literal(literal) => match literal.value {
+                LiteralTypes::Number(number) => (self.builder.ins().f64const(number), Kind::Number),
+                LiteralTypes::Bool(value) => (
+                    self.builder.ins().iconst(types::I8, i64::from(value)),
+                    Kind::Bool,
+                ),
+                _ => return None,
+            },
+            Expression::Grouping(grouping) => self.expression(grouping.expression)?,
+            Expression::Variable(_) => {
+                let (variable, kind) = self.local(expression)?;
+                (self.builder.use_var(variable), kind)
+            }
+            Expression::Assign(assign) => {
+                let (variable, kind) = self.local(expression)?;
+                let (value, value_kind) = self.expression(assign.value)?;
+                if value_kind != kind {
+                    return None;
+                }
+                self.builder.def_var(variable, value);
+                (value, kind)
+            }
+            Expression::Unary(unary) => {
+                let (right, kind) = self.expression(unary.right)?;
+                match (&unary.operator.token_type, kind) {
+                    (TokenType::Minus, Kind::Number) => {
+                        (self.builder.ins().fneg(right), Kind::Number)
+                    }
+                    (TokenType::Bang, Kind::Bool) => (
+                        self.builder.ins().icmp_imm(IntCC::Equal, right, 0),
+                        Kind::Bool,
+                    ),
+                    (TokenType::Bang, Kind::Number) => {
+                        (self.builder.ins().iconst(types::I8, 0), Kind::Bool)
+                    }
+                    _ => return None,
+                }
+            }
+            Expression::Binary(binary) => {
+                let (left, left_kind) = self.expression(binary.left)?;
+                let (right, right_kind) = self.expression(binary.right)?;
+                let operator = &binary.operator.token_type;
+                match (left_kind, right_kind) {
+                    (Kind::Number, Kind::Number) => self.number_operation(operator, left, right)?,
+                    (Kind::Bool, Kind::Bool) => {
+                        let condition = match operator {
+                            TokenType::EqualEqual => IntCC::Equal,
+                            TokenType::BangEqual => IntCC::NotEqual,
+                            _ => return None,
+                        };
+                        (self.builder.ins().icmp(condition, left, right), Kind::Bool)
+                    }
+                    // Values of different types are never equal
+                    _ => {
+                        let equal = match operator {
+                            TokenType::EqualEqual => 0,
+                            TokenType::BangEqual => 1,
+                            _ => return None,
+                        };
+                        (self.builder.ins().iconst(types::I8, equal), Kind::Bool)
+                    }
+                }
+            }
+            Expression::Logical(logical) => {
+                let (left, kind) = self.expression(logical.left)?;
+                if kind == Kind::Number {
+                    // The left operand is true, so `or` is it and `and` the
+                    // right one
+                    if logical.operator.token_type == TokenType::Or {
+                        return Some((left, kind));
+                    }
+                    return self.expression(logical.right);
+                }
+                let right_block = self.builder.create_block();
+                let after = self.builder.create_block();
+                self.builder.append_block_param(after, types::I8);
+                if logical.operator.token_type == TokenType::Or {
+                    self.builder
+                        .ins()
+                        .brif(left, after, &[left], right_block, &[]);
+                } else {
+                    self.builder
+                        .ins()
+                        .brif(left, right_block, &[], after, &[left]);
+                }
+                self.builder.switch_to_block(right_block);
+                let (right, right_kind) = self.expression(logical.right)?;
+                if right_kind != Kind::Bool {
+                    return None;
+                }
+                self.builder.ins().jump(after, &[right]);
+                self.builder.switch_to_block(after);
+                (self.builder.block_params(after)[0], Kind::Bool)
+            }
+            Expression::Call(call) if self.calls_itself(call) => (self.call(call)?, Kind::Number),
+            _ => return None,
+        })
+    }
+
+    fn number_operation(
+        &mut self,
+        operator: &TokenType,
+        left: ir::Value,
+        right: ir::Value,
+    ) -> Option<(ir::Value, Kind)> {
+        let condition = match operator {
+            TokenType::Plus => return Some((self.builder.ins().fadd(left, right), Kind::Number)),
+            TokenType::Minus => return Some((self.builder.ins().fsub(left, right), Kind::Number)),
+            TokenType::Star => return Some((self.builder.ins().fmul(left, right), Kind::Number)),
+            TokenType::Slash => return Some((self.builder.ins().fdiv(left, right), Kind::Number)),
+            TokenType::Greater => FloatCC::GreaterThan,
+            TokenType::GreaterEqual => FloatCC::GreaterThanOrEqual,
+            TokenType::Less => FloatCC::LessThan,
+            TokenType::LessEqual => FloatCC::LessThanOrEqual,
+            TokenType::EqualEqual => FloatCC::Equal,
+            TokenType::BangEqual => FloatCC::NotEqual,
+            _ => return None,
+        };
+        Some((self.builder.ins().fcmp(condition, left, right), Kind::Bool))
+    }
+
+    // Whether the call is of the function through its global name
+    fn calls_itself(&self, call: &Call) -> bool {
+        match &self.ast[call.callee] {
+            Expression::Variable(variable) => {
+                variable.resolved.get().is_none()
+                    && variable.name.lexeme == self.declaration.name.lexeme
+                    && call.arguments.len() == self.declaration.params.len()
+            }
+            _ => false,
+        }
+    }
+
+    fn arguments(&mut self, call: &Call) -> Option<Vec<ir::Value>> {
+        self.recursive = true;
+        call.arguments
+            .iter()
+            .map(|&argument| self.number(argument))
+            .collect()
+    }
+
+    fn call(&mut self, call: &Call) -> Option<ir::Value> {
+        let arguments = self.arguments(call)?;
+        let size = (arguments.len().max(1) * mem::size_of::<f64>()) as u32;
+        let argument_slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            size,
+            3,
+        ));
+        let out_slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            mem::size_of::<f64>() as u32,
+            3,
+        ));
+        for (i, argument) in arguments.into_iter().enumerate() {
+            let offset = (i * mem::size_of::<f64>()) as i32;
+            self.builder
+                .ins()
+                .stack_store(argument, argument_slot, offset);
+        }
+
+        // Nesting too deeply gives up, for the interpreter to report it
+        let calling = self.builder.create_block();
+        let overflowed = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(self.depth, calling, &[], overflowed, &[]);
+        self.builder.switch_to_block(overflowed);
+        self.finish(GAVE_UP);
+        self.builder.ins().jump(calling, &[]);
+
+        self.builder.switch_to_block(calling);
+        let arguments = self
+            .builder
+            .ins()
+            .stack_addr(self.pointer, argument_slot, 0);
+        let out = self.builder.ins().stack_addr(self.pointer, out_slot, 0);
+        let depth = self.builder.ins().iadd_imm(self.depth, -1);
+        let call = self
+            .builder
+            .ins()
+            .call(self.itself, &[arguments, depth, self.interrupt, out]);
+        let status = self.builder.inst_results(call)[0];
+
+        // A `nil` where a number is needed is an error the interpreter
+        // reports, other failures are passed on
+        let returned = self.builder.create_block();
+        let failed = self.builder.create_block();
+        let is_number = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, status, RETURNED_NUMBER);
+        self.builder
+            .ins()
+            .brif(is_number, returned, &[], failed, &[]);
+        self.builder.switch_to_block(failed);
+        let is_nil = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, status, RETURNED_NIL);
+        let gave_up = self.builder.ins().iconst(types::I32, GAVE_UP);
+        let status = self.builder.ins().select(is_nil, gave_up, status);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.switch_to_block(returned);
+        Some(self.builder.ins().stack_load(types::F64, out_slot, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, Interpreter, Lox};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // What the source prints with the engine, the error it fails with, and
+    // how many functions were compiled to native code
+    fn run(source: &str, engine: Engine) -> (String, Option<String>, usize) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::builder()
+            .output(SharedBuffer(Rc::clone(&output)))
+            .prelude(false)
+            .build();
+        interpreter.set_engine(engine);
+        interpreter.jit.threshold = 2;
+        let mut lox = Lox::with_interpreter(interpreter);
+        lox.set_error_reporting(false);
+        let error = lox.run(source).err().map(|err| err.to_string());
+        let native = lox
+            .interpreter
+            .jit
+            .functions
+            .values()
+            .filter(|(_, tier)| matches!(tier, Tier::Native(_)))
+            .count();
+        let printed = String::from_utf8_lossy(&output.borrow()).to_string();
+        (printed, error, native)
+    }
+
+    #[test]
+    fn test_same_as_tree_walker() {
+        let sources = [
+            // Compiled
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+             print fib(20); print fib(1.5);",
+            "fun sum(n) { var total = 0; for (var i = 0; i <= n; i = i + 1) {\n\
+             if (i == 3 or !(i < 8) and i != 9) total = total + i; } return total; }\n\
+             print sum(10); print sum(20); print sum(-1);",
+            "fun count(n, acc) { if (n == 0) return acc; return count(n - 1, acc + 1); }\n\
+             print count(10, 0); print count(100000, 0);",
+            "fun nothing(n) { if (n > 1) return; } print nothing(1); print nothing(2); print nothing(3);",
+            "fun deep(n) { return 1 + deep(n - 1); } print deep(1);",
+            "fun half(n) { if (n > 0) return n / 2; } print half(4) + half(8); print half(-1) + 1;",
+            // Not compiled
+            "fun show(n) { print n; } show(1); show(2); show(3);",
+            "fun add(a, b) { return a + b; } print add(1, 2); print add(3, 4); print add(\"a\", \"b\");",
+            "var scale = 2; fun f(n) { return n * scale; } print f(1); print f(2); print f(3);",
+        ];
+        for source in sources {
+            let (printed, error, _) = run(source, Engine::Jit);
+            let (tree_printed, tree_error, _) = run(source, Engine::TreeWalker);
+            assert_eq!((printed, error), (tree_printed, tree_error), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_compiles_numeric_functions() {
+        let (_, _, native) = run(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } fib(10);",
+            Engine::Jit,
+        );
+        assert_eq!(native, 1);
+        let (_, _, native) = run(
+            "fun show(n) { print n; } show(1); show(2); show(3);",
+            Engine::Jit,
+        );
+        assert_eq!(native, 0);
+    }
+
+    #[test]
+    fn test_gives_up_to_interpreter() {
+        // A native call that overflows is run again by the interpreter
+        let source = "fun deep(n) { return 1 + deep(n - 1); } deep(1); deep(2);";
+        let (_, error, native) = run(source, Engine::Jit);
+        assert_eq!(native, 0);
+        assert_eq!(error, run(source, Engine::TreeWalker).1);
+        assert!(error.unwrap().contains("Stack overflow."));
+
+        // As is one with a global that no longer is the function
+        let source = "fun f(n) { if (n < 1) return 0; return f(n - 1) + 1; }\n\
+                      print f(3); print f(3); var g = f; fun f(n) { return 10; } print g(3);";
+        assert_eq!(run(source, Engine::Jit).0, "3\n3\n11\n");
+    }
+}
@@ -0,0 +1,403 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::class::{Instance, LoxClass};
+use crate::interpreter::Value;
+
+// Arrays and objects are parsed and written recursively, so deeper nesting
+// is an error instead of a stack overflow
+const MAX_DEPTH: usize = 128;
+
+/// Converts JSON text to Lox values. Arrays become arrays, and objects
+/// become instances of a class named `Object` with one field per key.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let object_class = Rc::new(RefCell::new(LoxClass::new(
+        "Object".to_string(),
+        None,
+        HashMap::new(),
+    )));
+    let mut parser = JsonParser {
+        chars: text.chars().collect(),
+        position: 0,
+        depth: 0,
+        object_class,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position < parser.chars.len() {
+        return Err(parser.error("end of input"));
+    }
+    Ok(value)
+}
+
+/// Converts a Lox value to JSON text. Instances are written as objects with
+/// their fields sorted by name; functions and classes can't be converted.
+pub fn stringify(value: &Value) -> Result<String, String> {
+    let mut output = String::new();
+    write_value(&mut output, value, &mut Vec::new())?;
+    Ok(output)
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+    // Arrays and objects the parser is inside of
+    depth: usize,
+    object_class: Rc<RefCell<LoxClass>>,
+}
+
+impl JsonParser {
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{' | '[') if self.depth == MAX_DEPTH => Err(format!(
+                "Invalid JSON at position {}: nested too deeply.",
+                self.position
+            )),
+            Some('{') => {
+                self.depth += 1;
+                let object = self.object();
+                self.depth -= 1;
+                object
+            }
+            Some('[') => {
+                self.depth += 1;
+                let array = self.array();
+                self.depth -= 1;
+                array
+            }
+            Some('"') => Ok(Value::String(self.string()?.into())),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('n') => self.keyword("null", Value::Nil),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.position += 1;
+        let mut instance = Instance::new(Rc::clone(&self.object_class));
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Value::Instance(Rc::new(RefCell::new(instance))));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+            instance.set(key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(Value::Instance(Rc::new(RefCell::new(instance))));
+                }
+                _ => return Err(self.error("',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.position += 1;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Value::Array(Rc::new(RefCell::new(elements))));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(Rc::new(RefCell::new(elements))));
+                }
+                _ => return Err(self.error("',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("'\"'")),
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.position += 1;
+                            let escaped = self.unicode_escape()?;
+                            string.push(escaped);
+                            continue;
+                        }
+                        _ => return Err(self.error("an escape sequence")),
+                    };
+                    self.position += 1;
+                    string.push(escaped);
+                }
+                Some(c) if (c as u32) < 0x20 => return Err(self.error("'\"'")),
+                Some(c) => {
+                    self.position += 1;
+                    string.push(c);
+                }
+            }
+        }
+    }
+
+    // Reads the hex digits after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex_digits()?;
+        if (0xD800..0xDC00).contains(&high) {
+            if self.peek() == Some('\\') && self.chars.get(self.position + 1) == Some(&'u') {
+                self.position += 2;
+                let low = self.hex_digits()?;
+                if (0xDC00..0xE000).contains(&low) {
+                    let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                    return char::from_u32(code).ok_or_else(|| self.error("a valid code point"));
+                }
+            }
+            return Err(self.error("a low surrogate"));
+        }
+        char::from_u32(high).ok_or_else(|| self.error("a valid code point"))
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.iter().skip(self.position).take(4).collect();
+        if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("four hex digits"));
+        }
+        self.position += 4;
+        Ok(u32::from_str_radix(&digits, 16).unwrap())
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        if self.peek() == Some('-') {
+            self.position += 1;
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-') {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        match text.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(Value::Number(number)),
+            _ => {
+                self.position = start;
+                Err(self.error("a number"))
+            }
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, String> {
+        let matches = keyword
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.position + i) == Some(&c));
+        if !matches {
+            return Err(self.error("a value"));
+        }
+        self.position += keyword.len();
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", expected)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn error(&self, expected: &str) -> String {
+        format!(
+            "Invalid JSON at position {}: expected {}.",
+            self.position, expected
+        )
+    }
+}
+
+// `parents` holds the arrays and instances currently being written, to
+// detect values that contain themselves.
+fn write_value(output: &mut String, value: &Value, parents: &mut Vec<usize>) -> Result<(), String> {
+    match value {
+        Value::Nil => output.push_str("null"),
+        Value::Bool(b) => output.push_str(&b.to_string()),
+        Value::Number(n) => {
+            if !n.is_finite() {
+                return Err(format!("Can't convert {} to JSON.", n));
+            }
+            output.push_str(&n.to_string());
+        }
+        Value::String(s) => write_string(output, s),
+        Value::Array(array) => {
+            enter(parents, Rc::as_ptr(array) as usize)?;
+            output.push('[');
+            for (i, element) in array.borrow().iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_value(output, element, parents)?;
+            }
+            output.push(']');
+            parents.pop();
+        }
+        Value::Instance(instance) => {
+            enter(parents, Rc::as_ptr(instance) as usize)?;
+            let instance = instance.borrow();
+            let mut keys: Vec<&String> = instance.fields.keys().collect();
+            keys.sort();
+            output.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_string(output, key);
+                output.push(':');
+                write_value(output, &instance.fields[key], parents)?;
+            }
+            output.push('}');
+            parents.pop();
+        }
+        Value::Callable(callable) => {
+            return Err(format!("Can't convert {} to JSON.", callable));
+        }
+        Value::Native(handle) => {
+            return Err(format!("Can't convert {} to JSON.", handle));
+        }
+    }
+    Ok(())
+}
+
+fn enter(parents: &mut Vec<usize>, pointer: usize) -> Result<(), String> {
+    if parents.contains(&pointer) {
+        return Err("Can't convert a value that contains itself to JSON.".to_string());
+    }
+    if parents.len() == MAX_DEPTH {
+        return Err("Can't convert a value nested this deeply to JSON.".to_string());
+    }
+    parents.push(pointer);
+    Ok(())
+}
+
+pub(crate) fn write_string(output: &mut String, string: &str) {
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_stringify() {
+        let text = r#" {"name": "Lox", "tags": ["a", "b\n"], "version": 1.5,
+            "nested": {"ok": true, "none": null}, "escaped": "é😀"} "#;
+        let value = parse(text).unwrap();
+        assert_eq!(
+            stringify(&value).unwrap(),
+            r#"{"escaped":"é😀","name":"Lox","nested":{"none":null,"ok":true},"tags":["a","b\n"],"version":1.5}"#
+        );
+        assert!(matches!(parse("-12e2").unwrap(), Value::Number(n) if n == -1200.0));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse("[1, 2").unwrap_err(),
+            "Invalid JSON at position 5: expected ',' or ']'."
+        );
+        assert_eq!(
+            parse("{\"a\" 1}").unwrap_err(),
+            "Invalid JSON at position 5: expected ':'."
+        );
+        assert_eq!(
+            parse("tru").unwrap_err(),
+            "Invalid JSON at position 0: expected a value."
+        );
+        assert_eq!(
+            parse("1 2").unwrap_err(),
+            "Invalid JSON at position 2: expected end of input."
+        );
+        let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(
+            parse(&nested).unwrap_err(),
+            "Invalid JSON at position 128: nested too deeply."
+        );
+    }
+
+    #[test]
+    fn test_stringify_errors() {
+        let array = Rc::new(RefCell::new(Vec::new()));
+        array.borrow_mut().push(Value::Array(Rc::clone(&array)));
+        assert_eq!(
+            stringify(&Value::Array(Rc::clone(&array))).unwrap_err(),
+            "Can't convert a value that contains itself to JSON."
+        );
+        array.borrow_mut().clear();
+
+        let mut nested = Value::Nil;
+        for _ in 0..200 {
+            nested = Value::Array(Rc::new(RefCell::new(vec![nested])));
+        }
+        assert_eq!(
+            stringify(&nested).unwrap_err(),
+            "Can't convert a value nested this deeply to JSON."
+        );
+
+        assert_eq!(
+            stringify(&Value::Number(f64::NAN)).unwrap_err(),
+            "Can't convert NaN to JSON."
+        );
+    }
+}
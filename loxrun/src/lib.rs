@@ -0,0 +1,587 @@
+//! A tree-walking interpreter for Lox that can be embedded in other
+//! applications.
+//!
+//! ```no_run
+//! let mut lox = loxrun::Lox::new();
+//! lox.run("print \"Hello from Lox!\";").unwrap();
+//! ```
+
+pub mod ast;
+pub mod ast_json;
+pub mod builder;
+pub mod callable;
+pub mod class;
+mod closures;
+pub mod convert;
+mod error;
+pub mod expression;
+pub mod host;
+pub mod interpreter;
+pub mod javascript;
+#[cfg(feature = "jit")]
+mod jit;
+mod json;
+pub mod optimizer;
+pub mod parser;
+pub mod printer;
+pub mod query;
+mod regex;
+pub mod resolver;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod stmt;
+
+pub use convert::{FromLox, ToLox};
+pub use error::{diagnostics_to_json, LoxError, Stage};
+pub use host::{InterpreterHooks, NativeHandle};
+pub use interpreter::{Engine, Interpreter, InterpreterError, Value};
+pub use liblox::codes::ErrorCode;
+pub use liblox::diagnostics::{
+    Diagnostic, DiagnosticSink, ErrorReporter, Severity, SharedDiagnosticSink, SharedErrorReporter,
+};
+
+use ast::{Ast, StmtId};
+use liblox::diagnostics::stderr_reporter;
+use liblox::echo;
+use liblox::log;
+use liblox::scanner::Scanner;
+use liblox::tokens::{Token, TokenType};
+use parser::Parser;
+use resolver::Resolver;
+use std::cell::RefCell;
+use std::rc::Rc;
+use stmt::{PrintStmt, Stmt};
+
+/// Runs Lox sources one after the other in the same interpreter.
+pub struct Lox {
+    pub interpreter: Interpreter,
+    // What run() is doing, for reporting internal errors
+    activity: String,
+    reporter: Option<SharedErrorReporter>,
+    max_errors: Option<usize>,
+    warn_shadowing: bool,
+    warnings_as_errors: bool,
+    // Whether bare expressions are echoed, as they are in the REPL
+    mode: echo::Mode,
+    // Echo instances with their fields
+    echo_fields: bool,
+    diagnostics: Option<SharedDiagnosticSink>,
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self::with_interpreter(Interpreter::new())
+    }
+
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Lox {
+            interpreter,
+            activity: String::new(),
+            reporter: Some(stderr_reporter()),
+            max_errors: None,
+            warn_shadowing: false,
+            warnings_as_errors: false,
+            mode: echo::Mode::Script,
+            echo_fields: false,
+            diagnostics: None,
+        }
+    }
+
+    /// Sets where warnings found while parsing and resolving are reported.
+    /// Without a sink they are dropped.
+    pub fn set_diagnostic_sink(&mut self, sink: Option<SharedDiagnosticSink>) {
+        self.diagnostics = sink;
+    }
+
+    /// Enables warnings for local variables that shadow another local
+    /// variable, which are off by default.
+    pub fn set_warn_shadowing(&mut self, enabled: bool) {
+        self.warn_shadowing = enabled;
+    }
+
+    /// Makes sources with warnings fail with `LoxError::Warnings` before they
+    /// run. The warnings aren't reported to the diagnostic sink then.
+    pub fn set_warnings_as_errors(&mut self, enabled: bool) {
+        self.warnings_as_errors = enabled;
+    }
+
+    /// Sets whether sources are run as REPL input, which echoes the value of
+    /// a bare expression, or as scripts, which are `Mode::Script` by default.
+    pub fn set_mode(&mut self, mode: echo::Mode) {
+        self.mode = mode;
+    }
+
+    /// Makes an echoed instance show its fields, like `Foo { flavor: "Sesame" }`,
+    /// instead of printing as `Foo instance`.
+    pub fn set_echo_fields(&mut self, enabled: bool) {
+        self.echo_fields = enabled;
+    }
+
+    /// Enables or disables printing of syntax errors to stderr as they are
+    /// found. They are returned in `LoxError::Syntax` either way.
+    pub fn set_error_reporting(&mut self, enabled: bool) {
+        self.reporter = enabled.then(stderr_reporter);
+    }
+
+    /// Sets where syntax errors are reported as they are found, instead of
+    /// stderr. The scanner and parser share it, so the errors arrive in the
+    /// order they were found.
+    pub fn set_error_reporter(&mut self, reporter: Option<SharedErrorReporter>) {
+        self.reporter = reporter;
+    }
+
+    /// Stops parsing a source after `max` syntax errors instead of reporting
+    /// all of them.
+    pub fn set_max_errors(&mut self, max: Option<usize>) {
+        self.max_errors = max;
+    }
+
+    /// Describes the step run() was at, which is the step that failed if
+    /// it panicked.
+    pub fn activity(&self) -> &str {
+        &self.activity
+    }
+
+    /// Scans, parses, resolves and executes the source. In `Mode::Repl`, a
+    /// source that is a single expression without a semicolon has its value
+    /// printed.
+    pub fn run(&mut self, source: &str) -> Result<(), LoxError> {
+        let echo_fields = self.echo_fields && echo::should_echo(source, self.mode);
+        let (ast, statements) = self.analyze(source)?;
+        let result = log::timed("execute", || -> Result<(), InterpreterError> {
+            for statement in &statements {
+                self.activity = format!("executing the {}", ast[*statement].describe());
+                match &ast[*statement] {
+                    // The only statement of an echoed source
+                    Stmt::Print(print) if echo_fields => {
+                        self.interpreter.print_with_fields(&ast, print.expression)?;
+                    }
+                    _ => {
+                        self.interpreter
+                            .execute(&ast, std::slice::from_ref(statement))?;
+                    }
+                }
+            }
+            Ok(())
+        });
+        result.map_err(|err| LoxError::from(err).locate(source))
+    }
+
+    /// Scans, parses and resolves the source without running it, finding the
+    /// errors and warnings that `run` would find before running it.
+    pub fn check(&mut self, source: &str) -> Result<(), LoxError> {
+        self.analyze(source).map(|_| ())
+    }
+
+    /// Translates the source to JavaScript, see `javascript::to_javascript`.
+    /// Fails like `check` does.
+    pub fn to_javascript(&mut self, source: &str) -> Result<String, LoxError> {
+        let (ast, statements) = self.analyze(source)?;
+        Ok(javascript::to_javascript(&ast, &statements))
+    }
+
+    fn analyze(&mut self, source: &str) -> Result<(Rc<Ast>, Vec<StmtId>), LoxError> {
+        let echo = echo::should_echo(source, self.mode);
+        // Held back until it is known whether they fail the source, and
+        // until their columns are known
+        let warnings = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+        let sink: Option<SharedDiagnosticSink> =
+            if self.warnings_as_errors || self.diagnostics.is_some() {
+                Some(warnings.clone())
+            } else {
+                None
+            };
+
+        self.activity = "scanning the source".to_string();
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.set_error_reporter(self.reporter.clone());
+        let tokens = log::timed("scan", || scanner.take_tokens());
+
+        self.activity = "parsing".to_string();
+        let mut parser = Parser::with_ids(tokens, self.interpreter.node_ids.clone());
+        parser.set_error_reporter(self.reporter.clone());
+        parser.set_max_errors(self.max_errors);
+        parser.set_diagnostic_sink(sink.clone());
+        // Printed by a statement added once parsing succeeded
+        let mut echoed = None;
+        let parse_result = log::timed("parse", || {
+            if echo {
+                echoed = Some(parser.parse_expression()?);
+                Ok(Vec::new())
+            } else {
+                parser.parse()
+            }
+        });
+        if scanner.had_error || parse_result.is_err() {
+            self.pass_on(source, warnings.take());
+            return Err(LoxError::syntax(source, &scanner, &parser));
+        }
+        let mut statements = parse_result.unwrap();
+        let mut ast = parser.into_ast();
+        if let Some(expression) = echoed {
+            let keyword = Token::new_keyword(TokenType::Print, "print", 1);
+            statements.push(ast.add_statement(Stmt::Print(PrintStmt {
+                keyword,
+                expression,
+            })));
+        }
+        optimizer::fold_constants(&mut ast, &statements);
+
+        self.activity = "resolving variables".to_string();
+        let mut resolver = Resolver::new();
+        resolver.set_diagnostic_sink(sink);
+        resolver.set_warn_shadowing(self.warn_shadowing);
+        if let Err(err) = log::timed("resolve", || resolver.resolve_stmts(&ast, &statements)) {
+            self.pass_on(source, warnings.take());
+            return Err(LoxError::from(err).locate(source));
+        }
+        optimizer::eliminate_dead_code(&mut ast, &mut statements);
+
+        let warnings = warnings.take();
+        if self.warnings_as_errors && !warnings.is_empty() {
+            let errors = warnings.into_iter().map(Diagnostic::into_error).collect();
+            return Err(LoxError::Warnings(errors).locate(source));
+        }
+        self.pass_on(source, warnings);
+
+        Ok((Rc::new(ast), statements))
+    }
+
+    // Reports warnings that were held back to the diagnostic sink
+    fn pass_on(&self, source: &str, warnings: Vec<Diagnostic>) {
+        if let Some(sink) = &self.diagnostics {
+            for warning in warnings {
+                sink.borrow_mut().report(warning.locate(source));
+            }
+        }
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Re-emits a source in the canonical layout of `printer::format_program`,
+/// or fails with the syntax errors that kept it from being parsed.
+pub fn format(source: &str) -> Result<String, LoxError> {
+    let mut scanner = Scanner::new(source.to_string());
+    let mut parser = Parser::new(scanner.take_tokens());
+    parser.set_error_reporting(false);
+    let result = parser.parse();
+    match result {
+        Ok(statements) if !scanner.had_error => Ok(printer::format_program(
+            parser.ast(),
+            &statements,
+            scanner.comments(),
+            source,
+        )),
+        _ => Err(LoxError::syntax(source, &scanner, &parser)),
+    }
+}
+
+// Lets the tests exercise Interpreter::set_memory_limit
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: liblox::memory::CountingAllocator = liblox::memory::CountingAllocator;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_errors() {
+        let mut lox = Lox::new();
+        assert!(lox.run("var a = 1;").is_ok());
+        lox.set_error_reporting(false);
+        match lox.run("print ;\nvar 1;") {
+            Err(err @ LoxError::Syntax(_)) => assert_eq!(
+                err.to_string(),
+                "[line 1] Error at ';': Expect expression.\n\
+                 [line 2] Error at '1': Expect variable name."
+            ),
+            _ => panic!("expected syntax errors"),
+        }
+        lox.set_max_errors(Some(1));
+        assert_eq!(
+            lox.run("print ;\nvar 1;").unwrap_err().to_string(),
+            "[line 1] Error at ';': Expect expression."
+        );
+        lox.set_max_errors(None);
+        let err = lox.run("{ var b = b; }").unwrap_err();
+        assert!(matches!(err, LoxError::Resolve(_)));
+        assert_eq!(err.code(), Some(ErrorCode::LocalInOwnInitializer));
+        match lox.run("print a + nil;") {
+            Err(LoxError::Runtime(err)) => {
+                assert_eq!(
+                    err.to_string(),
+                    "Operands must be two numbers or two strings.\n[line 1]"
+                )
+            }
+            _ => panic!("expected a runtime error"),
+        }
+        // Only the REPL echoes bare expressions
+        assert_eq!(
+            lox.run("1 + 2").unwrap_err().to_string(),
+            "[line 1] Error at end: Expect ';' after expression."
+        );
+        lox.set_mode(echo::Mode::Repl);
+        assert!(lox.run("1 + 2").is_ok());
+    }
+
+    #[test]
+    fn test_error_reporter() {
+        let reported = Rc::new(RefCell::new(Vec::<String>::new()));
+        let mut lox = Lox::new();
+        lox.set_error_reporter(Some(reported.clone()));
+        assert!(lox.run("print #;\nvar 1;").is_err());
+        assert_eq!(
+            *reported.borrow(),
+            vec![
+                "[line 1] Error: Unexpected character.",
+                "[line 1] Error at ';': Expect expression.",
+                "[line 2] Error at '1': Expect variable name.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let warnings = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+        let mut lox = Lox::new();
+        lox.set_diagnostic_sink(Some(warnings.clone()));
+        lox.set_warn_shadowing(true);
+        let source = "fun f(a) {\n  { var a = 1; }\n  return a;\n  print a;\n}\nf(1);";
+        assert!(lox.run(source).is_ok());
+
+        let messages: Vec<String> = warnings.borrow().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "[line 4] Warning: Unreachable code after 'return'.",
+                "[line 2] Warning: 'a' declared on line 2 shadows the local variable \
+                 declared on line 1.",
+                "[line 2] Warning: Local variable 'a' is never read."
+            ]
+        );
+        assert!(warnings
+            .borrow()
+            .iter()
+            .all(|warning| warning.severity == Severity::Warning));
+        let columns: Vec<_> = warnings.borrow().iter().map(|w| w.column).collect();
+        assert_eq!(columns, vec![Some(3), Some(9), Some(9)]);
+
+        // Assigning a variable doesn't read it
+        warnings.borrow_mut().clear();
+        assert!(lox.run("{ var b = 1; b = 2; var _c; }").is_ok());
+        assert_eq!(
+            warnings.borrow()[0].to_string(),
+            "[line 1] Warning: Local variable 'b' is never read."
+        );
+        assert_eq!(warnings.borrow().len(), 1);
+
+        warnings.borrow_mut().clear();
+        assert!(lox.run("{ fun foo() {} fun _bar() {} }").is_ok());
+        assert_eq!(
+            warnings.borrow()[0].to_string(),
+            "[line 1] Warning: Local function 'foo' is never used."
+        );
+        assert_eq!(warnings.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_static_arity() {
+        let warnings = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+        let mut lox = Lox::new();
+        lox.set_error_reporting(false);
+        lox.set_diagnostic_sink(Some(warnings.clone()));
+        // Calls that never run can't fail
+        let source = "fun f(a) {}\nclass A { init(x, y) {} }\nfun g() { f(); A(1, 2); }";
+        assert!(lox.run(source).is_ok());
+        assert_eq!(warnings.borrow().len(), 1);
+        assert_eq!(warnings.borrow()[0].code, ErrorCode::ArgumentCount);
+        assert_eq!(
+            warnings.borrow()[0].to_string(),
+            "[line 3] Warning: 'f' expects 1 arguments but is called with 0."
+        );
+
+        let err = lox.run("g();").unwrap_err();
+        assert!(matches!(err, LoxError::Runtime(_)));
+        assert_eq!(err.code(), Some(ErrorCode::ArityMismatch));
+
+        // Names that are bound more than once are left to the runtime
+        warnings.borrow_mut().clear();
+        assert!(lox.run("fun h(a) {}\nh = clock;\nh();").is_ok());
+        assert!(lox
+            .run("class B { init(a) {} }\nclass C < B {}\nC(1);")
+            .is_ok());
+        assert!(warnings.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_declarations() {
+        let mut lox = Lox::new();
+        lox.set_error_reporting(false);
+        let err = lox.run("fun f(a, b, a) {}").unwrap_err();
+        assert_eq!(err.code(), Some(ErrorCode::DuplicateVariable));
+        assert_eq!(
+            err.to_string(),
+            "[line 1] Error at 'a': Already a variable with this name in this scope."
+        );
+        let err = lox
+            .run("class A {\n  m() {}\n  n(a) {}\n  m(a) {}\n}")
+            .unwrap_err();
+        assert_eq!(err.code(), Some(ErrorCode::DuplicateMethod));
+        assert_eq!(err.line(), Some(4));
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let mut lox = Lox::new();
+        lox.set_error_reporting(false);
+        let err = lox
+            .run("fun f(a, a) {}\nclass C { m() {} m() {} }\nreturn 1;")
+            .unwrap_err();
+        let diagnostics: Vec<_> = err
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.column, diagnostic.code))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (Some(1), Some(10), ErrorCode::DuplicateVariable),
+                (Some(2), Some(18), ErrorCode::DuplicateMethod),
+                (Some(3), Some(1), ErrorCode::ReturnFromTopLevel),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_runtime_error_spans() {
+        let mut lox = Lox::new();
+        let err = lox
+            .run(
+                "var a = 1;
+print a + nil;",
+            )
+            .unwrap_err();
+        assert_eq!((err.line(), err.column()), (Some(2), Some(9)));
+        assert_eq!(err.span(), Some(19..20));
+        let err = lox.run("print clock(1);").unwrap_err();
+        assert_eq!(err.column(), Some(14));
+
+        // The span of a function defined by an earlier source isn't in this one
+        lox.run("fun f() { return -\"a\"; }").unwrap();
+        let err = lox.run("f();").unwrap_err();
+        assert_eq!(
+            (err.line(), err.column(), err.span()),
+            (Some(1), None, None)
+        );
+    }
+
+    #[test]
+    fn test_slots_across_runs() {
+        let mut lox = Lox::new();
+        let source = "fun counter(start) {\n  var count = start;\n  \
+                      fun next() { count = count + 1; return count; }\n  return next;\n}\n\
+                      var next = counter(10);";
+        assert!(lox.run(source).is_ok());
+        // Later runs mustn't reuse the ids of the earlier ones
+        let source = "var total = 0;\n{\n  var a = 1;\n  var b = 2;\n  \
+                      for (var i = 0; i < 3; i = i + 1) { var c = i; total = total + a + b + c; }\n\
+                      }\nclass A { init(x) { this.x = x; } }\n{ class B < A { get() { return super.x; } } \
+                      total = total + B(5).x; }";
+        assert!(lox.run(source).is_ok());
+        assert!(lox.run("next(); var n = next();").is_ok());
+        assert_eq!(lox.interpreter.get_global::<f64>("n").unwrap(), 12.0);
+        assert_eq!(lox.interpreter.get_global::<f64>("total").unwrap(), 17.0);
+        assert_eq!(lox.interpreter.eval("next()").unwrap(), Value::Number(13.0));
+    }
+
+    #[test]
+    fn test_blocks_without_declarations() {
+        let mut lox = Lox::with_interpreter(Interpreter::builder().prelude(false).build());
+        // Only the blocks with a declaration get a scope of their own
+        let source =
+            "fun f(a) {\n  var b = 1;\n  { { b = b + a; } { var c = b; { b = c * 2; } } }\n  \
+                      if (a > 0) { return b; print b; var d; }\n  return 0;\n}\n\
+                      var x = f(2);\n{ x = x + 1; }";
+        assert!(lox.run(source).is_ok());
+        assert_eq!(lox.interpreter.get_global::<f64>("x").unwrap(), 7.0);
+    }
+
+    #[test]
+    fn test_call_depth() {
+        let mut lox = Lox::with_interpreter(Interpreter::builder().prelude(false).build());
+        let source = "fun sum(n) { if (n == 0) return 0; return n + sum(n - 1); }";
+        assert!(lox.run(source).is_ok());
+        // Deeper than the stack of the test's thread would allow
+        let value = lox.interpreter.eval("sum(5000)").unwrap();
+        assert_eq!(value, Value::Number(12502500.0));
+
+        lox.interpreter.set_max_call_depth(Some(100));
+        let err = lox.interpreter.eval("sum(100)").err().unwrap();
+        assert_eq!(err.to_string(), "Stack overflow.\n[line 1]");
+        assert_eq!(err.diagnostics()[0].code, ErrorCode::StackOverflow);
+        let value = lox.interpreter.eval("sum(99)").unwrap();
+        assert_eq!(value, Value::Number(4950.0));
+    }
+
+    #[test]
+    fn test_constant_conditions() {
+        let warnings = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+        let mut lox = Lox::new();
+        lox.set_diagnostic_sink(Some(warnings.clone()));
+        let source = "if (1 == 1) print 1;\nwhile (!true) {}\n\
+                      fun f(a) { while (true) { if (a) return; } }";
+        assert!(lox.run(source).is_ok());
+        let warnings: Vec<(ErrorCode, Option<i32>)> = warnings
+            .borrow()
+            .iter()
+            .map(|warning| (warning.code, warning.line))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                (ErrorCode::ConstantCondition, Some(1)),
+                (ErrorCode::ConstantCondition, Some(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let warnings = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+        let mut lox = Lox::new();
+        lox.set_error_reporting(false);
+        lox.set_diagnostic_sink(Some(warnings.clone()));
+        // Nothing is run, so neither the print nor the runtime error happen
+        assert!(lox.check("{ var a = 1; }\nprint nil + 1;").is_ok());
+        assert_eq!(warnings.borrow()[0].code, ErrorCode::UnusedVariable);
+        assert!(matches!(lox.check("print ;"), Err(LoxError::Syntax(_))));
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let warnings = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+        let mut lox = Lox::new();
+        lox.set_diagnostic_sink(Some(warnings.clone()));
+        lox.set_warnings_as_errors(true);
+        let err = lox
+            .run("var ran = false;\n{ var a; }\nran = true;")
+            .unwrap_err();
+        assert!(matches!(err, LoxError::Warnings(_)));
+        assert_eq!(err.stage(), Stage::Resolve);
+        assert_eq!(
+            err.to_string(),
+            "[line 2] Error: Local variable 'a' is never read."
+        );
+        assert_eq!(err.diagnostics()[0].severity, Severity::Error);
+        assert_eq!(err.diagnostics()[0].column, Some(7));
+        assert!(warnings.borrow().is_empty());
+        // The source failed before it ran
+        assert!(matches!(lox.run("print ran;"), Err(LoxError::Runtime(_))));
+    }
+}
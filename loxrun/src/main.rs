@@ -1,113 +1,742 @@
-use std::env;
-use std::fs;
-use std::io::{self, Write};
-use std::process;
-
-mod callable;
-mod class;
-mod expression;
-mod interpreter;
-mod parser;
-mod printer;
-mod resolver;
-mod stmt;
-
-use liblox::scanner::Scanner;
-use parser::Parser;
-use resolver::Resolver;
-
-// Define exit codes constants
-const EXIT_CODE_OK: i32 = 0;
-const EXIT_CODE_CMD_LINE_ERROR: i32 = 64;
-const EXIT_CODE_DATA_ERROR: i32 = 65;
-const EXIT_CODE_SCRIPT_ERROR: i32 = 70;
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() > 2 {
-        println!("Usage: rlox [script]");
-        process::exit(EXIT_CODE_CMD_LINE_ERROR);
-    } else if args.len() == 2 {
-        run_file(&args[1]);
-    } else {
-        run_prompt();
-    }
-}
-
-fn run_file(filename: &str) {
-    match fs::read_to_string(filename) {
-        Ok(contents) => {
-            let mut interpreter = interpreter::Interpreter::new();
-            let error_code = run(&mut interpreter, contents);
-            if error_code != 0 {
-                process::exit(error_code);
-            }
-        }
-        Err(err) => {
-            eprintln!("Error reading file {}: {}", filename, err);
-            process::exit(74);
-        }
-    }
-}
-
-fn run_prompt() {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    let mut input = String::new();
-
-    let mut interpreter = interpreter::Interpreter::new();
-    loop {
-        print!("> ");
-        stdout.flush().expect("Failed to flush stdout");
-
-        input.clear();
-        if stdin.read_line(&mut input).is_err() {
-            eprintln!("Error reading input");
-            break;
-        }
-
-        if input.trim().is_empty() {
-            break;
-        }
-
-        run(&mut interpreter, input.clone());
-    }
-}
-
-fn run(interpreter: &mut interpreter::Interpreter, source: String) -> i32 {
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens().clone();
-
-    let mut parser = Parser::new(tokens);
-    let parse_result = parser.parse();
-
-    if scanner.had_error {
-        return EXIT_CODE_DATA_ERROR;
-    }
-    if let Err(_) = parse_result {
-        return EXIT_CODE_DATA_ERROR;
-    }
-
-    let mut resolver = Resolver::new(interpreter);
-    if let Err(err) = resolver.resolve_stmts(parse_result.as_ref().unwrap()) {
-        eprintln!("{}", err.message);
-        return EXIT_CODE_DATA_ERROR;
-    }
-
-    let statements = parse_result.unwrap();
-    let result = interpreter.execute(&statements);
-    if let Err(err) = result {
-        eprintln!("{}", err.message);
-        return EXIT_CODE_SCRIPT_ERROR;
-    }
-    // Print the expression tree
-    // This is just for debugging purposes
-    // In a real interpreter, you might not want to print the entire tree
-    // but rather just the result of the evaluation
-    // You can comment this out if you don't want to see the tree
-    // let pretty = printer::pretty_print(&expr);
-    // println!("{}", pretty);
-
-    EXIT_CODE_OK
-}
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::Instant;
+
+use liblox::crash;
+use liblox::echo;
+use liblox::highlight;
+use liblox::log::{self, Verbosity};
+use liblox::memory::{self, CountingAllocator};
+use liblox::repl::{self, LineEditor, NameProvider};
+use loxrun::interpreter::Environment;
+use loxrun::{diagnostics_to_json, Diagnostic, Engine, ErrorCode, Interpreter, Lox, Stage, Value};
+
+// Define exit codes constants
+const EXIT_CODE_OK: i32 = 0;
+const EXIT_CODE_CMD_LINE_ERROR: i32 = 64;
+const EXIT_CODE_DATA_ERROR: i32 = 65;
+const EXIT_CODE_SCRIPT_ERROR: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    crash::install_panic_hook();
+
+    let mut program = None;
+    let mut options = Options {
+        allow_exec: false,
+        prelude: true,
+        memory_limit: None,
+        max_errors: None,
+        max_call_depth: None,
+        engine: Engine::TreeWalker,
+        warnings: false,
+        warn_shadowing: false,
+        warnings_as_errors: false,
+        diagnostics: DiagnosticFormat::Text,
+        emit: None,
+        history_file: repl::default_history_file(),
+        colors: true,
+        listen: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-q" | "--quiet" => log::set_verbosity(Verbosity::Quiet),
+            "-v" | "--verbose" => log::set_verbosity(Verbosity::Verbose),
+            "--timings" => log::set_timings(true),
+            "--allow-exec" => options.allow_exec = true,
+            "--no-prelude" => options.prelude = false,
+            "--warnings" => options.warnings = true,
+            "--warn-shadowing" => options.warn_shadowing = true,
+            "--warnings-as-errors" => options.warnings_as_errors = true,
+            "--diagnostics=text" => options.diagnostics = DiagnosticFormat::Text,
+            "--diagnostics=json" => options.diagnostics = DiagnosticFormat::Json,
+            "--pretty-errors" => options.diagnostics = DiagnosticFormat::Pretty,
+            "--emit=html" => options.emit = Some(Emit::Html),
+            "--emit=js" => options.emit = Some(Emit::JavaScript),
+            "--engine=tree" => options.engine = Engine::TreeWalker,
+            "--engine=closures" => options.engine = Engine::Closures,
+            #[cfg(feature = "jit")]
+            "--engine=jit" => options.engine = Engine::Jit,
+            "--memory-limit" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(limit) => options.memory_limit = Some(limit),
+                None => usage(),
+            },
+            "--max-call-depth" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(max) if max > 0 => options.max_call_depth = Some(max),
+                _ => usage(),
+            },
+            "--history" => match args.next() {
+                Some(path) => options.history_file = Some(PathBuf::from(path)),
+                None => usage(),
+            },
+            "--no-history" => options.history_file = None,
+            "--no-color" => options.colors = false,
+            "--listen" => match args.next() {
+                Some(address) => options.listen = Some(address),
+                None => usage(),
+            },
+            "--max-errors" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(max) if max > 0 => options.max_errors = Some(max),
+                _ => usage(),
+            },
+            "lint" => process::exit(lint(args)),
+            "fmt" => process::exit(fmt(args)),
+            // Everything after the script or code belongs to the script
+            "-e" | "--eval" => match args.next() {
+                Some(code) => {
+                    program = Some(Program::Inline(code));
+                    break;
+                }
+                None => usage(),
+            },
+            _ if !arg.starts_with('-') => {
+                program = Some(Program::File(arg));
+                break;
+            }
+            _ => usage(),
+        }
+    }
+
+    if let Some(address) = &options.listen {
+        if program.is_some() || options.emit.is_some() {
+            usage();
+        }
+        listen(address, &options);
+    }
+    match (program, options.emit) {
+        (Some(program), Some(emit)) => emit_program(&program, emit, &options),
+        (Some(program), None) => run_program(&program, args.collect(), &options),
+        (None, Some(_)) => usage(),
+        (None, None) => run_prompt(&options),
+    }
+    report_statistics();
+}
+
+fn usage() -> ! {
+    println!(
+        "Usage: loxrun [--quiet | --verbose] [--timings] [--allow-exec] [--no-prelude] \
+         [--memory-limit <bytes>] [--max-call-depth <depth>] [--max-errors <count>] \
+         [--warnings] [--warn-shadowing] [--warnings-as-errors] \
+         [--diagnostics=text|json] [--pretty-errors] \
+         [--engine={}] [--emit=html|js] [--history <file> | --no-history] [--no-color] \
+         [--listen [<host>:]<port> | -e <code> [args...] | script [args...]]",
+        if cfg!(feature = "jit") {
+            "tree|closures|jit"
+        } else {
+            "tree|closures"
+        }
+    );
+    println!(
+        "       loxrun lint [--allow <rule> | --warn <rule> | --deny <rule>]... \
+         [--diagnostics=text|json] [--pretty-errors] <script>..."
+    );
+    println!("       loxrun fmt [--check] [--diagnostics=text|json] [--pretty-errors] <script>...");
+    let rules: Vec<&str> = ErrorCode::warnings()
+        .filter_map(|code| code.rule_name())
+        .collect();
+    println!("\nLint rules: {}", rules.join(", "));
+    process::exit(EXIT_CODE_CMD_LINE_ERROR);
+}
+
+/// Interpreter settings taken from the command line.
+#[derive(Clone)]
+struct Options {
+    allow_exec: bool,
+    prelude: bool,
+    memory_limit: Option<usize>,
+    max_errors: Option<usize>,
+    // Replaces the interpreter's default limit when given
+    max_call_depth: Option<usize>,
+    // How the interpreter runs the script, for comparing their speed
+    engine: Engine,
+    // Print warnings along with the errors, which jlox doesn't have
+    warnings: bool,
+    warn_shadowing: bool,
+    warnings_as_errors: bool,
+    diagnostics: DiagnosticFormat,
+    // Printed instead of running the script
+    emit: Option<Emit>,
+    // Where the REPL keeps its history, `~/.lox_history` by default
+    history_file: Option<PathBuf>,
+    // Highlight the REPL input as it is typed
+    colors: bool,
+    // Serve the REPL on this address instead of the terminal
+    listen: Option<String>,
+}
+
+thread_local! {
+    // Warnings of the current run, printed together with its errors. Each
+    // thread runs its own interpreter and so has its own warnings.
+    static WARNINGS: Rc<RefCell<Vec<Diagnostic>>> = Rc::new(RefCell::new(Vec::new()));
+}
+
+fn take_warnings() -> Vec<Diagnostic> {
+    WARNINGS.with(|warnings| warnings.borrow_mut().drain(..).collect())
+}
+
+/// Where the program to run comes from.
+enum Program {
+    /// A script file.
+    File(String),
+    /// Code given with `-e`.
+    Inline(String),
+}
+
+impl Program {
+    /// The name errors in the program are reported with.
+    fn name(&self) -> &str {
+        match self {
+            Program::File(filename) => filename,
+            Program::Inline(_) => "<eval>",
+        }
+    }
+
+    /// Reads the source of the program, exiting if the file can't be read.
+    fn source(&self) -> String {
+        match self {
+            Program::File(filename) => match fs::read_to_string(filename) {
+                Ok(source) => source,
+                Err(err) => {
+                    eprintln!("Error reading file {}: {}", filename, err);
+                    process::exit(EX_IOERR);
+                }
+            },
+            Program::Inline(code) => code.clone(),
+        }
+    }
+}
+
+/// How errors and warnings are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiagnosticFormat {
+    /// A line each like jlox, which test runners compare against.
+    Text,
+    /// Text followed by the offending line with the error underlined.
+    Pretty,
+    /// A JSON array, see `diagnostics_to_json`.
+    Json,
+}
+
+/// What `--emit` turns the script into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    /// The source with syntax highlighting.
+    Html,
+    /// A translation that runs without the interpreter.
+    JavaScript,
+}
+
+impl Options {
+    fn create_lox(&self, script_args: Vec<String>) -> Lox {
+        let mut interpreter = Interpreter::builder()
+            .script_args(script_args)
+            .allow_exec(self.allow_exec)
+            .prelude(self.prelude)
+            .build();
+        if let Err(err) = interpreter.set_memory_limit(self.memory_limit) {
+            eprintln!("{}", err);
+            process::exit(EXIT_CODE_CMD_LINE_ERROR);
+        }
+        if let Some(max) = self.max_call_depth {
+            interpreter.set_max_call_depth(Some(max));
+        }
+        interpreter.set_engine(self.engine);
+        let mut lox = Lox::with_interpreter(interpreter);
+        // Errors are printed once the run failed, together with warnings
+        lox.set_error_reporting(false);
+        lox.set_max_errors(self.max_errors);
+        lox.set_warn_shadowing(self.warn_shadowing);
+        lox.set_warnings_as_errors(self.warnings_as_errors);
+        if self.warnings {
+            lox.set_diagnostic_sink(Some(WARNINGS.with(|warnings| warnings.clone())));
+        }
+        lox
+    }
+}
+
+fn report_statistics() {
+    if let Some(report) = log::timings_report() {
+        eprint!("{}", report);
+    }
+    log::verbose(format!("memory: {}", memory::stats()));
+}
+
+fn run_program(program: &Program, script_args: Vec<String>, options: &Options) {
+    let source = program.source();
+    let mut lox = options.create_lox(script_args);
+    let error_code = run_guarded(&mut lox, source, program.name(), options);
+    if error_code != 0 {
+        report_statistics();
+        process::exit(error_code);
+    }
+}
+
+fn emit_program(program: &Program, emit: Emit, options: &Options) {
+    let source = program.source();
+    let filename = program.name();
+    match emit {
+        Emit::Html => println!("{}", highlight::highlight_html(&source)),
+        Emit::JavaScript => {
+            let mut lox = options.create_lox(Vec::new());
+            match lox.to_javascript(&source) {
+                Ok(javascript) => print!("{}", javascript),
+                Err(err) => {
+                    let mut diagnostics = take_warnings();
+                    diagnostics.extend_from_slice(err.diagnostics());
+                    print_diagnostics(&diagnostics, &source, filename, options.diagnostics);
+                    process::exit(EXIT_CODE_DATA_ERROR);
+                }
+            }
+        }
+    }
+}
+
+fn run_prompt(options: &Options) {
+    let mut editor = match LineEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Error initializing line editor: {}", err);
+            process::exit(EX_IOERR);
+        }
+    };
+
+    if let Some(path) = &options.history_file {
+        if let Err(err) = editor.set_history_file(path.clone()) {
+            eprintln!("Error reading history {}: {}", path.display(), err);
+        }
+    }
+
+    if !options.colors {
+        editor.set_highlighting(false);
+    }
+
+    log::info("Lox REPL. Enter an empty line to exit.");
+    let prompt = if log::enabled(Verbosity::Normal) {
+        "> "
+    } else {
+        ""
+    };
+
+    let mut lox = options.create_lox(Vec::new());
+    lox.set_mode(echo::Mode::Repl);
+    lox.set_echo_fields(true);
+    editor.set_name_provider(GlobalNames(Rc::clone(&lox.interpreter.globals)));
+    // Ctrl-C cancels the running input instead of ending the session
+    let token = lox.interpreter.cancel_token();
+    if let Err(err) = ctrlc::set_handler(move || token.cancel()) {
+        log::verbose(format!("Ctrl-C handler not installed: {}", err));
+    }
+
+    while let Some(input) = editor.read_line(prompt) {
+        if input.trim().is_empty() {
+            break;
+        }
+        if repl::parse_command(&input) == Some(("history", "")) {
+            print_history(&editor);
+        } else {
+            run_input(
+                &mut lox,
+                &input,
+                options,
+                &mut io::stdout(),
+                &mut io::stderr(),
+            );
+        }
+        lox.interpreter.cancel_token().reset();
+    }
+}
+
+// Runs a line of REPL input, which is Lox code or a command. Command results
+// are written to `out`, and errors and timings to `err`.
+fn run_input(
+    lox: &mut Lox,
+    input: &str,
+    options: &Options,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) {
+    match repl::parse_command(input) {
+        Some(("time", "")) => {
+            let _ = writeln!(err, "Usage: :time <code>");
+        }
+        Some(("type", "")) => {
+            let _ = writeln!(err, "Usage: :type <expression>");
+        }
+        Some(("type", expression)) => write_type(lox, expression, options, out, err),
+        Some(("time", code)) => {
+            let steps = lox.interpreter.steps();
+            let start = Instant::now();
+            run_reporting(lox, code.to_string(), "<stdin>", options, err);
+            let _ = writeln!(
+                err,
+                "Time: {}, {} steps",
+                log::format_duration(start.elapsed()),
+                lox.interpreter.steps() - steps
+            );
+        }
+        Some(_) => {
+            let _ = writeln!(err, "Unknown command: {}", input.trim());
+        }
+        None => {
+            run_reporting(lox, input.to_string(), "<stdin>", options, err);
+        }
+    }
+}
+
+// Evaluates the expression and writes what type its value has
+fn write_type(
+    lox: &mut Lox,
+    expression: &str,
+    options: &Options,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) {
+    match crash::catch_internal_error(|| lox.interpreter.eval(expression)) {
+        Ok(Ok(value)) => {
+            let _ = writeln!(out, "{}", value.describe_type());
+        }
+        Ok(Err(error)) => {
+            write_diagnostics(
+                err,
+                error.diagnostics(),
+                expression,
+                "<stdin>",
+                options.diagnostics,
+            );
+        }
+        Err(error) => {
+            let _ = writeln!(err, "{}", error);
+            lox.interpreter.environment = Rc::clone(&lox.interpreter.globals);
+        }
+    }
+}
+
+/// Serves the REPL to the clients that connect to `address`, each with an
+/// interpreter of its own. Only returns if the address can't be listened on.
+fn listen(address: &str, options: &Options) -> ! {
+    let address = &listen_address(address);
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Error listening on {}: {}", address, err);
+            process::exit(EX_IOERR);
+        }
+    };
+    let bound = listener
+        .local_addr()
+        .map_or_else(|_| address.to_string(), |bound| bound.to_string());
+    log::info(format!("Lox REPL listening on {}.", bound));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let options = options.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().map(|peer| peer.to_string());
+                    let peer = peer.unwrap_or_else(|_| "unknown peer".to_string());
+                    log::verbose(format!("{} connected", peer));
+                    if let Err(err) = serve(stream, &options) {
+                        log::verbose(format!("Connection to {} failed: {}", peer, err));
+                    }
+                    log::verbose(format!("{} disconnected", peer));
+                });
+            }
+            Err(err) => log::verbose(format!("Connection not accepted: {}", err)),
+        }
+    }
+    unreachable!("incoming() never ends");
+}
+
+// A bare port is served on the loopback interface only, other hosts have to
+// be asked for explicitly
+fn listen_address(address: &str) -> String {
+    match address.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        Err(_) => address.to_string(),
+    }
+}
+
+// Runs a REPL session over the connection. Input that ends inside a block,
+// call or string continues on the next line, and an empty line ends the
+// session.
+fn serve(stream: TcpStream, options: &Options) -> io::Result<()> {
+    let mut lox = options.create_lox(Vec::new());
+    // Clients mustn't run commands or touch files on the server, whatever
+    // the command line allows
+    lox.interpreter.allow_exec = false;
+    lox.interpreter.allow_files = false;
+    lox.set_mode(echo::Mode::Repl);
+    lox.set_echo_fields(true);
+    // The connection is read line by line below, readLine() mustn't wait on
+    // the server's stdin
+    lox.interpreter.input = Box::new(io::empty());
+    lox.interpreter.output = Box::new(stream.try_clone()?);
+    lox.interpreter.error_output = Box::new(stream.try_clone()?);
+    let mut out = stream.try_clone()?;
+    let mut err = stream.try_clone()?;
+    let mut lines = BufReader::new(stream).lines();
+
+    writeln!(out, "Lox REPL. Enter an empty line to exit.")?;
+    loop {
+        write!(out, "> ")?;
+        let Some(mut input) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        if input.trim().is_empty() {
+            return Ok(());
+        }
+        while repl::is_incomplete(&input) {
+            write!(out, ". ")?;
+            let Some(line) = lines.next().transpose()? else {
+                return Ok(());
+            };
+            input.push('\n');
+            input.push_str(&line);
+        }
+        run_input(&mut lox, &input, options, &mut out, &mut err);
+    }
+}
+
+fn print_history(editor: &LineEditor) {
+    for (number, line) in editor.history().enumerate() {
+        println!("{:>5}  {}", number + 1, line);
+    }
+}
+
+// Completes the names defined in the REPL so far
+struct GlobalNames(Rc<RefCell<Environment>>);
+
+impl NameProvider for GlobalNames {
+    fn globals(&self) -> Vec<String> {
+        self.0.borrow().names()
+    }
+
+    fn members(&self, receiver: &str) -> Vec<String> {
+        match self.0.borrow().get(&receiver.to_string()) {
+            Some(Value::Instance(instance)) => instance.borrow().member_names(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Runs the source, reporting a panic inside the interpreter as an internal
+/// error together with what was being done at the time.
+fn run_guarded(lox: &mut Lox, source: String, file: &str, options: &Options) -> i32 {
+    run_reporting(lox, source, file, options, &mut io::stderr())
+}
+
+// Runs the source like run_guarded, writing the errors to `errors`
+fn run_reporting(
+    lox: &mut Lox,
+    source: String,
+    file: &str,
+    options: &Options,
+    errors: &mut dyn Write,
+) -> i32 {
+    let result = crash::catch_internal_error(|| lox.run(&source));
+    let mut diagnostics = take_warnings();
+    if let Ok(Err(err)) = &result {
+        diagnostics.extend_from_slice(err.diagnostics());
+    }
+    write_diagnostics(errors, &diagnostics, &source, file, options.diagnostics);
+
+    match result {
+        Ok(Ok(())) => EXIT_CODE_OK,
+        Ok(Err(err)) => match err.stage() {
+            Stage::Runtime => EXIT_CODE_SCRIPT_ERROR,
+            Stage::Syntax | Stage::Resolve => EXIT_CODE_DATA_ERROR,
+        },
+        Err(error) => {
+            let _ = writeln!(errors, "{}", error.with_context(lox.activity().to_string()));
+            // Unwinding may have left the interpreter inside a nested scope
+            lox.interpreter.environment = Rc::clone(&lox.interpreter.globals);
+            EXIT_CODE_SCRIPT_ERROR
+        }
+    }
+}
+
+fn print_diagnostics(
+    diagnostics: &[Diagnostic],
+    source: &str,
+    file: &str,
+    format: DiagnosticFormat,
+) {
+    write_diagnostics(&mut io::stderr(), diagnostics, source, file, format);
+}
+
+fn write_diagnostics(
+    errors: &mut dyn Write,
+    diagnostics: &[Diagnostic],
+    source: &str,
+    file: &str,
+    format: DiagnosticFormat,
+) {
+    match format {
+        DiagnosticFormat::Text => {
+            for diagnostic in diagnostics {
+                let _ = writeln!(errors, "{}", diagnostic);
+            }
+        }
+        DiagnosticFormat::Pretty => {
+            for diagnostic in diagnostics {
+                let _ = writeln!(errors, "{}", diagnostic.render(source));
+            }
+        }
+        DiagnosticFormat::Json if diagnostics.is_empty() => {}
+        DiagnosticFormat::Json => {
+            let _ = writeln!(errors, "{}", diagnostics_to_json(diagnostics, file));
+        }
+    }
+}
+
+/// Rewrites the scripts in the canonical layout. With `--check` it only
+/// lists the scripts that aren't formatted, and fails if there are any.
+fn fmt(args: impl Iterator<Item = String>) -> i32 {
+    let mut check = false;
+    let mut format = DiagnosticFormat::Text;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--diagnostics=text" => format = DiagnosticFormat::Text,
+            "--diagnostics=json" => format = DiagnosticFormat::Json,
+            "--pretty-errors" => format = DiagnosticFormat::Pretty,
+            _ if !arg.starts_with('-') => files.push(arg),
+            _ => usage(),
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut exit_code = EXIT_CODE_OK;
+    for file in &files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("Error reading file {}: {}", file, err);
+                exit_code = exit_code.max(EX_IOERR);
+                continue;
+            }
+        };
+        let formatted = match loxrun::format(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                print_diagnostics(err.diagnostics(), &source, file, format);
+                exit_code = exit_code.max(EXIT_CODE_DATA_ERROR);
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file);
+            exit_code = exit_code.max(EXIT_CODE_DATA_ERROR);
+        } else if let Err(err) = fs::write(file, formatted) {
+            eprintln!("Error writing file {}: {}", file, err);
+            exit_code = exit_code.max(EX_IOERR);
+        }
+    }
+    exit_code
+}
+
+/// What `lint` does with the warnings of a rule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Checks the scripts for errors and warnings without running them. Fails
+/// if there are errors or warnings of denied rules.
+fn lint(mut args: impl Iterator<Item = String>) -> i32 {
+    let mut levels: HashMap<ErrorCode, Level> = ErrorCode::warnings()
+        .map(|code| (code, Level::Warn))
+        .collect();
+    // Shadowing is often intended, so it's only reported when asked for
+    levels.insert(ErrorCode::ShadowedVariable, Level::Allow);
+    let mut format = DiagnosticFormat::Text;
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        let level = match arg.as_str() {
+            "--allow" => Level::Allow,
+            "--warn" => Level::Warn,
+            "--deny" => Level::Deny,
+            "--diagnostics=text" => {
+                format = DiagnosticFormat::Text;
+                continue;
+            }
+            "--diagnostics=json" => {
+                format = DiagnosticFormat::Json;
+                continue;
+            }
+            "--pretty-errors" => {
+                format = DiagnosticFormat::Pretty;
+                continue;
+            }
+            _ if !arg.starts_with('-') => {
+                files.push(arg);
+                continue;
+            }
+            _ => usage(),
+        };
+        match args.next().as_deref().and_then(ErrorCode::from_rule_name) {
+            Some(code) => levels.insert(code, level),
+            None => usage(),
+        };
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut exit_code = EXIT_CODE_OK;
+    for file in &files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("Error reading file {}: {}", file, err);
+                exit_code = exit_code.max(EX_IOERR);
+                continue;
+            }
+        };
+        let warnings = Rc::new(RefCell::new(Vec::new()));
+        let mut lox = Lox::with_interpreter(Interpreter::builder().prelude(false).build());
+        lox.set_error_reporting(false);
+        lox.set_warn_shadowing(levels[&ErrorCode::ShadowedVariable] != Level::Allow);
+        lox.set_diagnostic_sink(Some(warnings.clone()));
+        let result = lox.check(&source);
+
+        let level = |diagnostic: &Diagnostic| levels.get(&diagnostic.code).copied();
+        let mut diagnostics: Vec<Diagnostic> = warnings
+            .borrow()
+            .iter()
+            .filter(|warning| level(warning) != Some(Level::Allow))
+            .cloned()
+            .collect();
+        let denied = diagnostics
+            .iter()
+            .any(|warning| level(warning) == Some(Level::Deny));
+        if let Err(err) = &result {
+            diagnostics.extend_from_slice(err.diagnostics());
+        }
+        print_diagnostics(&diagnostics, &source, file, format);
+        if result.is_err() || denied {
+            exit_code = exit_code.max(EXIT_CODE_DATA_ERROR);
+        }
+    }
+    exit_code
+}
@@ -0,0 +1,357 @@
+//! Passes that simplify the syntax tree between parsing and resolving,
+//! without changing what the program does.
+
+use liblox::tokens::{LiteralTypes, TokenType};
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::{Expression, Literal};
+use crate::stmt::{BlockStmt, Stmt};
+
+/// Replaces expressions on literals by their value, such as `2 * 3 + 1` by
+/// `7`, and logical expressions with a literal on the left by the side they
+/// evaluate to, such as `true and x` by `x`. Expressions that would fail at
+/// runtime are left for the interpreter to report.
+pub fn fold_constants(ast: &mut Ast, statements: &[StmtId]) {
+    for &statement in statements {
+        fold_statement(ast, statement);
+    }
+}
+
+fn fold_statement(ast: &mut Ast, statement: StmtId) {
+    match &ast[statement] {
+        Stmt::Expression(stmt) => fold_expression(ast, stmt.expression),
+        Stmt::Print(stmt) => fold_expression(ast, stmt.expression),
+        Stmt::Var(stmt) => {
+            if let Some(initializer) = stmt.initializer {
+                fold_expression(ast, initializer);
+            }
+        }
+        Stmt::Return(stmt) => {
+            if let Some(value) = stmt.value {
+                fold_expression(ast, value);
+            }
+        }
+        Stmt::Block(block) => fold_constants(ast, &block.statements.clone()),
+        Stmt::If(stmt) => {
+            let (condition, then_branch, else_branch) =
+                (stmt.condition, stmt.then_branch, stmt.else_branch);
+            fold_expression(ast, condition);
+            fold_statement(ast, then_branch);
+            if let Some(else_branch) = else_branch {
+                fold_statement(ast, else_branch);
+            }
+        }
+        Stmt::While(stmt) => {
+            let (condition, body) = (stmt.condition, stmt.body);
+            fold_expression(ast, condition);
+            fold_statement(ast, body);
+        }
+        Stmt::ForIn(stmt) => {
+            let (iterable, body) = (stmt.iterable, stmt.body);
+            fold_expression(ast, iterable);
+            fold_statement(ast, body);
+        }
+        Stmt::Function(function) => fold_constants(ast, &function.body.clone()),
+        Stmt::Class(class) => {
+            for method in class.methods.clone() {
+                fold_constants(ast, &method.body);
+            }
+        }
+    }
+}
+
+/// Folds the constants of a single expression, see `fold_constants`.
+pub fn fold_expression(ast: &mut Ast, expression: ExprId) {
+    match &ast[expression] {
+        Expression::Assign(assign) => fold_expression(ast, assign.value),
+        Expression::Binary(binary) => {
+            let (left, right) = (binary.left, binary.right);
+            fold_expression(ast, left);
+            fold_expression(ast, right);
+        }
+        Expression::Call(call) => {
+            let callee = call.callee;
+            for argument in call.arguments.clone() {
+                fold_expression(ast, argument);
+            }
+            fold_expression(ast, callee);
+        }
+        Expression::Get(get) => fold_expression(ast, get.object),
+        Expression::Grouping(grouping) => fold_expression(ast, grouping.expression),
+        Expression::Logical(logical) => {
+            let (left, right) = (logical.left, logical.right);
+            fold_expression(ast, left);
+            fold_expression(ast, right);
+        }
+        Expression::Set(set) => {
+            let (object, value) = (set.object, set.value);
+            fold_expression(ast, object);
+            fold_expression(ast, value);
+        }
+        Expression::Unary(unary) => fold_expression(ast, unary.right),
+        Expression::Literal(_)
+        | Expression::Super(_)
+        | Expression::This(_)
+        | Expression::Variable(_) => {}
+    }
+    if let Some(folded) = folded(ast, expression) {
+        ast[expression] = folded;
+    }
+}
+
+// The simpler expression with the same value, once the operands are folded
+fn folded(ast: &mut Ast, expression: ExprId) -> Option<Expression> {
+    let id = *ast[expression];
+    let literal = |value| Some(Expression::Literal(Literal { id, value }));
+    let literal_value = |operand: ExprId| match &ast[operand] {
+        Expression::Literal(literal) => Some(literal.value.clone()),
+        _ => None,
+    };
+    match &ast[expression] {
+        Expression::Grouping(grouping) => literal(literal_value(grouping.expression)?),
+        Expression::Unary(unary) => match (&unary.operator.token_type, literal_value(unary.right)?)
+        {
+            (TokenType::Minus, LiteralTypes::Number(value)) => {
+                literal(LiteralTypes::Number(-value))
+            }
+            (TokenType::Bang, value) => literal(LiteralTypes::Bool(!is_truthy(&value))),
+            _ => None,
+        },
+        Expression::Binary(binary) => {
+            let left = literal_value(binary.left)?;
+            let right = literal_value(binary.right)?;
+            binary_value(&binary.operator.token_type, &left, &right).and_then(literal)
+        }
+        Expression::Logical(logical) => {
+            let left = literal_value(logical.left)?;
+            // `or` stops at a truthy left side and `and` at a falsy one
+            let stops = is_truthy(&left) == (logical.operator.token_type == TokenType::Or);
+            let side = if stops { logical.left } else { logical.right };
+            // The side is only referred to from here
+            let placeholder = Expression::Literal(Literal {
+                id,
+                value: LiteralTypes::Nil,
+            });
+            Some(mem::replace(&mut ast[side], placeholder))
+        }
+        _ => None,
+    }
+}
+
+/// Removes statements that can never run: those after a `return` in the
+/// same block other than declarations, the branches of `if` statements that a literal condition
+/// rules out, and loops whose condition is a falsy literal. Conditions such
+/// as `1 > 2` become literals in `fold_constants`.
+///
+/// Run after resolving, so that errors and warnings in the removed code are
+/// still reported, and since the removed code declares no variable that the
+/// rest of the code uses.
+pub fn eliminate_dead_code(ast: &mut Ast, statements: &mut Vec<StmtId>) {
+    *statements = mem::take(statements)
+        .into_iter()
+        .filter_map(|statement| prune(ast, statement))
+        .collect();
+    if let Some(index) = statements
+        .iter()
+        .position(|&statement| matches!(ast[statement], Stmt::Return(_)))
+    {
+        // Declarations stay, as they decide whether a block gets a scope
+        let mut position = 0;
+        statements.retain(|&statement| {
+            position += 1;
+            position <= index + 1 || ast[statement].is_declaration()
+        });
+    }
+}
+
+// The statement without its dead code, or nothing if it does nothing
+fn prune(ast: &mut Ast, statement: StmtId) -> Option<StmtId> {
+    // Taken out while its children are pruned, and put back if it stays
+    let placeholder = Stmt::Block(BlockStmt {
+        statements: Vec::new(),
+    });
+    let pruned = match mem::replace(&mut ast[statement], placeholder) {
+        Stmt::If(mut stmt) => match literal_truth(ast, stmt.condition) {
+            Some(true) => return prune(ast, stmt.then_branch),
+            Some(false) => return stmt.else_branch.and_then(|branch| prune(ast, branch)),
+            None => {
+                stmt.then_branch = prune_nested(ast, stmt.then_branch);
+                stmt.else_branch = stmt.else_branch.and_then(|branch| prune(ast, branch));
+                Stmt::If(stmt)
+            }
+        },
+        Stmt::While(mut stmt) => {
+            if literal_truth(ast, stmt.condition) == Some(false) {
+                return None;
+            }
+            stmt.body = prune_nested(ast, stmt.body);
+            Stmt::While(stmt)
+        }
+        Stmt::ForIn(mut stmt) => {
+            stmt.body = prune_nested(ast, stmt.body);
+            Stmt::ForIn(stmt)
+        }
+        Stmt::Block(mut block) => {
+            eliminate_dead_code(ast, &mut block.statements);
+            Stmt::Block(block)
+        }
+        // Just parsed, so the declarations aren't shared yet
+        Stmt::Function(mut function) => {
+            eliminate_dead_code(ast, &mut Rc::make_mut(&mut function).body);
+            Stmt::Function(function)
+        }
+        Stmt::Class(mut class) => {
+            for method in &mut class.methods {
+                eliminate_dead_code(ast, &mut Rc::make_mut(method).body);
+            }
+            Stmt::Class(class)
+        }
+        statement => statement,
+    };
+    ast[statement] = pruned;
+    Some(statement)
+}
+
+// Bodies and branches must be a statement, so nothing becomes an empty block
+fn prune_nested(ast: &mut Ast, statement: StmtId) -> StmtId {
+    prune(ast, statement).unwrap_or_else(|| {
+        ast.add_statement(Stmt::Block(BlockStmt {
+            statements: Vec::new(),
+        }))
+    })
+}
+
+fn literal_truth(ast: &Ast, condition: ExprId) -> Option<bool> {
+    match &ast[condition] {
+        Expression::Literal(literal) => Some(is_truthy(&literal.value)),
+        _ => None,
+    }
+}
+
+fn binary_value(
+    operator: &TokenType,
+    left: &LiteralTypes,
+    right: &LiteralTypes,
+) -> Option<LiteralTypes> {
+    use LiteralTypes::{Bool, Number};
+    match (operator, left, right) {
+        (TokenType::EqualEqual, left, right) => Some(Bool(left == right)),
+        (TokenType::BangEqual, left, right) => Some(Bool(left != right)),
+        (TokenType::Plus, LiteralTypes::String(left), LiteralTypes::String(right)) => {
+            Some(LiteralTypes::String(format!("{}{}", left, right)))
+        }
+        (operator, Number(left), Number(right)) => match operator {
+            TokenType::Plus => Some(Number(left + right)),
+            TokenType::Minus => Some(Number(left - right)),
+            TokenType::Star => Some(Number(left * right)),
+            TokenType::Slash => Some(Number(left / right)),
+            TokenType::Greater => Some(Bool(left > right)),
+            TokenType::GreaterEqual => Some(Bool(left >= right)),
+            TokenType::Less => Some(Bool(left < right)),
+            TokenType::LessEqual => Some(Bool(left <= right)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_truthy(value: &LiteralTypes) -> bool {
+    !matches!(value, LiteralTypes::Nil | LiteralTypes::Bool(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::printer::pretty_print;
+    use liblox::scanner::Scanner;
+
+    fn parse(source: &str) -> (Ast, Vec<StmtId>) {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        (parser.into_ast(), statements)
+    }
+
+    // Folds the expression statement
+    fn fold(source: &str) -> (Ast, ExprId) {
+        let (mut ast, statements) = parse(source);
+        fold_constants(&mut ast, &statements);
+        match &ast[statements[0]] {
+            Stmt::Expression(stmt) => {
+                let expression = stmt.expression;
+                (ast, expression)
+            }
+            _ => panic!("expected an expression statement"),
+        }
+    }
+
+    fn printed(source: &str) -> String {
+        let (ast, expression) = fold(source);
+        pretty_print(&ast, expression)
+    }
+
+    fn is_variable(expression: &Expression, name: &str) -> bool {
+        matches!(expression, Expression::Variable(variable) if variable.name.lexeme == name)
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(printed("2 * 3 + 1;"), "7");
+        assert_eq!(printed("-(1 + 2) < 0 == !nil;"), "true");
+        assert_eq!(printed("\"a\" + \"b\" != \"ab\";"), "false");
+        assert_eq!(printed("nil and x;"), "nil");
+        assert_eq!(printed("0 or x;"), "0");
+        let (ast, expression) = fold("true and x;");
+        assert!(is_variable(&ast[expression], "x"));
+        let (ast, expression) = fold("false or x + (1 + 1);");
+        match &ast[expression] {
+            Expression::Binary(binary) => {
+                assert!(is_variable(&ast[binary.left], "x"));
+                assert_eq!(pretty_print(&ast, binary.right), "2");
+            }
+            _ => panic!("expected a binary expression"),
+        }
+        // Left for the interpreter, which reports the error
+        let (ast, expression) = fold("1 + \"a\";");
+        assert!(matches!(ast[expression], Expression::Binary(_)));
+        let (ast, expression) = fold("-\"a\";");
+        assert!(matches!(ast[expression], Expression::Unary(_)));
+    }
+
+    #[test]
+    fn test_eliminate_dead_code() {
+        let source = "fun f() { print 1; return 2; print 3; }\n\
+                      if (1 > 2) print 4; else { print 5; }\n\
+                      if (nil) print 6;\n\
+                      while (false) print 7;\n\
+                      while (x) if (!true) print 8;";
+        let (mut ast, mut statements) = parse(source);
+        fold_constants(&mut ast, &statements);
+        eliminate_dead_code(&mut ast, &mut statements);
+
+        assert_eq!(statements.len(), 3);
+        match &ast[statements[0]] {
+            Stmt::Function(function) => {
+                assert_eq!(function.body.len(), 2);
+                assert!(matches!(ast[function.body[1]], Stmt::Return(_)));
+            }
+            _ => panic!("expected a function"),
+        }
+        match &ast[statements[1]] {
+            Stmt::Block(block) => assert!(matches!(ast[block.statements[0]], Stmt::Print(_))),
+            _ => panic!("expected the else branch"),
+        }
+        match &ast[statements[2]] {
+            Stmt::While(stmt) => assert_eq!(
+                ast[stmt.body],
+                Stmt::Block(BlockStmt {
+                    statements: Vec::new()
+                })
+            ),
+            _ => panic!("expected a loop"),
+        }
+    }
+}
@@ -1,795 +1,1226 @@
-use liblox::tokens::{LiteralTypes, Token, TokenType};
-
-use crate::{
-    expression::{
-        Assign, Binary, Call, Expression, Get, Grouping, Literal, Logical, Set, Super, This, Unary,
-        Variable,
-    },
-    stmt::{
-        BlockStmt, ClassStmt, ExpressionStmt, FunctionStmt, IfStmt, PrintStmt, ReturnStmt, Stmt,
-        VarStmt, WhileStmt,
-    },
-};
-
-// Production rules
-// program -> statement* EOF ;
-
-// declaration -> classDecl | funDecl | varDecl | statement ;
-// classDecl -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
-// funDecls -> "fun" function ;
-// function -> IDENTIFIER "(" parameters? ")" block ;
-// parameters -> IDENTIFIER ( "," IDENTIFIER )* ;
-// varDecl -> "var" IDENTIFIER ("=" expression)? ";" ;
-// statement -> exprStmt | forStmt | ifStmt | printStmt | returnStm | whileStmt | block ;
-// exprStmt -> expression ";" ;
-// forStmt -> "for" "(" (varDecl | exprStmt | ";") expression? ";" expression? ")" statement ;
-// ifStmt -> "if" "(" expression ")" statement ( "else" statement )? ;
-// printStmt -> "print" expression ";" ;
-// returnStmt -> "return" expression? ";" ;
-// whileStmt -> "while" "(" expression ")" statement ;
-// block -> "{" declaration* "}" ;
-
-// expression -> assignment ;
-// assignment -> ( call "." )? IDENTIFIER "=" assignment | logical_or ;
-// logical_or -> logical_and ( "or" logical_and )* ;
-// logical_and -> equality ( "and" equality )* ;
-// equality -> comparison ( ( "!=" | "==" ) comparison )* ;
-// comparison -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
-// term -> factor ( ( "-" | "+" ) factor )* ;
-// factor -> unary ( ( "/" | "*" ) unary )* ;
-// unary -> ( "!" | "-" ) unary | call ;
-// call -> primary ( "(" arguments? ")" )* ;
-// primary -> NUMBER | STRING | "true" | "false" | "nil" | "this" | "(" expression ")" | IDENTIFIER | "super" "." IDENTIFIER;
-// arguments -> expression ( "," expression )* ;
-
-pub struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
-    current_id: usize,
-}
-
-#[derive(Debug)]
-pub struct ParserError {
-    pub message: String,
-}
-
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser {
-            tokens,
-            current: 0,
-            current_id: 0,
-        }
-    }
-
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParserError> {
-        let mut has_error = false;
-        let mut statements = Vec::new();
-        while !self.is_at_end() {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-                Err(err) => {
-                    has_error = true;
-                    eprintln!("{}", err.message);
-                    self.synchronize();
-                }
-            }
-        }
-        if has_error {
-            return Err(ParserError {
-                message: "Parsing failed with errors.".to_string(),
-            });
-        }
-        Ok(statements)
-    }
-
-    pub fn synchronize(&mut self) {
-        self.advance();
-
-        while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
-                return;
-            }
-
-            // Check for valid tokens denoting the start of a new statement
-            match self.tokens[self.current].token_type {
-                TokenType::Class
-                | TokenType::Fun
-                | TokenType::Var
-                | TokenType::For
-                | TokenType::If
-                | TokenType::While
-                | TokenType::Print
-                | TokenType::Return => return,
-                _ => self.advance(),
-            }
-        }
-    }
-
-    pub fn declaration(&mut self) -> Result<Stmt, ParserError> {
-        if self.match_token(&[TokenType::Class]) {
-            self.class_declaration()
-        } else if self.match_token(&[TokenType::Fun]) {
-            self.fun_declaration("function".to_string())
-        } else if self.match_token(&[TokenType::Var]) {
-            self.var_declaration()
-        } else {
-            self.statement()
-        }
-    }
-
-    pub fn class_declaration(&mut self) -> Result<Stmt, ParserError> {
-        let name = self.consume(TokenType::Identifier, "Expect class name.")?;
-
-        let superclass = if self.match_token(&[TokenType::Less]) {
-            Some(Box::new(Variable {
-                id: self.next_id(),
-                name: self.consume(TokenType::Identifier, "Expect superclass name.")?,
-            }))
-        } else {
-            None
-        };
-
-        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
-
-        let mut methods = Vec::new();
-        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            let stmt = self.fun_declaration("method".to_string())?;
-            if let Stmt::Function(method) = stmt {
-                methods.push(method);
-            }
-        }
-        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
-        Ok(Stmt::Class(ClassStmt {
-            name,
-            superclass,
-            methods,
-        }))
-    }
-
-    pub fn fun_declaration(&mut self, kind: String) -> Result<Stmt, ParserError> {
-        let name = self.consume_msg(TokenType::Identifier, format!("Expect {} name.", kind))?;
-        self.consume_msg(
-            TokenType::LeftParen,
-            format!("Expect '(' after {} name.", kind),
-        )?;
-
-        let mut params = Vec::new();
-        if !self.check(&TokenType::RightParen) {
-            loop {
-                if params.len() >= 255 {
-                    let line = self.tokens[self.current].line;
-                    let name = &self.tokens[self.current].lexeme;
-                    return Err(ParserError {
-                        message: format!(
-                            "[line {}] Error at '{}': {}",
-                            line, name, "Can't have more than 255 parameters."
-                        ),
-                    });
-                }
-                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
-                if !self.match_token(&[TokenType::Comma]) {
-                    break;
-                }
-            }
-        }
-        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
-        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
-        let body = match self.block()? {
-            Stmt::Block(block) => block,
-            _ => {
-                return Err(ParserError {
-                    message: "Expected block after function declaration.".to_string(),
-                })
-            }
-        };
-        Ok(Stmt::Function(FunctionStmt {
-            name,
-            params,
-            body: body.statements,
-        }))
-    }
-
-    pub fn var_declaration(&mut self) -> Result<Stmt, ParserError> {
-        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
-        let initializer = if self.match_token(&[TokenType::Equal]) {
-            Some(Box::new(self.expression()?))
-        } else {
-            None
-        };
-        self.consume(
-            TokenType::Semicolon,
-            "Expect ';' after variable declaration.",
-        )?;
-        Ok(Stmt::Var(VarStmt { name, initializer }))
-    }
-
-    pub fn statement(&mut self) -> Result<Stmt, ParserError> {
-        if self.match_token(&[TokenType::For]) {
-            self.for_statement()
-        } else if self.match_token(&[TokenType::If]) {
-            self.if_statement()
-        } else if self.match_token(&[TokenType::Print]) {
-            self.print_statement()
-        } else if self.match_token(&[TokenType::Return]) {
-            self.return_statement()
-        } else if self.match_token(&[TokenType::While]) {
-            self.while_statement()
-        } else if self.match_token(&[TokenType::LeftBrace]) {
-            self.block()
-        } else {
-            self.expression_statement()
-        }
-    }
-
-    pub fn for_statement(&mut self) -> Result<Stmt, ParserError> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
-
-        let initializer = if self.match_token(&[TokenType::Var]) {
-            Some(self.var_declaration()?)
-        } else if self.match_token(&[TokenType::Semicolon]) {
-            None
-        } else {
-            Some(self.expression_statement()?)
-        };
-
-        let condition = if !self.check(&TokenType::Semicolon) {
-            Some(self.expression()?)
-        } else {
-            None
-        };
-        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
-
-        let increment = if !self.check(&TokenType::RightParen) {
-            Some(self.expression()?)
-        } else {
-            None
-        };
-        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
-
-        let mut body = Box::new(self.statement()?);
-
-        if let Some(increment) = increment {
-            body = Box::new(Stmt::Block(BlockStmt {
-                statements: vec![
-                    *body,
-                    Stmt::Expression(ExpressionStmt {
-                        expression: Box::new(increment),
-                    }),
-                ],
-            }));
-        }
-
-        if let Some(condition) = condition {
-            body = Box::new(Stmt::While(WhileStmt {
-                condition: Box::new(condition),
-                body,
-            }));
-        } else {
-            body = Box::new(Stmt::While(WhileStmt {
-                condition: Box::new(Expression::Literal(Literal {
-                    id: self.next_id(),
-                    value: LiteralTypes::Bool(true),
-                })),
-                body,
-            }));
-        }
-
-        if let Some(initializer) = initializer {
-            Ok(Stmt::Block(BlockStmt {
-                statements: vec![initializer, *body],
-            }))
-        } else {
-            Ok(*body)
-        }
-    }
-
-    pub fn if_statement(&mut self) -> Result<Stmt, ParserError> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
-        let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
-        let then_branch = Box::new(self.statement()?);
-        let else_branch = if self.match_token(&[TokenType::Else]) {
-            Some(Box::new(self.statement()?))
-        } else {
-            None
-        };
-        Ok(Stmt::If(IfStmt {
-            condition: Box::new(condition),
-            then_branch,
-            else_branch,
-        }))
-    }
-
-    pub fn print_statement(&mut self) -> Result<Stmt, ParserError> {
-        let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(PrintStmt {
-            expression: Box::new(value),
-        }))
-    }
-
-    pub fn return_statement(&mut self) -> Result<Stmt, ParserError> {
-        let keyword = self.previous().clone();
-        let value = if !self.check(&TokenType::Semicolon) {
-            Some(Box::new(self.expression()?))
-        } else {
-            None
-        };
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
-        Ok(Stmt::Return(ReturnStmt { keyword, value }))
-    }
-
-    pub fn while_statement(&mut self) -> Result<Stmt, ParserError> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
-        let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
-        let body = Box::new(self.statement()?);
-        Ok(Stmt::While(WhileStmt {
-            condition: Box::new(condition),
-            body,
-        }))
-    }
-
-    pub fn block(&mut self) -> Result<Stmt, ParserError> {
-        let mut has_error = false;
-        let mut last_error: String = "".to_string();
-        let mut statements = Vec::new();
-        while !self.is_at_end() && self.tokens[self.current].token_type != TokenType::RightBrace {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-                Err(err) => {
-                    has_error = true;
-                    last_error = err.message.clone();
-                    self.synchronize();
-                }
-            }
-        }
-        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
-        if has_error {
-            return Err(ParserError {
-                message: last_error,
-            });
-        }
-        Ok(Stmt::Block(BlockStmt { statements }))
-    }
-
-    pub fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
-        let expr = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
-        Ok(Stmt::Expression(ExpressionStmt {
-            expression: Box::new(expr),
-        }))
-    }
-
-    pub fn expression(&mut self) -> Result<Expression, ParserError> {
-        self.assignment()
-    }
-
-    pub fn assignment(&mut self) -> Result<Expression, ParserError> {
-        let expr = self.or()?;
-
-        if self.match_token(&[TokenType::Equal]) {
-            let value = self.assignment()?;
-            match expr {
-                Expression::Variable(ref var) => {
-                    return Ok(Expression::Assign(Assign {
-                        id: self.next_id(),
-                        name: var.name.clone(),
-                        value: Box::new(value),
-                    }));
-                }
-                Expression::Get(ref get) => {
-                    return Ok(Expression::Set(Set {
-                        id: self.next_id(),
-                        object: get.object.clone(),
-                        name: get.name.clone(),
-                        value: Box::new(value),
-                    }));
-                }
-                _ => {
-                    return Err(ParserError {
-                        message: format!(
-                            "[line {}] Error at '=': Invalid assignment target.",
-                            self.previous().line
-                        ),
-                    });
-                }
-            }
-        }
-
-        Ok(expr)
-    }
-
-    pub fn or(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.and()?;
-
-        while self.match_token(&[TokenType::Or]) {
-            let operator = self.previous().clone();
-            let right = self.and()?;
-            expr = Expression::Logical(Logical {
-                id: self.next_id(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            });
-        }
-
-        Ok(expr)
-    }
-
-    pub fn and(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.equality()?;
-
-        while self.match_token(&[TokenType::And]) {
-            let operator = self.previous().clone();
-            let right = self.equality()?;
-            expr = Expression::Logical(Logical {
-                id: self.next_id(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            });
-        }
-
-        Ok(expr)
-    }
-
-    pub fn equality(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.comparison()?;
-
-        while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous().clone();
-            let right = self.comparison()?;
-            expr = Expression::Binary(Binary {
-                id: self.next_id(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            });
-        }
-
-        Ok(expr)
-    }
-
-    pub fn comparison(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.term()?;
-
-        while self.match_token(&[
-            TokenType::Greater,
-            TokenType::GreaterEqual,
-            TokenType::Less,
-            TokenType::LessEqual,
-        ]) {
-            let operator = self.previous().clone();
-            let right = self.term()?;
-            expr = Expression::Binary(Binary {
-                id: self.next_id(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            });
-        }
-
-        Ok(expr)
-    }
-
-    pub fn term(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.factor()?;
-
-        while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous().clone();
-            let right = self.factor()?;
-            expr = Expression::Binary(Binary {
-                id: self.next_id(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            });
-        }
-
-        Ok(expr)
-    }
-
-    pub fn factor(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.unary()?;
-
-        while self.match_token(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            expr = Expression::Binary(Binary {
-                id: self.next_id(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            });
-        }
-
-        Ok(expr)
-    }
-
-    pub fn unary(&mut self) -> Result<Expression, ParserError> {
-        if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            Ok(Expression::Unary(Unary {
-                id: self.next_id(),
-                operator,
-                right: Box::new(right),
-            }))
-        } else {
-            self.call()
-        }
-    }
-
-    pub fn call(&mut self) -> Result<Expression, ParserError> {
-        let mut expr = self.primary()?;
-
-        loop {
-            if self.match_token(&[TokenType::LeftParen]) {
-                expr = self.finish_call(expr)?;
-            } else if self.match_token(&[TokenType::Dot]) {
-                let name =
-                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
-                expr = Expression::Get(Get {
-                    id: self.next_id(),
-                    object: Box::new(expr),
-                    name,
-                });
-            } else {
-                break;
-            }
-        }
-
-        Ok(expr)
-    }
-
-    pub fn finish_call(&mut self, callee: Expression) -> Result<Expression, ParserError> {
-        let mut arguments = Vec::new();
-        if !self.check(&TokenType::RightParen) {
-            loop {
-                if arguments.len() >= 255 {
-                    let line = self.tokens[self.current].line;
-                    let name = &self.tokens[self.current].lexeme;
-                    return Err(ParserError {
-                        message: format!(
-                            "[line {}] Error at '{}': {}",
-                            line, name, "Can't have more than 255 arguments."
-                        ),
-                    });
-                }
-                arguments.push(self.expression()?);
-                if !self.match_token(&[TokenType::Comma]) {
-                    break;
-                }
-            }
-        }
-        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
-        Ok(Expression::Call(Call {
-            id: self.next_id(),
-            callee: Box::new(callee),
-            paren,
-            arguments,
-        }))
-    }
-
-    pub fn primary(&mut self) -> Result<Expression, ParserError> {
-        if self.match_token(&[TokenType::False]) {
-            Ok(Expression::Literal(Literal {
-                id: self.next_id(),
-                value: LiteralTypes::Bool(false),
-            }))
-        } else if self.match_token(&[TokenType::True]) {
-            Ok(Expression::Literal(Literal {
-                id: self.next_id(),
-                value: LiteralTypes::Bool(true),
-            }))
-        } else if self.match_token(&[TokenType::Nil]) {
-            Ok(Expression::Literal(Literal {
-                id: self.next_id(),
-                value: LiteralTypes::Nil,
-            }))
-        } else if self.match_token(&[TokenType::Number]) {
-            let number = self.previous().clone();
-            Ok(Expression::Literal(Literal {
-                id: self.next_id(),
-                value: number.literal,
-            }))
-        } else if self.match_token(&[TokenType::String]) {
-            let string = self.previous().clone();
-            Ok(Expression::Literal(Literal {
-                id: self.next_id(),
-                value: string.literal,
-            }))
-        } else if self.match_token(&[TokenType::LeftParen]) {
-            let expr = self.expression()?;
-            self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-            Ok(Expression::Grouping(Grouping {
-                id: self.next_id(),
-                expression: Box::new(expr),
-            }))
-        } else if self.match_token(&[TokenType::Super]) {
-            let keyword = self.previous();
-            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
-            let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
-            Ok(Expression::Super(Super {
-                id: self.next_id(),
-                keyword: keyword.clone(),
-                method: method.clone(),
-            }))
-        } else if self.match_token(&[TokenType::This]) {
-            Ok(Expression::This(This {
-                id: self.next_id(),
-                keyword: self.previous().clone(),
-            }))
-        } else if self.match_token(&[TokenType::Identifier]) {
-            let identifier = self.previous().clone();
-            match identifier.literal {
-                LiteralTypes::String(ref s) => {
-                    if s.is_empty() {
-                        return Err(ParserError {
-                            message: "Empty identifier".to_string(),
-                        });
-                    }
-                    Ok(Expression::Variable(Variable {
-                        id: self.next_id(),
-                        name: identifier.clone(),
-                    }))
-                }
-                _ => Err(ParserError {
-                    message: "Expected identifier".to_string(),
-                }),
-            }
-        } else {
-            let line = self.tokens[self.current].line;
-            let name = self.tokens[self.current].lexeme.clone();
-            Err(ParserError {
-                message: format!(
-                    "[line {}] Error at '{}': {}",
-                    line, name, "Expect expression."
-                ),
-            })
-        }
-    }
-
-    pub fn match_token(&mut self, tokens: &[TokenType]) -> bool {
-        for token in tokens {
-            if self.check(token) {
-                self.advance();
-                return true;
-            }
-        }
-
-        false
-    }
-
-    pub fn consume(&mut self, token: TokenType, message: &str) -> Result<Token, ParserError> {
-        self.consume_msg(token, message.to_string())
-    }
-
-    pub fn consume_msg(&mut self, token: TokenType, message: String) -> Result<Token, ParserError> {
-        if self.check(&token) {
-            self.advance();
-            Ok(self.previous())
-        } else if self.is_at_end() {
-            let line = self.tokens[self.current].line;
-            Err(ParserError {
-                message: format!("[line {}] Error at end: {}", line, message),
-            })
-        } else {
-            let line = self.tokens[self.current].line;
-            let name = self.tokens[self.current].lexeme.clone();
-            Err(ParserError {
-                message: format!("[line {}] Error at '{}': {}", line, name, message),
-            })
-        }
-    }
-
-    pub fn advance(&mut self) {
-        if !self.is_at_end() {
-            self.current += 1;
-        }
-    }
-
-    pub fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
-    }
-
-    pub fn check(&self, token: &TokenType) -> bool {
-        if self.is_at_end() {
-            false
-        } else {
-            self.tokens[self.current].token_type == *token
-        }
-    }
-
-    pub fn is_at_end(&self) -> bool {
-        self.tokens[self.current].token_type == TokenType::Eof
-    }
-
-    fn next_id(&mut self) -> usize {
-        let id = self.current_id;
-        self.current_id += 1;
-        id
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use liblox::scanner::Scanner;
-
-    #[test]
-    fn test_parser() {
-        let expression = "1 + 2 * 3 - 4 / 5;";
-
-        let four_div_five = Box::new(Expression::Binary(Binary {
-            id: 7,
-            left: Box::new(Expression::Literal(Literal {
-                id: 5,
-                value: LiteralTypes::Number(4.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Slash,
-                lexeme: "/".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 6,
-                value: LiteralTypes::Number(5.0),
-            })),
-        }));
-        let two_mul_three = Box::new(Expression::Binary(Binary {
-            id: 3,
-            left: Box::new(Expression::Literal(Literal {
-                id: 1,
-                value: LiteralTypes::Number(2.0),
-            })),
-            operator: Token {
-                token_type: TokenType::Star,
-                lexeme: "*".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: Box::new(Expression::Literal(Literal {
-                id: 2,
-                value: LiteralTypes::Number(3.0),
-            })),
-        }));
-        let reference = Expression::Binary(Binary {
-            id: 8,
-            left: Box::new(Expression::Binary(Binary {
-                id: 4,
-                left: Box::new(Expression::Literal(Literal {
-                    id: 0,
-                    value: LiteralTypes::Number(1.0),
-                })),
-                operator: Token {
-                    token_type: TokenType::Plus,
-                    lexeme: "+".to_string(),
-                    literal: LiteralTypes::Nil,
-                    line: 1,
-                },
-                right: two_mul_three,
-            })),
-            operator: Token {
-                token_type: TokenType::Minus,
-                lexeme: "-".to_string(),
-                literal: LiteralTypes::Nil,
-                line: 1,
-            },
-            right: four_div_five,
-        });
-
-        let mut scanner = Scanner::new(expression.to_string());
-        let tokens = scanner.scan_tokens();
-        let mut parser = Parser::new(tokens.clone());
-        let statements = &parser.parse().unwrap()[0];
-        let expression = match statements {
-            Stmt::Expression(ExpressionStmt { expression }) => expression.clone(),
-            _ => panic!("Expected an expression statement"),
-        };
-        assert_eq!(*expression, reference);
-    }
-}
+use liblox::codes::ErrorCode;
+use liblox::diagnostics::{stderr_reporter, Diagnostic, SharedDiagnosticSink, SharedErrorReporter};
+use liblox::tokens::{LiteralTypes, Token, TokenType};
+use std::cell::Cell;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::{
+    ast::{Ast, ExprId, StmtId},
+    expression::{
+        Assign, Binary, Call, Expression, Get, Grouping, Literal, Logical, NodeId, NodeIds, Set,
+        Super, This, Unary, Variable,
+    },
+    stmt::{
+        BlockStmt, ClassStmt, ExpressionStmt, ForInStmt, FunctionStmt, IfStmt, PrintStmt,
+        ReturnStmt, Stmt, VarStmt, WhileStmt,
+    },
+};
+
+// Production rules
+// program -> statement* EOF ;
+
+// declaration -> classDecl | funDecl | varDecl | statement ;
+// classDecl -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+// funDecls -> "fun" function ;
+// function -> IDENTIFIER "(" parameters? ")" block ;
+// parameters -> IDENTIFIER ( "," IDENTIFIER )* ;
+// varDecl -> "var" IDENTIFIER ("=" expression)? ";" ;
+// statement -> exprStmt | forStmt | ifStmt | printStmt | returnStm | whileStmt | block ;
+// exprStmt -> expression ";" ;
+// forStmt -> "for" "(" (varDecl | exprStmt | ";") expression? ";" expression? ")" statement
+//          | "for" "(" "var" IDENTIFIER "in" expression ")" statement ;
+// ifStmt -> "if" "(" expression ")" statement ( "else" statement )? ;
+// printStmt -> "print" expression ";" ;
+// returnStmt -> "return" expression? ";" ;
+// whileStmt -> "while" "(" expression ")" statement ;
+// block -> "{" declaration* "}" ;
+
+// expression -> assignment ;
+// assignment -> ( call "." )? IDENTIFIER "=" assignment | logical_or ;
+// logical_or -> logical_and ( "or" logical_and )* ;
+// logical_and -> equality ( "and" equality )* ;
+// equality -> comparison ( ( "!=" | "==" ) comparison )* ;
+// comparison -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+// term -> factor ( ( "-" | "+" ) factor )* ;
+// factor -> unary ( ( "/" | "*" ) unary )* ;
+// unary -> ( "!" | "-" ) unary | call ;
+// call -> primary ( "(" arguments? ")" )* ;
+// primary -> NUMBER | STRING | "true" | "false" | "nil" | "this" | "(" expression ")" | IDENTIFIER | "super" "." IDENTIFIER;
+// arguments -> expression ( "," expression )* ;
+
+pub struct Parser {
+    // The tokens read so far, which reach a few past `current`
+    tokens: Vec<Token>,
+    // The tokens yet to be read
+    pending: Box<dyn Iterator<Item = Token>>,
+    current: usize,
+    // The nodes parsed so far
+    ast: Ast,
+    // Shared with the parsers of other sources run together
+    ids: NodeIds,
+    // Receives the syntax errors as they are found
+    reporter: Option<SharedErrorReporter>,
+    // The syntax errors, as they are reported
+    errors: Vec<ParserError>,
+    // Index of the token each error was found at, parallel to `errors`
+    error_tokens: Vec<usize>,
+    // Token ranges skipped by `synchronize`
+    recovered: Vec<Range<usize>>,
+    // Number of blocks being parsed, whose '}' must survive recovery
+    open_blocks: usize,
+    // Token after the last block that failed, which recovered by itself
+    recovered_block_end: Option<usize>,
+    // Where the last error was found, or a failed block ended
+    last_error_token: Option<usize>,
+    // Parsing stops once this many errors were found
+    max_errors: Option<usize>,
+    // Receives the warnings, which are dropped without one
+    diagnostics: Option<SharedDiagnosticSink>,
+}
+
+#[derive(Debug)]
+pub struct ParserError {
+    pub code: ErrorCode,
+    pub message: String,
+    // Line of the offending token, unknown for the errors that only fail the
+    // parse after the others were reported
+    pub line: Option<i32>,
+    // What the error was found at, such as ` at 'x'` or ` at end`
+    pub location: String,
+}
+
+impl ParserError {
+    fn new(code: ErrorCode, message: &str) -> Self {
+        ParserError {
+            code,
+            message: message.to_string(),
+            line: None,
+            location: String::new(),
+        }
+    }
+
+    fn at(token: &Token, code: ErrorCode, message: &str) -> Self {
+        let location = if token.token_type == TokenType::Eof {
+            " at end".to_string()
+        } else {
+            format!(" at '{}'", token.lexeme)
+        };
+        ParserError {
+            code,
+            message: message.to_string(),
+            line: Some(token.line),
+            location,
+        }
+    }
+}
+
+/// Formats like jlox, e.g. `[line 1] Error at ';': Expect expression.`.
+impl std::fmt::Display for ParserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "[line {}] Error{}: {}",
+                line, self.location, self.message
+            ),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParserError {}
+
+impl Parser {
+    /// Creates a parser that reads the tokens as it goes, so they can come
+    /// straight from a scanner. They end with `Eof`, which is added if
+    /// missing.
+    pub fn new<I>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = Token>,
+        I::IntoIter: 'static,
+    {
+        Self::with_ids(tokens, NodeIds::new())
+    }
+
+    /// Creates a parser that takes the ids of its expressions from `ids`, for
+    /// sources whose resolved variables must not clash with others.
+    pub fn with_ids<I>(tokens: I, ids: NodeIds) -> Self
+    where
+        I: IntoIterator<Item = Token>,
+        I::IntoIter: 'static,
+    {
+        let mut parser = Parser {
+            tokens: Vec::new(),
+            pending: Box::new(tokens.into_iter()),
+            current: 0,
+            ast: Ast::new(),
+            ids,
+            reporter: Some(stderr_reporter()),
+            errors: Vec::new(),
+            error_tokens: Vec::new(),
+            recovered: Vec::new(),
+            open_blocks: 0,
+            recovered_block_end: None,
+            last_error_token: None,
+            max_errors: None,
+            diagnostics: None,
+        };
+        parser.read_ahead();
+        parser
+    }
+
+    /// Sets where warnings are reported.
+    pub fn set_diagnostic_sink(&mut self, sink: Option<SharedDiagnosticSink>) {
+        self.diagnostics = sink;
+    }
+
+    fn warn(&self, code: ErrorCode, token: &Token, message: &str) {
+        if let Some(sink) = &self.diagnostics {
+            sink.borrow_mut().report(Diagnostic {
+                span: token.span.clone(),
+                ..Diagnostic::warning(code, token.line, message)
+            });
+        }
+    }
+
+    /// Enables or disables printing of syntax errors to stderr. Errors are
+    /// still recorded in `errors` when disabled.
+    pub fn set_error_reporting(&mut self, enabled: bool) {
+        self.reporter = enabled.then(stderr_reporter);
+    }
+
+    /// Sets where syntax errors are reported as they are found, instead of
+    /// stderr. They are recorded in `errors` either way.
+    pub fn set_error_reporter(&mut self, reporter: Option<SharedErrorReporter>) {
+        self.reporter = reporter;
+    }
+
+    /// Stops parsing after `max` errors, or never with `None`, which is the
+    /// default.
+    pub fn set_max_errors(&mut self, max: Option<usize>) {
+        self.max_errors = max;
+    }
+
+    fn too_many_errors(&self) -> bool {
+        self.max_errors.is_some_and(|max| self.errors.len() >= max)
+    }
+
+    /// The syntax errors, in the order they were found.
+    pub fn errors(&self) -> &[ParserError] {
+        &self.errors
+    }
+
+    /// Indices into the tokens of where each error was found, parallel to
+    /// `errors`.
+    pub fn error_tokens(&self) -> &[usize] {
+        &self.error_tokens
+    }
+
+    /// Ranges of token indices that were skipped to recover from errors.
+    pub fn recovered(&self) -> &[Range<usize>] {
+        &self.recovered
+    }
+
+    /// The arena the parsed nodes are in.
+    pub fn ast(&self) -> &Ast {
+        &self.ast
+    }
+
+    pub fn into_ast(self) -> Ast {
+        self.ast
+    }
+
+    /// Parses the source into the arena, returning its top-level statements.
+    pub fn parse(&mut self) -> Result<Vec<StmtId>, ParserError> {
+        let mut has_error = false;
+        let mut statements = Vec::new();
+        while !self.is_at_end() && !self.too_many_errors() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => {
+                    has_error = true;
+                    self.report(self.current, err);
+                    self.synchronize();
+                }
+            }
+        }
+        // Some errors are reported without failing a statement
+        if has_error || !self.errors.is_empty() {
+            return Err(ParserError::new(
+                ErrorCode::SyntaxError,
+                "Parsing failed with errors.",
+            ));
+        }
+        Ok(statements)
+    }
+
+    /// Parses a source that consists of a single expression, as used for
+    /// implicit output.
+    pub fn parse_expression(&mut self) -> Result<ExprId, ParserError> {
+        let result = self.expression().and_then(|expression| {
+            if self.is_at_end() {
+                Ok(expression)
+            } else {
+                Err(ParserError::at(
+                    &self.tokens[self.current],
+                    ErrorCode::ExpectedToken,
+                    "Expect end of expression.",
+                ))
+            }
+        });
+        match result {
+            // Some errors are reported without failing the expression
+            Ok(expression) if self.errors.is_empty() => return Ok(expression),
+            Ok(_) => {}
+            Err(err) => self.report(self.current, err),
+        }
+        Err(ParserError::new(
+            ErrorCode::SyntaxError,
+            "Parsing failed with errors.",
+        ))
+    }
+
+    // Records an error found at the token with the index `token`
+    fn report(&mut self, token: usize, err: ParserError) {
+        let token = token.min(self.tokens.len() - 1);
+        // A second error at the same token follows from the first one
+        if self.last_error_token == Some(token) || self.too_many_errors() {
+            return;
+        }
+        if let Some(reporter) = &self.reporter {
+            reporter.borrow_mut().report(&err.to_string());
+        }
+        self.errors.push(err);
+        self.error_tokens.push(token);
+        self.last_error_token = Some(token);
+    }
+
+    /// Skips the rest of the statement an error was found in. Blocks opened
+    /// in the skipped tokens are skipped as a whole, so that their contents
+    /// and closing brace aren't mistaken for statements of their own.
+    pub fn synchronize(&mut self) {
+        let start = self.current;
+        // The error comes from a block that ended its statement already
+        if self.recovered_block_end.take() == Some(start) {
+            return;
+        }
+        let mut depth = 0;
+
+        while !self.is_at_end() {
+            match self.tokens[self.current].token_type {
+                // Closes the block being parsed, which consumes it itself
+                TokenType::RightBrace if depth == 0 && self.open_blocks > 0 => break,
+                TokenType::RightBrace if depth > 0 => depth -= 1,
+                TokenType::LeftBrace => depth += 1,
+                // Check for valid tokens denoting the start of a new statement
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                    if depth == 0 && self.current > start =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            self.advance();
+
+            let ends_statement = match self.previous().token_type {
+                TokenType::Semicolon => true,
+                TokenType::RightBrace => !self.check(&TokenType::Else),
+                _ => false,
+            };
+            if depth == 0 && ends_statement {
+                break;
+            }
+        }
+        if self.current > start {
+            self.recovered.push(start..self.current);
+        }
+    }
+
+    pub fn declaration(&mut self) -> Result<StmtId, ParserError> {
+        if self.match_token(&[TokenType::Class]) {
+            self.class_declaration()
+        } else if self.match_token(&[TokenType::Fun]) {
+            self.fun_declaration("function".to_string())
+        } else if self.match_token(&[TokenType::Var]) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        }
+    }
+
+    pub fn class_declaration(&mut self) -> Result<StmtId, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect class name.")?;
+
+        let superclass = if self.match_token(&[TokenType::Less]) {
+            Some(Box::new(Variable {
+                id: self.next_id(),
+                name: self.consume(TokenType::Identifier, "Expect superclass name.")?,
+                resolved: Cell::new(None),
+            }))
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(self.ast.add_statement(Stmt::Class(ClassStmt {
+            name,
+            superclass,
+            methods,
+        })))
+    }
+
+    pub fn fun_declaration(&mut self, kind: String) -> Result<StmtId, ParserError> {
+        let function = self.function(&kind)?;
+        Ok(self.ast.add_statement(Stmt::Function(function)))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Rc<FunctionStmt>, ParserError> {
+        let name = self.consume_msg(TokenType::Identifier, format!("Expect {} name.", kind))?;
+        self.consume_msg(
+            TokenType::LeftParen,
+            format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    return Err(ParserError::at(
+                        &self.tokens[self.current],
+                        ErrorCode::TooManyParameters,
+                        "Can't have more than 255 parameters.",
+                    ));
+                }
+                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.block_body()?;
+        Ok(Rc::new(FunctionStmt { name, params, body }))
+    }
+
+    pub fn var_declaration(&mut self) -> Result<StmtId, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let initializer = if self.match_token(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(self
+            .ast
+            .add_statement(Stmt::Var(VarStmt { name, initializer })))
+    }
+
+    pub fn statement(&mut self) -> Result<StmtId, ParserError> {
+        if self.match_token(&[TokenType::For]) {
+            self.for_statement()
+        } else if self.match_token(&[TokenType::If]) {
+            self.if_statement()
+        } else if self.match_token(&[TokenType::Print]) {
+            self.print_statement()
+        } else if self.match_token(&[TokenType::Return]) {
+            self.return_statement()
+        } else if self.match_token(&[TokenType::While]) {
+            self.while_statement()
+        } else if self.match_token(&[TokenType::LeftBrace]) {
+            self.block()
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    pub fn for_statement(&mut self) -> Result<StmtId, ParserError> {
+        let keyword = self.take_previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        if self.is_for_in() {
+            return self.for_in_statement();
+        }
+
+        let initializer = if self.match_token(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else if self.match_token(&[TokenType::Semicolon]) {
+            None
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if !self.check(&TokenType::RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.statement()?;
+
+        if let Some(increment) = increment {
+            let increment = self.ast.add_statement(Stmt::Expression(ExpressionStmt {
+                expression: increment,
+            }));
+            body = self.ast.add_statement(Stmt::Block(BlockStmt {
+                statements: vec![body, increment],
+            }));
+        }
+
+        let condition = match condition {
+            Some(condition) => condition,
+            None => self.ast.add_expression(Expression::Literal(Literal {
+                id: self.next_id(),
+                value: LiteralTypes::Bool(true),
+            })),
+        };
+        body = self.ast.add_statement(Stmt::While(WhileStmt {
+            keyword,
+            condition,
+            body,
+        }));
+
+        if let Some(initializer) = initializer {
+            Ok(self.ast.add_statement(Stmt::Block(BlockStmt {
+                statements: vec![initializer, body],
+            })))
+        } else {
+            Ok(body)
+        }
+    }
+
+    // `in` is only a keyword directly after the loop variable, so it stays
+    // usable as an identifier elsewhere.
+    fn is_for_in(&self) -> bool {
+        self.check(&TokenType::Var)
+            && self.tokens.get(self.current + 2).is_some_and(|token| {
+                token.token_type == TokenType::Identifier && token.lexeme == "in"
+            })
+    }
+
+    fn for_in_statement(&mut self) -> Result<StmtId, ParserError> {
+        self.advance();
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for-in clause.")?;
+        let body = self.statement()?;
+        Ok(self.ast.add_statement(Stmt::ForIn(ForInStmt {
+            name,
+            iterable,
+            body,
+        })))
+    }
+
+    pub fn if_statement(&mut self) -> Result<StmtId, ParserError> {
+        let keyword = self.take_previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let then_branch = self.statement()?;
+        let else_branch = if self.match_token(&[TokenType::Else]) {
+            Some(self.statement()?)
+        } else {
+            None
+        };
+        Ok(self.ast.add_statement(Stmt::If(IfStmt {
+            keyword,
+            condition,
+            then_branch,
+            else_branch,
+        })))
+    }
+
+    pub fn print_statement(&mut self) -> Result<StmtId, ParserError> {
+        let keyword = self.take_previous();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(self.ast.add_statement(Stmt::Print(PrintStmt {
+            keyword,
+            expression: value,
+        })))
+    }
+
+    pub fn return_statement(&mut self) -> Result<StmtId, ParserError> {
+        let keyword = self.take_previous();
+        let value = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(self
+            .ast
+            .add_statement(Stmt::Return(ReturnStmt { keyword, value })))
+    }
+
+    pub fn while_statement(&mut self) -> Result<StmtId, ParserError> {
+        let keyword = self.take_previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = self.statement()?;
+        Ok(self.ast.add_statement(Stmt::While(WhileStmt {
+            keyword,
+            condition,
+            body,
+        })))
+    }
+
+    pub fn block(&mut self) -> Result<StmtId, ParserError> {
+        let statements = self.block_body()?;
+        Ok(self
+            .ast
+            .add_statement(Stmt::Block(BlockStmt { statements })))
+    }
+
+    // The statements of a block or function body, after its '{'
+    fn block_body(&mut self) -> Result<Vec<StmtId>, ParserError> {
+        self.open_blocks += 1;
+        let result = self.block_statements();
+        self.open_blocks -= 1;
+        result
+    }
+
+    fn block_statements(&mut self) -> Result<Vec<StmtId>, ParserError> {
+        let mut has_error = false;
+        let mut statements = Vec::new();
+        let mut reported_unreachable = false;
+        while !self.is_at_end()
+            && self.tokens[self.current].token_type != TokenType::RightBrace
+            && !self.too_many_errors()
+        {
+            let after_return = statements
+                .last()
+                .is_some_and(|&last| matches!(self.ast[last], Stmt::Return(_)));
+            if !reported_unreachable && after_return {
+                reported_unreachable = true;
+                self.warn(
+                    ErrorCode::UnreachableCode,
+                    &self.tokens[self.current],
+                    "Unreachable code after 'return'.",
+                );
+            }
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => {
+                    has_error = true;
+                    self.report(self.current, err);
+                    self.synchronize();
+                }
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        if has_error {
+            // The errors were reported already, so the one returned to fail
+            // the enclosing statement is dropped by report()
+            self.recovered_block_end = Some(self.current);
+            self.last_error_token = Some(self.current);
+            return Err(ParserError::new(
+                ErrorCode::SyntaxError,
+                "Block has errors.",
+            ));
+        }
+        Ok(statements)
+    }
+
+    pub fn expression_statement(&mut self) -> Result<StmtId, ParserError> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(self
+            .ast
+            .add_statement(Stmt::Expression(ExpressionStmt { expression: expr })))
+    }
+
+    pub fn expression(&mut self) -> Result<ExprId, ParserError> {
+        self.assignment()
+    }
+
+    pub fn assignment(&mut self) -> Result<ExprId, ParserError> {
+        let expr = self.or()?;
+
+        if self.match_token(&[TokenType::Equal]) {
+            let equals = self.current - 1;
+            let value = self.assignment()?;
+            if !matches!(self.ast[expr], Expression::Variable(_) | Expression::Get(_)) {
+                // Reported at the '=' without failing, as the parser isn't
+                // confused about where it is
+                let err = ParserError::at(
+                    &self.tokens[equals],
+                    ErrorCode::InvalidAssignmentTarget,
+                    "Invalid assignment target.",
+                );
+                self.report(equals, err);
+                return Ok(expr);
+            }
+            // The target becomes the assignment, which takes over its tokens
+            let id = self.next_id();
+            let placeholder = Expression::Literal(Literal {
+                id,
+                value: LiteralTypes::Nil,
+            });
+            self.ast[expr] = match mem::replace(&mut self.ast[expr], placeholder) {
+                Expression::Variable(var) => Expression::Assign(Assign {
+                    id,
+                    name: var.name,
+                    value,
+                    resolved: Cell::new(None),
+                }),
+                Expression::Get(get) => Expression::Set(Set {
+                    id,
+                    object: get.object,
+                    name: get.name,
+                    value,
+                }),
+                _ => unreachable!("checked above"),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    pub fn or(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.and()?;
+
+        while self.match_token(&[TokenType::Or]) {
+            let operator = self.take_previous();
+            let right = self.and()?;
+            expr = self.ast.add_expression(Expression::Logical(Logical {
+                id: self.next_id(),
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn and(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.equality()?;
+
+        while self.match_token(&[TokenType::And]) {
+            let operator = self.take_previous();
+            let right = self.equality()?;
+            expr = self.ast.add_expression(Expression::Logical(Logical {
+                id: self.next_id(),
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn equality(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.comparison()?;
+
+        while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let operator = self.take_previous();
+            let right = self.comparison()?;
+            expr = self.ast.add_expression(Expression::Binary(Binary {
+                id: self.next_id(),
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn comparison(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.term()?;
+
+        while self.match_token(&[
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::LessEqual,
+        ]) {
+            let operator = self.take_previous();
+            let right = self.term()?;
+            expr = self.ast.add_expression(Expression::Binary(Binary {
+                id: self.next_id(),
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn term(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.factor()?;
+
+        while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
+            let operator = self.take_previous();
+            let right = self.factor()?;
+            expr = self.ast.add_expression(Expression::Binary(Binary {
+                id: self.next_id(),
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn factor(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.unary()?;
+
+        while self.match_token(&[TokenType::Slash, TokenType::Star]) {
+            let operator = self.take_previous();
+            let right = self.unary()?;
+            expr = self.ast.add_expression(Expression::Binary(Binary {
+                id: self.next_id(),
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn unary(&mut self) -> Result<ExprId, ParserError> {
+        if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.take_previous();
+            let right = self.unary()?;
+            Ok(self.ast.add_expression(Expression::Unary(Unary {
+                id: self.next_id(),
+                operator,
+                right,
+            })))
+        } else {
+            self.call()
+        }
+    }
+
+    pub fn call(&mut self) -> Result<ExprId, ParserError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_token(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name =
+                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = self.ast.add_expression(Expression::Get(Get {
+                    id: self.next_id(),
+                    object: expr,
+                    name,
+                }));
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    pub fn finish_call(&mut self, callee: ExprId) -> Result<ExprId, ParserError> {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Err(ParserError::at(
+                        &self.tokens[self.current],
+                        ErrorCode::TooManyArguments,
+                        "Can't have more than 255 arguments.",
+                    ));
+                }
+                arguments.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        Ok(self.ast.add_expression(Expression::Call(Call {
+            id: self.next_id(),
+            callee,
+            paren,
+            arguments,
+        })))
+    }
+
+    pub fn primary(&mut self) -> Result<ExprId, ParserError> {
+        if self.match_token(&[TokenType::False]) {
+            Ok(self.ast.add_expression(Expression::Literal(Literal {
+                id: self.next_id(),
+                value: LiteralTypes::Bool(false),
+            })))
+        } else if self.match_token(&[TokenType::True]) {
+            Ok(self.ast.add_expression(Expression::Literal(Literal {
+                id: self.next_id(),
+                value: LiteralTypes::Bool(true),
+            })))
+        } else if self.match_token(&[TokenType::Nil]) {
+            Ok(self.ast.add_expression(Expression::Literal(Literal {
+                id: self.next_id(),
+                value: LiteralTypes::Nil,
+            })))
+        } else if self.match_token(&[TokenType::Number]) {
+            let number = self.take_previous();
+            Ok(self.ast.add_expression(Expression::Literal(Literal {
+                id: self.next_id(),
+                value: number.literal,
+            })))
+        } else if self.match_token(&[TokenType::String]) {
+            let string = self.take_previous();
+            Ok(self.ast.add_expression(Expression::Literal(Literal {
+                id: self.next_id(),
+                value: string.literal,
+            })))
+        } else if self.match_token(&[TokenType::LeftParen]) {
+            let expr = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+            Ok(self.ast.add_expression(Expression::Grouping(Grouping {
+                id: self.next_id(),
+                expression: expr,
+            })))
+        } else if self.match_token(&[TokenType::Super]) {
+            let keyword = self.take_previous();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+            Ok(self.ast.add_expression(Expression::Super(Super {
+                id: self.next_id(),
+                keyword,
+                method,
+                resolved: Cell::new(None),
+            })))
+        } else if self.match_token(&[TokenType::This]) {
+            let keyword = self.take_previous();
+            Ok(self.ast.add_expression(Expression::This(This {
+                id: self.next_id(),
+                keyword,
+                resolved: Cell::new(None),
+            })))
+        } else if self.match_token(&[TokenType::Identifier]) {
+            let identifier = self.take_previous();
+            match identifier.literal {
+                LiteralTypes::String(ref s) => {
+                    if s.is_empty() {
+                        return Err(ParserError::new(ErrorCode::SyntaxError, "Empty identifier"));
+                    }
+                    Ok(self.ast.add_expression(Expression::Variable(Variable {
+                        id: self.next_id(),
+                        name: identifier,
+                        resolved: Cell::new(None),
+                    })))
+                }
+                _ => Err(ParserError::new(
+                    ErrorCode::SyntaxError,
+                    "Expected identifier",
+                )),
+            }
+        } else {
+            Err(ParserError::at(
+                &self.tokens[self.current],
+                ErrorCode::ExpectedExpression,
+                "Expect expression.",
+            ))
+        }
+    }
+
+    pub fn match_token(&mut self, tokens: &[TokenType]) -> bool {
+        for token in tokens {
+            if self.check(token) {
+                self.advance();
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn consume(&mut self, token: TokenType, message: &str) -> Result<Token, ParserError> {
+        self.consume_msg(token, message.to_string())
+    }
+
+    pub fn consume_msg(&mut self, token: TokenType, message: String) -> Result<Token, ParserError> {
+        if self.check(&token) {
+            self.advance();
+            Ok(self.take_previous())
+        } else {
+            Err(ParserError::at(
+                &self.tokens[self.current],
+                ErrorCode::ExpectedToken,
+                &message,
+            ))
+        }
+    }
+
+    pub fn advance(&mut self) {
+        if !self.is_at_end() {
+            self.current += 1;
+            self.read_ahead();
+        }
+    }
+
+    // Reads the tokens up to the furthest one looked at, which is two past
+    // the current one
+    fn read_ahead(&mut self) {
+        while self.tokens.len() <= self.current + 2
+            && self
+                .tokens
+                .last()
+                .is_none_or(|token| token.token_type != TokenType::Eof)
+        {
+            let token = self.pending.next().unwrap_or_else(|| {
+                let line = self.tokens.last().map_or(1, |token| token.line);
+                Token::new(TokenType::Eof, String::new(), LiteralTypes::Nil, line)
+            });
+            self.tokens.push(token);
+        }
+    }
+
+    pub fn previous(&self) -> &Token {
+        &self.tokens[self.current - 1]
+    }
+
+    // Moves the previous token into the syntax tree instead of copying it.
+    // Its type and line stay behind, as errors and recovery look back at
+    // them, but it is never part of the tree twice.
+    fn take_previous(&mut self) -> Token {
+        let previous = &mut self.tokens[self.current - 1];
+        Token {
+            token_type: previous.token_type.clone(),
+            lexeme: std::mem::take(&mut previous.lexeme),
+            literal: std::mem::replace(&mut previous.literal, LiteralTypes::Nil),
+            line: previous.line,
+            span: previous.span.clone(),
+        }
+    }
+
+    pub fn check(&self, token: &TokenType) -> bool {
+        if self.is_at_end() {
+            false
+        } else {
+            self.tokens[self.current].token_type == *token
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.tokens[self.current].token_type == TokenType::Eof
+    }
+
+    fn next_id(&self) -> NodeId {
+        self.ids.next_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use liblox::scanner::Scanner;
+
+    fn literal(ast: &mut Ast, value: f64) -> ExprId {
+        ast.add_expression(Expression::Literal(Literal {
+            id: NodeId(ast.expression_count()),
+            value: LiteralTypes::Number(value),
+        }))
+    }
+
+    fn binary(
+        ast: &mut Ast,
+        left: ExprId,
+        operator: TokenType,
+        lexeme: &str,
+        right: ExprId,
+    ) -> ExprId {
+        ast.add_expression(Expression::Binary(Binary {
+            id: NodeId(ast.expression_count()),
+            left,
+            operator: Token {
+                token_type: operator,
+                lexeme: lexeme.to_string(),
+                literal: LiteralTypes::Nil,
+                line: 1,
+                span: None,
+            },
+            right,
+        }))
+    }
+
+    #[test]
+    fn test_parser() {
+        let expression = "1 + 2 * 3 - 4 / 5;";
+
+        // Allocated in the order the parser finishes the nodes
+        let mut reference = Ast::new();
+        let one = literal(&mut reference, 1.0);
+        let two = literal(&mut reference, 2.0);
+        let three = literal(&mut reference, 3.0);
+        let two_mul_three = binary(&mut reference, two, TokenType::Star, "*", three);
+        let sum = binary(&mut reference, one, TokenType::Plus, "+", two_mul_three);
+        let four = literal(&mut reference, 4.0);
+        let five = literal(&mut reference, 5.0);
+        let four_div_five = binary(&mut reference, four, TokenType::Slash, "/", five);
+        let difference = binary(&mut reference, sum, TokenType::Minus, "-", four_div_five);
+        let statement = reference.add_statement(Stmt::Expression(ExpressionStmt {
+            expression: difference,
+        }));
+
+        let tokens = Scanner::new(expression.to_string()).map(Result::unwrap);
+        let mut parser = Parser::new(tokens);
+        assert_eq!(parser.parse().unwrap(), vec![statement]);
+        assert_eq!(parser.into_ast(), reference);
+    }
+
+    #[test]
+    fn test_parse_streamed_tokens() {
+        // Tokens are read as the parser gets to them
+        let read = Rc::new(Cell::new(0));
+        let counter = read.clone();
+        let tokens = Scanner::new("print 1; print 2;".to_string())
+            .map(Result::unwrap)
+            .inspect(move |_| counter.set(counter.get() + 1));
+        let mut parser = Parser::new(tokens);
+        assert_eq!(read.get(), 3);
+        parser.declaration().unwrap();
+        assert_eq!(read.get(), 6);
+
+        // A missing `Eof` is added
+        let mut scanner = Scanner::new("var a = 1;".to_string());
+        let mut tokens = scanner.take_tokens();
+        tokens.pop();
+        let mut parser = Parser::new(tokens);
+        assert_eq!(parser.parse().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        let mut scanner = Scanner::new("1 + 2".to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let expression = parser.parse_expression().unwrap();
+        assert!(matches!(parser.ast()[expression], Expression::Binary(_)));
+
+        let mut scanner = Scanner::new("1 + 2 3".to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        assert!(parser.parse_expression().is_err());
+    }
+
+    #[test]
+    fn test_shared_node_ids() {
+        let ids = NodeIds::new();
+        let parse = |source: &str| {
+            let mut scanner = Scanner::new(source.to_string());
+            let mut parser = Parser::with_ids(scanner.take_tokens(), ids.clone());
+            let expression = parser.parse_expression().unwrap();
+            *parser.ast()[expression]
+        };
+        // Like two lines of the REPL
+        let first = parse("a + b");
+        let second = parse("a");
+        assert_eq!(first, NodeId(2));
+        assert_eq!(second, NodeId(3));
+        assert_eq!(ids.next_id(), NodeId(4));
+    }
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        let _ = parser.parse();
+        parser
+            .errors()
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_cascading_errors() {
+        // The body of the function isn't taken for statements of its own
+        assert_eq!(
+            parse_errors("fun f( { print 1; }\nprint 2;"),
+            ["[line 1] Error at '{': Expect parameter name."]
+        );
+        assert_eq!(
+            parse_errors("if (a b) { print 1; } else { print 2; }"),
+            ["[line 1] Error at 'b': Expect ')' after condition."]
+        );
+        // Recovering inside a block leaves its '}' alone
+        assert_eq!(
+            parse_errors("{ print 1 }\nprint ;"),
+            [
+                "[line 1] Error at '}': Expect ';' after value.",
+                "[line 2] Error at ';': Expect expression."
+            ]
+        );
+        assert_eq!(
+            parse_errors("fun f() { var = 1; }\nprint ;"),
+            [
+                "[line 1] Error at '=': Expect variable name.",
+                "[line 2] Error at ';': Expect expression."
+            ]
+        );
+
+        let mut scanner = Scanner::new("var = 1; print 2;".to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        assert!(parser.parse().is_err());
+        assert_eq!(parser.recovered().to_vec(), vec![1..4]);
+    }
+
+    #[test]
+    fn test_block_errors() {
+        assert_eq!(
+            parse_errors("fun f() {\n  var = 1;\n  { print ; }\n}\nprint ;"),
+            [
+                "[line 2] Error at '=': Expect variable name.",
+                "[line 3] Error at ';': Expect expression.",
+                "[line 5] Error at ';': Expect expression."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        // Reported at the '=' while the rest of the statement still parses
+        let mut scanner = Scanner::new(
+            "a + b = c;
+print 1;"
+                .to_string(),
+        );
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_error_reporting(false);
+        assert!(parser.parse().is_err());
+        assert_eq!(
+            parser.errors()[0].to_string(),
+            "[line 1] Error at '=': Invalid assignment target."
+        );
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(parser.error_tokens(), &[3]);
+    }
+}
@@ -1,29 +1,39 @@
-use liblox::tokens::LiteralTypes;
+use std::collections::{HashMap, HashSet};
 
+use liblox::scanner::Comment;
+use liblox::tokens::{LiteralTypes, Token, TokenType};
+
+use crate::ast::{Ast, ExprId, StmtId};
 use crate::expression::*;
+use crate::query::{self, Node, Span};
+use crate::stmt::{FunctionStmt, Stmt};
 
-pub fn pretty_print(expr: &Expression) -> String {
-    match expr {
+pub fn pretty_print(ast: &Ast, expr: ExprId) -> String {
+    match &ast[expr] {
         Expression::Assign(assign) => {
-            let value = pretty_print(&*assign.value);
+            let value = pretty_print(ast, assign.value);
             format!("{} = {}", assign.name.lexeme, value)
         }
         Expression::Binary(binary) => {
-            let left = pretty_print(&*binary.left);
-            let right = pretty_print(&*binary.right);
+            let left = pretty_print(ast, binary.left);
+            let right = pretty_print(ast, binary.right);
             format!("({} {} {})", binary.operator.lexeme, left, right)
         }
         Expression::Call(call) => {
-            let callee = pretty_print(&*call.callee);
-            let args: Vec<String> = call.arguments.iter().map(|arg| pretty_print(arg)).collect();
+            let callee = pretty_print(ast, call.callee);
+            let args: Vec<String> = call
+                .arguments
+                .iter()
+                .map(|&arg| pretty_print(ast, arg))
+                .collect();
             format!("{}({})", callee, args.join(", "))
         }
         Expression::Get(get) => {
-            let object = pretty_print(&*get.object);
+            let object = pretty_print(ast, get.object);
             format!("{} . {}", object, get.name.lexeme)
         }
         Expression::Grouping(grouping) => {
-            let expr = pretty_print(&*grouping.expression);
+            let expr = pretty_print(ast, grouping.expression);
             format!("(group {})", expr)
         }
         Expression::Literal(literal) => match &literal.value {
@@ -33,52 +43,131 @@ pub fn pretty_print(expr: &Expression) -> String {
             LiteralTypes::Nil => format!("nil"),
         },
         Expression::Logical(logical) => {
-            let left = pretty_print(&*logical.left);
-            let right = pretty_print(&*logical.right);
+            let left = pretty_print(ast, logical.left);
+            let right = pretty_print(ast, logical.right);
             format!("({} {} {})", logical.operator.lexeme, left, right)
         }
         Expression::Set(set) => {
-            let object = pretty_print(&*set.object);
-            let value = pretty_print(&*set.value);
+            let object = pretty_print(ast, set.object);
+            let value = pretty_print(ast, set.value);
             format!("{} . {} = {}", object, set.name.lexeme, value)
         }
         Expression::Super(super_expr) => {
             format!("super . {}", super_expr.method.lexeme)
         }
-        Expression::This(this) => {
-            format!("this")
-        }
+        Expression::This(_) => "this".to_string(),
         Expression::Unary(unary) => {
-            let right = pretty_print(&*unary.right);
+            let right = pretty_print(ast, unary.right);
             format!("({} {})", unary.operator.lexeme, right)
         }
-        Expression::Variable(variable) => {
-            format!("{}", variable.name)
+        Expression::Variable(variable) => variable.name.lexeme.clone(),
+    }
+}
+
+/// Prints a statement in the parenthesized form of `pretty_print`, with
+/// the statements it contains nested in it.
+pub fn pretty_print_stmt(ast: &Ast, stmt: StmtId) -> String {
+    match &ast[stmt] {
+        Stmt::Expression(expr_stmt) => format!("(; {})", pretty_print(ast, expr_stmt.expression)),
+        Stmt::Print(print_stmt) => format!("(print {})", pretty_print(ast, print_stmt.expression)),
+        Stmt::Var(var_stmt) => match var_stmt.initializer {
+            Some(initializer) => format!(
+                "(var {} {})",
+                var_stmt.name.lexeme,
+                pretty_print(ast, initializer)
+            ),
+            None => format!("(var {})", var_stmt.name.lexeme),
+        },
+        Stmt::Block(block_stmt) => {
+            let mut printed = "(block".to_string();
+            for &statement in &block_stmt.statements {
+                printed.push(' ');
+                printed.push_str(&pretty_print_stmt(ast, statement));
+            }
+            printed + ")"
         }
+        Stmt::If(if_stmt) => {
+            let condition = pretty_print(ast, if_stmt.condition);
+            let then_branch = pretty_print_stmt(ast, if_stmt.then_branch);
+            match if_stmt.else_branch {
+                Some(else_branch) => format!(
+                    "(if {} {} {})",
+                    condition,
+                    then_branch,
+                    pretty_print_stmt(ast, else_branch)
+                ),
+                None => format!("(if {} {})", condition, then_branch),
+            }
+        }
+        Stmt::While(while_stmt) => format!(
+            "(while {} {})",
+            pretty_print(ast, while_stmt.condition),
+            pretty_print_stmt(ast, while_stmt.body)
+        ),
+        Stmt::ForIn(for_in) => format!(
+            "(for {} {} {})",
+            for_in.name.lexeme,
+            pretty_print(ast, for_in.iterable),
+            pretty_print_stmt(ast, for_in.body)
+        ),
+        Stmt::Return(return_stmt) => match return_stmt.value {
+            Some(value) => format!("(return {})", pretty_print(ast, value)),
+            None => "(return)".to_string(),
+        },
+        Stmt::Function(function) => pretty_print_function(ast, function),
+        Stmt::Class(class_stmt) => {
+            let mut printed = format!("(class {}", class_stmt.name.lexeme);
+            if let Some(superclass) = &class_stmt.superclass {
+                printed.push_str(&format!(" < {}", superclass.name.lexeme));
+            }
+            for method in &class_stmt.methods {
+                printed.push(' ');
+                printed.push_str(&pretty_print_function(ast, method));
+            }
+            printed + ")"
+        }
+    }
+}
+
+fn pretty_print_function(ast: &Ast, function: &FunctionStmt) -> String {
+    let params: Vec<&str> = function
+        .params
+        .iter()
+        .map(|param| param.lexeme.as_str())
+        .collect();
+    let mut printed = format!("(fun {}({})", function.name.lexeme, params.join(", "));
+    for &statement in &function.body {
+        printed.push(' ');
+        printed.push_str(&pretty_print_stmt(ast, statement));
     }
+    printed + ")"
 }
 
-pub fn rpn_print(expr: &Expression) -> String {
-    match expr {
+pub fn rpn_print(ast: &Ast, expr: ExprId) -> String {
+    match &ast[expr] {
         Expression::Assign(assign) => {
-            let value = rpn_print(&*assign.value);
+            let value = rpn_print(ast, assign.value);
             format!("{} = {}", assign.name.lexeme, value)
         }
         Expression::Binary(binary) => {
-            let left = rpn_print(&*binary.left);
-            let right = rpn_print(&*binary.right);
+            let left = rpn_print(ast, binary.left);
+            let right = rpn_print(ast, binary.right);
             format!("{} {} {}", left, right, binary.operator.lexeme)
         }
         Expression::Call(call) => {
-            let callee = rpn_print(&*call.callee);
-            let args: Vec<String> = call.arguments.iter().map(|arg| rpn_print(arg)).collect();
+            let callee = rpn_print(ast, call.callee);
+            let args: Vec<String> = call
+                .arguments
+                .iter()
+                .map(|&arg| rpn_print(ast, arg))
+                .collect();
             format!("{}({})", callee, args.join(", "))
         }
         Expression::Get(get) => {
-            let object = rpn_print(&*get.object);
+            let object = rpn_print(ast, get.object);
             format!("{} . {}", object, get.name.lexeme)
         }
-        Expression::Grouping(grouping) => rpn_print(&*grouping.expression),
+        Expression::Grouping(grouping) => rpn_print(ast, grouping.expression),
         Expression::Literal(literal) => match &literal.value {
             LiteralTypes::String(s) => format!("{}", s),
             LiteralTypes::Number(n) => format!("{}", n),
@@ -86,27 +175,460 @@ pub fn rpn_print(expr: &Expression) -> String {
             LiteralTypes::Nil => format!("nil"),
         },
         Expression::Logical(logical) => {
-            let left = rpn_print(&*logical.left);
-            let right = rpn_print(&*logical.right);
+            let left = rpn_print(ast, logical.left);
+            let right = rpn_print(ast, logical.right);
             format!("{} {} {}", left, right, logical.operator.lexeme)
         }
         Expression::Set(set) => {
-            let object = rpn_print(&*set.object);
-            let value = rpn_print(&*set.value);
+            let object = rpn_print(ast, set.object);
+            let value = rpn_print(ast, set.value);
             format!("{} . {} = {}", object, set.name.lexeme, value)
         }
         Expression::Super(super_expr) => {
             format!("super . {}", super_expr.method.lexeme)
         }
-        Expression::This(this) => {
-            format!("this")
-        }
+        Expression::This(_) => "this".to_string(),
         Expression::Unary(unary) => {
-            let right = rpn_print(&*unary.right);
+            let right = rpn_print(ast, unary.right);
             format!("{} {}", right, unary.operator.lexeme)
         }
-        Expression::Variable(variable) => {
-            format!("{}", variable.name)
+        Expression::Variable(variable) => variable.name.lexeme.clone(),
+    }
+}
+
+/// Re-emits a program as canonical Lox source: two spaces of indentation,
+/// one statement per line and only the parentheses precedence needs.
+/// Comments stay before the statement they preceded or at the end of the
+/// line they ended, and runs of blank lines in `source` between statements
+/// become one.
+///
+/// `for` loops, which the parser desugars into `while` loops, are turned
+/// back into `for` loops. That relies on the order the parser allocates
+/// nodes in, so an equivalent loop written out by hand may come out as a
+/// `for` loop too.
+pub fn format_program(
+    ast: &Ast,
+    statements: &[StmtId],
+    comments: &[Comment],
+    source: &str,
+) -> String {
+    let blank_lines = source
+        .lines()
+        .zip(1..)
+        .filter(|(line, _)| line.trim().is_empty())
+        .map(|(_, number)| number)
+        .collect();
+    let mut formatter = Formatter {
+        ast,
+        comments,
+        next_comment: 0,
+        blank_lines,
+        spans: query::nodes(ast, statements)
+            .into_iter()
+            .filter_map(|(node, span)| match node {
+                Node::Statement(id) => Some((id, span?)),
+                Node::Expression(_) => None,
+            })
+            .collect(),
+        indent: 0,
+        output: String::new(),
+    };
+    let mut at_start = true;
+    formatter.statements(statements, &mut at_start);
+    formatter.comments_before(i32::MAX, &mut at_start);
+    formatter.output
+}
+
+// Binding strength of the expression grammar's levels
+const ASSIGNMENT: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const TERM: u8 = 6;
+const FACTOR: u8 = 7;
+const UNARY: u8 = 8;
+const CALL: u8 = 9;
+const PRIMARY: u8 = 10;
+
+fn operator_precedence(operator: &Token) -> u8 {
+    match operator.token_type {
+        TokenType::Or => OR,
+        TokenType::And => AND,
+        TokenType::BangEqual | TokenType::EqualEqual => EQUALITY,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            COMPARISON
+        }
+        TokenType::Minus | TokenType::Plus => TERM,
+        _ => FACTOR,
+    }
+}
+
+fn precedence(ast: &Ast, expression: ExprId) -> u8 {
+    match &ast[expression] {
+        Expression::Assign(_) | Expression::Set(_) => ASSIGNMENT,
+        Expression::Binary(Binary { operator, .. })
+        | Expression::Logical(Logical { operator, .. }) => operator_precedence(operator),
+        Expression::Unary(_) => UNARY,
+        Expression::Grouping(grouping) => precedence(ast, grouping.expression),
+        Expression::Call(_) | Expression::Get(_) => CALL,
+        _ => PRIMARY,
+    }
+}
+
+/// The parts of a `for` loop the parser desugared into a `while` loop.
+struct ForLoop {
+    initializer: Option<StmtId>,
+    condition: Option<ExprId>,
+    increment: Option<ExprId>,
+    body: StmtId,
+}
+
+impl ForLoop {
+    fn recover(ast: &Ast, statement: StmtId) -> Option<ForLoop> {
+        let (initializer, loop_id) = match &ast[statement] {
+            Stmt::Block(block) => match block.statements[..] {
+                [initializer, loop_id]
+                    if matches!(ast[initializer], Stmt::Var(_) | Stmt::Expression(_)) =>
+                {
+                    (Some(initializer), loop_id)
+                }
+                _ => return None,
+            },
+            _ => (None, statement),
+        };
+        let Stmt::While(while_stmt) = &ast[loop_id] else {
+            return None;
+        };
+        if while_stmt.keyword.token_type != TokenType::For {
+            return None;
+        }
+        let condition = match &ast[while_stmt.condition] {
+            Expression::Literal(Literal {
+                value: LiteralTypes::Bool(true),
+                ..
+            }) => None,
+            _ => Some(while_stmt.condition),
+        };
+        // The increment is parsed before the body, so its expressions come
+        // first; a body that merely ends in an expression has them last
+        let (body, increment) = match &ast[while_stmt.body] {
+            Stmt::Block(block) => match block.statements[..] {
+                [body, increment] => match &ast[increment] {
+                    Stmt::Expression(stmt)
+                        if first_expression(ast, body).is_none_or(|id| stmt.expression < id) =>
+                    {
+                        (body, Some(stmt.expression))
+                    }
+                    _ => (while_stmt.body, None),
+                },
+                _ => (while_stmt.body, None),
+            },
+            _ => (while_stmt.body, None),
+        };
+        Some(ForLoop {
+            initializer,
+            condition,
+            increment,
+            body,
+        })
+    }
+}
+
+fn first_expression(ast: &Ast, statement: StmtId) -> Option<ExprId> {
+    query::nodes(ast, &[statement])
+        .into_iter()
+        .filter_map(|(node, _)| match node {
+            Node::Expression(id) => Some(id),
+            Node::Statement(_) => None,
+        })
+        .min()
+}
+
+struct Formatter<'a> {
+    ast: &'a Ast,
+    comments: &'a [Comment],
+    // The comments before it have been written
+    next_comment: usize,
+    blank_lines: HashSet<i32>,
+    spans: HashMap<StmtId, Span>,
+    indent: usize,
+    output: String,
+}
+
+impl Formatter<'_> {
+    fn begin_line(&mut self) {
+        for _ in 0..self.indent {
+            self.output.push_str("  ");
+        }
+    }
+
+    // Keeps a blank line above `line`, unless it would open a block
+    fn separate(&mut self, line: i32, at_start: &mut bool) {
+        if !*at_start && self.blank_lines.contains(&(line - 1)) {
+            self.output.push('\n');
+        }
+        *at_start = false;
+    }
+
+    // Writes the comments above `line` on lines of their own
+    fn comments_before(&mut self, line: i32, at_start: &mut bool) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.line >= line {
+                break;
+            }
+            self.separate(comment.line, at_start);
+            self.begin_line();
+            self.output.push_str(&comment.text);
+            self.output.push('\n');
+            self.next_comment += 1;
+        }
+    }
+
+    // Writes each statement on lines of its own
+    fn statements(&mut self, statements: &[StmtId], at_start: &mut bool) {
+        for &statement in statements {
+            let span = self.spans.get(&statement).copied();
+            if let Some(span) = span {
+                self.comments_before(span.first_line, at_start);
+                self.separate(span.first_line, at_start);
+            }
+            *at_start = false;
+            self.begin_line();
+            self.statement(statement);
+            if let Some(span) = span {
+                if let Some(comment) = self.comments.get(self.next_comment) {
+                    if comment.line == span.last_line {
+                        self.output.push(' ');
+                        self.output.push_str(&comment.text);
+                        self.next_comment += 1;
+                    }
+                }
+            }
+            self.output.push('\n');
+        }
+    }
+
+    fn block(&mut self, statements: &[StmtId]) {
+        if statements.is_empty() {
+            self.output.push_str("{}");
+            return;
+        }
+        self.output.push_str("{\n");
+        self.indent += 1;
+        self.statements(statements, &mut true);
+        self.indent -= 1;
+        self.begin_line();
+        self.output.push('}');
+    }
+
+    fn function(&mut self, function: &FunctionStmt) {
+        let params: Vec<&str> = function
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        self.output
+            .push_str(&format!("{}({}) ", function.name.lexeme, params.join(", ")));
+        self.block(&function.body);
+    }
+
+    fn for_loop(&mut self, for_loop: ForLoop) {
+        self.output.push_str("for (");
+        match for_loop.initializer {
+            Some(initializer) => self.statement(initializer),
+            None => self.output.push(';'),
+        }
+        if let Some(condition) = for_loop.condition {
+            self.output.push(' ');
+            self.expression(condition, ASSIGNMENT);
+        }
+        self.output.push(';');
+        if let Some(increment) = for_loop.increment {
+            self.output.push(' ');
+            self.expression(increment, ASSIGNMENT);
+        }
+        self.output.push_str(") ");
+        self.statement(for_loop.body);
+    }
+
+    // Writes a statement from the current position on, leaving the line open
+    fn statement(&mut self, statement: StmtId) {
+        let ast = self.ast;
+        if let Some(for_loop) = ForLoop::recover(ast, statement) {
+            return self.for_loop(for_loop);
+        }
+        match &ast[statement] {
+            Stmt::Expression(stmt) => {
+                self.expression(stmt.expression, ASSIGNMENT);
+                self.output.push(';');
+            }
+            Stmt::Print(stmt) => {
+                self.output.push_str("print ");
+                self.expression(stmt.expression, ASSIGNMENT);
+                self.output.push(';');
+            }
+            Stmt::Var(stmt) => {
+                self.output.push_str("var ");
+                self.output.push_str(&stmt.name.lexeme);
+                if let Some(initializer) = stmt.initializer {
+                    self.output.push_str(" = ");
+                    self.expression(initializer, ASSIGNMENT);
+                }
+                self.output.push(';');
+            }
+            Stmt::Block(block) => self.block(&block.statements),
+            Stmt::If(stmt) => {
+                self.output.push_str("if (");
+                self.expression(stmt.condition, ASSIGNMENT);
+                self.output.push_str(") ");
+                self.statement(stmt.then_branch);
+                if let Some(else_branch) = stmt.else_branch {
+                    if self.output.ends_with('}') {
+                        self.output.push(' ');
+                    } else {
+                        self.output.push('\n');
+                        self.begin_line();
+                    }
+                    self.output.push_str("else ");
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While(stmt) => {
+                self.output.push_str("while (");
+                self.expression(stmt.condition, ASSIGNMENT);
+                self.output.push_str(") ");
+                self.statement(stmt.body);
+            }
+            Stmt::ForIn(stmt) => {
+                self.output
+                    .push_str(&format!("for (var {} in ", stmt.name.lexeme));
+                self.expression(stmt.iterable, ASSIGNMENT);
+                self.output.push_str(") ");
+                self.statement(stmt.body);
+            }
+            Stmt::Return(stmt) => {
+                self.output.push_str("return");
+                if let Some(value) = stmt.value {
+                    self.output.push(' ');
+                    self.expression(value, ASSIGNMENT);
+                }
+                self.output.push(';');
+            }
+            Stmt::Function(function) => {
+                self.output.push_str("fun ");
+                self.function(function);
+            }
+            Stmt::Class(class) => {
+                self.output.push_str("class ");
+                self.output.push_str(&class.name.lexeme);
+                if let Some(superclass) = &class.superclass {
+                    self.output.push_str(" < ");
+                    self.output.push_str(&superclass.name.lexeme);
+                }
+                if class.methods.is_empty() {
+                    self.output.push_str(" {}");
+                    return;
+                }
+                self.output.push_str(" {\n");
+                self.indent += 1;
+                for (i, method) in class.methods.iter().enumerate() {
+                    if i > 0 {
+                        self.output.push('\n');
+                    }
+                    self.comments_before(method.name.line, &mut true);
+                    self.begin_line();
+                    self.function(method);
+                    self.output.push('\n');
+                }
+                self.indent -= 1;
+                self.begin_line();
+                self.output.push('}');
+            }
+        }
+    }
+
+    // Writes an expression, parenthesized if it binds less tightly than
+    // `min_precedence`. The source's own parentheses are dropped.
+    fn expression(&mut self, mut expression: ExprId, min_precedence: u8) {
+        let ast = self.ast;
+        while let Expression::Grouping(grouping) = &ast[expression] {
+            expression = grouping.expression;
+        }
+        let parenthesize = precedence(ast, expression) < min_precedence;
+        if parenthesize {
+            self.output.push('(');
+        }
+        match &ast[expression] {
+            Expression::Assign(assign) => {
+                self.output.push_str(&assign.name.lexeme);
+                self.output.push_str(" = ");
+                self.expression(assign.value, ASSIGNMENT);
+            }
+            Expression::Binary(Binary {
+                left,
+                operator,
+                right,
+                ..
+            })
+            | Expression::Logical(Logical {
+                left,
+                operator,
+                right,
+                ..
+            }) => {
+                let precedence = operator_precedence(operator);
+                self.expression(*left, precedence);
+                self.output.push_str(&format!(" {} ", operator.lexeme));
+                self.expression(*right, precedence + 1);
+            }
+            Expression::Call(call) => {
+                self.expression(call.callee, CALL);
+                self.output.push('(');
+                for (i, &argument) in call.arguments.iter().enumerate() {
+                    if i > 0 {
+                        self.output.push_str(", ");
+                    }
+                    self.expression(argument, ASSIGNMENT);
+                }
+                self.output.push(')');
+            }
+            Expression::Get(get) => {
+                self.expression(get.object, CALL);
+                self.output.push('.');
+                self.output.push_str(&get.name.lexeme);
+            }
+            Expression::Grouping(_) => unreachable!("groupings are unwrapped"),
+            Expression::Literal(literal) => match &literal.value {
+                LiteralTypes::String(s) => self.output.push_str(&format!("\"{}\"", s)),
+                LiteralTypes::Number(n) => self.output.push_str(&n.to_string()),
+                LiteralTypes::Bool(b) => self.output.push_str(&b.to_string()),
+                LiteralTypes::Nil => self.output.push_str("nil"),
+            },
+            Expression::Set(set) => {
+                self.expression(set.object, CALL);
+                self.output.push('.');
+                self.output.push_str(&set.name.lexeme);
+                self.output.push_str(" = ");
+                self.expression(set.value, ASSIGNMENT);
+            }
+            Expression::Super(super_expr) => {
+                self.output.push_str("super.");
+                self.output.push_str(&super_expr.method.lexeme);
+            }
+            Expression::This(_) => self.output.push_str("this"),
+            Expression::Unary(unary) => {
+                self.output.push_str(&unary.operator.lexeme);
+                // `- -a` rather than `--a`
+                if precedence(ast, unary.right) == UNARY {
+                    self.output.push(' ');
+                }
+                self.expression(unary.right, UNARY);
+            }
+            Expression::Variable(variable) => self.output.push_str(&variable.name.lexeme),
+        }
+        if parenthesize {
+            self.output.push(')');
         }
     }
 }
@@ -114,64 +636,153 @@ pub fn rpn_print(expr: &Expression) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use liblox::tokens::{Token, TokenType};
+
+    fn literal(ast: &mut Ast, value: f64) -> ExprId {
+        let id = NodeId(ast.expression_count());
+        ast.add_expression(Expression::Literal(Literal {
+            id,
+            value: LiteralTypes::Number(value),
+        }))
+    }
+
+    fn binary(ast: &mut Ast, left: ExprId, operator: Token, right: ExprId) -> ExprId {
+        let id = NodeId(ast.expression_count());
+        ast.add_expression(Expression::Binary(Binary {
+            id,
+            left,
+            operator,
+            right,
+        }))
+    }
 
     #[test]
     fn test_pretty_print() {
-        let expr = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Unary(Unary {
-                id: 1,
-                operator: Token::new(TokenType::Minus, "-".to_string(), LiteralTypes::Nil, 1),
-                right: Box::new(Expression::Literal(Literal {
-                    id: 2,
-                    value: LiteralTypes::Number(123.0),
-                })),
-            })),
-            operator: Token::new(TokenType::Star, "*".to_string(), LiteralTypes::Nil, 1),
-            right: Box::new(Expression::Grouping(Grouping {
-                id: 3,
-                expression: Box::new(Expression::Literal(Literal {
-                    id: 4,
-                    value: LiteralTypes::Number(45.67),
-                })),
-            })),
-        });
-
-        assert_eq!(pretty_print(&expr), "(* (- 123) (group 45.67))");
+        let mut ast = Ast::new();
+        let number = literal(&mut ast, 123.0);
+        let left = ast.add_expression(Expression::Unary(Unary {
+            id: NodeId(1),
+            operator: Token::new(TokenType::Minus, "-".to_string(), LiteralTypes::Nil, 1),
+            right: number,
+        }));
+        let number = literal(&mut ast, 45.67);
+        let right = ast.add_expression(Expression::Grouping(Grouping {
+            id: NodeId(3),
+            expression: number,
+        }));
+        let star = Token::new(TokenType::Star, "*".to_string(), LiteralTypes::Nil, 1);
+        let expr = binary(&mut ast, left, star, right);
+
+        assert_eq!(pretty_print(&ast, expr), "(* (- 123) (group 45.67))");
     }
 
     #[test]
     fn test_rpn_print() {
-        let expr = Expression::Binary(Binary {
-            id: 0,
-            left: Box::new(Expression::Binary(Binary {
-                id: 1,
-                left: Box::new(Expression::Literal(Literal {
-                    id: 2,
-                    value: LiteralTypes::Number(1.0),
-                })),
-                operator: Token::new(TokenType::Plus, "+".to_string(), LiteralTypes::Nil, 1),
-                right: Box::new(Expression::Literal(Literal {
-                    id: 3,
-                    value: LiteralTypes::Number(2.0),
-                })),
-            })),
-            operator: Token::new(TokenType::Star, "*".to_string(), LiteralTypes::Nil, 1),
-            right: Box::new(Expression::Binary(Binary {
-                id: 4,
-                left: Box::new(Expression::Literal(Literal {
-                    id: 5,
-                    value: LiteralTypes::Number(4.0),
-                })),
-                operator: Token::new(TokenType::Minus, "-".to_string(), LiteralTypes::Nil, 1),
-                right: Box::new(Expression::Literal(Literal {
-                    id: 6,
-                    value: LiteralTypes::Number(3.0),
-                })),
-            })),
-        });
-
-        assert_eq!(rpn_print(&expr), "1 2 + 4 3 - *");
+        let mut ast = Ast::new();
+        let (one, two) = (literal(&mut ast, 1.0), literal(&mut ast, 2.0));
+        let plus = Token::new(TokenType::Plus, "+".to_string(), LiteralTypes::Nil, 1);
+        let left = binary(&mut ast, one, plus, two);
+        let (four, three) = (literal(&mut ast, 4.0), literal(&mut ast, 3.0));
+        let minus = Token::new(TokenType::Minus, "-".to_string(), LiteralTypes::Nil, 1);
+        let right = binary(&mut ast, four, minus, three);
+        let star = Token::new(TokenType::Star, "*".to_string(), LiteralTypes::Nil, 1);
+        let expr = binary(&mut ast, left, star, right);
+
+        assert_eq!(rpn_print(&ast, expr), "1 2 + 4 3 - *");
+    }
+
+    #[test]
+    fn test_pretty_print_stmt() {
+        let source = "var a = 1; var b;\n\
+                      class A < B { init(x) { this.x = x; } get() { return super.get(); } }\n\
+                      fun f(n) { while (n > 0) n = n - 1; if (!n) print a.b; else return; }\n\
+                      for (var i in items) { f(i); }";
+        let mut scanner = liblox::scanner::Scanner::new(source.to_string());
+        let mut parser = crate::parser::Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        let printed: Vec<String> = statements
+            .iter()
+            .map(|&statement| pretty_print_stmt(&ast, statement))
+            .collect();
+
+        assert_eq!(
+            printed,
+            vec![
+                "(var a 1)",
+                "(var b)",
+                "(class A < B (fun init(x) (; this . x = x)) (fun get() (return super . get())))",
+                "(fun f(n) (while (> n 0) (; n = (- n 1))) (if (! n) (print a . b) (return)))",
+                "(for i items (block (; f(i))))",
+            ]
+        );
+    }
+
+    // The statements of a source as `pretty_print_stmt` prints them, less
+    // the groupings the formatter may drop
+    fn parse(source: &str) -> Vec<String> {
+        let mut scanner = liblox::scanner::Scanner::new(source.to_string());
+        let mut parser = crate::parser::Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        statements
+            .iter()
+            .map(|&statement| {
+                let mut printed = pretty_print_stmt(parser.ast(), statement);
+                while let Some(start) = printed.find("(group ") {
+                    let mut depth = 0;
+                    let end = start
+                        + printed[start..]
+                            .find(|c| {
+                                depth += match c {
+                                    '(' => 1,
+                                    ')' => -1,
+                                    _ => 0,
+                                };
+                                depth == 0
+                            })
+                            .unwrap();
+                    printed.replace_range(end..=end, "");
+                    printed.replace_range(start..start + "(group ".len(), "");
+                }
+                printed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_format_program() {
+        let source = "// Counts\nvar a=1;   // one\n\n\n\
+                      fun f(x,y){ if (x>y) return (x); else { return -(-y); } }\n\
+                      class A < B { init(x) { this.x = x; }\n  // Delegates\n  get() { return super.get(); } }\n\
+                      for (var i = 0; i < 3; i = i + 1) { print i; }\nfor (;;) print (a = 2) or !(true and false);\n\
+                      while (a < 3) a = (a + 1) * 2 - (3 - a);\nprint a.b(1, \"two\").c;";
+
+        assert_eq!(
+            crate::format(source).unwrap(),
+            "// Counts\n\
+             var a = 1; // one\n\
+             \n\
+             fun f(x, y) {\n  if (x > y) return x;\n  else {\n    return - -y;\n  }\n}\n\
+             class A < B {\n  init(x) {\n    this.x = x;\n  }\n\n  // Delegates\n  get() {\n    return super.get();\n  }\n}\n\
+             for (var i = 0; i < 3; i = i + 1) {\n  print i;\n}\n\
+             for (;;) print (a = 2) or !(true and false);\n\
+             while (a < 3) a = (a + 1) * 2 - (3 - a);\n\
+             print a.b(1, \"two\").c;\n"
+        );
+    }
+
+    #[test]
+    fn test_format_roundtrip() {
+        let sources = [
+            "var a = (1 + 2) * 3 - -4 / (5 - 6); print a == 1 != (a < 2);",
+            "print a or (b or c) and !d; a.b.c = d = e(1)(2).f;",
+            "fun f() { for (;;) { if (a) if (b) print 1; else print 2; } } class A {}",
+            "for (a = 0; a < 1;) print a; for (var x in xs) { {} }",
+            "{ var i = 0; while (i < 3) { print i; i = i + 1; } }",
+        ];
+        for source in sources {
+            let formatted = crate::format(source).unwrap();
+            assert_eq!(parse(&formatted), parse(source), "{}", formatted);
+            assert_eq!(crate::format(&formatted).unwrap(), formatted);
+        }
     }
 }
@@ -0,0 +1,294 @@
+//! Finds things in a parsed program, for lint rules and refactoring tools.
+//!
+//! ```
+//! let source = "var a = 1; fun f(x) { a = x; } f(a);";
+//! let mut scanner = liblox::scanner::Scanner::new(source.to_string());
+//! let mut parser = loxrun::parser::Parser::new(scanner.take_tokens());
+//! let statements = parser.parse().unwrap();
+//!
+//! let calls = loxrun::query::find_calls_to(parser.ast(), &statements, "f");
+//! let assignments = loxrun::query::find_assignments_of(parser.ast(), &statements, "a");
+//! assert_eq!((calls.len(), assignments.len()), (1, 1));
+//! ```
+
+use liblox::tokens::Token;
+
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::{Binary, Expression, Logical};
+use crate::stmt::{FunctionStmt, Stmt};
+
+/// A statement or expression of an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Node {
+    Statement(StmtId),
+    Expression(ExprId),
+}
+
+/// The source lines a node was parsed from. Only tokens the syntax tree
+/// keeps count, so a closing brace or semicolon on a line of its own isn't
+/// part of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub first_line: i32,
+    pub last_line: i32,
+}
+
+// The span covering both, if either has one
+fn merge(span: Option<Span>, other: Option<Span>) -> Option<Span> {
+    match (span, other) {
+        (Some(span), Some(other)) => Some(Span {
+            first_line: span.first_line.min(other.first_line),
+            last_line: span.last_line.max(other.last_line),
+        }),
+        _ => span.or(other),
+    }
+}
+
+fn token_span(token: &Token) -> Option<Span> {
+    Some(Span {
+        first_line: token.line,
+        last_line: token.line,
+    })
+}
+
+/// Every node of the statements and what they contain, each before its
+/// children, with its span. Nodes without tokens, like a literal, have no
+/// span.
+pub fn nodes(ast: &Ast, statements: &[StmtId]) -> Vec<(Node, Option<Span>)> {
+    let mut collector = Collector {
+        ast,
+        nodes: Vec::new(),
+    };
+    for &statement in statements {
+        collector.statement(statement);
+    }
+    collector.nodes
+}
+
+/// The calls of a function or method named `name`: calls of a variable
+/// `name()` and of a property `object.name()`.
+pub fn find_calls_to(ast: &Ast, statements: &[StmtId], name: &str) -> Vec<ExprId> {
+    expressions(ast, statements)
+        .filter(|&id| {
+            let Expression::Call(call) = &ast[id] else {
+                return false;
+            };
+            match &ast[call.callee] {
+                Expression::Variable(variable) => variable.name.lexeme == name,
+                Expression::Get(get) => get.name.lexeme == name,
+                _ => false,
+            }
+        })
+        .collect()
+}
+
+/// The assignments to variables named `name`. Declarations aren't
+/// assignments, and neither are stores to properties.
+pub fn find_assignments_of(ast: &Ast, statements: &[StmtId], name: &str) -> Vec<ExprId> {
+    expressions(ast, statements)
+        .filter(|&id| matches!(&ast[id], Expression::Assign(assign) if assign.name.lexeme == name))
+        .collect()
+}
+
+fn expressions(ast: &Ast, statements: &[StmtId]) -> impl Iterator<Item = ExprId> {
+    nodes(ast, statements)
+        .into_iter()
+        .filter_map(|(node, _)| match node {
+            Node::Expression(id) => Some(id),
+            Node::Statement(_) => None,
+        })
+}
+
+struct Collector<'a> {
+    ast: &'a Ast,
+    nodes: Vec<(Node, Option<Span>)>,
+}
+
+impl Collector<'_> {
+    // Adds the statement and its children, and returns its span
+    fn statement(&mut self, id: StmtId) -> Option<Span> {
+        let index = self.nodes.len();
+        self.nodes.push((Node::Statement(id), None));
+        let ast = self.ast;
+        let mut tokens: Vec<&Token> = Vec::new();
+        let mut expressions = Vec::new();
+        let mut statements = Vec::new();
+        let mut functions: Vec<&FunctionStmt> = Vec::new();
+        match &ast[id] {
+            Stmt::Expression(stmt) => expressions.push(stmt.expression),
+            Stmt::Print(stmt) => {
+                tokens.push(&stmt.keyword);
+                expressions.push(stmt.expression);
+            }
+            Stmt::Var(stmt) => {
+                tokens.push(&stmt.name);
+                expressions.extend(stmt.initializer);
+            }
+            Stmt::Block(block) => statements.extend(&block.statements),
+            Stmt::If(stmt) => {
+                tokens.push(&stmt.keyword);
+                expressions.push(stmt.condition);
+                statements.push(stmt.then_branch);
+                statements.extend(stmt.else_branch);
+            }
+            Stmt::While(stmt) => {
+                tokens.push(&stmt.keyword);
+                expressions.push(stmt.condition);
+                statements.push(stmt.body);
+            }
+            Stmt::ForIn(stmt) => {
+                tokens.push(&stmt.name);
+                expressions.push(stmt.iterable);
+                statements.push(stmt.body);
+            }
+            Stmt::Return(stmt) => {
+                tokens.push(&stmt.keyword);
+                expressions.extend(stmt.value);
+            }
+            Stmt::Function(function) => functions.push(function),
+            Stmt::Class(class) => {
+                tokens.push(&class.name);
+                if let Some(superclass) = &class.superclass {
+                    tokens.push(&superclass.name);
+                }
+                functions.extend(class.methods.iter().map(|method| method.as_ref()));
+            }
+        }
+        for function in functions {
+            tokens.push(&function.name);
+            tokens.extend(&function.params);
+            statements.extend(&function.body);
+        }
+
+        let mut span = tokens.into_iter().map(token_span).fold(None, merge);
+        for child in expressions {
+            span = merge(span, self.expression(child));
+        }
+        for child in statements {
+            span = merge(span, self.statement(child));
+        }
+        self.nodes[index].1 = span;
+        span
+    }
+
+    // Adds the expression and its children, and returns its span
+    fn expression(&mut self, id: ExprId) -> Option<Span> {
+        let index = self.nodes.len();
+        self.nodes.push((Node::Expression(id), None));
+        let ast = self.ast;
+        let mut tokens: Vec<&Token> = Vec::new();
+        let mut children = Vec::new();
+        match &ast[id] {
+            Expression::Assign(assign) => {
+                tokens.push(&assign.name);
+                children.push(assign.value);
+            }
+            Expression::Binary(Binary {
+                left,
+                operator,
+                right,
+                ..
+            })
+            | Expression::Logical(Logical {
+                left,
+                operator,
+                right,
+                ..
+            }) => {
+                tokens.push(operator);
+                children.extend([*left, *right]);
+            }
+            Expression::Call(call) => {
+                tokens.push(&call.paren);
+                children.push(call.callee);
+                children.extend(&call.arguments);
+            }
+            Expression::Get(get) => {
+                tokens.push(&get.name);
+                children.push(get.object);
+            }
+            Expression::Grouping(grouping) => children.push(grouping.expression),
+            Expression::Literal(_) => {}
+            Expression::Set(set) => {
+                tokens.push(&set.name);
+                children.extend([set.object, set.value]);
+            }
+            Expression::Super(super_expr) => {
+                tokens.extend([&super_expr.keyword, &super_expr.method]);
+            }
+            Expression::This(this) => tokens.push(&this.keyword),
+            Expression::Unary(unary) => {
+                tokens.push(&unary.operator);
+                children.push(unary.right);
+            }
+            Expression::Variable(variable) => tokens.push(&variable.name),
+        }
+
+        let mut span = tokens.into_iter().map(token_span).fold(None, merge);
+        for child in children {
+            span = merge(span, self.expression(child));
+        }
+        self.nodes[index].1 = span;
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::printer::{pretty_print, pretty_print_stmt};
+    use liblox::scanner::Scanner;
+
+    fn parse(source: &str) -> (Ast, Vec<StmtId>) {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        (parser.into_ast(), statements)
+    }
+
+    #[test]
+    fn test_nodes() {
+        let (ast, statements) = parse("print 1;\nfun f(a) {\n  return a +\n    a;\n}\n");
+        let printed: Vec<(String, Option<(i32, i32)>)> = nodes(&ast, &statements)
+            .into_iter()
+            .map(|(node, span)| {
+                let printed = match node {
+                    Node::Statement(id) => pretty_print_stmt(&ast, id),
+                    Node::Expression(id) => pretty_print(&ast, id),
+                };
+                (printed, span.map(|span| (span.first_line, span.last_line)))
+            })
+            .collect();
+
+        assert_eq!(
+            printed,
+            vec![
+                ("(print 1)".to_string(), Some((1, 1))),
+                ("1".to_string(), None),
+                ("(fun f(a) (return (+ a a)))".to_string(), Some((2, 4))),
+                ("(return (+ a a))".to_string(), Some((3, 4))),
+                ("(+ a a)".to_string(), Some((3, 4))),
+                ("a".to_string(), Some((3, 3))),
+                ("a".to_string(), Some((4, 4))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_calls_and_assignments() {
+        let (ast, statements) = parse(
+            "fun f() { a = f(); }\nclass C { m() { this.a = 1; return this.f(a); } }\nvar a = g(f);",
+        );
+        let calls = find_calls_to(&ast, &statements, "f");
+        let printed: Vec<String> = calls.iter().map(|&id| pretty_print(&ast, id)).collect();
+        assert_eq!(printed, vec!["f()", "this . f(a)"]);
+
+        let assignments = find_assignments_of(&ast, &statements, "a");
+        let printed: Vec<String> = assignments
+            .iter()
+            .map(|&id| pretty_print(&ast, id))
+            .collect();
+        assert_eq!(printed, vec!["a = f()"]);
+    }
+}
@@ -0,0 +1,592 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A small regular expression engine for the regex natives.
+///
+/// Supported syntax: literals, `.`, character classes (`[a-z]`, `[^0-9]`),
+/// the escapes `\d \w \s \D \W \S \n \r \t`, anchors `^` and `$`, groups
+/// with alternation `(a|b)`, and the quantifiers `* + ? {n} {n,} {n,m}`,
+/// each optionally followed by `?` to match lazily.
+///
+/// Patterns are compiled to a program for a Pike VM, which runs all ways of
+/// matching side by side. Matching takes time linear in the length of the
+/// text and no stack, whatever the pattern, and gives up once `interrupt`
+/// is set.
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat(Repeat),
+}
+
+#[derive(Debug)]
+struct Repeat {
+    node: Box<Node>,
+    min: usize,
+    max: Option<usize>,
+    lazy: bool,
+}
+
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    // Continues at both targets, preferring the first
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+// Counted repetitions are compiled by copying, so `(a{1000}){1000}` would
+// take a million instructions
+const MAX_PROGRAM_SIZE: usize = 100_000;
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut parser = PatternParser {
+            chars: pattern.chars().collect(),
+            position: 0,
+        };
+        let alternatives = parser.alternation()?;
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        let mut compiler = Compiler {
+            program: Vec::new(),
+        };
+        compiler.alternation(&alternatives)?;
+        compiler.emit(Inst::Match)?;
+        Ok(Self {
+            program: compiler.program,
+        })
+    }
+
+    /// Returns the character range of the leftmost match starting at or
+    /// after `start`.
+    pub fn find_at(
+        &self,
+        text: &[char],
+        start: usize,
+        interrupt: &AtomicBool,
+    ) -> Option<(usize, usize)> {
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        // Position + 1 at which each instruction was last added to a list
+        let mut added = vec![0; self.program.len()];
+        let mut found = None;
+        for position in start..=text.len() {
+            if interrupt.load(Ordering::Relaxed) {
+                return None;
+            }
+            // Threads starting later are less preferred than the running ones
+            if found.is_none() {
+                self.add_thread(&mut current, &mut added, 0, position, position, text);
+            }
+            if current.is_empty() {
+                break;
+            }
+            let c = text.get(position).copied();
+            for &(pc, begin) in &current {
+                let matched = match &self.program[pc] {
+                    Inst::Char(expected) => c == Some(*expected),
+                    Inst::Any => c.is_some_and(|c| c != '\n'),
+                    Inst::Class { ranges, negated } => c.is_some_and(|c| {
+                        ranges
+                            .iter()
+                            .any(|(start, end)| (*start..=*end).contains(&c))
+                            != *negated
+                    }),
+                    Inst::Match => {
+                        // Less preferred threads can't win anymore
+                        found = Some((begin, position));
+                        break;
+                    }
+                    _ => unreachable!("only consuming instructions are queued"),
+                };
+                if matched {
+                    self.add_thread(&mut next, &mut added, pc + 1, begin, position + 1, text);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        found
+    }
+
+    // Follows jumps, splits and anchors from `pc` and queues the instructions
+    // that consume a character or match, in order of preference
+    fn add_thread(
+        &self,
+        threads: &mut Vec<(usize, usize)>,
+        added: &mut [usize],
+        pc: usize,
+        begin: usize,
+        position: usize,
+        text: &[char],
+    ) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if added[pc] == position + 1 {
+                continue;
+            }
+            added[pc] = position + 1;
+            match self.program[pc] {
+                Inst::Jump(target) => stack.push(target),
+                Inst::Split(preferred, other) => {
+                    stack.push(other);
+                    stack.push(preferred);
+                }
+                Inst::Start => {
+                    if position == 0 {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::End => {
+                    if position == text.len() {
+                        stack.push(pc + 1);
+                    }
+                }
+                _ => threads.push((pc, begin)),
+            }
+        }
+    }
+
+    pub fn is_match(&self, text: &str, interrupt: &AtomicBool) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.find_at(&chars, 0, interrupt).is_some()
+    }
+
+    /// Returns all non-overlapping matches from left to right.
+    pub fn find_all(&self, text: &str, interrupt: &AtomicBool) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        self.matches(&chars, interrupt)
+            .into_iter()
+            .map(|(start, end)| chars[start..end].iter().collect())
+            .collect()
+    }
+
+    /// Replaces all non-overlapping matches with `replacement`.
+    pub fn replace_all(&self, text: &str, replacement: &str, interrupt: &AtomicBool) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut output = String::with_capacity(text.len());
+        let mut position = 0;
+        for (start, end) in self.matches(&chars, interrupt) {
+            output.extend(&chars[position..start]);
+            output.push_str(replacement);
+            position = end;
+        }
+        output.extend(&chars[position..]);
+        output
+    }
+
+    fn matches(&self, chars: &[char], interrupt: &AtomicBool) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut position = 0;
+        while position <= chars.len() {
+            let Some((start, end)) = self.find_at(chars, position, interrupt) else {
+                break;
+            };
+            matches.push((start, end));
+            // Step over empty matches so the search always makes progress
+            position = if end > start { end } else { end + 1 };
+        }
+        matches
+    }
+}
+
+struct PatternParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl PatternParser {
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.position += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars[self.position];
+        self.position += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '(' => {
+                let alternatives = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(self.error("missing ')'"));
+                }
+                self.position += 1;
+                Ok(Node::Group(alternatives))
+            }
+            '[' => self.class(),
+            '\\' => self.escape(),
+            '*' | '+' | '?' | '{' => Err(self.error("nothing to repeat")),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let Some(c) = self.peek() else {
+            return Err(self.error("trailing '\\'"));
+        };
+        self.position += 1;
+        let (ranges, negated) = match c {
+            'd' => (DIGIT, false),
+            'w' => (WORD, false),
+            's' => (SPACE, false),
+            'D' => (DIGIT, true),
+            'W' => (WORD, true),
+            'S' => (SPACE, true),
+            c => return Ok(Node::Char(escaped_char(c))),
+        };
+        Ok(Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("missing ']'"));
+            };
+            self.position += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let start = if c == '\\' {
+                let Some(escaped) = self.peek() else {
+                    return Err(self.error("missing ']'"));
+                };
+                self.position += 1;
+                match escaped {
+                    'd' => {
+                        ranges.extend_from_slice(DIGIT);
+                        continue;
+                    }
+                    'w' => {
+                        ranges.extend_from_slice(WORD);
+                        continue;
+                    }
+                    's' => {
+                        ranges.extend_from_slice(SPACE);
+                        continue;
+                    }
+                    escaped => escaped_char(escaped),
+                }
+            } else {
+                c
+            };
+            if self.peek() == Some('-')
+                && self.chars.get(self.position + 1).is_some_and(|c| *c != ']')
+            {
+                let end = self.chars[self.position + 1];
+                self.position += 2;
+                if end < start {
+                    return Err(self.error("invalid range in class"));
+                }
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn quantifier(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => return self.counted(node),
+            _ => return Ok(node),
+        };
+        self.position += 1;
+        Ok(self.repeat(node, min, max))
+    }
+
+    fn counted(&mut self, node: Node) -> Result<Node, String> {
+        self.position += 1;
+        let min = self.count().ok_or_else(|| self.error("expected a count"))?;
+        let max = if self.peek() == Some(',') {
+            self.position += 1;
+            self.count()
+        } else {
+            Some(min)
+        };
+        if self.peek() != Some('}') {
+            return Err(self.error("missing '}'"));
+        }
+        self.position += 1;
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("invalid repetition count"));
+        }
+        Ok(self.repeat(node, min, max))
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let digits: String = self.chars[start..self.position].iter().collect();
+        digits.parse().ok()
+    }
+
+    fn repeat(&mut self, node: Node, min: usize, max: Option<usize>) -> Node {
+        let lazy = self.peek() == Some('?');
+        if lazy {
+            self.position += 1;
+        }
+        Node::Repeat(Repeat {
+            node: Box::new(node),
+            min,
+            max,
+            lazy,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("Invalid regex at position {}: {}.", self.position, message)
+    }
+}
+
+fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        c => c,
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM_SIZE {
+            return Err("Invalid regex: pattern is too large.".to_string());
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    // Points the split or jump at `pc` to the next instruction to be emitted
+    fn patch(&mut self, pc: usize, second: bool) {
+        let target = self.program.len();
+        match &mut self.program[pc] {
+            Inst::Split(_, other) if second => *other = target,
+            Inst::Split(preferred, _) => *preferred = target,
+            Inst::Jump(to) => *to = target,
+            _ => unreachable!("only splits and jumps are patched"),
+        }
+    }
+
+    fn alternation(&mut self, alternatives: &[Vec<Node>]) -> Result<(), String> {
+        let mut jumps = Vec::new();
+        let (last, rest) = alternatives.split_last().expect("at least one alternative");
+        for sequence in rest {
+            let split = self.emit(Inst::Split(0, 0))?;
+            self.patch(split, false);
+            self.sequence(sequence)?;
+            jumps.push(self.emit(Inst::Jump(0))?);
+            self.patch(split, true);
+        }
+        self.sequence(last)?;
+        for jump in jumps {
+            self.patch(jump, false);
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, nodes: &[Node]) -> Result<(), String> {
+        nodes.iter().try_for_each(|node| self.node(node))
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Char(c) => self.emit(Inst::Char(*c)).map(drop),
+            Node::Any => self.emit(Inst::Any).map(drop),
+            Node::Class { ranges, negated } => self
+                .emit(Inst::Class {
+                    ranges: ranges.clone(),
+                    negated: *negated,
+                })
+                .map(drop),
+            Node::Start => self.emit(Inst::Start).map(drop),
+            Node::End => self.emit(Inst::End).map(drop),
+            Node::Group(alternatives) => self.alternation(alternatives),
+            Node::Repeat(repeat) => self.repeat(repeat),
+        }
+    }
+
+    fn repeat(&mut self, repeat: &Repeat) -> Result<(), String> {
+        // Repeating a node that matches nothing, like `()`, matches nothing
+        let start = self.program.len();
+        self.node(&repeat.node)?;
+        if self.program.len() == start {
+            return Ok(());
+        }
+        self.program.truncate(start);
+
+        for _ in 0..repeat.min {
+            self.node(&repeat.node)?;
+        }
+        // The preferred branch of a split goes through the node once more,
+        // unless the repetition is lazy
+        let (more, done) = if repeat.lazy {
+            (true, false)
+        } else {
+            (false, true)
+        };
+        match repeat.max {
+            None => {
+                let split = self.emit(Inst::Split(0, 0))?;
+                self.patch(split, more);
+                self.node(&repeat.node)?;
+                self.emit(Inst::Jump(split))?;
+                self.patch(split, done);
+            }
+            Some(max) => {
+                let mut splits = Vec::new();
+                for _ in repeat.min..max {
+                    let split = self.emit(Inst::Split(0, 0))?;
+                    self.patch(split, more);
+                    self.node(&repeat.node)?;
+                    splits.push(split);
+                }
+                for split in splits {
+                    self.patch(split, done);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_matching() {
+        let running = AtomicBool::new(false);
+        let regex = Regex::new(r"^(\w+)@([a-z]+\.)+[a-z]{2,3}$").unwrap();
+        assert!(regex.is_match("lox_user@mail.example.org", &running));
+        assert!(!regex.is_match("lox@example", &running));
+
+        let regex = Regex::new(r"\d+(\.\d+)?").unwrap();
+        assert_eq!(
+            regex.find_all("1 + 2.50 = 3.5", &running),
+            vec!["1", "2.50", "3.5"]
+        );
+
+        let regex = Regex::new("<.+?>").unwrap();
+        assert_eq!(regex.find_all("<a><b>", &running), vec!["<a>", "<b>"]);
+
+        let regex = Regex::new("[^aeiou ]").unwrap();
+        assert_eq!(
+            regex.replace_all("hello world", "_", &running),
+            "_e__o _o___"
+        );
+
+        let regex = Regex::new("x*").unwrap();
+        assert_eq!(regex.replace_all("abc", "-", &running), "-a-b-c-");
+    }
+
+    #[test]
+    fn test_regex_long_and_ambiguous() {
+        let running = AtomicBool::new(false);
+        let long = "a".repeat(131072);
+        assert!(Regex::new("^a*$").unwrap().is_match(&long, &running));
+        assert!(Regex::new("^(a|b)+?$").unwrap().is_match(&long, &running));
+
+        let text = format!("{}c", "a".repeat(100));
+        assert!(!Regex::new("(a*)*b").unwrap().is_match(&text, &running));
+        assert_eq!(
+            Regex::new("(a?){3}a{2,}?")
+                .unwrap()
+                .find_all("aaaaa", &running),
+            vec!["aaaaa"]
+        );
+
+        let interrupted = AtomicBool::new(true);
+        assert!(!Regex::new("a").unwrap().is_match("a", &interrupted));
+    }
+
+    #[test]
+    fn test_regex_errors() {
+        assert_eq!(
+            Regex::new("(ab").unwrap_err(),
+            "Invalid regex at position 3: missing ')'."
+        );
+        assert_eq!(
+            Regex::new("a)").unwrap_err(),
+            "Invalid regex at position 1: unmatched ')'."
+        );
+        assert_eq!(
+            Regex::new("*a").unwrap_err(),
+            "Invalid regex at position 1: nothing to repeat."
+        );
+        assert_eq!(
+            Regex::new("[a-").unwrap_err(),
+            "Invalid regex at position 3: missing ']'."
+        );
+        assert_eq!(
+            Regex::new("(a{1000}){1000}").unwrap_err(),
+            "Invalid regex: pattern is too large."
+        );
+    }
+}
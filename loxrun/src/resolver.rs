@@ -1,14 +1,39 @@
-use crate::expression::Expression;
-use crate::interpreter::Interpreter;
-use crate::stmt::{BlockStmt, Stmt};
-use liblox::tokens::Token;
-use std::collections::HashMap;
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::{Expression, Literal, LocalSlot, Variable};
+use crate::stmt::{BlockStmt, FunctionStmt, Stmt};
+use liblox::codes::ErrorCode;
+use liblox::diagnostics::{Diagnostic, SharedDiagnosticSink};
+use liblox::tokens::{LiteralTypes, Token};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::ops::Range;
 
 #[derive(Debug)]
 pub struct ResolverError {
+    pub code: ErrorCode,
     pub message: String,
+    pub line: i32,
+    // The offending name or keyword
+    pub lexeme: String,
+    // Byte range of the offending token in its source
+    pub span: Option<Range<usize>>,
 }
 
+/// Formats like jlox, e.g. `[line 1] Error at 'return': Can't return from
+/// top-level code.`.
+impl std::fmt::Display for ResolverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] Error at '{}': {}",
+            self.line, self.lexeme, self.message
+        )
+    }
+}
+
+impl std::error::Error for ResolverError {}
+
 #[derive(Debug, Clone, PartialEq)]
 enum FunctionType {
     None,
@@ -24,84 +49,182 @@ enum ClassType {
     Subclass,
 }
 
-pub struct Resolver<'a> {
-    interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+/// What is known about a local variable while its scope is resolved.
+struct Local {
+    defined: bool,
+    used: bool,
+    // Number of parameters, if it is a function or class declaration
+    arity: Option<usize>,
+    // Declared with `fun`, so reported as never used rather than never read
+    function: bool,
+    line: i32,
+    span: Option<Range<usize>>,
+    // Position in the scope in declaration order, which is the slot of the
+    // variable in its environment
+    index: usize,
+}
+
+impl Local {
+    // For `this` and `super`, which are alone in their scopes and never
+    // reported as unused
+    fn implicit() -> Self {
+        Local {
+            defined: true,
+            used: true,
+            arity: None,
+            function: false,
+            line: 0,
+            span: None,
+            index: 0,
+        }
+    }
+}
+
+pub struct Resolver {
+    scopes: Vec<HashMap<String, Local>>,
     current_function: FunctionType,
     current_class: ClassType,
+    // Receives the warnings, which are dropped without one
+    diagnostics: Option<SharedDiagnosticSink>,
+    // Whether to warn about locals shadowing those of enclosing scopes
+    warn_shadowing: bool,
+    // How often each name is declared or assigned in the source
+    bindings: HashMap<String, usize>,
+    // Number of parameters of the global functions and classes
+    global_arities: HashMap<String, usize>,
+    // Errors of the statements resolved so far
+    errors: Vec<ResolverError>,
 }
-impl<'a> Resolver<'a> {
-    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+impl Resolver {
+    pub fn new() -> Self {
         Resolver {
-            interpreter,
             scopes: Vec::new(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            diagnostics: None,
+            warn_shadowing: false,
+            bindings: HashMap::new(),
+            global_arities: HashMap::new(),
+            errors: Vec::new(),
         }
     }
 
-    pub fn resolve_stmts(&mut self, statements: &Vec<Stmt>) -> Result<(), ResolverError> {
-        let mut error = ResolverError {
-            message: "".to_string(),
-        };
-        for statement in statements {
-            let result = self.resolve_stmt(statement);
-            if let Err(e) = result {
-                error.message = error.message + "\n" + e.message.as_str();
-            }
+    /// Sets where warnings are reported.
+    pub fn set_diagnostic_sink(&mut self, sink: Option<SharedDiagnosticSink>) {
+        self.diagnostics = sink;
+    }
+
+    /// Enables warnings for local variables that shadow a local variable of
+    /// an enclosing scope, which are off by default.
+    pub fn set_warn_shadowing(&mut self, enabled: bool) {
+        self.warn_shadowing = enabled;
+    }
+
+    /// Resolves a whole source, returning all of its errors.
+    pub fn resolve_stmts(
+        &mut self,
+        ast: &Ast,
+        statements: &[StmtId],
+    ) -> Result<(), Vec<ResolverError>> {
+        self.bindings.clear();
+        count_bindings(ast, statements, &mut self.bindings);
+        self.resolve_statements(ast, statements);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(mem::take(&mut self.errors))
         }
-        if !error.message.is_empty() {
-            return Err(error);
+    }
+
+    // Resolves the statements one by one, so that an error in one of them
+    // doesn't hide those in the next ones
+    fn resolve_statements(&mut self, ast: &Ast, statements: &[StmtId]) {
+        for &statement in statements {
+            if let Err(err) = self.resolve_stmt(ast, statement) {
+                self.errors.push(err);
+            }
         }
-        Ok(())
     }
 
-    pub fn resolve_stmt(&mut self, statement: &Stmt) -> Result<(), ResolverError> {
-        match statement {
-            Stmt::Expression(expr) => self.resolve_expr(&expr.expression),
-            Stmt::Print(expr) => self.resolve_expr(&expr.expression),
+    pub fn resolve_stmt(&mut self, ast: &Ast, statement: StmtId) -> Result<(), ResolverError> {
+        match &ast[statement] {
+            Stmt::Expression(expr) => self.resolve_expr(ast, expr.expression),
+            Stmt::Print(expr) => self.resolve_expr(ast, expr.expression),
             Stmt::Var(expr) => {
                 self.declare(&expr.name)?;
-                if let Some(init) = &expr.initializer {
-                    self.resolve_expr(&init)?;
+                if let Some(init) = expr.initializer {
+                    self.resolve_expr(ast, init)?;
                 }
                 self.define(&expr.name)?;
                 Ok(())
             }
-            Stmt::Block(expr) => self.resolve_block(&expr),
+            Stmt::Block(expr) => self.resolve_block(ast, expr),
             Stmt::If(expr) => {
-                self.resolve_expr(&expr.condition)?;
-                self.resolve_stmt(&expr.then_branch)?;
-                if let Some(else_branch) = &expr.else_branch {
-                    self.resolve_stmt(else_branch.as_ref())?;
+                self.check_condition(ast, &expr.keyword, expr.condition);
+                self.resolve_expr(ast, expr.condition)?;
+                self.resolve_stmt(ast, expr.then_branch)?;
+                if let Some(else_branch) = expr.else_branch {
+                    self.resolve_stmt(ast, else_branch)?;
                 }
                 Ok(())
             }
             Stmt::While(expr) => {
-                self.resolve_expr(&expr.condition)?;
-                self.resolve_stmt(expr.body.as_ref())?;
+                // `while (true)` is how loops that end with a return are written
+                let infinite = matches!(
+                    &ast[expr.condition],
+                    Expression::Literal(Literal {
+                        value: LiteralTypes::Bool(true),
+                        ..
+                    })
+                );
+                if !infinite {
+                    self.check_condition(ast, &expr.keyword, expr.condition);
+                }
+                self.resolve_expr(ast, expr.condition)?;
+                self.resolve_stmt(ast, expr.body)?;
                 Ok(())
             }
+            Stmt::ForIn(stmt) => {
+                self.resolve_expr(ast, stmt.iterable)?;
+                self.begin_scope();
+                self.declare(&stmt.name)?;
+                self.define(&stmt.name)?;
+                let result = self.resolve_stmt(ast, stmt.body);
+                self.end_scope();
+                result
+            }
             Stmt::Return(expr) => {
                 if self.current_function == FunctionType::None {
-                    return self
-                        .make_resolve_error(&expr.keyword, "Can't return from top-level code.");
+                    return self.make_resolve_error(
+                        &expr.keyword,
+                        ErrorCode::ReturnFromTopLevel,
+                        "Can't return from top-level code.",
+                    );
                 }
-                if let Some(val) = &expr.value {
+                if let Some(val) = expr.value {
                     if self.current_function == FunctionType::Initializer {
                         return self.make_resolve_error(
                             &expr.keyword,
+                            ErrorCode::ReturnValueFromInitializer,
                             "Can't return a value from an initializer.",
                         );
                     }
-                    self.resolve_expr(val.as_ref())?;
+                    self.resolve_expr(ast, val)?;
                 }
                 Ok(())
             }
             Stmt::Function(expr) => {
                 self.declare(&expr.name)?;
                 self.define(&expr.name)?;
-                self.resolve_function(&expr.params, &expr.body, FunctionType::Function)?;
+                if let Some(local) = self
+                    .scopes
+                    .last_mut()
+                    .and_then(|scope| scope.get_mut(&expr.name.lexeme))
+                {
+                    local.function = true;
+                }
+                self.set_arity(&expr.name, expr.params.len());
+                self.resolve_function(ast, &expr.params, &expr.body, FunctionType::Function)?;
                 Ok(())
             }
             Stmt::Class(stmt) => {
@@ -110,34 +233,53 @@ impl<'a> Resolver<'a> {
 
                 self.declare(&stmt.name)?;
                 self.define(&stmt.name)?;
+                // Without an initializer the arity is that of the superclass's
+                let initializer = stmt.methods.iter().rev().find(|m| m.name.lexeme == "init");
+                match initializer {
+                    Some(init) => self.set_arity(&stmt.name, init.params.len()),
+                    None if stmt.superclass.is_none() => self.set_arity(&stmt.name, 0),
+                    None => {}
+                }
 
                 if stmt.superclass.is_some()
                     && stmt.name.lexeme == stmt.superclass.as_ref().unwrap().name.lexeme
                 {
                     return self.make_resolve_error(
                         &stmt.superclass.as_ref().unwrap().name,
+                        ErrorCode::InheritFromSelf,
                         "A class can't inherit from itself.",
                     );
                 }
 
+                let mut method_names = HashSet::new();
+                for method in stmt.methods.iter() {
+                    if !method_names.insert(&method.name.lexeme) {
+                        return self.make_resolve_error(
+                            &method.name,
+                            ErrorCode::DuplicateMethod,
+                            "Already a method with this name in this class.",
+                        );
+                    }
+                }
+
                 if let Some(superclass) = &stmt.superclass {
                     self.current_class = ClassType::Subclass;
-                    self.resolve_expr(&Expression::Variable(superclass.as_ref().clone()))?;
+                    self.resolve_variable(superclass)?;
                 }
 
                 if stmt.superclass.is_some() {
                     self.begin_scope();
-                    self.scopes.last_mut().unwrap().insert(
-                        "super".to_string(),
-                        true, // Mark the superclass as defined
-                    );
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .insert("super".to_string(), Local::implicit());
                 }
 
                 self.begin_scope();
-                self.scopes.last_mut().unwrap().insert(
-                    "this".to_string(),
-                    true, // Mark the class as defined
-                );
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert("this".to_string(), Local::implicit());
 
                 for method in stmt.methods.iter() {
                     let declaration = if method.name.lexeme == "init" {
@@ -145,7 +287,7 @@ impl<'a> Resolver<'a> {
                     } else {
                         FunctionType::Method
                     };
-                    self.resolve_function(&method.params, &method.body, declaration)?;
+                    self.resolve_function(ast, &method.params, &method.body, declaration)?;
                 }
                 self.end_scope();
 
@@ -158,108 +300,117 @@ impl<'a> Resolver<'a> {
         }
     }
 
-    fn resolve_block(&mut self, block: &BlockStmt) -> Result<(), ResolverError> {
+    fn resolve_block(&mut self, ast: &Ast, block: &BlockStmt) -> Result<(), ResolverError> {
+        if !block.declares_variables(ast) {
+            self.resolve_statements(ast, &block.statements);
+            return Ok(());
+        }
         self.begin_scope();
-        self.resolve_stmts(&block.statements)?;
+        self.resolve_statements(ast, &block.statements);
         self.end_scope();
         Ok(())
     }
 
     fn resolve_function(
         &mut self,
+        ast: &Ast,
         params: &Vec<Token>,
-        body: &Vec<Stmt>,
+        body: &[StmtId],
         function_type: FunctionType,
     ) -> Result<(), ResolverError> {
         let enclosing_function = self.current_function.clone();
         self.current_function = function_type;
         self.begin_scope();
         for param in params {
-            self.declare(param)?;
+            // A repeated parameter is recorded rather than returned, which
+            // would leave the function's scope open
+            if let Err(err) = self.declare(param) {
+                self.errors.push(err);
+                continue;
+            }
             self.define(param)?;
+            // Parameters are part of the signature, so needn't be read
+            self.mark_used(param);
         }
-        self.resolve_stmts(body)?;
+        self.resolve_statements(ast, body);
         self.end_scope();
         self.current_function = enclosing_function;
         Ok(())
     }
 
-    fn resolve_expr(&mut self, expr: &Expression) -> Result<(), ResolverError> {
-        match expr {
-            Expression::Variable(var) => {
-                if !self.scopes.is_empty()
-                    && self.scopes.last().unwrap().get(&var.name.lexeme) == Some(&false)
-                {
-                    return self.make_resolve_error(
-                        &var.name,
-                        "Can't read local variable in its own initializer.",
-                    );
-                }
-                self.resolve_local(expr, &var.name)?;
-                Ok(())
-            }
+    pub fn resolve_expr(&mut self, ast: &Ast, expr: ExprId) -> Result<(), ResolverError> {
+        match &ast[expr] {
+            Expression::Variable(var) => self.resolve_variable(var),
             Expression::Assign(assign) => {
-                self.resolve_expr(assign.value.as_ref())?;
-                self.resolve_local(expr, &assign.name)?;
+                self.resolve_expr(ast, assign.value)?;
+                self.resolve_local(&assign.resolved, &assign.name)?;
                 Ok(())
             }
             Expression::Binary(binary) => {
-                self.resolve_expr(binary.left.as_ref())?;
-                self.resolve_expr(binary.right.as_ref())?;
+                self.resolve_expr(ast, binary.left)?;
+                self.resolve_expr(ast, binary.right)?;
                 Ok(())
             }
             Expression::Call(call) => {
-                self.resolve_expr(call.callee.as_ref())?;
-                for arg in call.arguments.iter() {
-                    self.resolve_expr(arg)?;
+                self.resolve_expr(ast, call.callee)?;
+                if let Expression::Variable(callee) = &ast[call.callee] {
+                    self.check_arity(&callee.name, call.arguments.len());
+                }
+                for &arg in call.arguments.iter() {
+                    self.resolve_expr(ast, arg)?;
                 }
                 Ok(())
             }
             Expression::Get(get) => {
-                self.resolve_expr(get.object.as_ref())?;
+                self.resolve_expr(ast, get.object)?;
                 Ok(())
             }
             Expression::Grouping(group) => {
-                self.resolve_expr(&group.expression)?;
+                self.resolve_expr(ast, group.expression)?;
                 Ok(())
             }
             Expression::Literal(_) => Ok(()),
             Expression::Logical(logical) => {
-                self.resolve_expr(logical.left.as_ref())?;
-                self.resolve_expr(logical.right.as_ref())?;
+                self.resolve_expr(ast, logical.left)?;
+                self.resolve_expr(ast, logical.right)?;
                 Ok(())
             }
             Expression::Set(set) => {
-                self.resolve_expr(set.value.as_ref())?;
-                self.resolve_expr(set.object.as_ref())?;
+                self.resolve_expr(ast, set.value)?;
+                self.resolve_expr(ast, set.object)?;
                 Ok(())
             }
             Expression::Super(superclass) => {
                 if self.current_class == ClassType::None {
                     return self.make_resolve_error(
                         &superclass.keyword,
+                        ErrorCode::SuperOutsideClass,
                         "Can't use 'super' outside of a class.",
                     );
                 } else if self.current_class != ClassType::Subclass {
                     return self.make_resolve_error(
                         &superclass.keyword,
+                        ErrorCode::SuperWithoutSuperclass,
                         "Can't use 'super' in a class with no superclass.",
                     );
                 }
 
-                self.resolve_local(expr, &superclass.keyword)?;
+                self.resolve_local(&superclass.resolved, &superclass.keyword)?;
                 Ok(())
             }
             Expression::This(this) => {
                 if self.current_class == ClassType::None {
-                    return self
-                        .make_resolve_error(&this.keyword, "Can't use 'this' outside of a class.");
+                    return self.make_resolve_error(
+                        &this.keyword,
+                        ErrorCode::ThisOutsideClass,
+                        "Can't use 'this' outside of a class.",
+                    );
                 }
-                self.resolve_local(expr, &this.keyword)?;
+                self.resolve_local(&this.resolved, &this.keyword)?;
                 Ok(())
             }
             Expression::Unary(unary) => {
-                self.resolve_expr(unary.right.as_ref())?;
+                self.resolve_expr(ast, unary.right)?;
                 Ok(())
             }
         }
@@ -269,43 +420,402 @@ impl<'a> Resolver<'a> {
         self.scopes.push(HashMap::new());
     }
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        if self.diagnostics.is_none() {
+            return;
+        }
+        let mut unused: Vec<(&String, &Local)> = scope
+            .iter()
+            .filter(|(name, local)| !local.used && !name.starts_with('_'))
+            .collect();
+        unused.sort_by_key(|(_, local)| local.index);
+        for (name, local) in unused {
+            let message = if local.function {
+                format!("Local function '{}' is never used.", name)
+            } else {
+                format!("Local variable '{}' is never read.", name)
+            };
+            self.warn(
+                ErrorCode::UnusedVariable,
+                local.line,
+                local.span.clone(),
+                &message,
+            );
+        }
     }
     fn declare(&mut self, token: &Token) -> Result<(), ResolverError> {
         if let Some(scope) = self.scopes.last_mut() {
             if scope.contains_key(&token.lexeme) {
-                return self
-                    .make_resolve_error(token, "Already a variable with this name in this scope.");
+                return self.make_resolve_error(
+                    token,
+                    ErrorCode::DuplicateVariable,
+                    "Already a variable with this name in this scope.",
+                );
             }
-            scope.insert(token.lexeme.clone(), false);
+            let local = Local {
+                defined: false,
+                used: false,
+                arity: None,
+                function: false,
+                line: token.line,
+                span: token.span.clone(),
+                index: scope.len(),
+            };
+            scope.insert(token.lexeme.clone(), local);
+        }
+        if self.warn_shadowing {
+            self.check_shadowing(token);
         }
         Ok(())
     }
+    fn check_condition(&self, ast: &Ast, keyword: &Token, condition: ExprId) {
+        if is_constant(ast, condition) {
+            self.warn(
+                ErrorCode::ConstantCondition,
+                keyword.line,
+                keyword.span.clone(),
+                &format!("Condition of '{}' is always the same.", keyword.lexeme),
+            );
+        }
+    }
+    // Warns about a call that will fail for its number of arguments. It is
+    // left to the runtime to fail, as the call may never run.
+    fn check_arity(&self, callee: &Token, arguments: usize) {
+        match self.static_arity(callee) {
+            Some(arity) if arity != arguments => {
+                self.warn(
+                    ErrorCode::ArgumentCount,
+                    callee.line,
+                    callee.span.clone(),
+                    &format!(
+                        "'{}' expects {} arguments but is called with {}.",
+                        callee.lexeme, arity, arguments
+                    ),
+                );
+            }
+            _ => {}
+        }
+    }
+    fn check_shadowing(&self, token: &Token) {
+        let enclosing = self.scopes.len().saturating_sub(1);
+        let shadowed = self.scopes[..enclosing]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&token.lexeme));
+        if let Some(shadowed) = shadowed {
+            self.warn(
+                ErrorCode::ShadowedVariable,
+                token.line,
+                token.span.clone(),
+                &format!(
+                    "'{}' declared on line {} shadows the local variable declared on line {}.",
+                    token.lexeme, token.line, shadowed.line
+                ),
+            );
+        }
+    }
+    // Reports a warning about the source text of the span
+    fn warn(&self, code: ErrorCode, line: i32, span: Option<Range<usize>>, message: &str) {
+        if let Some(sink) = &self.diagnostics {
+            sink.borrow_mut().report(Diagnostic {
+                span,
+                ..Diagnostic::warning(code, line, message)
+            });
+        }
+    }
     fn define(&mut self, token: &Token) -> Result<(), ResolverError> {
         if let Some(scope) = self.scopes.last_mut() {
-            if let Some(defined) = scope.get_mut(&token.lexeme) {
-                *defined = true;
+            if let Some(local) = scope.get_mut(&token.lexeme) {
+                local.defined = true;
             } else {
-                return self.make_resolve_error(token, "Variable not declared in this scope.");
+                return self.make_resolve_error(
+                    token,
+                    ErrorCode::UndeclaredVariable,
+                    "Variable not declared in this scope.",
+                );
             }
         }
         Ok(())
     }
-    fn resolve_local(&mut self, expr: &Expression, name: &Token) -> Result<(), ResolverError> {
+    fn resolve_variable(&mut self, var: &Variable) -> Result<(), ResolverError> {
+        if !self.scopes.is_empty()
+            && self
+                .scopes
+                .last()
+                .unwrap()
+                .get(&var.name.lexeme)
+                .is_some_and(|local| !local.defined)
+        {
+            return self.make_resolve_error(
+                &var.name,
+                ErrorCode::LocalInOwnInitializer,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+        self.resolve_local(&var.resolved, &var.name)?;
+        self.mark_used(&var.name);
+        Ok(())
+    }
+    fn resolve_local(
+        &mut self,
+        resolved: &Cell<Option<LocalSlot>>,
+        name: &Token,
+    ) -> Result<(), ResolverError> {
         for (i, scope) in self.scopes.iter().enumerate().rev() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr, self.scopes.len() - 1 - i);
+            if let Some(local) = scope.get(&name.lexeme) {
+                resolved.set(Some(LocalSlot {
+                    depth: self.scopes.len() - 1 - i,
+                    slot: local.index,
+                }));
                 break;
             }
         }
         Ok(())
     }
-    fn make_resolve_error(&mut self, token: &Token, message: &str) -> Result<(), ResolverError> {
+    fn set_arity(&mut self, name: &Token, arity: usize) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                if let Some(local) = scope.get_mut(&name.lexeme) {
+                    local.arity = Some(arity);
+                }
+            }
+            None => {
+                self.global_arities.insert(name.lexeme.clone(), arity);
+            }
+        }
+    }
+
+    // Arity of the function or class the name refers to, if it can't refer
+    // to anything else while the source runs
+    fn static_arity(&self, name: &Token) -> Option<usize> {
+        if self.bindings.get(&name.lexeme) != Some(&1) {
+            return None;
+        }
+        match self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+        {
+            Some(local) => local.arity,
+            None => self.global_arities.get(&name.lexeme).copied(),
+        }
+    }
+
+    // Records that the innermost variable of the name is read
+    fn mark_used(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.used = true;
+        }
+    }
+    fn make_resolve_error(
+        &mut self,
+        token: &Token,
+        code: ErrorCode,
+        message: &str,
+    ) -> Result<(), ResolverError> {
         Err(ResolverError {
-            message: format!(
-                "[line {}] Error at '{}': {}",
-                token.line, token.lexeme, message
-            ),
+            code,
+            message: message.to_string(),
+            line: token.line,
+            lexeme: token.lexeme.clone(),
+            span: token.span.clone(),
         })
     }
 }
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Whether the value of the expression is known without running it
+fn is_constant(ast: &Ast, expr: ExprId) -> bool {
+    match &ast[expr] {
+        Expression::Literal(_) => true,
+        Expression::Grouping(group) => is_constant(ast, group.expression),
+        Expression::Unary(unary) => is_constant(ast, unary.right),
+        Expression::Binary(binary) => {
+            is_constant(ast, binary.left) && is_constant(ast, binary.right)
+        }
+        Expression::Logical(logical) => {
+            is_constant(ast, logical.left) && is_constant(ast, logical.right)
+        }
+        _ => false,
+    }
+}
+
+// Counts the declarations and assignments of each name. Calls are only
+// checked against names bound once, which can't be rebound at runtime.
+fn count_bindings(ast: &Ast, statements: &[StmtId], counts: &mut HashMap<String, usize>) {
+    for &statement in statements {
+        match &ast[statement] {
+            Stmt::Expression(stmt) => count_expr_bindings(ast, stmt.expression, counts),
+            Stmt::Print(stmt) => count_expr_bindings(ast, stmt.expression, counts),
+            Stmt::Var(stmt) => {
+                bind(&stmt.name, counts);
+                if let Some(init) = stmt.initializer {
+                    count_expr_bindings(ast, init, counts);
+                }
+            }
+            Stmt::Block(block) => count_bindings(ast, &block.statements, counts),
+            Stmt::If(stmt) => {
+                count_expr_bindings(ast, stmt.condition, counts);
+                count_bindings(ast, &[stmt.then_branch], counts);
+                if let Some(else_branch) = stmt.else_branch {
+                    count_bindings(ast, &[else_branch], counts);
+                }
+            }
+            Stmt::While(stmt) => {
+                count_expr_bindings(ast, stmt.condition, counts);
+                count_bindings(ast, &[stmt.body], counts);
+            }
+            Stmt::ForIn(stmt) => {
+                bind(&stmt.name, counts);
+                count_expr_bindings(ast, stmt.iterable, counts);
+                count_bindings(ast, &[stmt.body], counts);
+            }
+            Stmt::Return(stmt) => {
+                if let Some(value) = stmt.value {
+                    count_expr_bindings(ast, value, counts);
+                }
+            }
+            Stmt::Function(function) => {
+                bind(&function.name, counts);
+                count_function_bindings(ast, function, counts);
+            }
+            Stmt::Class(class) => {
+                bind(&class.name, counts);
+                for method in &class.methods {
+                    count_function_bindings(ast, method, counts);
+                }
+            }
+        }
+    }
+}
+
+fn count_function_bindings(
+    ast: &Ast,
+    function: &FunctionStmt,
+    counts: &mut HashMap<String, usize>,
+) {
+    for param in &function.params {
+        bind(param, counts);
+    }
+    count_bindings(ast, &function.body, counts);
+}
+
+fn count_expr_bindings(ast: &Ast, expr: ExprId, counts: &mut HashMap<String, usize>) {
+    match &ast[expr] {
+        Expression::Assign(assign) => {
+            bind(&assign.name, counts);
+            count_expr_bindings(ast, assign.value, counts);
+        }
+        Expression::Binary(binary) => {
+            count_expr_bindings(ast, binary.left, counts);
+            count_expr_bindings(ast, binary.right, counts);
+        }
+        Expression::Logical(logical) => {
+            count_expr_bindings(ast, logical.left, counts);
+            count_expr_bindings(ast, logical.right, counts);
+        }
+        Expression::Call(call) => {
+            count_expr_bindings(ast, call.callee, counts);
+            for &arg in &call.arguments {
+                count_expr_bindings(ast, arg, counts);
+            }
+        }
+        Expression::Get(get) => count_expr_bindings(ast, get.object, counts),
+        Expression::Set(set) => {
+            count_expr_bindings(ast, set.object, counts);
+            count_expr_bindings(ast, set.value, counts);
+        }
+        Expression::Grouping(group) => count_expr_bindings(ast, group.expression, counts),
+        Expression::Unary(unary) => count_expr_bindings(ast, unary.right, counts),
+        Expression::Literal(_)
+        | Expression::Variable(_)
+        | Expression::Super(_)
+        | Expression::This(_) => {}
+    }
+}
+
+fn bind(name: &Token, counts: &mut HashMap<String, usize>) {
+    *counts.entry(name.lexeme.clone()).or_insert(0) += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use liblox::scanner::Scanner;
+
+    fn parse(source: &str) -> (Ast, Vec<StmtId>) {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut parser = Parser::new(scanner.take_tokens());
+        let statements = parser.parse().unwrap();
+        (parser.into_ast(), statements)
+    }
+
+    #[test]
+    fn test_resolutions() {
+        let (ast, statements) = parse("var a = 1;\n{ var b = a; print b; }");
+        let mut resolver = Resolver::new();
+        assert!(resolver.resolve_stmts(&ast, &statements).is_ok());
+
+        let Stmt::Block(block) = &ast[statements[1]] else {
+            panic!("expected a block");
+        };
+        let resolved = |statement: StmtId| match &ast[statement] {
+            Stmt::Var(stmt) => ast[stmt.initializer.unwrap()].resolution().unwrap().get(),
+            Stmt::Print(stmt) => ast[stmt.expression].resolution().unwrap().get(),
+            _ => panic!("expected a variable or print statement"),
+        };
+        // Only `b` is local, `a` is global and left unresolved
+        assert_eq!(resolved(block.statements[0]), None);
+        assert_eq!(
+            resolved(block.statements[1]),
+            Some(LocalSlot { depth: 0, slot: 0 })
+        );
+    }
+
+    #[test]
+    fn test_resolve_slots() {
+        let source = "fun f(x, y) {\n  var z = x;\n  fun g() { return y + z; }\n  return g;\n}";
+        let (ast, statements) = parse(source);
+        assert!(Resolver::new().resolve_stmts(&ast, &statements).is_ok());
+
+        let slot = |expression: ExprId| {
+            let local = ast[expression].resolution().unwrap().get().unwrap();
+            (local.depth, local.slot)
+        };
+        let returned = |statement: StmtId| match &ast[statement] {
+            Stmt::Return(stmt) => stmt.value.unwrap(),
+            _ => panic!("expected a return statement"),
+        };
+        let Stmt::Function(f) = &ast[statements[0]] else {
+            panic!("expected a function");
+        };
+        // x, then g in f, and y and z from g
+        let Stmt::Var(z) = &ast[f.body[0]] else {
+            panic!("expected a variable");
+        };
+        assert_eq!(slot(z.initializer.unwrap()), (0, 0));
+        assert_eq!(slot(returned(f.body[2])), (0, 3));
+        let Stmt::Function(g) = &ast[f.body[1]] else {
+            panic!("expected a function");
+        };
+        let Expression::Binary(sum) = &ast[returned(g.body[0])] else {
+            panic!("expected a sum");
+        };
+        assert_eq!(slot(sum.left), (1, 1));
+        assert_eq!(slot(sum.right), (1, 2));
+    }
+}
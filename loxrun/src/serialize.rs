@@ -0,0 +1,204 @@
+//! Serde support for `Value`, enabled with the `serde` feature.
+//!
+//! Values map onto the serde data model like they map onto JSON in the
+//! jsonStringify() and jsonParse() builtins: arrays are sequences and
+//! instances are maps of their fields. Functions and classes can't be
+//! serialized, and maps are deserialized as instances of a class named
+//! `Object`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::class::{Instance, LoxClass};
+use crate::interpreter::Value;
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let parents = RefCell::new(Vec::new());
+        SerializeValue {
+            value: self,
+            parents: &parents,
+        }
+        .serialize(serializer)
+    }
+}
+
+// `parents` holds the arrays and instances currently being serialized, to
+// detect values that contain themselves.
+struct SerializeValue<'a> {
+    value: &'a Value,
+    parents: &'a RefCell<Vec<usize>>,
+}
+
+impl SerializeValue<'_> {
+    fn nested<'b>(&'b self, value: &'b Value) -> SerializeValue<'b> {
+        SerializeValue {
+            value,
+            parents: self.parents,
+        }
+    }
+
+    fn enter<E: ser::Error>(&self, pointer: usize) -> Result<(), E> {
+        let mut parents = self.parents.borrow_mut();
+        if parents.contains(&pointer) {
+            return Err(E::custom("Can't serialize a value that contains itself."));
+        }
+        parents.push(pointer);
+        Ok(())
+    }
+}
+
+impl Serialize for SerializeValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => serializer.serialize_f64(*n),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(array) => {
+                self.enter(Rc::as_ptr(array) as usize)?;
+                let array = array.borrow();
+                let mut seq = serializer.serialize_seq(Some(array.len()))?;
+                for element in array.iter() {
+                    seq.serialize_element(&self.nested(element))?;
+                }
+                self.parents.borrow_mut().pop();
+                seq.end()
+            }
+            Value::Instance(instance) => {
+                self.enter(Rc::as_ptr(instance) as usize)?;
+                let instance = instance.borrow();
+                let mut keys: Vec<&String> = instance.fields.keys().collect();
+                keys.sort();
+                let mut map = serializer.serialize_map(Some(keys.len()))?;
+                for key in keys {
+                    map.serialize_entry(key, &self.nested(&instance.fields[key]))?;
+                }
+                self.parents.borrow_mut().pop();
+                map.end()
+            }
+            Value::Callable(callable) => {
+                Err(ser::Error::custom(format!("Can't serialize {}.", callable)))
+            }
+            Value::Native(handle) => {
+                Err(ser::Error::custom(format!("Can't serialize {}.", handle)))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let object_class = Rc::new(RefCell::new(LoxClass::new(
+            "Object".to_string(),
+            None,
+            HashMap::new(),
+        )));
+        ValueSeed {
+            object_class: &object_class,
+        }
+        .deserialize(deserializer)
+    }
+}
+
+// Carries the class shared by all the instances of one deserialized value.
+#[derive(Clone, Copy)]
+struct ValueSeed<'a> {
+    object_class: &'a Rc<RefCell<LoxClass>>,
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a Lox value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::Number(value as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::Number(value as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::Number(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(Rc::new(value.to_string())))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut elements = Vec::new();
+        while let Some(element) = seq.next_element_seed(self)? {
+            elements.push(element);
+        }
+        Ok(Value::Array(Rc::new(RefCell::new(elements))))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut instance = Instance::new(Rc::clone(self.object_class));
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map.next_value_seed(self)?;
+            instance.set(key, value);
+        }
+        Ok(Value::Instance(Rc::new(RefCell::new(instance))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde_round_trip() {
+        let text = r#"{"name":"Lox","tags":["a",null,true],"version":1.5}"#;
+        let value: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(), text);
+        assert_eq!(value.type_name(), "Object");
+    }
+
+    #[test]
+    fn test_serialize_errors() {
+        let array = Rc::new(RefCell::new(Vec::new()));
+        array.borrow_mut().push(Value::Array(Rc::clone(&array)));
+        let error = serde_json::to_string(&Value::Array(Rc::clone(&array))).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't serialize a value that contains itself."
+        );
+        array.borrow_mut().clear();
+    }
+}
@@ -1,69 +1,119 @@
-use crate::{expression::Expression, expression::Variable};
-use liblox::tokens::Token;
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Stmt {
-    Class(ClassStmt),
-    Expression(ExpressionStmt),
-    Function(FunctionStmt),
-    If(IfStmt),
-    Print(PrintStmt),
-    Block(BlockStmt),
-    Return(ReturnStmt),
-    Var(VarStmt),
-    While(WhileStmt),
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ClassStmt {
-    pub name: Token,
-    pub superclass: Option<Box<Variable>>,
-    pub methods: Vec<FunctionStmt>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ExpressionStmt {
-    pub expression: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct FunctionStmt {
-    pub name: Token,
-    pub params: Vec<Token>,
-    pub body: Vec<Stmt>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct IfStmt {
-    pub condition: Box<Expression>,
-    pub then_branch: Box<Stmt>,
-    pub else_branch: Option<Box<Stmt>>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct PrintStmt {
-    pub expression: Box<Expression>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct BlockStmt {
-    pub statements: Vec<Stmt>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct ReturnStmt {
-    pub keyword: Token,
-    pub value: Option<Box<Expression>>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct VarStmt {
-    pub name: Token,
-    pub initializer: Option<Box<Expression>>,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct WhileStmt {
-    pub condition: Box<Expression>,
-    pub body: Box<Stmt>,
-}
+use crate::ast::{Ast, ExprId, StmtId};
+use crate::expression::Variable;
+use liblox::tokens::Token;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Class(ClassStmt),
+    Expression(ExpressionStmt),
+    Function(Rc<FunctionStmt>),
+    If(IfStmt),
+    Print(PrintStmt),
+    Block(BlockStmt),
+    Return(ReturnStmt),
+    Var(VarStmt),
+    While(WhileStmt),
+    ForIn(ForInStmt),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassStmt {
+    pub name: Token,
+    pub superclass: Option<Box<Variable>>,
+    pub methods: Vec<Rc<FunctionStmt>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionStmt {
+    pub expression: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionStmt {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<StmtId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfStmt {
+    pub keyword: Token,
+    pub condition: ExprId,
+    pub then_branch: StmtId,
+    pub else_branch: Option<StmtId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintStmt {
+    pub keyword: Token,
+    pub expression: ExprId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStmt {
+    pub statements: Vec<StmtId>,
+}
+
+impl BlockStmt {
+    /// Whether the block needs a scope of its own, which is only the case if
+    /// it declares something.
+    pub fn declares_variables(&self, ast: &Ast) -> bool {
+        self.statements.iter().any(|&id| ast[id].is_declaration())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnStmt {
+    pub keyword: Token,
+    pub value: Option<ExprId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarStmt {
+    pub name: Token,
+    pub initializer: Option<ExprId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhileStmt {
+    /// The `while`, or the `for` of a desugared for loop.
+    pub keyword: Token,
+    pub condition: ExprId,
+    pub body: StmtId,
+}
+
+/// `for (var name in iterable) body`, driven by the iterator protocol:
+/// `iterable.iter()` returns an object with `done()` and `next()` methods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForInStmt {
+    pub name: Token,
+    pub iterable: ExprId,
+    pub body: StmtId,
+}
+
+impl Stmt {
+    /// Whether the statement declares a name in the enclosing scope.
+    pub fn is_declaration(&self) -> bool {
+        matches!(self, Stmt::Var(_) | Stmt::Function(_) | Stmt::Class(_))
+    }
+
+    /// Short description of the statement for diagnostics.
+    pub fn describe(&self) -> String {
+        match self {
+            Stmt::Class(class) => format!("class declaration '{}'", class.name.lexeme),
+            Stmt::Expression(_) => "expression statement".to_string(),
+            Stmt::Function(function) => format!("function declaration '{}'", function.name.lexeme),
+            Stmt::If(_) => "if statement".to_string(),
+            Stmt::Print(_) => "print statement".to_string(),
+            Stmt::Block(_) => "block".to_string(),
+            Stmt::Return(ret) => format!("return statement at line {}", ret.keyword.line),
+            Stmt::Var(var) => format!(
+                "declaration of variable '{}' at line {}",
+                var.name.lexeme, var.name.line
+            ),
+            Stmt::While(_) => "while loop".to_string(),
+            Stmt::ForIn(for_in) => format!("for-in loop over '{}'", for_in.name.lexeme),
+        }
+    }
+}
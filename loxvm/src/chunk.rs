@@ -19,6 +19,51 @@ pub enum OpCode {
     Return = 13,
 }
 
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0 => Ok(OpCode::Constant),
+            1 => Ok(OpCode::Nil),
+            2 => Ok(OpCode::True),
+            3 => Ok(OpCode::False),
+            4 => Ok(OpCode::Equal),
+            5 => Ok(OpCode::Greater),
+            6 => Ok(OpCode::Less),
+            7 => Ok(OpCode::Add),
+            8 => Ok(OpCode::Subtract),
+            9 => Ok(OpCode::Multiply),
+            10 => Ok(OpCode::Divide),
+            11 => Ok(OpCode::Not),
+            12 => Ok(OpCode::Negate),
+            13 => Ok(OpCode::Return),
+            _ => Err(byte),
+        }
+    }
+}
+
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Constant => "OP_CONSTANT",
+            OpCode::Nil => "OP_NIL",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::Less => "OP_LESS",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::Return => "OP_RETURN",
+        }
+    }
+}
+
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: Vec<f64>,
@@ -71,26 +116,15 @@ impl Chunk {
             write!(output, "{:04} ", self.lines[offset]).unwrap();
         }
 
-        let instruction = unsafe { ::std::mem::transmute(self.code[offset]) };
-        match instruction {
-            OpCode::Constant => self.disassemble_constant_instruction(output, offset),
-            OpCode::Nil => self.disassemble_simple_instruction(output, "OP_NIL", offset),
-            OpCode::True => self.disassemble_simple_instruction(output, "OP_TRUE", offset),
-            OpCode::False => self.disassemble_simple_instruction(output, "OP_FALSE", offset),
-            OpCode::Equal => self.disassemble_simple_instruction(output, "OP_EQUAL", offset),
-            OpCode::Greater => self.disassemble_simple_instruction(output, "OP_GREATER", offset),
-            OpCode::Less => self.disassemble_simple_instruction(output, "OP_LESS", offset),
-            OpCode::Add => self.disassemble_simple_instruction(output, "OP_ADD", offset),
-            OpCode::Subtract => self.disassemble_simple_instruction(output, "OP_SUBTRACT", offset),
-            OpCode::Multiply => self.disassemble_simple_instruction(output, "OP_MULTIPLY", offset),
-            OpCode::Divide => self.disassemble_simple_instruction(output, "OP_DIVIDE", offset),
-            OpCode::Not => self.disassemble_simple_instruction(output, "OP_NOT", offset),
-            OpCode::Negate => self.disassemble_simple_instruction(output, "OP_NEGATE", offset),
-            OpCode::Return => self.disassemble_simple_instruction(output, "OP_RETURN", offset),
-            //_ => {
-            //    writeln!(output, "Unknown opcode {}", instruction as u8).unwrap();
-            //    offset + 1
-            //}
+        match OpCode::try_from(self.code[offset]) {
+            Ok(OpCode::Constant) => self.disassemble_constant_instruction(output, offset),
+            Ok(instruction) => {
+                self.disassemble_simple_instruction(output, instruction.name(), offset)
+            }
+            Err(byte) => {
+                writeln!(output, "Unknown opcode {}", byte).unwrap();
+                offset + 1
+            }
         }
     }
 
@@ -154,4 +188,20 @@ mod tests {
         let result = String::from_utf8_lossy(&output.borrow()).to_string();
         assert_eq!(result, "== test chunk ==\n0000 0001 OP_CONSTANT 0000 1.2\n");
     }
+    #[test]
+    fn test_disassemble_unknown_opcode() {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut output_writer = Box::new(VecWriter(Rc::clone(&output)));
+
+        let mut chunk = Chunk::new();
+        chunk.write(200, 1);
+        chunk.write_op_code(OpCode::Return, 1);
+        chunk.disassemble(&mut output_writer, "test chunk");
+
+        let result = String::from_utf8_lossy(&output.borrow()).to_string();
+        assert_eq!(
+            result,
+            "== test chunk ==\n0000 0001 Unknown opcode 200\n0001    | OP_RETURN\n"
+        );
+    }
 }
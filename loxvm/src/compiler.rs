@@ -1,3 +1,5 @@
+use liblox::diagnostics::stderr_reporter;
+use liblox::log;
 use liblox::scanner::Scanner;
 use liblox::tokens::TokenType;
 
@@ -5,8 +7,12 @@ use crate::parser::Parser;
 
 pub fn compile(source: String) -> Result<crate::chunk::Chunk, String> {
     let mut scanner = Scanner::new(source);
-    let mut parser = Parser::new(scanner.scan_tokens().to_vec());
-    parser.expression();
-    parser.emit_return();
+    scanner.set_error_reporter(Some(stderr_reporter()));
+    let tokens = log::timed("scan", || scanner.take_tokens());
+    let mut parser = Parser::new(tokens);
+    log::timed("compile", || {
+        parser.expression();
+        parser.emit_return();
+    });
     Ok(parser.chunk)
 }
@@ -5,67 +5,184 @@ mod testing;
 mod virtualmachine;
 
 use std::env;
-use std::io::Write;
+use std::path::PathBuf;
 use std::process;
+use std::time::Instant;
 
-use virtualmachine::VirtualMachine;
+use liblox::crash;
+use liblox::echo;
+use liblox::log::{self, Verbosity};
+use liblox::memory::{self, CountingAllocator};
+use liblox::repl::{self, LineEditor};
+use virtualmachine::{Value, VirtualMachine};
 
 // Define exit codes constants
 const EXIT_CODE_OK: i32 = 0;
 const EXIT_CODE_CMD_LINE_ERROR: i32 = 64;
 const EXIT_CODE_DATA_ERROR: i32 = 65;
 const EXIT_CODE_SCRIPT_ERROR: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    crash::install_panic_hook();
 
-    if args.len() > 2 {
-        println!("Usage: loxvm [script]");
-        process::exit(EXIT_CODE_CMD_LINE_ERROR);
-    } else if args.len() == 2 {
-        run_file(&args[1]);
-    } else {
-        run_prompt();
+    let mut script = None;
+    let mut code = None;
+    let mut history_file = repl::default_history_file();
+    let mut colors = true;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-q" | "--quiet" => log::set_verbosity(Verbosity::Quiet),
+            "-v" | "--verbose" => log::set_verbosity(Verbosity::Verbose),
+            "--timings" => log::set_timings(true),
+            "--history" => match args.next() {
+                Some(path) => history_file = Some(PathBuf::from(path)),
+                None => usage(),
+            },
+            "--no-history" => history_file = None,
+            "--no-color" => colors = false,
+            "-e" | "--eval" => match args.next() {
+                Some(source) if code.is_none() => code = Some(source),
+                _ => usage(),
+            },
+            _ if script.is_none() && !arg.starts_with('-') => script = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    match (script, code) {
+        (Some(script), None) => run_file(&script),
+        (None, Some(code)) => run_source(code),
+        (None, None) => run_prompt(history_file, colors),
+        (Some(_), Some(_)) => usage(),
+    }
+    report_statistics();
+}
+
+fn usage() -> ! {
+    println!(
+        "Usage: loxvm [--quiet | --verbose] [--timings] \
+         [--history <file> | --no-history] \
+         [--no-color] [-e <code> | script]"
+    );
+    process::exit(EXIT_CODE_CMD_LINE_ERROR);
+}
+
+fn report_statistics() {
+    if let Some(report) = log::timings_report() {
+        eprint!("{}", report);
     }
+    log::verbose(format!("memory: {}", memory::stats()));
 }
 
 fn run_file(filename: &str) {
     match std::fs::read_to_string(filename) {
-        Ok(contents) => {
-            let mut output = std::io::stdout();
-            let mut vm = VirtualMachine::new();
-            if let Err(err) = vm.interpret(&mut output, contents) {
-                eprintln!("Runtime error: {}", err);
-                process::exit(EXIT_CODE_SCRIPT_ERROR);
-            }
-        }
+        Ok(contents) => run_source(contents),
         Err(err) => {
             eprintln!("Error reading file {}: {}", filename, err);
-            process::exit(74);
+            process::exit(EX_IOERR);
         }
     }
 }
 
-fn run_prompt() {
-    let stdin = std::io::stdin();
-    let mut stdout = std::io::stdout();
-    let mut input = String::new();
-
+fn run_source(source: String) {
     let mut vm = VirtualMachine::new();
-    loop {
-        print!("> ");
-        stdout.flush().expect("Failed to flush stdout");
-        input.clear();
-        if stdin.read_line(&mut input).is_err() {
-            eprintln!("Error reading input");
-            continue;
+    let error_code = interpret_guarded(&mut vm, source, echo::Mode::Script);
+    if error_code != EXIT_CODE_OK {
+        report_statistics();
+        process::exit(error_code);
+    }
+}
+
+fn run_prompt(history_file: Option<PathBuf>, colors: bool) {
+    let mut editor = match LineEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Error initializing line editor: {}", err);
+            process::exit(EX_IOERR);
         }
+    };
+
+    if let Some(path) = history_file
+        && let Err(err) = editor.set_history_file(path.clone())
+    {
+        eprintln!("Error reading history {}: {}", path.display(), err);
+    }
+
+    if !colors {
+        editor.set_highlighting(false);
+    }
+
+    log::info("Lox VM REPL. Enter an empty line to exit.");
+    let prompt = if log::enabled(Verbosity::Normal) {
+        "> "
+    } else {
+        ""
+    };
+
+    let mut vm = VirtualMachine::new();
+    while let Some(input) = editor.read_line(prompt) {
         if input.trim().is_empty() {
             break;
         }
-        let mut output = std::io::stdout();
-        if let Err(err) = vm.interpret(&mut output, input.trim().to_string()) {
+        match repl::parse_command(&input) {
+            Some(("history", "")) => {
+                for (number, line) in editor.history().enumerate() {
+                    println!("{:>5}  {}", number + 1, line);
+                }
+            }
+            Some(("time", "")) => eprintln!("Usage: :time <code>"),
+            Some(("type", "")) => eprintln!("Usage: :type <expression>"),
+            Some(("type", expression)) => {
+                if let Some(value) = evaluate_guarded(&mut vm, expression.to_string()) {
+                    println!("{}", value.type_name());
+                }
+            }
+            Some(("time", code)) => {
+                let start = Instant::now();
+                interpret_guarded(&mut vm, code.to_string(), echo::Mode::Repl);
+                eprintln!("Time: {}", log::format_duration(start.elapsed()));
+            }
+            Some(_) => eprintln!("Unknown command: {}", input.trim()),
+            None => {
+                interpret_guarded(&mut vm, input.trim().to_string(), echo::Mode::Repl);
+            }
+        }
+    }
+}
+
+/// Interprets the source, reporting a panic inside the VM as an internal
+/// error together with the instruction that was executing. The value is
+/// printed as `echo::should_echo` decides for the mode.
+fn interpret_guarded(vm: &mut VirtualMachine, source: String, mode: echo::Mode) -> i32 {
+    let echo = echo::should_echo(&source, mode);
+    match evaluate_guarded(vm, source) {
+        Some(value) => {
+            if echo {
+                println!("{}", value);
+            }
+            EXIT_CODE_OK
+        }
+        None => EXIT_CODE_SCRIPT_ERROR,
+    }
+}
+
+// Interprets the source like interpret_guarded, returning its value unless
+// it failed
+fn evaluate_guarded(vm: &mut VirtualMachine, source: String) -> Option<Value> {
+    match crash::catch_internal_error(|| vm.interpret(source)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(err)) => {
             eprintln!("Runtime error: {}", err);
+            None
+        }
+        Err(error) => {
+            eprintln!("{}", error.with_context(vm.crash_context()));
+            None
         }
     }
 }
@@ -1,4 +1,4 @@
-use liblox::tokens::{Token, TokenType};
+use liblox::tokens::{LiteralTypes, Token, TokenType};
 
 use crate::chunk::Chunk;
 use crate::chunk::OpCode;
@@ -50,7 +50,9 @@ impl Parser {
     }
 
     fn number(&mut self) {
-        let value: f64 = self.previous().lexeme.parse().unwrap();
+        let LiteralTypes::Number(value) = self.previous().literal else {
+            unreachable!("number tokens hold their value");
+        };
         self.emit_constant(value);
     }
 
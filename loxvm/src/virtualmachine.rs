@@ -1,4 +1,6 @@
-use std::io::Write;
+use std::io::{self, Write};
+
+use liblox::log;
 
 use crate::chunk::Chunk;
 use crate::chunk::OpCode;
@@ -8,72 +10,102 @@ pub struct VirtualMachine {
     chunk: Chunk,
     ip: usize,
     stack: Vec<Value>,
+    // Offset of the instruction being executed, kept for crash reports
+    instruction_start: Option<usize>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Number(f64),
     Bool(bool),
     Nil,
 }
 
-pub enum InterpretResult {
-    Ok,
-    CompileError,
-    RuntimeError,
-}
-
 impl VirtualMachine {
     pub fn new() -> Self {
         Self {
             chunk: Chunk::new(),
             ip: 0,
             stack: Vec::new(),
+            instruction_start: None,
         }
     }
 
-    pub fn interpret<T: Write + ?Sized>(
-        &mut self,
-        output: &mut T,
-        source: String,
-    ) -> Result<InterpretResult, String> {
+    /// Describes what the VM was doing, for reporting an internal error.
+    pub fn crash_context(&self) -> String {
+        match self.instruction_start {
+            Some(offset) => {
+                let name = match self
+                    .chunk
+                    .code
+                    .get(offset)
+                    .map(|byte| OpCode::try_from(*byte))
+                {
+                    Some(Ok(op_code)) => op_code.name().to_string(),
+                    _ => "an unknown opcode".to_string(),
+                };
+                format!(
+                    "executing {} at offset {:04} (line {})",
+                    name, offset, self.chunk.lines[offset]
+                )
+            }
+            None => "compiling the input".to_string(),
+        }
+    }
+
+    /// Compiles and runs the source, returning the value it evaluates to.
+    /// Whether that value is shown is up to the caller, see
+    /// `liblox::echo::should_echo`.
+    ///
+    /// Each call compiles a fresh chunk and starts with an empty stack. The
+    /// VM has no variables yet, so nothing else carries over between calls;
+    /// once globals compile, their table belongs to the VM rather than the
+    /// chunk, so that REPL lines see the globals of earlier lines. The
+    /// execution trace of `debug_trace` builds goes to stderr.
+    pub fn interpret(&mut self, source: String) -> Result<Value, String> {
+        self.instruction_start = None;
         self.chunk = compiler::compile(source)?;
         self.ip = 0;
-        self.run(output)
+        self.stack.clear();
+        let result = log::timed("execute", || self.run(&mut io::stderr()));
+        self.instruction_start = None;
+        result
     }
 
-    fn run<T: Write + ?Sized>(&mut self, output: &mut T) -> Result<InterpretResult, String> {
+    // Runs the chunk, writing the execution trace of `debug_trace` builds
+    // to `trace`
+    #[cfg_attr(not(any(test, feature = "debug_trace")), allow(unused_variables))]
+    fn run<T: Write + ?Sized>(&mut self, trace: &mut T) -> Result<Value, String> {
         while self.ip < self.chunk.code.len() {
             #[cfg(any(test, feature = "debug_trace"))]
-            {
-                write!(output, "          ").unwrap();
+            if log::enabled(log::Verbosity::Normal) {
+                write!(trace, "          ").unwrap();
                 for slot in &self.stack {
-                    write!(output, "[ ").unwrap();
-                    write!(output, "{}", slot).unwrap();
-                    write!(output, " ]").unwrap();
+                    write!(trace, "[ ").unwrap();
+                    write!(trace, "{}", slot).unwrap();
+                    write!(trace, " ]").unwrap();
                 }
-                writeln!(output, "").unwrap();
-                self.chunk.disassemble_instruction(output, self.ip);
+                writeln!(trace, "").unwrap();
+                self.chunk.disassemble_instruction(trace, self.ip);
             }
+            self.instruction_start = Some(self.ip);
             let instruction = self.read_byte();
             match instruction {
                 x if x == OpCode::Return as u8 => {
-                    let _ = self.stack.pop().map_or((), |value| {
-                        writeln!(output, "{}", value).unwrap();
-                    });
-                    return Ok(InterpretResult::Ok);
+                    return Ok(self.stack.pop().unwrap_or(Value::Nil));
                 }
                 x if x == OpCode::Nil as u8 => self.stack.push(Value::Nil),
                 x if x == OpCode::True as u8 => self.stack.push(Value::Bool(true)),
                 x if x == OpCode::False as u8 => self.stack.push(Value::Bool(false)),
                 x if x == OpCode::Equal as u8 => self.equal_op(),
-                x if x == OpCode::Greater as u8 => self.binary_op(|a, b| Value::Bool(a > b)),
-                x if x == OpCode::Less as u8 => self.binary_op(|a, b| Value::Bool(a < b)),
-                x if x == OpCode::Add as u8 => self.binary_op(|a, b| Value::Number(a + b)),
-                x if x == OpCode::Subtract as u8 => self.binary_op(|a, b| Value::Number(a - b)),
-                x if x == OpCode::Multiply as u8 => self.binary_op(|a, b| Value::Number(a * b)),
-                x if x == OpCode::Divide as u8 => self.binary_op(|a, b| Value::Number(a / b)),
+                x if x == OpCode::Greater as u8 => self.binary_op(|a, b| Value::Bool(a > b))?,
+                x if x == OpCode::Less as u8 => self.binary_op(|a, b| Value::Bool(a < b))?,
+                x if x == OpCode::Add as u8 => self.binary_op(|a, b| Value::Number(a + b))?,
+                x if x == OpCode::Subtract as u8 => self.binary_op(|a, b| Value::Number(a - b))?,
+                x if x == OpCode::Multiply as u8 => self.binary_op(|a, b| Value::Number(a * b))?,
+                x if x == OpCode::Divide as u8 => self.binary_op(|a, b| Value::Number(a / b))?,
                 x if x == OpCode::Not as u8 => self.not_op(),
-                x if x == OpCode::Negate as u8 => self.unary_op(|a| -a),
+                x if x == OpCode::Negate as u8 => self.unary_op(|a| -a)?,
                 x if x == OpCode::Constant as u8 => {
                     let constant = self.read_constant();
                     self.stack.push(Value::Number(constant));
@@ -83,7 +115,7 @@ impl VirtualMachine {
                 }
             }
         }
-        Ok(InterpretResult::Ok)
+        Ok(Value::Nil)
     }
 
     fn read_byte(&mut self) -> u8 {
@@ -97,36 +129,40 @@ impl VirtualMachine {
         self.chunk.constants[constant_index]
     }
 
-    fn binary_op(&mut self, op: fn(f64, f64) -> Value) {
+    fn runtime_error(&self, message: &str) -> String {
+        let line = self
+            .instruction_start
+            .map_or(0, |offset| self.chunk.lines[offset]);
+        format!("{}\n[line {}]", message, line)
+    }
+
+    fn binary_op(&mut self, op: fn(f64, f64) -> Value) -> Result<(), String> {
         let b = self.stack.pop().unwrap();
         let a = self.stack.pop().unwrap();
-        let Value::Number(b) = b else {
-            panic!("Operand must be a number.");
-        };
-        let Value::Number(a) = a else {
-            panic!("Operand must be a number.");
+        let (Value::Number(a), Value::Number(b)) = (a, b) else {
+            return Err(self.runtime_error("Operands must be numbers."));
         };
         self.stack.push(op(a, b));
+        Ok(())
     }
 
-    fn binary_logic_op(&mut self, op: fn(bool, bool) -> bool) {
+    fn binary_logic_op(&mut self, op: fn(bool, bool) -> bool) -> Result<(), String> {
         let b = self.stack.pop().unwrap();
         let a = self.stack.pop().unwrap();
-        let Value::Bool(b) = b else {
-            panic!("Operand must be a boolean.");
-        };
-        let Value::Bool(a) = a else {
-            panic!("Operand must be a boolean.");
+        let (Value::Bool(a), Value::Bool(b)) = (a, b) else {
+            return Err(self.runtime_error("Operands must be booleans."));
         };
         self.stack.push(Value::Bool(op(a, b)));
+        Ok(())
     }
 
-    fn unary_op(&mut self, op: fn(f64) -> f64) {
+    fn unary_op(&mut self, op: fn(f64) -> f64) -> Result<(), String> {
         let a = self.stack.pop().unwrap();
         let Value::Number(a) = a else {
-            panic!("Operand must be a number.");
+            return Err(self.runtime_error("Operand must be a number."));
         };
         self.stack.push(Value::Number(op(a)));
+        Ok(())
     }
 
     fn not_op(&mut self) {
@@ -158,6 +194,16 @@ impl VirtualMachine {
     }
 }
 
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::Nil => "nil",
+        }
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -185,10 +231,11 @@ mod tests {
 
         let mut vm = VirtualMachine::new();
         vm.chunk = chunk;
-        vm.run(&mut output_writer).unwrap();
+        let value = vm.run(&mut output_writer).unwrap();
 
         let result = String::from_utf8_lossy(&output.borrow()).to_string();
         assert_eq!(result, "          \n0000 0001 OP_RETURN\n");
+        assert_eq!(value, Value::Nil);
     }
     #[test]
     fn test_constant_op() {
@@ -221,15 +268,16 @@ mod tests {
 
         let mut vm = VirtualMachine::new();
         vm.chunk = chunk;
-        vm.run(&mut output_writer).unwrap();
+        let value = vm.run(&mut output_writer).unwrap();
 
         let result = String::from_utf8_lossy(&output.borrow()).to_string();
         assert_eq!(
             result,
             "          \n0000 0001 OP_CONSTANT 0000 1.2\
             \n          [ 1.2 ]\n0002 0002 OP_NEGATE\
-            \n          [ -1.2 ]\n0003 0003 OP_RETURN\n-1.2\n"
+            \n          [ -1.2 ]\n0003 0003 OP_RETURN\n"
         );
+        assert_eq!(value, Value::Number(-1.2));
     }
     #[test]
     fn test_add_op() {
@@ -247,7 +295,7 @@ mod tests {
 
         let mut vm = VirtualMachine::new();
         vm.chunk = chunk;
-        vm.run(&mut output_writer).unwrap();
+        let value = vm.run(&mut output_writer).unwrap();
 
         let result = String::from_utf8_lossy(&output.borrow()).to_string();
         assert_eq!(
@@ -259,9 +307,9 @@ mod tests {
             \n          [ 1.2 ][ 1.2 ]\
             \n0004    | OP_ADD\
             \n          [ 2.4 ]\
-            \n0005 0002 OP_RETURN\
-            \n2.4\n"
+            \n0005 0002 OP_RETURN\n"
         );
+        assert_eq!(value, Value::Number(2.4));
     }
 
     #[test]
@@ -292,7 +340,7 @@ mod tests {
 
         let mut vm = VirtualMachine::new();
         vm.chunk = chunk;
-        vm.run(&mut output_writer).unwrap();
+        let value = vm.run(&mut output_writer).unwrap();
 
         let result = String::from_utf8_lossy(&output.borrow()).to_string();
         assert_eq!(
@@ -310,8 +358,29 @@ mod tests {
             \n          [ 0.8214285714285714 ]\
             \n0008    | OP_NEGATE\
             \n          [ -0.8214285714285714 ]\
-            \n0009    | OP_RETURN\
-            \n-0.8214285714285714\n"
+            \n0009    | OP_RETURN\n"
+        );
+        assert_eq!(value, Value::Number(-0.8214285714285714));
+    }
+
+    #[test]
+    fn test_negate_non_number() {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut output_writer = Box::new(VecWriter(Rc::clone(&output)));
+
+        let mut chunk = Chunk::new();
+        chunk.write_op_code(OpCode::True, 1);
+        chunk.write_op_code(OpCode::Negate, 2);
+        chunk.write_op_code(OpCode::Return, 2);
+
+        let mut vm = VirtualMachine::new();
+        vm.chunk = chunk;
+        let result = vm.run(&mut output_writer);
+
+        assert_eq!(result.err().unwrap(), "Operand must be a number.\n[line 2]");
+        assert_eq!(
+            vm.crash_context(),
+            "executing OP_NEGATE at offset 0001 (line 2)"
         );
     }
 }
@@ -0,0 +1,119 @@
+//! WebAssembly bindings for the tree-walking interpreter, meant to power a
+//! browser playground. Build with `wasm-pack build --target web`.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use loxrun::host::Clock;
+use loxrun::{Interpreter, Lox};
+use wasm_bindgen::prelude::*;
+
+/// What a script printed, and the errors it produced.
+#[wasm_bindgen]
+pub struct RunResult {
+    output: String,
+    errors: String,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        self.output.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn errors(&self) -> String {
+        self.errors.clone()
+    }
+}
+
+/// Runs the source in a fresh interpreter. exec() and the file natives are
+/// disabled and input() always returns nil.
+#[wasm_bindgen]
+pub fn run(source: &str) -> RunResult {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let interpreter = Interpreter::builder()
+        .output(SharedBuffer(Rc::clone(&output)))
+        .error_output(SharedBuffer(Rc::clone(&errors)))
+        .input(io::empty())
+        .clock(JsClock)
+        .allow_exec(false)
+        .allow_files(false)
+        .build();
+    let mut lox = Lox::with_interpreter(interpreter);
+    lox.set_error_reporting(false);
+
+    if let Err(err) = lox.run(source) {
+        let _ = writeln!(errors.borrow_mut(), "{}", err);
+    }
+
+    let output = String::from_utf8_lossy(&output.borrow()).into_owned();
+    let errors = String::from_utf8_lossy(&errors.borrow()).into_owned();
+    RunResult { output, errors }
+}
+
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// std has no clock on wasm32-unknown-unknown, so ask JavaScript instead.
+struct JsClock;
+
+impl Clock for JsClock {
+    fn now(&self) -> f64 {
+        js_sys::Date::now() / 1000.0
+    }
+
+    // The browser can't block, so spin until the time is up.
+    fn sleep(&self, duration: Duration, interrupt: &AtomicBool) -> bool {
+        let deadline = js_sys::Date::now() + duration.as_secs_f64() * 1000.0;
+        while js_sys::Date::now() < deadline {
+            if interrupt.load(Ordering::Relaxed) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let result = run("print 1 + 2; printErr(\"warning\"); print missing;");
+        assert_eq!(result.output(), "3\n");
+        assert_eq!(
+            result.errors(),
+            "warning\nUndefined variable 'missing'.\n[line 1]\n"
+        );
+
+        let result = run("readFile(\"Cargo.toml\");");
+        assert_eq!(
+            result.errors(),
+            "readFile() is disabled in this interpreter.\n[line 1]\n"
+        );
+
+        let result = run("print ;");
+        assert_eq!(result.output(), "");
+        assert_eq!(
+            result.errors(),
+            "[line 1] Error at ';': Expect expression.\n"
+        );
+    }
+}